@@ -15,6 +15,7 @@
 //! Facade and handler for the rest of the blockchain implementation
 //! and mostly the chain pipeline.
 
+use crate::core::consensus::{self, HeaderInfo};
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
 use crate::core::core::merkle_proof::MerkleProof;
 use crate::core::core::verifier_cache::VerifierCache;
@@ -34,7 +35,10 @@ use crate::types::{
 	BlockStatus, ChainAdapter, CommitPos, NoStatus, Options, Tip, TxHashsetWriteStatus,
 };
 use crate::util::secp::pedersen::{Commitment, RangeProof};
-use crate::{util::RwLock, ChainStore};
+use crate::{
+	util::{Mutex, RwLock},
+	ChainStore,
+};
 use grin_store::Error::NotFoundErr;
 use grin_util::ToHex;
 use std::collections::HashMap;
@@ -47,9 +51,31 @@ use std::time::{Duration, Instant};
 /// Orphan pool size is limited by MAX_ORPHAN_SIZE
 pub const MAX_ORPHAN_SIZE: usize = 200;
 
+/// Most heights missing_block_ranges looks at in a single call
+pub const MAX_MISSING_BLOCKS_SCAN: u64 = 1_000;
+
 /// When evicting, very old orphans are evicted first
 const MAX_ORPHAN_AGE_SECS: u64 = 300;
 
+/// Number of worker threads used to validate the PoW of header batches
+const HEADER_VALIDATION_WORKERS: usize = 4;
+
+/// Height of the txhashset archive we offer to peers while our body head is
+/// at the provided height: the last archive interval boundary at least
+/// state_sync_threshold blocks below the head.
+pub fn txhashset_archive_height(head_height: u64) -> u64 {
+	let sync_threshold = global::state_sync_threshold() as u64;
+	let archive_interval = global::txhashset_archive_interval();
+	let height = head_height.saturating_sub(sync_threshold);
+	height.saturating_sub(height % archive_interval)
+}
+
+/// Whether the archive built at archive_height went stale with our body head
+/// at head_height, the head crossing the next archive interval boundary.
+pub fn txhashset_archive_refresh_due(archive_height: u64, head_height: u64) -> bool {
+	txhashset_archive_height(head_height) != archive_height
+}
+
 /// Banned block. We don't accept any blockchain with this has
 pub const BLOCK_TO_BAN: &str = "00020440a401086e57e1b7a92ebb0277c7f7fd47a38269ecc6789c2a80333725";
 
@@ -143,6 +169,23 @@ impl OrphanBlockPool {
 	}
 }
 
+/// Keeps the chain flagged as being in maintenance (compaction or txhashset
+/// write) for as long as it is alive.
+struct MaintenanceGuard<'a>(&'a AtomicUsize);
+
+impl<'a> MaintenanceGuard<'a> {
+	fn new(count: &'a AtomicUsize) -> MaintenanceGuard<'a> {
+		count.fetch_add(1, Ordering::SeqCst);
+		MaintenanceGuard(count)
+	}
+}
+
+impl<'a> Drop for MaintenanceGuard<'a> {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
 /// Facade to the blockchain block processing pipeline and storage. Provides
 /// the current view of the TxHashSet according to the chain state. Also
 /// maintains locking for the pipeline to avoid conflicting processing.
@@ -157,8 +200,14 @@ pub struct Chain {
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	// POW verification function
 	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+	// workers validating the PoW of header batches during sync
+	header_pow_pool: pipe::HeaderPowPool,
 	archive_mode: bool,
 	genesis: BlockHeader,
+	// number of maintenance operations (compaction, txhashset write) in progress
+	maintenance: AtomicUsize,
+	// height of the last txhashset archive we prepared ahead of requests
+	txhashset_archive: Mutex<Option<u64>>,
 }
 
 impl Chain {
@@ -221,9 +270,12 @@ impl Chain {
 			header_pmmr: Arc::new(RwLock::new(header_pmmr)),
 			sync_pmmr: Arc::new(RwLock::new(sync_pmmr)),
 			pow_verifier,
+			header_pow_pool: pipe::HeaderPowPool::new(HEADER_VALIDATION_WORKERS, pow_verifier),
 			verifier_cache,
 			archive_mode,
 			genesis: genesis.header,
+			maintenance: AtomicUsize::new(0),
+			txhashset_archive: Mutex::new(None),
 		};
 
 		// If known bad block exists on "current chain" then rewind prior to this.
@@ -596,6 +648,13 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Validate the PoW of a batch of headers on a worker pool, without taking
+	/// any of the chain locks. Results are in the same order as the headers,
+	/// so a failure can be attributed to the header that caused it.
+	pub fn validate_headers_pow(&self, headers: &[BlockHeader]) -> Vec<Result<(), Error>> {
+		self.header_pow_pool.validate(headers, Options::NONE)
+	}
+
 	/// Attempt to add new headers to the header chain (or fork).
 	/// This is only ever used during sync and is based on sync_head.
 	/// We update header_head here if our total work increases.
@@ -957,11 +1016,8 @@ impl Chain {
 	/// and no longer support requesting arbitrary txhashsets.
 	/// Here we return the header of the txhashset we are currently offering to peers.
 	pub fn txhashset_archive_header(&self) -> Result<BlockHeader, Error> {
-		let sync_threshold = global::state_sync_threshold() as u64;
 		let body_head = self.head()?;
-		let archive_interval = global::txhashset_archive_interval();
-		let mut txhashset_height = body_head.height.saturating_sub(sync_threshold);
-		txhashset_height = txhashset_height.saturating_sub(txhashset_height % archive_interval);
+		let txhashset_height = txhashset_archive_height(body_head.height);
 
 		debug!(
 			"txhashset_archive_header: body_head - {}, {}, txhashset height - {}",
@@ -971,6 +1027,46 @@ impl Chain {
 		self.get_header_by_height(txhashset_height)
 	}
 
+	/// Whether the txhashset archive we prepared last (if any) went stale and
+	/// isn't being rebuilt already.
+	pub fn txhashset_archive_stale(&self) -> bool {
+		let prepared = match self.txhashset_archive.try_lock() {
+			Some(prepared) => *prepared,
+			None => return false,
+		};
+		match (prepared, self.head()) {
+			(Some(height), Ok(head)) => txhashset_archive_refresh_due(height, head.height),
+			(None, Ok(_)) => true,
+			(_, Err(_)) => false,
+		}
+	}
+
+	/// Build the txhashset archive we offer to peers as soon as the one we
+	/// prepared last went stale, instead of on the first request for it.
+	/// Returns whether an archive was built, skips if one is being built
+	/// already.
+	pub fn refresh_txhashset_archive(&self) -> Result<bool, Error> {
+		let mut prepared = match self.txhashset_archive.try_lock() {
+			Some(prepared) => prepared,
+			None => return Ok(false),
+		};
+		let body_head = self.head()?;
+		if let Some(height) = *prepared {
+			if !txhashset_archive_refresh_due(height, body_head.height) {
+				return Ok(false);
+			}
+		}
+		let header = self.txhashset_archive_header()?;
+		debug!(
+			"refresh_txhashset_archive: building archive at {}, {}",
+			header.hash(),
+			header.height
+		);
+		self.txhashset_read(header.hash())?;
+		*prepared = Some(header.height);
+		Ok(true)
+	}
+
 	// Special handling to make sure the whole kernel set matches each of its
 	// roots in each block header, without truncation. We go back header by
 	// header, rewind and check each root. This fixes a potential weakness in
@@ -1160,6 +1256,7 @@ impl Chain {
 		txhashset_data: File,
 		status: &dyn TxHashsetWriteStatus,
 	) -> Result<bool, Error> {
+		let _maintenance = MaintenanceGuard::new(&self.maintenance);
 		status.on_setup();
 
 		// Initial check whether this txhashset is needed or not
@@ -1341,12 +1438,19 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Whether a compaction or txhashset write is currently in progress.
+	pub fn in_maintenance(&self) -> bool {
+		self.maintenance.load(Ordering::SeqCst) > 0
+	}
+
 	/// Triggers chain compaction.
 	///
 	/// * compacts the txhashset based on current prune_list
 	/// * removes historical blocks and associated data from the db (unless archive mode)
 	///
 	pub fn compact(&self) -> Result<(), Error> {
+		let _maintenance = MaintenanceGuard::new(&self.maintenance);
+
 		// A node may be restarted multiple times in a short period of time.
 		// We compact at most once per 60 blocks in this situation by comparing
 		// current "head" and "tail" height to our cut-through horizon and
@@ -1534,6 +1638,74 @@ impl Chain {
 		self.get_block_header(&hash)
 	}
 
+	/// Gets up to count headers of the header chain starting at the provided
+	/// height, stopping early at the header head.
+	/// Note: Takes a read lock on the header_pmmr.
+	pub fn headers_by_height(&self, start: u64, count: u64) -> Result<Vec<BlockHeader>, Error> {
+		let max_height = self.header_head()?.height;
+		let header_pmmr = self.header_pmmr.read();
+		let mut headers = vec![];
+		for height in start..start.saturating_add(count) {
+			if height > max_height {
+				break;
+			}
+			let hash = header_pmmr.get_header_hash_by_height(height)?;
+			headers.push(self.get_block_header(&hash)?);
+		}
+		Ok(headers)
+	}
+
+	/// Contiguous (inclusive) ranges of heights from from to to on our header
+	/// chain we don't have the block for, neither stored nor as an orphan.
+	/// Heights past the header head, or more than MAX_MISSING_BLOCKS_SCAN
+	/// from from, are left out.
+	/// Note: Takes a read lock on the header_pmmr, released before looking
+	/// the blocks up.
+	pub fn missing_block_ranges(&self, from: u64, to: u64) -> Result<Vec<(u64, u64)>, Error> {
+		let to = to
+			.min(self.header_head()?.height)
+			.min(from.saturating_add(MAX_MISSING_BLOCKS_SCAN - 1));
+		let hashes = {
+			let header_pmmr = self.header_pmmr.read();
+			(from..=to)
+				.map(|height| {
+					header_pmmr
+						.get_header_hash_by_height(height)
+						.map(|hash| (height, hash))
+				})
+				.collect::<Result<Vec<_>, _>>()?
+		};
+		let mut ranges: Vec<(u64, u64)> = vec![];
+		for (height, hash) in hashes {
+			if self.block_exists(hash)? || self.is_orphan(&hash) {
+				continue;
+			}
+			match ranges.last_mut() {
+				Some(last) if last.1 + 1 == height => last.1 = height,
+				_ => ranges.push((height, height)),
+			}
+		}
+		Ok(ranges)
+	}
+
+	/// Height of the first header of the locator on our header chain, the
+	/// common ancestor with the peer that sent it (locators go from the tip
+	/// backwards). None if we share none of them.
+	/// Note: Takes a read lock on the header_pmmr.
+	pub fn common_ancestor_height(&self, locator: &[Hash]) -> Option<u64> {
+		let header_pmmr = self.header_pmmr.read();
+		for hash in locator {
+			if let Ok(header) = self.get_block_header(hash) {
+				if let Ok(hash_at_height) = header_pmmr.get_header_hash_by_height(header.height) {
+					if hash_at_height == *hash {
+						return Some(header.height);
+					}
+				}
+			}
+		}
+		None
+	}
+
 	/// Gets the header hash at the provided height.
 	/// Note: Takes a read lock on the header_pmmr.
 	fn get_header_hash_by_height(&self, height: u64) -> Result<Hash, Error> {
@@ -1699,6 +1871,16 @@ impl Chain {
 		Ok(store::DifficultyIter::from(head.last_block_h, store))
 	}
 
+	/// Difficulty and secondary scaling expected for the next block on top of
+	/// the current chain head, as computed by the consensus retarget.
+	pub fn next_difficulty(&self) -> Result<HeaderInfo, Error> {
+		let head = self.head()?;
+		Ok(consensus::next_difficulty(
+			head.height + 1,
+			self.difficulty_iter()?,
+		))
+	}
+
 	/// Check whether we have a block without reading it
 	pub fn block_exists(&self, h: Hash) -> Result<bool, Error> {
 		self.store
@@ -110,6 +110,9 @@ pub enum ErrorKind {
 	/// We've been provided a bad txhashset
 	#[fail(display = "Invalid TxHashSet: {}", _0)]
 	InvalidTxHashSet(String),
+	/// A txhashset we downloaded proved corrupt while validating it
+	#[fail(display = "Corrupt TxHashSet: {}", _0)]
+	CorruptTxHashSet(String),
 	/// Internal issue when trying to save or load data from store
 	#[fail(display = "Chain Store Error: {}, reason: {}", _1, _0)]
 	StoreErr(store::Error, String),
@@ -189,6 +192,15 @@ impl Error {
 		self.inner.backtrace()
 	}
 
+	/// Whether the error is on our side and may go away when trying again,
+	/// like a busy store
+	pub fn is_transient(&self) -> bool {
+		match self.kind() {
+			ErrorKind::StoreErr(_, _) => true,
+			_ => false,
+		}
+	}
+
 	/// Whether the error is due to a block that was intrinsically wrong
 	pub fn is_bad_data(&self) -> bool {
 		// shorter to match on all the "not the block's fault" errors
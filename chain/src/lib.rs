@@ -48,7 +48,9 @@ pub mod types;
 
 // Re-export the base interface
 
-pub use crate::chain::{Chain, BLOCK_TO_BAN, MAX_ORPHAN_SIZE};
+pub use crate::chain::{
+	txhashset_archive_height, txhashset_archive_refresh_due, Chain, BLOCK_TO_BAN, MAX_ORPHAN_SIZE,
+};
 pub use crate::error::{Error, ErrorKind};
 pub use crate::store::ChainStore;
 pub use crate::types::{
@@ -27,10 +27,12 @@ use crate::error::{Error, ErrorKind};
 use crate::store;
 use crate::txhashset;
 use crate::types::{CommitPos, Options, Tip};
-use crate::util::RwLock;
+use crate::util::{Mutex, RwLock};
 use grin_core::core::hash::Hash;
+use std::cmp;
 use std::collections::HashSet;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
+use std::thread;
 
 /// Contextual information required to process a new block and either reject or
 /// accept it.
@@ -135,20 +137,30 @@ pub fn check_against_duplicate_kernel(
 // Validate only the proof of work in a block header.
 // Used to cheaply validate pow before checking if orphan or continuing block validation.
 fn validate_pow_only(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result<(), Error> {
+	check_header_pow(header, ctx.opts, ctx.pow_verifier)
+}
+
+// Proof of work checks that need nothing but the header itself, so they can
+// safely run outside of the chain locks.
+fn check_header_pow(
+	header: &BlockHeader,
+	opts: Options,
+	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+) -> Result<(), Error> {
 	let hash = header.hash();
 	if INVALID_BLOCK_HASHES.read().contains(&hash) {
 		error!("Invalid header found: {}. Rejecting it!", hash);
 		return Err(ErrorKind::InvalidHash.into());
 	}
 
-	if ctx.opts.contains(Options::SKIP_POW) {
+	if opts.contains(Options::SKIP_POW) {
 		// Some of our tests require this check to be skipped (we should revisit this).
 		return Ok(());
 	}
 	if !header.pow.is_primary() && !header.pow.is_secondary() {
 		return Err(ErrorKind::LowEdgebits.into());
 	}
-	if (ctx.pow_verifier)(header).is_err() {
+	if pow_verifier(header).is_err() {
 		error!(
 			"pipe: error validating header with cuckoo edge_bits {}",
 			header.pow.edge_bits(),
@@ -310,6 +322,87 @@ pub fn sync_block_headers(
 	})
 }
 
+/// Proof of work check of a header of a batch, with where to send its result.
+struct PowJob {
+	index: usize,
+	header: BlockHeader,
+	opts: Options,
+	results: mpsc::Sender<(usize, Result<(), Error>)>,
+}
+
+/// Long-lived pool of worker threads validating the proof of work of header
+/// batches, the thread handling the peer only dispatches the headers and
+/// collects the results. Only checks that don't depend on the chain state are
+/// done here.
+pub struct HeaderPowPool {
+	jobs: Mutex<mpsc::SyncSender<PowJob>>,
+}
+
+impl HeaderPowPool {
+	/// Starts the provided number of workers, verifying the PoW with
+	/// pow_verifier. They stop once the pool is dropped.
+	pub fn new(
+		workers: usize,
+		pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+	) -> HeaderPowPool {
+		let workers = cmp::max(workers, 1);
+		let (jobs, queue) = mpsc::sync_channel::<PowJob>(2 * workers);
+		let queue = Arc::new(Mutex::new(queue));
+		for i in 0..workers {
+			let queue = queue.clone();
+			let spawned = thread::Builder::new()
+				.name(format!("header_pow_{}", i))
+				.spawn(move || loop {
+					let job = match queue.lock().recv() {
+						Ok(job) => job,
+						Err(_) => break,
+					};
+					let res = check_header_pow(&job.header, job.opts, pow_verifier);
+					let _ = job.results.send((job.index, res));
+				});
+			if let Err(e) = spawned {
+				error!("header_pow_pool: failed to start worker: {}", e);
+			}
+		}
+		HeaderPowPool {
+			jobs: Mutex::new(jobs),
+		}
+	}
+
+	/// Validate the proof of work of a batch of headers, results are returned
+	/// in the same order as the headers.
+	pub fn validate(&self, headers: &[BlockHeader], opts: Options) -> Vec<Result<(), Error>> {
+		let jobs = self.jobs.lock().clone();
+		let (results_tx, results_rx) = mpsc::channel();
+		for (index, header) in headers.iter().enumerate() {
+			let job = PowJob {
+				index,
+				header: header.clone(),
+				opts,
+				results: results_tx.clone(),
+			};
+			// blocks while the workers are busy with earlier headers
+			if jobs.send(job).is_err() {
+				break;
+			}
+		}
+		drop(results_tx);
+
+		let mut results: Vec<Option<Result<(), Error>>> = headers.iter().map(|_| None).collect();
+		for (index, res) in results_rx.iter() {
+			results[index] = Some(res);
+		}
+		results
+			.into_iter()
+			.map(|res| {
+				res.unwrap_or_else(|| {
+					Err(ErrorKind::Other("header validation worker failed".into()).into())
+				})
+			})
+			.collect()
+	}
+}
+
 /// Process a block header. Update the header MMR and corresponding header_head if this header
 /// increases the total work relative to header_head.
 /// Note: In contrast to processing a full block we treat "already known" as success
@@ -474,7 +567,10 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result<(
 	if !ctx.opts.contains(Options::SKIP_POW) {
 		// Quick check of this header in isolation. No point proceeding if this fails.
 		// We can do this without needing to iterate over previous headers.
-		validate_pow_only(header, ctx)?;
+		// Skipped if the batch was already checked by validate_headers_pow.
+		if !ctx.opts.contains(Options::POW_VERIFIED) {
+			validate_pow_only(header, ctx)?;
+		}
 
 		if header.total_difficulty() <= prev.total_difficulty() {
 			return Err(ErrorKind::DifficultyTooLow.into());
@@ -34,6 +34,8 @@ bitflags! {
 		const SYNC = 0b0000_0010;
 		/// Block validation on a block we mined ourselves
 		const MINE = 0b0000_0100;
+		/// Header PoW was already checked (batched header validation).
+		const POW_VERIFIED = 0b0000_1000;
 	}
 }
 
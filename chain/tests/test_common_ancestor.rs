@@ -0,0 +1,46 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, mine_chain};
+use crate::core::core::hash::{Hash, Hashed};
+
+#[test]
+fn test_common_ancestor_height() {
+	let chain_dir = ".grin.common_ancestor";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 10);
+
+	// a peer on a fork of ours from height 5, its own headers unknown to us
+	let fork_tip = Hash::from_vec(&[1; 32]);
+	let fork_mid = Hash::from_vec(&[2; 32]);
+	let ancestor = chain.get_header_by_height(5).unwrap().hash();
+	let genesis = chain.get_header_by_height(0).unwrap().hash();
+	assert_eq!(
+		chain.common_ancestor_height(&[fork_tip, fork_mid, ancestor, genesis]),
+		Some(5)
+	);
+
+	// a peer behind us, its tip is the common ancestor
+	let tip = chain.get_header_by_height(7).unwrap().hash();
+	assert_eq!(chain.common_ancestor_height(&[tip, genesis]), Some(7));
+
+	// nothing in common (another chain entirely)
+	assert_eq!(chain.common_ancestor_height(&[fork_tip, fork_mid]), None);
+	assert_eq!(chain.common_ancestor_height(&[]), None);
+	clean_output_dir(chain_dir);
+}
@@ -0,0 +1,73 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_util as util;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, init_chain, mine_chain};
+use crate::chain::{ErrorKind, Options};
+use crate::core::core::hash::Hashed;
+use crate::core::core::BlockHeader;
+
+#[test]
+fn test_header_batch_validation() {
+	let chain_dir = ".grin.header_batch";
+	let sync_dir = ".grin.header_batch_sync";
+	util::init_test_logger();
+	clean_output_dir(chain_dir);
+	clean_output_dir(sync_dir);
+
+	let chain = mine_chain(chain_dir, 10);
+	let headers: Vec<BlockHeader> = (1..10)
+		.map(|h| chain.get_header_by_height(h).unwrap())
+		.collect();
+
+	// A valid batch passes through the pool, one result per header.
+	let results = chain.validate_headers_pow(&headers);
+	assert_eq!(results.len(), headers.len());
+	assert!(results.iter().all(|r| r.is_ok()));
+
+	// Break the PoW of a header in the middle of the batch, the failure must
+	// be reported against that header only.
+	let mut bad_headers = headers.clone();
+	bad_headers[4].pow.nonce += 1;
+	let results = chain.validate_headers_pow(&bad_headers);
+	assert_eq!(results.len(), bad_headers.len());
+	for (i, res) in results.into_iter().enumerate() {
+		if i == 4 {
+			assert_eq!(res.map_err(|e| e.kind()), Err(ErrorKind::InvalidPow));
+		} else {
+			assert!(res.is_ok());
+		}
+	}
+
+	// Headers validated by the pool still apply in order on a fresh chain.
+	let genesis = chain
+		.get_block(&chain.get_header_by_height(0).unwrap().hash())
+		.unwrap();
+	let sync_chain = init_chain(sync_dir, genesis);
+	sync_chain
+		.sync_block_headers(&headers, Options::SYNC | Options::POW_VERIFIED)
+		.unwrap();
+	assert_eq!(
+		sync_chain.header_head().unwrap().last_block_h,
+		headers.last().unwrap().hash()
+	);
+
+	clean_output_dir(chain_dir);
+	clean_output_dir(sync_dir);
+}
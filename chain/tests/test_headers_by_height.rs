@@ -0,0 +1,49 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_util as util;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, mine_chain};
+use crate::core::core::BlockHeader;
+
+#[test]
+fn test_headers_by_height() {
+	let chain_dir = ".grin.headers_by_height";
+	util::init_test_logger();
+	clean_output_dir(chain_dir);
+
+	let chain = mine_chain(chain_dir, 10);
+	let head = chain.header_head().unwrap().height;
+
+	// Exactly the requested range.
+	let headers = chain.headers_by_height(3, 4).unwrap();
+	let expected: Vec<BlockHeader> = (3..7)
+		.map(|h| chain.get_header_by_height(h).unwrap())
+		.collect();
+	assert_eq!(headers, expected);
+
+	// Ranges past the header head stop at the head.
+	let headers = chain.headers_by_height(head - 1, 100).unwrap();
+	assert_eq!(
+		headers.iter().map(|h| h.height).collect::<Vec<_>>(),
+		vec![head - 1, head]
+	);
+	assert!(chain.headers_by_height(head + 1, 10).unwrap().is_empty());
+	assert!(chain.headers_by_height(0, 0).unwrap().is_empty());
+
+	clean_output_dir(chain_dir);
+}
@@ -0,0 +1,75 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_util as util;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, init_chain, mine_chain};
+use crate::chain::types::Options;
+use crate::core::core::hash::Hashed;
+use crate::core::core::{Block, BlockHeader};
+
+#[test]
+fn test_missing_block_ranges() {
+	let src_dir = ".grin.missing_ranges_src";
+	let chain_dir = ".grin.missing_ranges";
+	util::init_test_logger();
+	clean_output_dir(src_dir);
+	clean_output_dir(chain_dir);
+
+	let src = mine_chain(src_dir, 10);
+	let head = src.head().unwrap().height;
+	let block = |height: u64| -> Block {
+		let header = src.get_header_by_height(height).unwrap();
+		src.get_block(&header.hash()).unwrap()
+	};
+
+	// A chain having all the headers but only some of the blocks, 6 and 7
+	// being held as orphans.
+	let chain = init_chain(chain_dir, block(0));
+	let headers: Vec<BlockHeader> = (1..=head)
+		.map(|h| src.get_header_by_height(h).unwrap())
+		.collect();
+	chain.sync_block_headers(&headers, Options::SYNC).unwrap();
+	for height in 1..=2 {
+		chain.process_block(block(height), Options::SYNC).unwrap();
+	}
+	for height in 6..=7 {
+		assert!(chain.process_block(block(height), Options::SYNC).is_err());
+		assert!(chain.is_orphan(&block(height).hash()));
+	}
+
+	assert_eq!(
+		chain.missing_block_ranges(1, head).unwrap(),
+		vec![(3, 5), (8, head)]
+	);
+
+	// Past the header head is left out, as are ranges we have in full.
+	assert_eq!(
+		chain.missing_block_ranges(0, head + 100).unwrap(),
+		vec![(3, 5), (8, head)]
+	);
+	assert_eq!(chain.missing_block_ranges(4, 6).unwrap(), vec![(4, 5)]);
+	assert!(chain.missing_block_ranges(6, 7).unwrap().is_empty());
+	assert!(chain.missing_block_ranges(5, 4).unwrap().is_empty());
+
+	// Nothing missing on the chain we mined.
+	assert!(src.missing_block_ranges(0, head).unwrap().is_empty());
+
+	clean_output_dir(src_dir);
+	clean_output_dir(chain_dir);
+}
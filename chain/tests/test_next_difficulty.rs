@@ -0,0 +1,61 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, mine_chain};
+use self::core::consensus::{self, HeaderInfo};
+use self::core::core::hash::Hashed;
+use self::core::pow::Difficulty;
+
+#[test]
+fn test_next_difficulty() {
+	let chain_dir = ".grin.next_difficulty";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 12);
+	let head = chain.head().unwrap();
+	assert_eq!(head.height, 11);
+
+	// Build the retarget window by hand, from the head back to genesis.
+	let window: Vec<HeaderInfo> = (0..=head.height)
+		.rev()
+		.map(|height| {
+			let header = chain.get_header_by_height(height).unwrap();
+			let prev_difficulty = if height == 0 {
+				Difficulty::zero()
+			} else {
+				chain
+					.get_header_by_height(height - 1)
+					.unwrap()
+					.total_difficulty()
+			};
+			HeaderInfo::new(
+				header.hash(),
+				header.timestamp.timestamp() as u64,
+				header.total_difficulty() - prev_difficulty,
+				header.pow.secondary_scaling,
+				header.pow.is_secondary(),
+			)
+		})
+		.collect();
+	let expected = consensus::next_difficulty(head.height + 1, window);
+
+	let next = chain.next_difficulty().unwrap();
+	assert_eq!(next.difficulty, expected.difficulty);
+	assert_eq!(next.secondary_scaling, expected.secondary_scaling);
+
+	clean_output_dir(chain_dir);
+}
@@ -12,9 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use grin_chain as chain;
+use grin_core as core;
+
 mod chain_test_helper;
 
 use self::chain_test_helper::{clean_output_dir, mine_chain};
+use crate::chain::{txhashset_archive_height, txhashset_archive_refresh_due};
+use crate::core::global::{self, ChainTypes};
 
 #[test]
 fn test() {
@@ -25,3 +30,32 @@ fn test() {
 	assert_eq!(10, header.height);
 	clean_output_dir(chain_dir);
 }
+
+// With an archive interval of 10 and a sync threshold of 20, the archive
+// built at 10 is offered up to a head at 39 and stale from 40 on.
+#[test]
+fn test_refresh_due_at_boundary() {
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	assert_eq!(txhashset_archive_height(5), 0);
+	assert_eq!(txhashset_archive_height(30), 10);
+	assert_eq!(txhashset_archive_height(39), 10);
+	assert_eq!(txhashset_archive_height(40), 20);
+
+	assert!(!txhashset_archive_refresh_due(10, 30));
+	assert!(!txhashset_archive_refresh_due(10, 39));
+	assert!(txhashset_archive_refresh_due(10, 40));
+	assert!(!txhashset_archive_refresh_due(20, 40));
+}
+
+// The archive gets built once ahead of requests, then not again until stale.
+#[test]
+fn test_refresh_archive() {
+	let chain_dir = ".txhashset_archive_refresh_test";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 35);
+	assert!(chain.txhashset_archive_stale());
+	assert!(chain.refresh_txhashset_archive().unwrap());
+	assert!(!chain.txhashset_archive_stale());
+	assert!(!chain.refresh_txhashset_archive().unwrap());
+	clean_output_dir(chain_dir);
+}
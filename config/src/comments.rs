@@ -329,11 +329,13 @@ fn comments() -> HashMap<String, String> {
 #peers_deny = [\"192.168.0.3:3414\", \"192.168.0.4:3414\"]
 #a list of preferred peers to connect to
 #peers_preferred = [\"192.168.0.1:3414\",\"192.168.0.2:3414\"]
+#will *never* ban peers in this list (own infrastructure, monitoring)
+#peers_never_ban = [\"192.168.0.5:3414\"]
 
 #how long a banned peer should stay banned
 #ban_window = 10800
 
-#maximum number of inbound peer connections
+#maximum number of inbound peer connections (0 for outbound only)
 #peer_max_inbound_count = 128
 
 #maximum number of outbound peer connections
@@ -343,9 +345,228 @@ fn comments() -> HashMap<String, String> {
 #until we get to at least this number)
 #peer_min_preferred_outbound_count = 8
 
+#warn when we stay short of peer_min_preferred_outbound_count for this many
+#seconds
+#outbound_deficit_warn_after = 300
+
+#once short of peer_min_preferred_outbound_count for this many seconds, also
+#try connecting to peers we previously failed to reach (never by default)
+#outbound_deficit_relax_after = 600
+
 #amount of incoming connections temporarily allowed to exceed peer_max_inbound_count
+#(none when it's 0)
 #peer_listener_buffer_count = 8
 
+#above this load factor (0.0 to 1.0) new inbound connections are refused more
+#and more often, all of them at full load
+#inbound_load_threshold = 0.5
+
+#seconds during which an orphan block stays known, other peers delivering it
+#again meanwhile don't get it processed twice
+#orphan_tracking_window = 60
+
+#maximum number of orphan blocks tracked at once
+#max_tracked_orphans = 256
+
+#bridge node, gossip clearnet addresses to Tor peers and onion addresses to
+#clearnet peers (otherwise each only learns addresses of its own kind)
+#bridge_mode = false
+
+#only pass on addresses of peers accepting connections, leaving out those that
+#connected to us without advertising a listening port
+#gossip_reachable_only = true
+
+#seconds we wait before dialing a peer again after getting disconnected from it
+#peer_min_reconnect_interval = 60
+
+#same for the peers in peers_preferred
+#peer_min_preferred_reconnect_interval = 10
+
+#connection directions to send keepalives on, keeping NAT mappings and tor
+#circuits from silently dropping (Inbound, Outbound, InboundTor, OutboundTor)
+#keepalive_directions = [\"InboundTor\", \"OutboundTor\"]
+
+#seconds between keepalives
+#keepalive_interval = 30
+
+#among sync peers of equal difficulty, prefer the ones that served us valid
+#headers or blocks most recently
+#prefer_useful_peers = true
+
+#seconds between asking connected peers for their current capabilities, to
+#notice changes they didn't announce (0 disables)
+#capabilities_probe_interval = 21600
+
+#headers past our validated tip taken from a single peer before another peer
+#has to claim at least as much total difficulty as they carry
+#max_uncorroborated_headers = 100000
+
+#local address outbound connections are made from, to egress a specific
+#interface on hosts with several (connections through tor don't use it)
+#outbound_bind = \"192.168.1.10\"
+
+#send the same handshake nonce over all our connections so peers reaching us
+#at several addresses (onion and clearnet) count us once, links our addresses
+#share_node_nonce = false
+
+#close connections sending messages of a type we don't know, instead of
+#skipping them as coming from a newer peer
+#strict_protocol = false
+
+#blocks the height advertised by a peer may drop by (as on a reorg) before
+#the peer gets parked
+#height_regression_tolerance = 60
+
+#number of onion addresses stored (the oldest ones make room for new ones),
+#and considered for dialing at once
+#max_onion_peers = 256
+
+#minimum seconds between two onion peer dials
+#onion_dial_interval = 10
+
+#number of bad compact blocks a peer may send within bad_compact_block_window
+#(in seconds) before it gets banned
+#bad_compact_block_ban_threshold = 3
+#bad_compact_block_window = 3600
+
+#run as a headers-only relay: only propagate headers and peer addresses, never
+#serve nor request full blocks, txhashsets or transactions
+#headers_only = false
+
+#address family tried first when a DNS seed resolves to both IPv4 and IPv6,
+#\"any\" keeps the resolver order, \"ipv4\" or \"ipv6\" puts that family first
+#preferred_address_family = \"any\"
+
+#weights of the signals combined into the sync priority of peers (difficulty,
+#ping round trip time, share of requests not refused, how recently a peer was
+#useful and its uptime), when set we sync from the highest ranked peer
+#sync_priority_weights = { difficulty = 1.0, rtt = 0.1, reputation = 0.1, recency = 0.1, uptime = 0.05 }
+
+#seconds a peer has to complete the handshake (noise encryption included, 0
+#for the default), and whether peers failing to complete it in time get banned
+#handshake_timeout_secs = 10
+#penalize_handshake_timeout = true
+
+#whether new outbound peers get dialed, when false the peer set stays as it is
+#(inbound connections are still accepted)
+#dialing_enabled = true
+
+#seconds a peer has after connecting to report its difficulty and height, and
+#what to do with peers never reporting them: \"ignore\", \"deprioritize\" (don't
+#sync from them, disconnect them first when over our limits) or \"evict\"
+#silent_peer_grace_secs = 120
+#silent_peer_policy = \"deprioritize\"
+
+#interval (in seconds) between asking a peer to check our listener is reachable
+#from outside, when set we only advertise ourselves as reachable once confirmed
+#(0 disables)
+#reachability_probe_interval = 0
+
+#total size (in bytes) of the message bodies being read from all peers at once,
+#past it connections reading large messages pause until there's room (0 disables)
+#max_inbound_buffer_bytes = 268435456
+
+#how many connected peers must advertise a tip before we download its blocks or
+#txhashset, below it we only sync headers (1 trusts any single peer)
+#min_corroborating_peers = 1
+
+#how many times we process again a block the chain failed on for a transient
+#reason (busy store), peers are never penalized for those (0 disables)
+#block_error_retries = 2
+
+#number of outbound peers saved as anchors and reconnected to first after a restart
+#(0 disables anchor peers)
+#anchor_peer_count = 2
+
+#largest message body (in bytes) accepted from a peer, defaults to the size
+#limit of the largest message types
+#max_message_size = 5392128
+
+#number of malformed messages a peer may send within ser_error_window (in seconds)
+#before it gets banned
+#ser_error_ban_threshold = 5
+#ser_error_window = 600
+
+#number of header batches on a fork losing against ours (in cumulative
+#difficulty) a peer may send before it gets parked for minority_fork_cooldown
+#(in seconds), peers sending invalid fork headers are banned (0 disables)
+#minority_fork_threshold = 3
+#minority_fork_cooldown = 1800
+
+#minimum interval (in seconds) between fresh answers to peer address requests
+#from the same peer, repeated requests get the previous answer (0 disables)
+#peer_addrs_request_interval = 60
+
+#interval (in seconds) between batches of received peer addresses passed on to
+#our peers, only addresses we could connect to ourselves are passed on
+#peer_addrs_regossip_interval = 600
+
+#maximum number of received peer addresses passed on per batch
+#peer_addrs_regossip_cap = 32
+
+#append a line (time, address, reason, ban count) for every peer we ban to
+#this file, kept apart from the rotating logs for later analysis
+#ban_log_path = \"/path/to/bans.log\"
+
+#serve txhashset archives to peers, set to false on bandwidth constrained nodes
+#(we can still download archives ourselves)
+#serve_txhashset = true
+
+#how many blocks behind the most advanced peer we may be while syncing and
+#still advertise full capabilities, further behind (or while rebuilding the
+#txhashset) we only advertise our peer list so peers don't ask us for history
+#full_capabilities_max_lag = 60
+
+#how many times we download the txhashset again, each time from a different
+#peer, when the one we got proves corrupt before giving up on state sync
+#max_txhashset_redownloads = 3
+
+#number of messages waiting to be sent to a peer beyond which we skip it when
+#relaying a new block (it still gets it through sync), so a few slow peers
+#don't delay propagation to the others
+#max_relay_queue = 10
+
+#reject txhashset archives anchored more than this many blocks below the
+#highest peer before downloading them (no limit by default)
+#txhashset_max_age = 10080
+
+#advertise an older protocol version (1 to 4) to peers, only meant for
+#testing interoperability with older nodes
+#protocol_version = 4
+
+#seed for the rng used in peer selection, only meant for reproducible testing
+#(by default peer selection is seeded from OS entropy)
+#rng_seed = 0
+
+#maximum number of peers we concurrently request headers from during sync,
+#lower it to throttle header validation on low-power nodes (unlimited by default)
+#max_header_sync_peers = 2
+
+#what to do when a peer we're already connected to opens another inbound
+#connection, \"reject_new\" keeps the existing one, \"replace_old\" swaps it
+#for the new one (loopback connections are never considered duplicates)
+#duplicate_inbound_policy = \"reject_new\"
+
+#externally reachable address advertised to peers, needed when bound to
+#0.0.0.0 or behind NAT (ignored when tor is enabled, the onion address is used)
+#advertised_addr = { Ip = \"203.0.113.5:3414\" }
+
+#pre-shared key of a private network (32 bytes, hex encoded), connections with
+#peers configured with the same key are encrypted and authenticated with Noise,
+#others stay in plaintext
+#noise_psk = \"000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\"
+
+#refuse peers not offering Noise encryption when noise_psk is set, instead of
+#falling back to plaintext (anyone in the middle can strip the offer)
+#require_noise = false
+
+#flag peers declining this many requests for a capability they advertise (a
+#txhashset archive for instance), if they decline most of them
+#capability_refusal_threshold = 3
+
+#park flagged peers for the ban window rather than only logging them
+#penalize_capability_refusals = false
+
 # 15 = Bit flags for FULL_NODE
 #This structure needs to be changed internally, to make it more configurable
 
@@ -319,6 +319,23 @@ pub fn clamp(actual: u64, goal: u64, clamp_factor: u64) -> u64 {
 	max(goal / clamp_factor, min(actual, goal * clamp_factor))
 }
 
+/// Ratio of a peer difficulty to our own, for instance 1.5 when the peer
+/// advertises 50% more work than we have. Difficulties share the same scale on
+/// a given chain type so the ratio is comparable across floonet and mainnet.
+/// When our difficulty is zero the ratio is 1.0 if theirs is zero too and
+/// infinity otherwise.
+pub fn difficulty_ratio(theirs: Difficulty, ours: Difficulty) -> f64 {
+	if ours.to_num() == 0 {
+		if theirs.to_num() == 0 {
+			1.0
+		} else {
+			std::f64::INFINITY
+		}
+	} else {
+		theirs.to_num() as f64 / ours.to_num() as f64
+	}
+}
+
 /// Computes the proof-of-work difficulty that the next block should comply
 /// with. Takes an iterator over past block headers information, from latest
 /// (highest height) to oldest (lowest height).
@@ -642,6 +659,23 @@ pub fn calc_mwc_block_overage(height: u64, genesis_had_reward: bool) -> u64 {
 mod test {
 	use super::*;
 
+	#[test]
+	fn test_difficulty_ratio() {
+		let ours = Difficulty::from_num(1_000);
+		assert_eq!(difficulty_ratio(Difficulty::from_num(1_500), ours), 1.5);
+		assert_eq!(difficulty_ratio(Difficulty::from_num(250), ours), 0.25);
+		assert_eq!(difficulty_ratio(Difficulty::from_num(1_000), ours), 1.0);
+
+		assert_eq!(
+			difficulty_ratio(Difficulty::from_num(1_000), Difficulty::zero()),
+			std::f64::INFINITY
+		);
+		assert_eq!(
+			difficulty_ratio(Difficulty::zero(), Difficulty::zero()),
+			1.0
+		);
+	}
+
 	#[test]
 	fn test_graph_weight() {
 		global::set_local_chain_type(global::ChainTypes::Mainnet);
@@ -850,13 +850,13 @@ impl Block {
 	// NRD kernels were introduced in HF3 and are not valid for block version < 4.
 	// Blocks prior to HF3 containing any NRD kernel(s) are invalid.
 	fn verify_nrd_kernels_for_header_version(&self) -> Result<(), Error> {
-		if self.kernels().iter().any(|k| k.is_nrd()) {
+		if self.kernels().iter().any(|k| k.is_nrd())
+			&& !global::nrd_kernels_valid_at(self.header.version.into())
+		{
 			if !global::is_nrd_enabled() {
 				return Err(Error::NRDKernelNotEnabled);
 			}
-			if self.header.version < HeaderVersion(4) {
-				return Err(Error::NRDKernelPreHF3);
-			}
+			return Err(Error::NRDKernelPreHF3);
 		}
 		Ok(())
 	}
@@ -17,10 +17,10 @@
 //! should be used sparingly.
 
 use crate::consensus::{
-	graph_weight, HeaderInfo, BASE_EDGE_BITS, BLOCK_KERNEL_WEIGHT, BLOCK_OUTPUT_WEIGHT,
-	BLOCK_TIME_SEC, COINBASE_MATURITY, CUT_THROUGH_HORIZON, DAY_HEIGHT, DEFAULT_MIN_EDGE_BITS,
+	HeaderInfo, BASE_EDGE_BITS, BLOCK_KERNEL_WEIGHT, BLOCK_OUTPUT_WEIGHT, BLOCK_TIME_SEC,
+	COINBASE_MATURITY, CUT_THROUGH_HORIZON, DAY_HEIGHT, DEFAULT_MIN_EDGE_BITS,
 	DIFFICULTY_ADJUST_WINDOW, INITIAL_DIFFICULTY, MAX_BLOCK_WEIGHT, PROOFSIZE,
-	SECOND_POW_EDGE_BITS, STATE_SYNC_THRESHOLD,
+	SECOND_POW_EDGE_BITS, STATE_SYNC_THRESHOLD, UNIT_DIFFICULTY,
 };
 use crate::pow::{self, new_cuckarood_ctx, new_cuckatoo_ctx, PoWContext};
 use crate::ser::ProtocolVersion;
@@ -40,8 +40,15 @@ use util::OneTime;
 /// We negotiate compatible versions with each peer via Hand/Shake.
 /// Note: We also use a specific (possible different) protocol version
 /// for both the backend database and MMR data files.
-/// NOTE, grin bump the protocol version to 1000, but in any case fo far 1,2,3 are supported.
-pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion(3);
+/// NOTE, grin bump the protocol version to 1000, but in any case fo far 1,2,3,4 are supported.
+/// Version 4 adds requesting headers by height or capping the headers of a
+/// locator request, the node uptime and clock in handshakes, the prune height
+/// in pings, capability updates after the handshake, requesting a peer's best
+/// header (GetTip/Tip) and asking peers to check our listener is reachable
+/// (RequestReachabilityCheck and ReachabilityCheck, with the listening flag
+/// of the hand), telling peers we declined their request (Declined) and
+/// empty messages keeping idle connections alive (KeepAlive).
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion(4);
 
 /// Automated testing edge_bits
 pub const AUTOMATED_TESTING_MIN_EDGE_BITS: u8 = 10;
@@ -129,6 +136,80 @@ impl ChainTypes {
 			ChainTypes::Mainnet => "main".to_owned(),
 		}
 	}
+
+	/// The full set of chain parameters for this chain type. Doesn't depend on
+	/// (nor change) the chain type set for the current thread.
+	pub fn params(&self) -> ChainParams {
+		match *self {
+			ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => ChainParams {
+				min_edge_bits: AUTOMATED_TESTING_MIN_EDGE_BITS,
+				base_edge_bits: AUTOMATED_TESTING_MIN_EDGE_BITS,
+				proofsize: AUTOMATED_TESTING_PROOF_SIZE,
+				coinbase_maturity: AUTOMATED_TESTING_COINBASE_MATURITY,
+				initial_block_difficulty: TESTING_INITIAL_DIFFICULTY,
+				initial_graph_weight: TESTING_INITIAL_GRAPH_WEIGHT,
+				max_block_weight: if *self == ChainTypes::PerfTesting {
+					MAX_BLOCK_WEIGHT
+				} else {
+					TESTING_MAX_BLOCK_WEIGHT
+				},
+				cut_through_horizon: AUTOMATED_TESTING_CUT_THROUGH_HORIZON,
+				state_sync_threshold: TESTING_STATE_SYNC_THRESHOLD,
+				txhashset_archive_interval: TESTING_TXHASHSET_ARCHIVE_INTERVAL,
+			},
+			ChainTypes::UserTesting => ChainParams {
+				min_edge_bits: USER_TESTING_MIN_EDGE_BITS,
+				base_edge_bits: USER_TESTING_MIN_EDGE_BITS,
+				proofsize: USER_TESTING_PROOF_SIZE,
+				coinbase_maturity: USER_TESTING_COINBASE_MATURITY,
+				initial_block_difficulty: TESTING_INITIAL_DIFFICULTY,
+				initial_graph_weight: TESTING_INITIAL_GRAPH_WEIGHT,
+				max_block_weight: TESTING_MAX_BLOCK_WEIGHT,
+				cut_through_horizon: USER_TESTING_CUT_THROUGH_HORIZON,
+				state_sync_threshold: TESTING_STATE_SYNC_THRESHOLD,
+				txhashset_archive_interval: TESTING_TXHASHSET_ARCHIVE_INTERVAL,
+			},
+			ChainTypes::Floonet | ChainTypes::Mainnet => ChainParams {
+				min_edge_bits: DEFAULT_MIN_EDGE_BITS,
+				base_edge_bits: BASE_EDGE_BITS,
+				proofsize: PROOFSIZE,
+				coinbase_maturity: COINBASE_MATURITY,
+				initial_block_difficulty: INITIAL_DIFFICULTY,
+				// graph_weight(0, SECOND_POW_EDGE_BITS), not called as it reads
+				// base_edge_bits back from params
+				initial_graph_weight: UNIT_DIFFICULTY as u32,
+				max_block_weight: MAX_BLOCK_WEIGHT,
+				cut_through_horizon: CUT_THROUGH_HORIZON,
+				state_sync_threshold: STATE_SYNC_THRESHOLD,
+				txhashset_archive_interval: TXHASHSET_ARCHIVE_INTERVAL,
+			},
+		}
+	}
+}
+
+/// Consensus and mining parameters of a chain type, see ChainTypes::params.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainParams {
+	/// The minimum acceptable edge_bits
+	pub min_edge_bits: u8,
+	/// Reference edge_bits used to compute factor on higher Cuck(at)oo graph sizes
+	pub base_edge_bits: u8,
+	/// The proofsize
+	pub proofsize: usize,
+	/// Coinbase maturity for coinbases to be spent
+	pub coinbase_maturity: u64,
+	/// Initial mining difficulty
+	pub initial_block_difficulty: u64,
+	/// Initial mining secondary scale
+	pub initial_graph_weight: u32,
+	/// Maximum allowed block weight
+	pub max_block_weight: u64,
+	/// Horizon at which we can cut-through and do full local pruning
+	pub cut_through_horizon: u32,
+	/// Threshold at which we can request a txhashset (and full blocks from)
+	pub state_sync_threshold: u32,
+	/// Number of blocks to reuse a txhashset zip for
+	pub txhashset_archive_interval: u64,
 }
 
 impl Default for ChainTypes {
@@ -218,6 +299,15 @@ pub fn is_nrd_enabled() -> bool {
 	})
 }
 
+/// Header version introduced by HF3, the first one NRD kernels may appear in.
+pub const NRD_MIN_HEADER_VERSION: u16 = 4;
+
+/// Are NRD kernels valid in a block of the given header version?
+/// Requires both the NRD feature flag and a header version at or past HF3.
+pub fn nrd_kernels_valid_at(header_version: u16) -> bool {
+	is_nrd_enabled() && header_version >= NRD_MIN_HEADER_VERSION
+}
+
 /// Return either a cuckoo context or a cuckatoo context
 /// Single change point
 /// MWC: We modify this to launch with cuckarood only on both floonet and mainnet
@@ -244,72 +334,38 @@ pub fn create_pow_context<T>(
 
 /// The minimum acceptable edge_bits
 pub fn min_edge_bits() -> u8 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => AUTOMATED_TESTING_MIN_EDGE_BITS,
-		ChainTypes::UserTesting => USER_TESTING_MIN_EDGE_BITS,
-		_ => DEFAULT_MIN_EDGE_BITS,
-	}
+	get_chain_type().params().min_edge_bits
 }
 
 /// Reference edge_bits used to compute factor on higher Cuck(at)oo graph sizes,
 /// while the min_edge_bits can be changed on a soft fork, changing
 /// base_edge_bits is a hard fork.
 pub fn base_edge_bits() -> u8 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => AUTOMATED_TESTING_MIN_EDGE_BITS,
-		ChainTypes::UserTesting => USER_TESTING_MIN_EDGE_BITS,
-		_ => BASE_EDGE_BITS,
-	}
+	get_chain_type().params().base_edge_bits
 }
 
 /// The proofsize
 pub fn proofsize() -> usize {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => AUTOMATED_TESTING_PROOF_SIZE,
-		ChainTypes::UserTesting => USER_TESTING_PROOF_SIZE,
-		_ => PROOFSIZE,
-	}
+	get_chain_type().params().proofsize
 }
 
 /// Coinbase maturity for coinbases to be spent
 pub fn coinbase_maturity() -> u64 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => {
-			AUTOMATED_TESTING_COINBASE_MATURITY
-		}
-		ChainTypes::UserTesting => USER_TESTING_COINBASE_MATURITY,
-		_ => COINBASE_MATURITY,
-	}
+	get_chain_type().params().coinbase_maturity
 }
 
 /// Initial mining difficulty
 pub fn initial_block_difficulty() -> u64 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => TESTING_INITIAL_DIFFICULTY,
-		ChainTypes::UserTesting => TESTING_INITIAL_DIFFICULTY,
-		ChainTypes::Floonet => INITIAL_DIFFICULTY,
-		ChainTypes::Mainnet => INITIAL_DIFFICULTY,
-	}
+	get_chain_type().params().initial_block_difficulty
 }
 /// Initial mining secondary scale
 pub fn initial_graph_weight() -> u32 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => TESTING_INITIAL_GRAPH_WEIGHT,
-		ChainTypes::UserTesting => TESTING_INITIAL_GRAPH_WEIGHT,
-		ChainTypes::Floonet => graph_weight(0, SECOND_POW_EDGE_BITS) as u32,
-		ChainTypes::Mainnet => graph_weight(0, SECOND_POW_EDGE_BITS) as u32,
-	}
+	get_chain_type().params().initial_graph_weight
 }
 
 /// Maximum allowed block weight.
 pub fn max_block_weight() -> u64 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting => TESTING_MAX_BLOCK_WEIGHT,
-		ChainTypes::PerfTesting => MAX_BLOCK_WEIGHT,
-		ChainTypes::UserTesting => TESTING_MAX_BLOCK_WEIGHT,
-		ChainTypes::Floonet => MAX_BLOCK_WEIGHT,
-		ChainTypes::Mainnet => MAX_BLOCK_WEIGHT,
-	}
+	get_chain_type().params().max_block_weight
 }
 
 /// Maximum allowed transaction weight (1 weight unit ~= 32 bytes)
@@ -320,33 +376,17 @@ pub fn max_tx_weight() -> u64 {
 
 /// Horizon at which we can cut-through and do full local pruning
 pub fn cut_through_horizon() -> u32 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => {
-			AUTOMATED_TESTING_CUT_THROUGH_HORIZON
-		}
-		ChainTypes::UserTesting => USER_TESTING_CUT_THROUGH_HORIZON,
-		_ => CUT_THROUGH_HORIZON,
-	}
+	get_chain_type().params().cut_through_horizon
 }
 
 /// Threshold at which we can request a txhashset (and full blocks from)
 pub fn state_sync_threshold() -> u32 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => TESTING_STATE_SYNC_THRESHOLD,
-		ChainTypes::UserTesting => TESTING_STATE_SYNC_THRESHOLD,
-		_ => STATE_SYNC_THRESHOLD,
-	}
+	get_chain_type().params().state_sync_threshold
 }
 
 /// Number of blocks to reuse a txhashset zip for.
 pub fn txhashset_archive_interval() -> u64 {
-	match get_chain_type() {
-		ChainTypes::AutomatedTesting | ChainTypes::PerfTesting => {
-			TESTING_TXHASHSET_ARCHIVE_INTERVAL
-		}
-		ChainTypes::UserTesting => TESTING_TXHASHSET_ARCHIVE_INTERVAL,
-		_ => TXHASHSET_ARCHIVE_INTERVAL,
-	}
+	get_chain_type().params().txhashset_archive_interval
 }
 
 /// Are we in production mode?
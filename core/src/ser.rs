@@ -321,7 +321,7 @@ where
 /// the p2p layer and our local db storage layer.
 /// We may speak multiple versions to various peers and a potentially *different*
 /// version for our local db.
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialOrd, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialOrd, PartialEq, Serialize)]
 pub struct ProtocolVersion(pub u32);
 
 impl ProtocolVersion {
@@ -264,6 +264,19 @@ fn block_with_nrd_kernel_nrd_not_enabled() {
 	);
 }
 
+#[test]
+fn nrd_kernels_valid_at_header_version() {
+	global::set_local_nrd_enabled(false);
+	assert!(!global::nrd_kernels_valid_at(4));
+	assert!(!global::nrd_kernels_valid_at(5));
+
+	global::set_local_nrd_enabled(true);
+	assert!(!global::nrd_kernels_valid_at(1));
+	assert!(!global::nrd_kernels_valid_at(3));
+	assert!(global::nrd_kernels_valid_at(4));
+	assert!(global::nrd_kernels_valid_at(5));
+}
+
 #[test]
 // builds a block with a tx spending another and check that cut_through occurred
 fn block_with_cut_through() {
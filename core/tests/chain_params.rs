@@ -0,0 +1,50 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+
+use self::core::consensus;
+use self::core::global::{self, ChainParams, ChainTypes};
+
+fn current_params() -> ChainParams {
+	ChainParams {
+		min_edge_bits: global::min_edge_bits(),
+		base_edge_bits: global::base_edge_bits(),
+		proofsize: global::proofsize(),
+		coinbase_maturity: global::coinbase_maturity(),
+		initial_block_difficulty: global::initial_block_difficulty(),
+		initial_graph_weight: global::initial_graph_weight(),
+		max_block_weight: global::max_block_weight(),
+		cut_through_horizon: global::cut_through_horizon(),
+		state_sync_threshold: global::state_sync_threshold(),
+		txhashset_archive_interval: global::txhashset_archive_interval(),
+	}
+}
+
+// The params of a chain type match the individual getters once the thread
+// chain type is set to it, and reading them doesn't touch the thread local.
+#[test]
+fn mainnet_params_match_getters() {
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	let mainnet = ChainTypes::Mainnet.params();
+	assert_eq!(global::get_chain_type(), ChainTypes::AutomatedTesting);
+	assert_ne!(mainnet, current_params());
+
+	global::set_local_chain_type(ChainTypes::Mainnet);
+	assert_eq!(mainnet, current_params());
+	assert_eq!(
+		mainnet.initial_graph_weight as u64,
+		consensus::graph_weight(0, consensus::SECOND_POW_EDGE_BITS)
+	);
+}
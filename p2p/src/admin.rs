@@ -0,0 +1,134 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admin actions on our peers (bans, capabilities, disconnects). They can be
+//! gated behind an authorization check supplied by the embedding server, so
+//! they're safe to expose over a network API.
+
+use std::sync::Arc;
+
+use crate::serv::Server;
+use crate::types::{Capabilities, Error, PeerAddr, ReasonForBan};
+
+/// An admin action, as passed to the authorization check.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminAction {
+	Ban(PeerAddr),
+	Unban(PeerAddr),
+	SetCapabilities(Capabilities),
+	Disconnect(PeerAddr),
+}
+
+/// Admin controls over our peers. Every action comes with the token the
+/// caller presented, if any.
+pub trait AdminControl: Send + Sync {
+	/// Ban a peer, disconnecting it if we're currently connected.
+	fn ban(&self, addr: PeerAddr, token: Option<&str>) -> Result<(), Error>;
+
+	/// Unban a banned peer.
+	fn unban(&self, addr: PeerAddr, token: Option<&str>) -> Result<(), Error>;
+
+	/// Change the capabilities we advertise.
+	fn set_capabilities(
+		&self,
+		capabilities: Capabilities,
+		token: Option<&str>,
+	) -> Result<(), Error>;
+
+	/// Disconnect a connected peer, without banning it.
+	fn disconnect(&self, addr: PeerAddr, token: Option<&str>) -> Result<(), Error>;
+}
+
+/// The server itself allows all admin actions, whatever the token, as its
+/// internal callers always did.
+impl AdminControl for Server {
+	fn ban(&self, addr: PeerAddr, _token: Option<&str>) -> Result<(), Error> {
+		self.peers.ban_peer(addr, ReasonForBan::ManualBan)
+	}
+
+	fn unban(&self, addr: PeerAddr, _token: Option<&str>) -> Result<(), Error> {
+		self.peers.unban_peer(addr)
+	}
+
+	fn set_capabilities(
+		&self,
+		capabilities: Capabilities,
+		_token: Option<&str>,
+	) -> Result<(), Error> {
+		Server::set_capabilities(self, capabilities);
+		Ok(())
+	}
+
+	fn disconnect(&self, addr: PeerAddr, _token: Option<&str>) -> Result<(), Error> {
+		self.peers.disconnect_peer(addr)
+	}
+}
+
+/// Admin controls only performing the actions the authorization check lets
+/// through, others fail with Error::Unauthorized.
+pub struct AuthorizedAdmin<A, F> {
+	inner: Arc<A>,
+	authorize: F,
+}
+
+impl<A, F> AuthorizedAdmin<A, F>
+where
+	A: AdminControl,
+	F: Fn(&AdminAction, Option<&str>) -> bool + Send + Sync,
+{
+	/// Gate the admin controls of inner behind the provided check, called
+	/// with the action and the token presented for it.
+	pub fn new(inner: Arc<A>, authorize: F) -> AuthorizedAdmin<A, F> {
+		AuthorizedAdmin { inner, authorize }
+	}
+
+	fn check(&self, action: AdminAction, token: Option<&str>) -> Result<(), Error> {
+		if (self.authorize)(&action, token) {
+			Ok(())
+		} else {
+			warn!("Refusing unauthorized admin action {:?}", action);
+			Err(Error::Unauthorized)
+		}
+	}
+}
+
+impl<A, F> AdminControl for AuthorizedAdmin<A, F>
+where
+	A: AdminControl,
+	F: Fn(&AdminAction, Option<&str>) -> bool + Send + Sync,
+{
+	fn ban(&self, addr: PeerAddr, token: Option<&str>) -> Result<(), Error> {
+		self.check(AdminAction::Ban(addr.clone()), token)?;
+		self.inner.ban(addr, token)
+	}
+
+	fn unban(&self, addr: PeerAddr, token: Option<&str>) -> Result<(), Error> {
+		self.check(AdminAction::Unban(addr.clone()), token)?;
+		self.inner.unban(addr, token)
+	}
+
+	fn set_capabilities(
+		&self,
+		capabilities: Capabilities,
+		token: Option<&str>,
+	) -> Result<(), Error> {
+		self.check(AdminAction::SetCapabilities(capabilities), token)?;
+		self.inner.set_capabilities(capabilities, token)
+	}
+
+	fn disconnect(&self, addr: PeerAddr, token: Option<&str>) -> Result<(), Error> {
+		self.check(AdminAction::Disconnect(addr.clone()), token)?;
+		self.inner.disconnect(addr, token)
+	}
+}
@@ -23,14 +23,16 @@
 use crate::core::ser;
 use crate::core::ser::ProtocolVersion;
 use crate::msg::{
-	read_body, read_discard, read_header, read_item, write_message, Msg, MsgHeader,
-	MsgHeaderWrapper,
+	read_body, read_discard, read_header, read_item, read_locator, write_message, Locator, Msg,
+	MsgHeader, MsgHeaderWrapper,
 };
+use crate::noise::{self, NoiseSession};
+use crate::read_budget::ReadBudget;
 use crate::types::Error;
 use crate::util::{RateCounter, RwLock};
 use std::io::{self, Read, Write};
 use std::net::{Shutdown, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{mpsc, Arc};
 use std::time::Duration;
@@ -54,6 +56,12 @@ pub trait MessageHandler: Send + 'static {
 		stopped: Arc<AtomicBool>,
 		tracker: Arc<Tracker>,
 	) -> Result<Option<Msg>, Error>;
+
+	/// A message of a type we don't know came in, its body gets skipped
+	/// unless this errors (closing the connection).
+	fn unknown(&mut self, _msg_type: u8, _msg_len: u64) -> Result<(), Error> {
+		Ok(())
+	}
 }
 
 // Macro to simplify the boilerplate around I/O and Grin error handling
@@ -108,6 +116,11 @@ impl<'a, R: Read> Message<'a, R> {
 		read_body(&self.header, self.stream, self.version)
 	}
 
+	/// Read a locator from the underlying connection, rejecting oversized ones.
+	pub fn locator(&mut self) -> Result<Locator, Error> {
+		read_locator(&self.header, self.stream, self.version)
+	}
+
 	/// Read a single "thing" from the underlying connection.
 	/// Return the thing and the total bytes read.
 	pub fn streaming_read<T: ser::Readable>(&mut self) -> Result<(T, u64), Error> {
@@ -170,6 +183,8 @@ impl StopHandle {
 pub struct ConnHandle {
 	/// Channel to allow sending data through the connection
 	pub send_channel: mpsc::SyncSender<Msg>,
+	/// Number of messages in the channel the writer didn't pick up yet
+	queued: Arc<AtomicUsize>,
 }
 
 impl ConnHandle {
@@ -181,17 +196,47 @@ impl ConnHandle {
 	/// If the buffer is full because there is an underlying issue with the peer
 	/// and potentially the peer connection. We assume this will be handled at the peer level.
 	pub fn send(&self, msg: Msg) -> Result<(), Error> {
+		// counted before sending so the writer never takes it below zero
+		self.queued.fetch_add(1, Ordering::Relaxed);
 		match self.send_channel.try_send(msg) {
 			Ok(()) => Ok(()),
 			Err(mpsc::TrySendError::Disconnected(_)) => {
+				self.queued.fetch_sub(1, Ordering::Relaxed);
 				Err(Error::Send("try_send disconnected".to_owned()))
 			}
 			Err(mpsc::TrySendError::Full(_)) => {
+				self.queued.fetch_sub(1, Ordering::Relaxed);
 				debug!("conn_handle: try_send but buffer is full, dropping msg");
 				Ok(())
 			}
 		}
 	}
+
+	/// Number of messages waiting to be written to the peer, a backed up
+	/// queue means a slow peer (or connection).
+	pub fn queued(&self) -> usize {
+		self.queued.load(Ordering::Relaxed)
+	}
+}
+
+/// Bytes sent to and received from all our peers since we started, whatever
+/// the connection.
+#[derive(Debug, Default)]
+pub struct TrafficTotals {
+	sent: AtomicU64,
+	received: AtomicU64,
+}
+
+impl TrafficTotals {
+	/// Bytes sent to all our peers since we started.
+	pub fn sent(&self) -> u64 {
+		self.sent.load(Ordering::Relaxed)
+	}
+
+	/// Bytes received from all our peers since we started.
+	pub fn received(&self) -> u64 {
+		self.received.load(Ordering::Relaxed)
+	}
 }
 
 pub struct Tracker {
@@ -199,61 +244,66 @@ pub struct Tracker {
 	pub sent_bytes: Arc<RwLock<RateCounter>>,
 	/// Bytes we've received.
 	pub received_bytes: Arc<RwLock<RateCounter>>,
+	/// Bytes all our connections sent and received.
+	totals: Arc<TrafficTotals>,
 }
 
 impl Tracker {
-	pub fn new() -> Tracker {
+	pub fn new(totals: Arc<TrafficTotals>) -> Tracker {
 		let received_bytes = Arc::new(RwLock::new(RateCounter::new()));
 		let sent_bytes = Arc::new(RwLock::new(RateCounter::new()));
 		Tracker {
 			received_bytes,
 			sent_bytes,
+			totals,
 		}
 	}
 
 	pub fn inc_received(&self, size: u64) {
 		self.received_bytes.write().inc(size);
+		self.totals.received.fetch_add(size, Ordering::Relaxed);
 	}
 
 	pub fn inc_sent(&self, size: u64) {
 		self.sent_bytes.write().inc(size);
+		self.totals.sent.fetch_add(size, Ordering::Relaxed);
 	}
 
 	pub fn inc_quiet_received(&self, size: u64) {
 		self.received_bytes.write().inc_quiet(size);
+		self.totals.received.fetch_add(size, Ordering::Relaxed);
 	}
 
 	pub fn inc_quiet_sent(&self, size: u64) {
 		self.sent_bytes.write().inc_quiet(size);
+		self.totals.sent.fetch_add(size, Ordering::Relaxed);
 	}
 }
 
 /// Start listening on the provided connection and wraps it. Does not hang
 /// the current thread, instead just returns a future and the Connection
-/// itself.
+/// itself. Everything goes through the Noise session, if any.
 pub fn listen<H>(
 	stream: TcpStream,
+	noise: Option<Arc<NoiseSession>>,
 	version: ProtocolVersion,
+	max_msg_size: u64,
+	read_budget: Arc<ReadBudget>,
 	tracker: Arc<Tracker>,
 	handler: H,
 ) -> io::Result<(ConnHandle, StopHandle)>
 where
 	H: MessageHandler,
 {
-	let (send_tx, send_rx) = mpsc::sync_channel(SEND_CHANNEL_CAP);
-
 	let stopped = Arc::new(AtomicBool::new(false));
 
-	let conn_handle = ConnHandle {
-		send_channel: send_tx,
-	};
-
-	let (reader_thread, writer_thread) = poll(
+	let (conn_handle, reader_thread, writer_thread) = poll(
 		stream,
-		conn_handle.clone(),
+		noise,
 		version,
+		max_msg_size,
+		read_budget,
 		handler,
-		send_rx,
 		stopped.clone(),
 		tracker,
 	)?;
@@ -270,32 +320,53 @@ where
 
 fn poll<H>(
 	conn: TcpStream,
-	conn_handle: ConnHandle,
+	noise: Option<Arc<NoiseSession>>,
 	version: ProtocolVersion,
+	max_msg_size: u64,
+	read_budget: Arc<ReadBudget>,
 	mut handler: H,
-	send_rx: mpsc::Receiver<Msg>,
 	stopped: Arc<AtomicBool>,
 	tracker: Arc<Tracker>,
-) -> io::Result<(JoinHandle<()>, JoinHandle<()>)>
+) -> io::Result<(ConnHandle, JoinHandle<()>, JoinHandle<()>)>
 where
 	H: MessageHandler,
 {
-	// Split out tcp stream out into separate reader/writer halves.
-	let mut reader = conn.try_clone().expect("clone conn for reader failed");
-	let mut writer = conn.try_clone().expect("clone conn for writer failed");
+	let (send_tx, send_rx) = mpsc::sync_channel(SEND_CHANNEL_CAP);
+	let conn_handle = ConnHandle {
+		send_channel: send_tx,
+		queued: Arc::new(AtomicUsize::new(0)),
+	};
+
+	// Split out tcp stream out into separate reader/writer halves, the raw
+	// streams are kept for timeouts and shutdown.
+	let reader_conn = conn.try_clone().expect("clone conn for reader failed");
+	let writer_conn = conn.try_clone().expect("clone conn for writer failed");
+	let mut reader = noise::reader(&conn, &noise)?;
+	let mut writer = noise::writer(&conn, &noise)?;
 	let reader_stopped = stopped.clone();
 
 	let reader_tracker = tracker.clone();
 	let writer_tracker = tracker;
+	let reader_handle = conn_handle.clone();
+	let queued = conn_handle.queued.clone();
 
 	let reader_thread = thread::Builder::new()
 		.name("peer_read".to_string())
 		.spawn(move || {
 			loop {
 				// check the read end
-				match try_header!(read_header(&mut reader, version), &reader) {
+				match try_header!(
+					read_header(&mut reader, version, max_msg_size),
+					&reader_conn
+				) {
 					Some(MsgHeaderWrapper::Known(header)) => {
-						let _ = reader.set_read_timeout(Some(BODY_IO_TIMEOUT));
+						// wait for room before buffering the body, held until consumed
+						let _reservation =
+							match read_budget.reserve(header.msg_len, &reader_stopped) {
+								Some(reservation) => reservation,
+								None => break,
+							};
+						let _ = reader_conn.set_read_timeout(Some(BODY_IO_TIMEOUT));
 						let msg = Message::from_header(header, &mut reader, version);
 
 						trace!(
@@ -313,7 +384,7 @@ where
 							reader_tracker.clone()
 						));
 						if let Some(Some(resp_msg)) = resp_msg {
-							try_break!(conn_handle.send(resp_msg));
+							try_break!(reader_handle.send(resp_msg));
 						}
 					}
 					Some(MsgHeaderWrapper::Unknown(msg_len, type_byte)) => {
@@ -324,6 +395,7 @@ where
 						// Increase received bytes counter
 						reader_tracker.inc_received(MsgHeader::LEN as u64 + msg_len);
 
+						try_break!(handler.unknown(type_byte, msg_len));
 						try_break!(read_discard(msg_len, &mut reader));
 					}
 					None => {}
@@ -337,21 +409,26 @@ where
 
 			debug!(
 				"Shutting down reader connection with {}",
-				reader
+				reader_conn
 					.peer_addr()
 					.map(|a| a.to_string())
 					.unwrap_or_else(|_| "?".to_owned())
 			);
-			let _ = reader.shutdown(Shutdown::Both);
+			let _ = reader_conn.shutdown(Shutdown::Both);
 		})?;
 
 	let writer_thread = thread::Builder::new()
 		.name("peer_write".to_string())
 		.spawn(move || {
 			let mut retry_send = Err(());
-			let _ = writer.set_write_timeout(Some(BODY_IO_TIMEOUT));
+			let _ = writer_conn.set_write_timeout(Some(BODY_IO_TIMEOUT));
 			loop {
-				let maybe_data = retry_send.or_else(|_| send_rx.recv_timeout(CHANNEL_TIMEOUT));
+				let maybe_data = retry_send.or_else(|_| {
+					send_rx.recv_timeout(CHANNEL_TIMEOUT).map(|data| {
+						queued.fetch_sub(1, Ordering::Relaxed);
+						data
+					})
+				});
 				retry_send = Err(());
 				match maybe_data {
 					Ok(data) => {
@@ -376,12 +453,12 @@ where
 
 			debug!(
 				"Shutting down writer connection with {}",
-				writer
+				writer_conn
 					.peer_addr()
 					.map(|a| a.to_string())
 					.unwrap_or_else(|_| "?".to_owned())
 			);
-			let _ = writer.shutdown(Shutdown::Both);
+			let _ = writer_conn.shutdown(Shutdown::Both);
 		})?;
-	Ok((reader_thread, writer_thread))
+	Ok((conn_handle, reader_thread, writer_thread))
 }
@@ -12,21 +12,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::conn::Tracker;
+use crate::conn::{Tracker, TrafficTotals};
 use crate::core::core::hash::Hash;
 use crate::core::pow::Difficulty;
-use crate::core::ser::ProtocolVersion;
+use crate::core::ser::{ProtocolVersion, Readable};
 use crate::msg::{read_message, write_message, Hand, Msg, Shake, TorAddress, Type, USER_AGENT};
+use crate::noise::{self, NoiseSession};
 use crate::peer::Peer;
 use crate::types::{
-	Capabilities, Direction, Error, P2PConfig, PeerAddr, PeerAddr::Ip, PeerAddr::Onion, PeerInfo,
-	PeerLiveInfo,
+	max_plausible_difficulty, Capabilities, Direction, Error, NetAdapter, P2PConfig, PeerAddr,
+	PeerAddr::Ip, PeerAddr::Onion, PeerInfo, PeerLiveInfo, ProtocolNegotiation,
 };
 use crate::util::RwLock;
+use chrono::Utc;
 use rand::{thread_rng, Rng};
 use std::collections::VecDeque;
+use std::io::{self, Read, Write};
 use std::net::{SocketAddr, TcpStream};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -41,13 +44,6 @@ const NONCES_CAP: usize = 100;
 /// 10 should be enough since most of servers don't have more than 10 IP addresses.
 const ADDRS_CAP: usize = 10;
 
-/// The initial Hand message should come in immediately after the connection is initiated.
-/// But for consistency use the same timeout for reading both Hand and Shake messages.
-const HAND_READ_TIMEOUT: Duration = Duration::from_millis(10_000);
-
-/// We need to allow time for the peer to receive our Hand message and send back a Shake reply.
-const SHAKE_READ_TIMEOUT: Duration = Duration::from_millis(10_000);
-
 /// Fail fast when trying to write a Hand message to the tcp stream.
 /// If we cannot write it within a couple of seconds then something has likely gone wrong.
 const HAND_WRITE_TIMEOUT: Duration = Duration::from_millis(2_000);
@@ -56,6 +52,10 @@ const HAND_WRITE_TIMEOUT: Duration = Duration::from_millis(2_000);
 /// If we cannot write it within a couple of seconds then something has likely gone wrong.
 const SHAKE_WRITE_TIMEOUT: Duration = Duration::from_millis(2_000);
 
+/// Largest clock skew (in seconds) we believe a peer has, whatever time it
+/// sends us in its hand or shake.
+const MAX_CLOCK_SKEW: i64 = 3_600;
+
 /// Handles the handshake negotiation when two peers connect and decides on
 /// protocol.
 pub struct Handshake {
@@ -72,6 +72,12 @@ pub struct Handshake {
 	protocol_version: ProtocolVersion,
 	tracker: Arc<Tracker>,
 	onion_address: Option<String>,
+	/// When this node started, to tell peers our uptime.
+	start_time: Instant,
+	/// Nonce sent on all our connections when share_node_nonce is set.
+	node_nonce: u64,
+	/// Whether we tell peers we accept connections on our address.
+	listening: AtomicBool,
 }
 
 impl Handshake {
@@ -81,13 +87,22 @@ impl Handshake {
 			nonces: Arc::new(RwLock::new(VecDeque::with_capacity(NONCES_CAP))),
 			addrs: Arc::new(RwLock::new(VecDeque::with_capacity(ADDRS_CAP))),
 			genesis,
+			protocol_version: config.protocol_version(),
+			listening: AtomicBool::new(config.reachability_probe_interval().is_none()),
 			config,
-			protocol_version: ProtocolVersion::local(),
-			tracker: Arc::new(Tracker::new()),
+			tracker: Arc::new(Tracker::new(Arc::new(TrafficTotals::default()))),
 			onion_address: onion_address,
+			start_time: Instant::now(),
+			node_nonce: thread_rng().gen(),
 		}
 	}
 
+	/// Sets whether we tell peers we accept connections on our address, until
+	/// a reachability probe confirmed it we only dial out.
+	pub fn set_listening(&self, listening: bool) {
+		self.listening.store(listening, Ordering::Relaxed);
+	}
+
 	/// Select a protocol version here that we know is supported by both us and the remote peer.
 	///
 	/// Current strategy is to simply use `min(local, remote)`.
@@ -107,12 +122,13 @@ impl Handshake {
 		self_addr: PeerAddr,
 		conn: &mut TcpStream,
 		peer_addr: Option<PeerAddr>,
-	) -> Result<PeerInfo, Error> {
+		adapter: &dyn NetAdapter,
+	) -> Result<(PeerInfo, Option<Arc<NoiseSession>>), Error> {
 		// Set explicit timeouts on the tcp stream for hand/shake messages.
 		// Once the peer is up and running we will set new values for these.
 		// We initiate this connection, writing a Hand message and read a Shake reply.
 		let _ = conn.set_write_timeout(Some(HAND_WRITE_TIMEOUT));
-		let _ = conn.set_read_timeout(Some(SHAKE_READ_TIMEOUT));
+		let deadline = Instant::now() + Duration::from_secs(self.config.handshake_timeout_secs());
 
 		// prepare the first part of the handshake
 		let nonce = self.next_nonce();
@@ -132,19 +148,26 @@ impl Handshake {
 			sender_addr: self_addr.clone(),
 			receiver_addr: peer_addr.clone(),
 			user_agent: USER_AGENT.to_string(),
+			uptime: Some(self.start_time.elapsed().as_secs()),
+			timestamp: Some(Utc::now().timestamp()),
+			listening: Some(self.listening.load(Ordering::Relaxed)),
 		};
 
 		// write and read the handshake response
 		let msg = Msg::new(Type::Hand, hand, self.protocol_version)?;
 		write_message(conn, &msg, self.tracker.clone())?;
 
-		let shake: Shake = read_message(conn, self.protocol_version, Type::Shake)?;
+		let shake: Shake =
+			read_handshake_message(conn, self.protocol_version, Type::Shake, deadline)?;
 		if shake.genesis != self.genesis {
 			return Err(Error::GenesisMismatch {
 				us: self.genesis,
 				peer: shake.genesis,
 			});
 		}
+		check_difficulty(shake.total_difficulty, adapter)?;
+
+		let noise = self.upgrade(capabilities, shake.capabilities, conn, true, deadline)?;
 
 		if shake.capabilities.contains(Capabilities::TOR_ADDRESS) && self.onion_address.is_some() {
 			let onion_address = self.onion_address.as_ref().unwrap().to_string();
@@ -156,7 +179,11 @@ impl Handshake {
 			// send tor address
 			let tor_address = TorAddress::new(onion_address);
 			let msg = Msg::new(Type::TorAddress, tor_address, self.protocol_version)?;
-			write_message(conn, &msg, self.tracker.clone())?;
+			write_message(
+				&mut noise::writer(conn, &noise)?,
+				&msg,
+				self.tracker.clone(),
+			)?;
 		} else {
 			debug!("non-Tor peer {:?}", self_addr);
 		}
@@ -166,6 +193,7 @@ impl Handshake {
 		let peer_info = PeerInfo {
 			capabilities: shake.capabilities,
 			user_agent: shake.user_agent,
+			peer_uptime: shake.uptime.map(Duration::from_secs),
 			addr: peer_addr,
 			version: negotiated_version,
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(shake.total_difficulty))),
@@ -174,11 +202,22 @@ impl Handshake {
 			} else {
 				Direction::Outbound
 			},
+			inbound_reachable: true,
+			node_nonce: None,
+			negotiation: Some(ProtocolNegotiation {
+				our_capabilities: capabilities,
+				our_version_max: self.protocol_version,
+				advertised_capabilities: shake.capabilities,
+				advertised_version_max: shake.version,
+				negotiated_version,
+			}),
 			header_sync_requested: Arc::new(AtomicUsize::new(0)),
 			last_header: Arc::new(Mutex::new(Instant::now())),
 			last_header_reset: Arc::new(Mutex::new(Instant::now())),
 		};
 
+		peer_info.set_clock_skew(clock_skew(shake.timestamp));
+
 		// If denied then we want to close the connection
 		// (without providing our peer with any details why).
 		if Peer::is_denied(&self.config, peer_info.addr.clone()) {
@@ -194,7 +233,7 @@ impl Handshake {
 			peer_info.capabilities,
 		);
 		// when more than one protocol version is supported, choosing should go here
-		Ok(peer_info)
+		Ok((peer_info, noise))
 	}
 
 	pub fn accept(
@@ -202,14 +241,15 @@ impl Handshake {
 		capab: Capabilities,
 		total_difficulty: Difficulty,
 		conn: &mut TcpStream,
-	) -> Result<PeerInfo, Error> {
+		adapter: &dyn NetAdapter,
+	) -> Result<(PeerInfo, Option<Arc<NoiseSession>>), Error> {
 		// Set explicit timeouts on the tcp stream for hand/shake messages.
 		// Once the peer is up and running we will set new values for these.
 		// We accept an inbound connection, reading a Hand then writing a Shake reply.
-		let _ = conn.set_read_timeout(Some(HAND_READ_TIMEOUT));
 		let _ = conn.set_write_timeout(Some(SHAKE_WRITE_TIMEOUT));
+		let deadline = Instant::now() + Duration::from_secs(self.config.handshake_timeout_secs());
 
-		let hand: Hand = read_message(conn, self.protocol_version, Type::Hand)?;
+		let hand: Hand = read_handshake_message(conn, self.protocol_version, Type::Hand, deadline)?;
 
 		// all the reasons we could refuse this connection for
 		if hand.genesis != self.genesis {
@@ -231,6 +271,7 @@ impl Handshake {
 				return Err(Error::PeerWithSelf);
 			}
 		}
+		check_difficulty(hand.total_difficulty, adapter)?;
 
 		let negotiated_version = self.negotiate_protocol_version(hand.version)?;
 
@@ -238,6 +279,7 @@ impl Handshake {
 		let peer_info = PeerInfo {
 			capabilities: hand.capabilities,
 			user_agent: hand.user_agent,
+			peer_uptime: hand.uptime.map(Duration::from_secs),
 			addr: resolve_peer_addr(hand.sender_addr.clone(), &conn),
 			version: negotiated_version,
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(hand.total_difficulty))),
@@ -246,10 +288,22 @@ impl Handshake {
 			} else {
 				Direction::Inbound
 			},
+			inbound_reachable: hand
+				.listening
+				.unwrap_or_else(|| advertises_listener(&hand.sender_addr)),
+			node_nonce: Some(hand.nonce),
+			negotiation: Some(ProtocolNegotiation {
+				our_capabilities: capab,
+				our_version_max: self.protocol_version,
+				advertised_capabilities: hand.capabilities,
+				advertised_version_max: hand.version,
+				negotiated_version,
+			}),
 			header_sync_requested: Arc::new(AtomicUsize::new(0)),
 			last_header: Arc::new(Mutex::new(Instant::now())),
 			last_header_reset: Arc::new(Mutex::new(Instant::now())),
 		};
+		peer_info.set_clock_skew(clock_skew(hand.timestamp));
 
 		// At this point we know the published ip and port of the peer
 		// so check if we are configured to explicitly allow or deny it.
@@ -266,19 +320,77 @@ impl Handshake {
 			genesis: self.genesis,
 			total_difficulty: total_difficulty,
 			user_agent: USER_AGENT.to_string(),
+			uptime: Some(self.start_time.elapsed().as_secs()),
+			timestamp: Some(Utc::now().timestamp()),
 		};
 
 		let msg = Msg::new(Type::Shake, shake, negotiated_version)?;
 		write_message(conn, &msg, self.tracker.clone())?;
 
+		let noise = self.upgrade(capab, peer_info.capabilities, conn, false, deadline)?;
+
 		trace!("Success handshake with {}.", peer_info.addr);
 
-		Ok(peer_info)
+		Ok((peer_info, noise))
 	}
 
-	/// Generate a new random nonce and store it in our ring buffer
+	/// Upgrades the connection to Noise encryption when both sides offered it
+	/// in the hand/shake, the side that connected initiates. Peers that
+	/// don't both offer it keep talking in plaintext, unless we offered it
+	/// and are configured to require it. The offer isn't authenticated, a
+	/// peer not making it may have had it stripped on the way. The Noise
+	/// handshake has to complete by the deadline of the hand/shake.
+	fn upgrade(
+		&self,
+		ours: Capabilities,
+		theirs: Capabilities,
+		conn: &mut TcpStream,
+		initiator: bool,
+		deadline: Instant,
+	) -> Result<Option<Arc<NoiseSession>>, Error> {
+		if !ours.contains(Capabilities::NOISE) {
+			return Ok(None);
+		}
+		if !theirs.contains(Capabilities::NOISE) {
+			if self.config.require_noise() {
+				debug!(
+					"Refusing {:?}, it doesn't offer Noise encryption",
+					conn.peer_addr()
+				);
+				return Err(Error::Noise("peer doesn't offer encryption".to_owned()));
+			}
+			warn!(
+				"Plaintext connection with {:?}, we offered Noise encryption but it didn't",
+				conn.peer_addr()
+			);
+			return Ok(None);
+		}
+		let psk = self
+			.config
+			.noise_psk()
+			.ok_or_else(|| Error::Noise("no pre-shared key configured".to_owned()))?;
+		let mut stream = DeadlineStream {
+			conn: &*conn,
+			deadline,
+		};
+		let session = if initiator {
+			noise::initiate(&mut stream, &psk)
+		} else {
+			noise::respond(&mut stream, &psk)
+		}
+		.map_err(deadline_error)?;
+		debug!("Noise session established with {:?}", conn.peer_addr());
+		Ok(Some(Arc::new(session)))
+	}
+
+	/// Generate a new random nonce and store it in our ring buffer, the same
+	/// one every time if we share our node nonce.
 	fn next_nonce(&self) -> u64 {
-		let nonce = thread_rng().gen();
+		let nonce = if self.config.share_node_nonce() {
+			self.node_nonce
+		} else {
+			thread_rng().gen()
+		};
 
 		let mut nonces = self.nonces.write();
 		nonces.push_back(nonce);
@@ -289,6 +401,122 @@ impl Handshake {
 	}
 }
 
+/// Reads from and writes to the connection until the deadline, however
+/// slowly the peer keeps up. Fails with TimedOut once it passed.
+struct DeadlineStream<'a> {
+	conn: &'a TcpStream,
+	deadline: Instant,
+}
+
+impl<'a> DeadlineStream<'a> {
+	/// Time left until the deadline, TimedOut once it passed.
+	fn remaining(&self) -> io::Result<Duration> {
+		let now = Instant::now();
+		if now >= self.deadline {
+			return Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				"handshake deadline passed",
+			));
+		}
+		Ok(self.deadline - now)
+	}
+}
+
+impl<'a> Read for DeadlineStream<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.conn.set_read_timeout(Some(self.remaining()?))?;
+		let mut conn = self.conn;
+		match conn.read(buf) {
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				"handshake read timed out",
+			)),
+			res => res,
+		}
+	}
+}
+
+impl<'a> Write for DeadlineStream<'a> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.conn.set_write_timeout(Some(self.remaining()?))?;
+		let mut conn = self.conn;
+		match conn.write(buf) {
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::Error::new(
+				io::ErrorKind::TimedOut,
+				"handshake write timed out",
+			)),
+			res => res,
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		let mut conn = self.conn;
+		conn.flush()
+	}
+}
+
+/// Handshake timeouts are the peer's doing, tell them apart from other
+/// connection errors.
+fn deadline_error(e: Error) -> Error {
+	match e {
+		Error::Connection(ref err) if err.kind() == io::ErrorKind::TimedOut => {
+			Error::HandshakeTimeout
+		}
+		e => e,
+	}
+}
+
+/// Read a handshake message, the whole of it by the deadline.
+fn read_handshake_message<T: Readable>(
+	conn: &TcpStream,
+	version: ProtocolVersion,
+	msg_type: Type,
+	deadline: Instant,
+) -> Result<T, Error> {
+	let mut reader = DeadlineStream { conn, deadline };
+	read_message(&mut reader, version, msg_type).map_err(deadline_error)
+}
+
+/// How far ahead of ours (in seconds) the clock of a peer is, from the time
+/// it sent in its hand or shake. Peers not sending it are taken to agree
+/// with us.
+fn clock_skew(timestamp: Option<i64>) -> i64 {
+	match timestamp {
+		Some(timestamp) => timestamp
+			.saturating_sub(Utc::now().timestamp())
+			.max(-MAX_CLOCK_SKEW)
+			.min(MAX_CLOCK_SKEW),
+		None => 0,
+	}
+}
+
+/// Refuses a peer claiming more total difficulty than it could plausibly have
+/// accumulated, judging by our own header head. Peers are only checked once
+/// we know a recent enough head to bound them by.
+fn check_difficulty(claimed: Difficulty, adapter: &dyn NetAdapter) -> Result<(), Error> {
+	let head = match adapter.header_head() {
+		Some(head) => head,
+		None => return Ok(()),
+	};
+	let mut headers = adapter
+		.headers_by_height(head.height.saturating_sub(1), 2)?
+		.into_iter()
+		.rev();
+	let last = match headers.next() {
+		Some(last) => last,
+		None => return Ok(()),
+	};
+	let block_difficulty = match headers.next() {
+		Some(prev) => last.total_difficulty() - prev.total_difficulty(),
+		None => last.total_difficulty(),
+	};
+	let age = (Utc::now() - last.timestamp).num_seconds().max(0) as u64;
+	match max_plausible_difficulty(head.total_difficulty, block_difficulty, age) {
+		Some(bound) if claimed > bound => Err(Error::ImpossibleDifficulty { claimed, bound }),
+		_ => Ok(()),
+	}
+}
+
 /// Resolve the correct peer_addr based on the connection and the advertised port.
 fn resolve_peer_addr(advertised: PeerAddr, conn: &TcpStream) -> PeerAddr {
 	match advertised {
@@ -303,3 +531,13 @@ fn resolve_peer_addr(advertised: PeerAddr, conn: &TcpStream) -> PeerAddr {
 		Onion(_) => advertised,
 	}
 }
+
+/// Whether the address a peer sent in its hand says it listens for inbound
+/// connections, for peers not telling explicitly. Older peers only dialing
+/// out advertise port 0.
+fn advertises_listener(advertised: &PeerAddr) -> bool {
+	match advertised {
+		Ip(socket_addr) => socket_addr.port() != 0,
+		Onion(_) => true,
+	}
+}
@@ -39,25 +39,37 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+mod admin;
 mod conn;
 pub mod handshake;
 pub mod libp2p_connection;
+mod log_throttle;
+mod metrics;
 pub mod msg;
+pub mod noise;
 mod peer;
 mod peers;
 mod protocol;
+mod read_budget;
 mod serv;
 mod store;
 pub mod types;
 
-pub use crate::conn::SEND_CHANNEL_CAP;
+pub use crate::admin::{AdminAction, AdminControl, AuthorizedAdmin};
+pub use crate::conn::{TrafficTotals, SEND_CHANNEL_CAP};
+pub use crate::log_throttle::LogThrottle;
+pub use crate::metrics::PeerMetricsExporter;
 pub use crate::peer::Peer;
 pub use crate::peers::Peers;
+pub use crate::read_budget::{ReadBudget, LIGHT_READ_BYTES};
 pub use crate::serv::{DummyAdapter, Server};
-pub use crate::store::{PeerData, State};
+pub use crate::store::{ExportedPeer, PeerData, State, SubnetBan, PEER_DATA_VERSION};
 pub use crate::types::{
-	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	AddressFamilyPreference, BlockAccept, Capabilities, CapabilityRefusals, ChainAdapter,
+	Direction, DuplicateConnectionPolicy, Error, HeaderTimestamp, IpPrefix, NetworkClass,
+	P2PConfig, PeerAddr, PeerInfo, PeerSetDiff, PeerSetSnapshot, PeerSnapshot, ProtocolNegotiation,
+	ReasonForBan, SeedSource, Seeding, SilentPeerPolicy, SyncPriorityCtx, SyncPriorityWeights,
+	TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
 };
 
 pub use crate::libp2p_connection::{
@@ -0,0 +1,126 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limiting of the log lines about a misbehaving peer, so a peer failing
+//! the same way over and over shows up as periodic summaries.
+
+use std::collections::HashMap;
+
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+
+use crate::types::PeerAddr;
+use crate::util::Mutex;
+
+/// Default window (in seconds) repeated errors get collapsed over.
+const LOG_THROTTLE_WINDOW: i64 = 60;
+
+/// Collapses repeated errors of the same kind from the same peer. The first
+/// one of a window gets logged, the others are only counted and reported in
+/// a single summary line once the window is over (or the peer is gone).
+pub struct LogThrottle {
+	window: Duration,
+	// per peer and error kind, start of the window and errors seen in it
+	entries: Mutex<HashMap<(PeerAddr, &'static str), (DateTime<Utc>, u64)>>,
+}
+
+impl Default for LogThrottle {
+	fn default() -> LogThrottle {
+		LogThrottle::new(Duration::seconds(LOG_THROTTLE_WINDOW))
+	}
+}
+
+impl LogThrottle {
+	pub fn new(window: Duration) -> LogThrottle {
+		LogThrottle {
+			window,
+			entries: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Records an error of the given kind from the peer, returns the line to
+	/// log if any. Errors repeated within the window return None, the next
+	/// one after it returns a summary with how many there were, unless the
+	/// window got flushed already.
+	pub fn note(&self, addr: &PeerAddr, kind: &'static str, now: DateTime<Utc>) -> Option<String> {
+		let window = self.window;
+		let mut entries = self.entries.lock();
+		let entry = entries.entry((addr.clone(), kind)).or_insert((now, 0));
+		if now - entry.0 <= window {
+			entry.1 += 1;
+			if entry.1 == 1 {
+				return Some(format!("{} from peer {}", kind, addr));
+			}
+			return None;
+		}
+
+		let count = entry.1;
+		*entry = (now, 1);
+		if count > 1 {
+			Some(summary(addr, kind, count, window))
+		} else {
+			Some(format!("{} from peer {}", kind, addr))
+		}
+	}
+
+	/// Forgets about the windows that are over, returns the summaries of
+	/// those that had repeated errors. To be called periodically, so the
+	/// summary of a peer that went quiet still gets logged.
+	pub fn flush(&self, now: DateTime<Utc>) -> Vec<String> {
+		let window = self.window;
+		let mut lines = vec![];
+		self.entries.lock().retain(|(addr, kind), (start, count)| {
+			if now - *start <= window {
+				return true;
+			}
+			if *count > 1 {
+				lines.push(summary(addr, kind, *count, window));
+			}
+			false
+		});
+		lines
+	}
+
+	/// Forgets about the peer, returns the summaries of its windows that had
+	/// repeated errors. To be called once we disconnected from it.
+	pub fn flush_peer(&self, peer: &PeerAddr, now: DateTime<Utc>) -> Vec<String> {
+		let window = self.window;
+		let mut lines = vec![];
+		self.entries.lock().retain(|(addr, kind), (start, count)| {
+			if addr != peer {
+				return true;
+			}
+			if *count > 1 {
+				lines.push(summary(
+					addr,
+					kind,
+					*count,
+					std::cmp::min(now - *start, window),
+				));
+			}
+			false
+		});
+		lines
+	}
+}
+
+fn summary(addr: &PeerAddr, kind: &str, count: u64, over: Duration) -> String {
+	format!(
+		"{} from peer {} ({} times in the last {}s)",
+		kind,
+		addr,
+		count,
+		over.num_seconds()
+	)
+}
@@ -0,0 +1,143 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Peer metrics in the Prometheus text exposition format, for a `/metrics`
+//! endpoint of the embedding server.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::core::ser::ProtocolVersion;
+use crate::peers::Peers;
+use crate::types::{Direction, PeerSetSnapshot};
+
+const DIRECTIONS: [(Direction, &str); 4] = [
+	(Direction::Inbound, "inbound"),
+	(Direction::Outbound, "outbound"),
+	(Direction::InboundTor, "inbound_tor"),
+	(Direction::OutboundTor, "outbound_tor"),
+];
+
+/// Our peer metrics at a given time, rendered as Prometheus text metrics.
+#[derive(Debug, Clone)]
+pub struct PeerMetricsExporter {
+	snapshot: PeerSetSnapshot,
+	sent_bytes: u64,
+	received_bytes: u64,
+	ban_count: u64,
+}
+
+impl PeerMetricsExporter {
+	/// Metrics for the provided connected peers, the bytes sent to and
+	/// received from all peers and the bans since we started.
+	pub fn new(
+		snapshot: PeerSetSnapshot,
+		sent_bytes: u64,
+		received_bytes: u64,
+		ban_count: u64,
+	) -> PeerMetricsExporter {
+		PeerMetricsExporter {
+			snapshot,
+			sent_bytes,
+			received_bytes,
+			ban_count,
+		}
+	}
+
+	/// Metrics of our currently connected peers.
+	pub fn from_peers(peers: &Peers) -> PeerMetricsExporter {
+		let totals = peers.traffic_totals();
+		PeerMetricsExporter::new(
+			peers.snapshot(),
+			totals.sent(),
+			totals.received(),
+			peers.ban_count(),
+		)
+	}
+
+	/// Number of connected peers per protocol version, lowest version first.
+	fn version_counts(&self) -> BTreeMap<ProtocolVersion, u32> {
+		let mut counts = BTreeMap::new();
+		for peer in self.snapshot.peers.values() {
+			*counts.entry(peer.version).or_insert(0) += 1;
+		}
+		counts
+	}
+
+	/// The metrics in the Prometheus text exposition format.
+	pub fn render(&self) -> String {
+		let mut out = String::new();
+
+		header(
+			&mut out,
+			"mwc_p2p_peers",
+			"gauge",
+			"Connected peers by direction.",
+		);
+		for (direction, label) in DIRECTIONS.iter() {
+			let count = self
+				.snapshot
+				.peers
+				.values()
+				.filter(|p| p.direction == *direction)
+				.count();
+			let _ = writeln!(out, "mwc_p2p_peers{{direction=\"{}\"}} {}", label, count);
+		}
+
+		header(
+			&mut out,
+			"mwc_p2p_peers_by_version",
+			"gauge",
+			"Connected peers by negotiated protocol version.",
+		);
+		for (version, count) in self.version_counts() {
+			let _ = writeln!(
+				out,
+				"mwc_p2p_peers_by_version{{version=\"{}\"}} {}",
+				version, count
+			);
+		}
+
+		header(
+			&mut out,
+			"mwc_p2p_sent_bytes_total",
+			"counter",
+			"Bytes sent to peers since the node started.",
+		);
+		let _ = writeln!(out, "mwc_p2p_sent_bytes_total {}", self.sent_bytes);
+
+		header(
+			&mut out,
+			"mwc_p2p_received_bytes_total",
+			"counter",
+			"Bytes received from peers since the node started.",
+		);
+		let _ = writeln!(out, "mwc_p2p_received_bytes_total {}", self.received_bytes);
+
+		header(
+			&mut out,
+			"mwc_p2p_bans_total",
+			"counter",
+			"Peers banned since the node started.",
+		);
+		let _ = writeln!(out, "mwc_p2p_bans_total {}", self.ban_count);
+
+		out
+	}
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
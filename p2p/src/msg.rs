@@ -67,6 +67,80 @@ enum_from_primitive! {
 		GetTransaction = 19,
 		TransactionKernel = 20,
 		TorAddress = 23,
+		GetHeadersByHeight = 24,
+		CapabilitiesUpdate = 25,
+		KeepAlive = 26,
+		GetCapabilities = 27,
+		GetTip = 28,
+		Tip = 29,
+		RequestReachabilityCheck = 30,
+		ReachabilityCheck = 31,
+		Declined = 32,
+	}
+}
+
+// Why we declined a request, told to the peer in a Declined reply.
+enum_from_primitive! {
+	#[derive(Debug, Clone, Copy, PartialEq)]
+	pub enum DeclineReason {
+		/// We're busy with maintenance, the peer may ask again later.
+		TryLater = 0,
+		/// We don't serve this, the peer should ask someone else.
+		NotServed = 1,
+		/// We don't want what the peer sent us, it wasn't kept.
+		Unwanted = 2,
+	}
+}
+
+/// Lowest protocol version supporting GetHeadersByHeight, older peers don't
+/// know the message and would just drop it.
+pub const HEADERS_BY_HEIGHT_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version whose GetHeaders carries how many headers at most
+/// the sender wants back, older peers always get as many as we have.
+pub const LOCATOR_COUNT_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version whose Hand and Shake carry the sender uptime.
+pub const PEER_UPTIME_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version whose Hand and Shake carry the sender clock.
+pub const PEER_TIME_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version whose Ping and Pong carry the lowest height the
+/// sender still holds full blocks for.
+pub const PRUNE_HEIGHT_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version supporting CapabilitiesUpdate, older peers keep the
+/// capabilities we advertised in the handshake.
+pub const CAPABILITIES_UPDATE_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version supporting GetTip, older peers don't know the
+/// message and would just drop it.
+pub const TIP_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version supporting RequestReachabilityCheck, older peers
+/// can't tell us whether our listener is reachable.
+pub const REACHABILITY_CHECK_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version supporting Declined, older peers get no reply to
+/// the requests we decline.
+pub const DECLINED_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version supporting KeepAlive, older peers would drop the
+/// connection on the unknown message.
+pub const KEEPALIVE_VERSION: ProtocolVersion = ProtocolVersion(4);
+
+/// Lowest protocol version a peer must be on for us to send it a message of
+/// this type, older peers don't know the newer types.
+pub fn min_version(msg_type: Type) -> ProtocolVersion {
+	match msg_type {
+		Type::GetHeadersByHeight => HEADERS_BY_HEIGHT_VERSION,
+		Type::CapabilitiesUpdate | Type::GetCapabilities => CAPABILITIES_UPDATE_VERSION,
+		Type::GetTip | Type::Tip => TIP_VERSION,
+		Type::RequestReachabilityCheck | Type::ReachabilityCheck => REACHABILITY_CHECK_VERSION,
+		Type::Declined => DECLINED_VERSION,
+		Type::KeepAlive => KEEPALIVE_VERSION,
+		_ => ProtocolVersion(1),
 	}
 }
 
@@ -80,17 +154,23 @@ fn default_max_msg_size() -> u64 {
 	max_block_size()
 }
 
+/// Largest message body we accept by default, the limit of the largest msg
+/// types (including the 4x headroom applied when reading headers).
+pub fn max_message_size() -> u64 {
+	default_max_msg_size() * 4
+}
+
 // Max msg size for each msg type.
 fn max_msg_size(msg_type: Type) -> u64 {
 	match msg_type {
 		Type::Error => 0,
-		Type::Hand => 128,
-		Type::Shake => 88,
-		Type::Ping => 16,
-		Type::Pong => 16,
+		Type::Hand => 136,
+		Type::Shake => 96,
+		Type::Ping => 24,
+		Type::Pong => 24,
 		Type::GetPeerAddrs => 4,
 		Type::PeerAddrs => 4 + (1 + 16 + 2) * MAX_PEER_ADDRS as u64,
-		Type::GetHeaders => 1 + 32 * MAX_LOCATORS as u64,
+		Type::GetHeaders => 1 + 32 * MAX_LOCATORS as u64 + 4,
 		Type::Header => 365,
 		Type::Headers => 2 + 365 * MAX_BLOCK_HEADERS as u64,
 		Type::GetBlock => 32,
@@ -105,6 +185,15 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::GetTransaction => 32,
 		Type::TransactionKernel => 32,
 		Type::TorAddress => 128,
+		Type::GetHeadersByHeight => 12,
+		Type::CapabilitiesUpdate => 4,
+		Type::KeepAlive => 0,
+		Type::GetCapabilities => 0,
+		Type::GetTip => 0,
+		Type::Tip => 48,
+		Type::RequestReachabilityCheck => 10,
+		Type::ReachabilityCheck => 9,
+		Type::Declined => 2,
 	}
 }
 
@@ -148,14 +237,28 @@ impl Msg {
 /// we do not want to block.
 ///
 /// Note: We return a MsgHeaderWrapper here as we may encounter an unknown msg type.
+/// Messages declaring a body larger than max_msg_size are rejected with MsgLen
+/// before anything gets allocated for the body.
 ///
 pub fn read_header<R: Read>(
 	stream: &mut R,
 	version: ProtocolVersion,
+	max_msg_size: u64,
 ) -> Result<MsgHeaderWrapper, Error> {
 	let mut head = vec![0u8; MsgHeader::LEN];
 	stream.read_exact(&mut head)?;
 	let header: MsgHeaderWrapper = ser::deserialize(&mut &head[..], version)?;
+	let msg_len = match header {
+		MsgHeaderWrapper::Known(ref h) => h.msg_len,
+		MsgHeaderWrapper::Unknown(msg_len, _) => msg_len,
+	};
+	if msg_len > max_msg_size {
+		debug!(
+			"read_header: msg_len {} exceeds max message size {}",
+			msg_len, max_msg_size
+		);
+		return Err(Error::MsgLen);
+	}
 	Ok(header)
 }
 
@@ -183,6 +286,22 @@ pub fn read_body<T: Readable, R: Read>(
 	ser::deserialize(&mut &body[..], version).map_err(From::from)
 }
 
+/// Read a locator message body from the provided stream. Locators with more
+/// than MAX_LOCATORS hashes are rejected with MsgLen before anything is read.
+pub fn read_locator<R: Read>(
+	h: &MsgHeader,
+	stream: &mut R,
+	version: ProtocolVersion,
+) -> Result<Locator, Error> {
+	if h.msg_len > max_msg_size(Type::GetHeaders) {
+		return Err(Error::MsgLen);
+	}
+	read_body(h, stream, version).map_err(|e| match e {
+		Error::Serialization(ser::Error::TooLargeReadErr(_)) => Error::MsgLen,
+		e => e,
+	})
+}
+
 /// Read (an unknown) message from the provided stream and discard it.
 pub fn read_discard<R: Read>(msg_len: u64, stream: &mut R) -> Result<(), Error> {
 	let mut buffer = vec![0u8; msg_len as usize];
@@ -196,7 +315,7 @@ pub fn read_message<T: Readable, R: Read>(
 	version: ProtocolVersion,
 	msg_type: Type,
 ) -> Result<T, Error> {
-	match read_header(stream, version)? {
+	match read_header(stream, version, max_message_size())? {
 		MsgHeaderWrapper::Known(header) => {
 			if header.msg_type == msg_type {
 				read_body(&header, stream, version)
@@ -356,6 +475,16 @@ pub struct Hand {
 	pub receiver_addr: PeerAddr,
 	/// name of version of the software
 	pub user_agent: String,
+	/// how long (in seconds) the sender has been running, only sent from
+	/// PEER_UPTIME_VERSION on and not to be trusted
+	pub uptime: Option<u64>,
+	/// unix timestamp of the sender clock, only sent from PEER_TIME_VERSION
+	/// on
+	pub timestamp: Option<i64>,
+	/// whether the sender accepts connections on sender_addr, only sent from
+	/// REACHABILITY_CHECK_VERSION on, older peers only dialing out advertise
+	/// port 0
+	pub listening: Option<bool>,
 }
 
 impl Writeable for Hand {
@@ -377,6 +506,15 @@ impl Writeable for Hand {
 		}
 		writer.write_bytes(&self.user_agent)?;
 		self.genesis.write(writer)?;
+		if self.version >= PEER_UPTIME_VERSION {
+			writer.write_u64(self.uptime.unwrap_or(0))?;
+		}
+		if self.version >= PEER_TIME_VERSION {
+			writer.write_i64(self.timestamp.unwrap_or(0))?;
+		}
+		if self.version >= REACHABILITY_CHECK_VERSION {
+			writer.write_u8(self.listening.unwrap_or(true) as u8)?;
+		}
 		Ok(())
 	}
 }
@@ -393,6 +531,21 @@ impl Readable for Hand {
 		let user_agent = String::from_utf8(ua)
 			.map_err(|e| ser::Error::CorruptedData(format!("Fail to read User Agent, {}", e)))?;
 		let genesis = Hash::read(reader)?;
+		let uptime = if version >= PEER_UPTIME_VERSION {
+			Some(reader.read_u64()?)
+		} else {
+			None
+		};
+		let timestamp = if version >= PEER_TIME_VERSION {
+			Some(reader.read_i64()?)
+		} else {
+			None
+		};
+		let listening = if version >= REACHABILITY_CHECK_VERSION {
+			Some(read_bool(reader, "invalid listening flag")?)
+		} else {
+			None
+		};
 		Ok(Hand {
 			version,
 			capabilities,
@@ -402,6 +555,9 @@ impl Readable for Hand {
 			sender_addr,
 			receiver_addr,
 			user_agent,
+			uptime,
+			timestamp,
+			listening,
 		})
 	}
 }
@@ -420,6 +576,12 @@ pub struct Shake {
 	pub total_difficulty: Difficulty,
 	/// name of version of the software
 	pub user_agent: String,
+	/// how long (in seconds) the sender has been running, only sent from
+	/// PEER_UPTIME_VERSION on and not to be trusted
+	pub uptime: Option<u64>,
+	/// unix timestamp of the sender clock, only sent from PEER_TIME_VERSION
+	/// on
+	pub timestamp: Option<i64>,
 }
 
 impl Writeable for Shake {
@@ -435,6 +597,12 @@ impl Writeable for Shake {
 		}
 		writer.write_bytes(&self.user_agent)?;
 		self.genesis.write(writer)?;
+		if self.version >= PEER_UPTIME_VERSION {
+			writer.write_u64(self.uptime.unwrap_or(0))?;
+		}
+		if self.version >= PEER_TIME_VERSION {
+			writer.write_i64(self.timestamp.unwrap_or(0))?;
+		}
 		Ok(())
 	}
 }
@@ -449,12 +617,24 @@ impl Readable for Shake {
 		let user_agent = String::from_utf8(ua)
 			.map_err(|e| ser::Error::CorruptedData(format!("Fail to read User Agent, {}", e)))?;
 		let genesis = Hash::read(reader)?;
+		let uptime = if version >= PEER_UPTIME_VERSION {
+			Some(reader.read_u64()?)
+		} else {
+			None
+		};
+		let timestamp = if version >= PEER_TIME_VERSION {
+			Some(reader.read_i64()?)
+		} else {
+			None
+		};
 		Ok(Shake {
 			version,
 			capabilities,
 			genesis,
 			total_difficulty,
 			user_agent,
+			uptime,
+			timestamp,
 		})
 	}
 }
@@ -479,6 +659,181 @@ impl Readable for GetPeerAddrs {
 	}
 }
 
+/// Sent to connected peers when our capabilities change after the handshake.
+pub struct CapabilitiesUpdate {
+	/// Capabilities of the sender from now on
+	pub capabilities: Capabilities,
+}
+
+impl Writeable for CapabilitiesUpdate {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u32(self.capabilities.bits())
+	}
+}
+
+impl Readable for CapabilitiesUpdate {
+	fn read<R: Reader>(reader: &mut R) -> Result<CapabilitiesUpdate, ser::Error> {
+		let capab = reader.read_u32()?;
+		let capabilities = Capabilities::from_bits_truncate(capab);
+		Ok(CapabilitiesUpdate { capabilities })
+	}
+}
+
+/// Empty message sent on otherwise idle connections, keeping NAT mappings and
+/// tor circuits alive. Peers not knowing it just discard it.
+pub struct KeepAlive;
+
+impl Writeable for KeepAlive {
+	fn write<W: Writer>(&self, _writer: &mut W) -> Result<(), ser::Error> {
+		Ok(())
+	}
+}
+
+/// Asks the peer for the capabilities it currently offers, answered with a
+/// CapabilitiesUpdate. Peers not knowing it just discard it.
+pub struct GetCapabilities;
+
+impl Writeable for GetCapabilities {
+	fn write<W: Writer>(&self, _writer: &mut W) -> Result<(), ser::Error> {
+		Ok(())
+	}
+}
+
+/// Asks the peer for its best header, answered with a Tip. Much cheaper than
+/// requesting headers when all we need is where the peer stands.
+pub struct GetTip;
+
+impl Writeable for GetTip {
+	fn write<W: Writer>(&self, _writer: &mut W) -> Result<(), ser::Error> {
+		Ok(())
+	}
+}
+
+/// Best header of the sender, in response to GetTip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tip {
+	/// Hash of the sender best header
+	pub hash: Hash,
+	/// Height of the sender best header
+	pub height: u64,
+	/// Total difficulty accumulated up to the sender best header
+	pub total_difficulty: Difficulty,
+}
+
+impl Writeable for Tip {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u64(self.height)?;
+		self.total_difficulty.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for Tip {
+	fn read<R: Reader>(reader: &mut R) -> Result<Tip, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let height = reader.read_u64()?;
+		let total_difficulty = Difficulty::read(reader)?;
+		Ok(Tip {
+			hash,
+			height,
+			total_difficulty,
+		})
+	}
+}
+
+/// Asks the peer to try connecting back to us on the provided port, from
+/// outside, answered with a ReachabilityCheck. The peer only ever dials the
+/// ip we're connected from, and sends the nonce as the first bytes of the
+/// connection so we can tell it apart from any other peer on that ip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestReachabilityCheck {
+	/// Port our listener is on
+	pub port: u16,
+	/// Identifies this check, on the connection back and in the answer
+	pub nonce: u64,
+}
+
+impl Writeable for RequestReachabilityCheck {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		ser_multiwrite!(writer, [write_u16, self.port], [write_u64, self.nonce]);
+		Ok(())
+	}
+}
+
+impl Readable for RequestReachabilityCheck {
+	fn read<R: Reader>(reader: &mut R) -> Result<RequestReachabilityCheck, ser::Error> {
+		let (port, nonce) = ser_multiread!(reader, read_u16, read_u64);
+		Ok(RequestReachabilityCheck { port, nonce })
+	}
+}
+
+/// Whether the sender could connect back to us, in response to the
+/// RequestReachabilityCheck with the same nonce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReachabilityCheck {
+	pub nonce: u64,
+	pub reachable: bool,
+}
+
+impl Writeable for ReachabilityCheck {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		ser_multiwrite!(
+			writer,
+			[write_u64, self.nonce],
+			[write_u8, self.reachable as u8]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for ReachabilityCheck {
+	fn read<R: Reader>(reader: &mut R) -> Result<ReachabilityCheck, ser::Error> {
+		let nonce = reader.read_u64()?;
+		let reachable = read_bool(reader, "invalid reachability check result")?;
+		Ok(ReachabilityCheck { nonce, reachable })
+	}
+}
+
+/// Reply to a request we won't serve, so the peer doesn't wait on us.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Declined {
+	/// Type of the message we declined.
+	pub msg_type: Type,
+	pub reason: DeclineReason,
+}
+
+impl Writeable for Declined {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		ser_multiwrite!(
+			writer,
+			[write_u8, self.msg_type as u8],
+			[write_u8, self.reason as u8]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for Declined {
+	fn read<R: Reader>(reader: &mut R) -> Result<Declined, ser::Error> {
+		let msg_type = Type::from_u8(reader.read_u8()?).ok_or(ser::Error::CorruptedData(
+			"unknown declined msg type".to_string(),
+		))?;
+		let reason = DeclineReason::from_u8(reader.read_u8()?).ok_or(ser::Error::CorruptedData(
+			"unknown decline reason".to_string(),
+		))?;
+		Ok(Declined { msg_type, reason })
+	}
+}
+
+fn read_bool<R: Reader>(reader: &mut R, what: &str) -> Result<bool, ser::Error> {
+	match reader.read_u8()? {
+		0 => Ok(false),
+		1 => Ok(true),
+		_ => Err(ser::Error::CorruptedData(what.to_string())),
+	}
+}
+
 /// Peer addresses we know of that are fresh enough, in response to
 /// GetPeerAddrs.
 #[derive(Debug, Clone, Serialize, PartialEq)]
@@ -558,6 +913,9 @@ impl Readable for PeerError {
 #[derive(Debug)]
 pub struct Locator {
 	pub hashes: Vec<Hash>,
+	/// how many headers at most the sender wants back, only sent from
+	/// LOCATOR_COUNT_VERSION on
+	pub count: Option<u32>,
 }
 
 impl Writeable for Locator {
@@ -572,6 +930,9 @@ impl Writeable for Locator {
 		for h in &self.hashes {
 			h.write(writer)?
 		}
+		if writer.protocol_version() >= LOCATOR_COUNT_VERSION {
+			writer.write_u32(self.count.unwrap_or(MAX_BLOCK_HEADERS))?;
+		}
 		Ok(())
 	}
 }
@@ -589,7 +950,42 @@ impl Readable for Locator {
 		for _ in 0..len {
 			hashes.push(Hash::read(reader)?);
 		}
-		Ok(Locator { hashes: hashes })
+		let count = if reader.protocol_version() >= LOCATOR_COUNT_VERSION {
+			Some(reader.read_u32()?)
+		} else {
+			None
+		};
+		Ok(Locator {
+			hashes: hashes,
+			count,
+		})
+	}
+}
+
+/// Request for the headers of a range of heights, answered with Headers.
+/// Peers serve at most MAX_BLOCK_HEADERS headers whatever the count.
+#[derive(Debug)]
+pub struct GetHeadersByHeight {
+	/// Height of the first header requested
+	pub start: u64,
+	/// Number of headers requested
+	pub count: u32,
+}
+
+impl Writeable for GetHeadersByHeight {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.start)?;
+		writer.write_u32(self.count)?;
+		Ok(())
+	}
+}
+
+impl Readable for GetHeadersByHeight {
+	fn read<R: Reader>(reader: &mut R) -> Result<GetHeadersByHeight, ser::Error> {
+		Ok(GetHeadersByHeight {
+			start: reader.read_u64()?,
+			count: reader.read_u32()?,
+		})
 	}
 }
 
@@ -614,12 +1010,18 @@ pub struct Ping {
 	pub total_difficulty: Difficulty,
 	/// total height
 	pub height: u64,
+	/// lowest height the sender still holds full blocks for, only sent from
+	/// PRUNE_HEIGHT_VERSION on
+	pub prune_height: Option<u64>,
 }
 
 impl Writeable for Ping {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
 		self.total_difficulty.write(writer)?;
 		self.height.write(writer)?;
+		if writer.protocol_version() >= PRUNE_HEIGHT_VERSION {
+			writer.write_u64(self.prune_height.unwrap_or(0))?;
+		}
 		Ok(())
 	}
 }
@@ -628,9 +1030,15 @@ impl Readable for Ping {
 	fn read<R: Reader>(reader: &mut R) -> Result<Ping, ser::Error> {
 		let total_difficulty = Difficulty::read(reader)?;
 		let height = reader.read_u64()?;
+		let prune_height = if reader.protocol_version() >= PRUNE_HEIGHT_VERSION {
+			Some(reader.read_u64()?)
+		} else {
+			None
+		};
 		Ok(Ping {
 			total_difficulty,
 			height,
+			prune_height,
 		})
 	}
 }
@@ -641,12 +1049,18 @@ pub struct Pong {
 	pub total_difficulty: Difficulty,
 	/// height accumulated by sender
 	pub height: u64,
+	/// lowest height the sender still holds full blocks for, only sent from
+	/// PRUNE_HEIGHT_VERSION on
+	pub prune_height: Option<u64>,
 }
 
 impl Writeable for Pong {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
 		self.total_difficulty.write(writer)?;
 		self.height.write(writer)?;
+		if writer.protocol_version() >= PRUNE_HEIGHT_VERSION {
+			writer.write_u64(self.prune_height.unwrap_or(0))?;
+		}
 		Ok(())
 	}
 }
@@ -655,9 +1069,15 @@ impl Readable for Pong {
 	fn read<R: Reader>(reader: &mut R) -> Result<Pong, ser::Error> {
 		let total_difficulty = Difficulty::read(reader)?;
 		let height = reader.read_u64()?;
+		let prune_height = if reader.protocol_version() >= PRUNE_HEIGHT_VERSION {
+			Some(reader.read_u64()?)
+		} else {
+			None
+		};
 		Ok(Pong {
 			total_difficulty,
 			height,
+			prune_height,
 		})
 	}
 }
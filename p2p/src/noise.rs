@@ -0,0 +1,305 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Optional Noise encryption of peer connections. Once both peers advertised
+//! the NOISE capability in the hand/shake, the connecting side runs a Noise
+//! NNpsk0 handshake keyed with the pre-shared key of the network, everything
+//! after that is sent in encrypted frames. A peer without the key can't
+//! complete the handshake.
+//!
+//! Frames are a 2 bytes (big endian) length followed by the ciphertext, the
+//! reader and writer halves of a connection share the session but each
+//! keeps its own nonce.
+
+use crate::types::Error;
+use snow::{Builder, StatelessTransportState};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const NOISE_PARAMS: &str = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s";
+
+/// Largest Noise message, ciphertext included.
+const MAX_FRAME_LEN: usize = 65535;
+
+/// Size of the authentication tag added to each frame.
+const TAG_LEN: usize = 16;
+
+/// Largest payload we put in a single frame.
+const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - TAG_LEN;
+
+/// Encryption state of a connection upgraded to Noise.
+pub struct NoiseSession {
+	transport: StatelessTransportState,
+	send_nonce: AtomicU64,
+	recv_nonce: AtomicU64,
+}
+
+impl NoiseSession {
+	/// Encrypts the payload into a frame, length prefix included.
+	fn encrypt(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+		let nonce = self.send_nonce.fetch_add(1, Ordering::SeqCst);
+		let mut frame = vec![0u8; 2 + payload.len() + TAG_LEN];
+		let len = self
+			.transport
+			.write_message(nonce, payload, &mut frame[2..])
+			.map_err(io_error)?;
+		frame[..2].copy_from_slice(&(len as u16).to_be_bytes());
+		frame.truncate(2 + len);
+		Ok(frame)
+	}
+
+	/// Decrypts the ciphertext of a frame.
+	fn decrypt(&self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+		let nonce = self.recv_nonce.fetch_add(1, Ordering::SeqCst);
+		let mut payload = vec![0u8; ciphertext.len()];
+		let len = self
+			.transport
+			.read_message(nonce, ciphertext, &mut payload)
+			.map_err(io_error)?;
+		payload.truncate(len);
+		Ok(payload)
+	}
+}
+
+fn io_error(e: snow::Error) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("noise: {}", e))
+}
+
+fn noise_error(e: snow::Error) -> Error {
+	Error::Noise(e.to_string())
+}
+
+fn write_frame<S: Write>(conn: &mut S, frame: &[u8]) -> Result<(), Error> {
+	conn.write_all(&(frame.len() as u16).to_be_bytes())?;
+	conn.write_all(frame)?;
+	Ok(())
+}
+
+fn read_frame<S: Read>(conn: &mut S) -> Result<Vec<u8>, Error> {
+	let mut len = [0u8; 2];
+	conn.read_exact(&mut len)?;
+	let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+	conn.read_exact(&mut frame)?;
+	Ok(frame)
+}
+
+/// Runs the Noise handshake as the side that opened the connection.
+pub fn initiate<S: Read + Write>(conn: &mut S, psk: &[u8; 32]) -> Result<NoiseSession, Error> {
+	let mut handshake = Builder::new(NOISE_PARAMS.parse().map_err(noise_error)?)
+		.psk(0, psk)
+		.build_initiator()
+		.map_err(noise_error)?;
+	let mut buf = vec![0u8; MAX_FRAME_LEN];
+
+	// -> psk, e
+	let len = handshake
+		.write_message(&[], &mut buf)
+		.map_err(noise_error)?;
+	write_frame(conn, &buf[..len])?;
+
+	// <- e, ee
+	let frame = read_frame(conn)?;
+	handshake
+		.read_message(&frame, &mut buf)
+		.map_err(noise_error)?;
+
+	let transport = handshake
+		.into_stateless_transport_mode()
+		.map_err(noise_error)?;
+	Ok(NoiseSession {
+		transport,
+		send_nonce: AtomicU64::new(0),
+		recv_nonce: AtomicU64::new(0),
+	})
+}
+
+/// Runs the Noise handshake as the side that accepted the connection.
+pub fn respond<S: Read + Write>(conn: &mut S, psk: &[u8; 32]) -> Result<NoiseSession, Error> {
+	let mut handshake = Builder::new(NOISE_PARAMS.parse().map_err(noise_error)?)
+		.psk(0, psk)
+		.build_responder()
+		.map_err(noise_error)?;
+	let mut buf = vec![0u8; MAX_FRAME_LEN];
+
+	// -> psk, e
+	let frame = read_frame(conn)?;
+	handshake
+		.read_message(&frame, &mut buf)
+		.map_err(noise_error)?;
+
+	// <- e, ee
+	let len = handshake
+		.write_message(&[], &mut buf)
+		.map_err(noise_error)?;
+	write_frame(conn, &buf[..len])?;
+
+	let transport = handshake
+		.into_stateless_transport_mode()
+		.map_err(noise_error)?;
+	Ok(NoiseSession {
+		transport,
+		send_nonce: AtomicU64::new(0),
+		recv_nonce: AtomicU64::new(0),
+	})
+}
+
+/// Reading half of an encrypted connection, decrypting frame by frame.
+pub struct NoiseReader {
+	conn: TcpStream,
+	session: Arc<NoiseSession>,
+	// raw bytes of the frame being received, kept across read timeouts
+	frame: Vec<u8>,
+	// decrypted payload of the last frame and how much of it was read
+	payload: Vec<u8>,
+	pos: usize,
+}
+
+impl NoiseReader {
+	pub fn new(conn: TcpStream, session: Arc<NoiseSession>) -> NoiseReader {
+		NoiseReader {
+			conn,
+			session,
+			frame: vec![],
+			payload: vec![],
+			pos: 0,
+		}
+	}
+
+	/// Length of the frame being received, once we have its prefix.
+	fn frame_len(&self) -> Option<usize> {
+		if self.frame.len() < 2 {
+			None
+		} else {
+			Some(u16::from_be_bytes([self.frame[0], self.frame[1]]) as usize)
+		}
+	}
+
+	fn next_frame(&mut self) -> io::Result<()> {
+		loop {
+			let missing = match self.frame_len() {
+				None => 2 - self.frame.len(),
+				Some(len) => 2 + len - self.frame.len(),
+			};
+			if missing == 0 {
+				break;
+			}
+			let start = self.frame.len();
+			self.frame.resize(start + missing, 0);
+			match self.conn.read(&mut self.frame[start..]) {
+				Ok(0) => {
+					self.frame.truncate(start);
+					return Err(io::ErrorKind::UnexpectedEof.into());
+				}
+				Ok(n) => self.frame.truncate(start + n),
+				Err(e) => {
+					self.frame.truncate(start);
+					return Err(e);
+				}
+			}
+		}
+		self.payload = self.session.decrypt(&self.frame[2..])?;
+		self.pos = 0;
+		self.frame.clear();
+		Ok(())
+	}
+}
+
+impl Read for NoiseReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		while self.pos == self.payload.len() {
+			self.next_frame()?;
+		}
+		let len = std::cmp::min(buf.len(), self.payload.len() - self.pos);
+		buf[..len].copy_from_slice(&self.payload[self.pos..self.pos + len]);
+		self.pos += len;
+		Ok(len)
+	}
+}
+
+/// Writing half of an encrypted connection. Every frame uses up a nonce, so
+/// the peer can't decrypt anything sent after a frame it didn't fully get:
+/// once a frame fails to go out the writer is broken for good, rather than
+/// letting the caller retry on a timeout.
+pub struct NoiseWriter<W: Write> {
+	conn: W,
+	session: Arc<NoiseSession>,
+	broken: bool,
+}
+
+impl<W: Write> NoiseWriter<W> {
+	pub fn new(conn: W, session: Arc<NoiseSession>) -> NoiseWriter<W> {
+		NoiseWriter {
+			conn,
+			session,
+			broken: false,
+		}
+	}
+}
+
+impl<W: Write> Write for NoiseWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.broken {
+			return Err(io::Error::new(
+				io::ErrorKind::BrokenPipe,
+				"noise: a previous frame wasn't sent",
+			));
+		}
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		let len = std::cmp::min(buf.len(), MAX_PAYLOAD_LEN);
+		let frame = self.session.encrypt(&buf[..len])?;
+		if let Err(e) = self.conn.write_all(&frame) {
+			self.broken = true;
+			return Err(io::Error::new(
+				io::ErrorKind::BrokenPipe,
+				format!("noise: frame not sent: {}", e),
+			));
+		}
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.conn.flush()
+	}
+}
+
+/// Reading half of the connection, decrypting if it was upgraded to Noise.
+pub fn reader(
+	conn: &TcpStream,
+	session: &Option<Arc<NoiseSession>>,
+) -> io::Result<Box<dyn Read + Send>> {
+	let conn = conn.try_clone()?;
+	Ok(match session {
+		Some(session) => Box::new(NoiseReader::new(conn, session.clone())),
+		None => Box::new(conn),
+	})
+}
+
+/// Writing half of the connection, encrypting if it was upgraded to Noise.
+pub fn writer(
+	conn: &TcpStream,
+	session: &Option<Arc<NoiseSession>>,
+) -> io::Result<Box<dyn Write + Send>> {
+	let conn = conn.try_clone()?;
+	Ok(match session {
+		Some(session) => Box::new(NoiseWriter::new(conn, session.clone())),
+		None => Box::new(conn),
+	})
+}
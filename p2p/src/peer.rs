@@ -30,14 +30,18 @@ use crate::core::pow::Difficulty;
 use crate::core::ser::Writeable;
 use crate::core::{core, global};
 use crate::handshake::Handshake;
-use crate::msg::{self, BanReason, GetPeerAddrs, Locator, Msg, Ping, TxHashSetRequest, Type};
+use crate::msg::{
+	self, BanReason, CapabilitiesUpdate, GetCapabilities, GetHeadersByHeight, GetPeerAddrs, GetTip,
+	KeepAlive, Locator, Msg, PeerAddrs, Ping, ReachabilityCheck, RequestReachabilityCheck,
+	TxHashSetRequest, Type,
+};
+use crate::noise::NoiseSession;
 use crate::protocol::Protocol;
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	BlockAccept, Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo,
+	ReasonForBan, TxHashSetRead, HEADERS_BUSY_TIMEOUT, TXHASHSET_BUSY_TIMEOUT,
 };
 use chrono::prelude::{DateTime, Utc};
-use std::time::Instant;
 
 const MAX_TRACK_SIZE: usize = 30;
 const MAX_PEER_MSG_PER_MIN: u64 = 500;
@@ -64,6 +68,8 @@ pub struct Peer {
 	stop_handle: Mutex<conn::StopHandle>,
 	// Whether or not we requested a txhashset from this peer
 	state_sync_requested: Arc<AtomicBool>,
+	// Whether the connection was upgraded to Noise encryption
+	encrypted: bool,
 }
 
 impl fmt::Debug for Peer {
@@ -77,10 +83,14 @@ impl Peer {
 	fn new(
 		info: PeerInfo,
 		conn: TcpStream,
+		noise: Option<Arc<NoiseSession>>,
 		adapter: Arc<dyn NetAdapter>,
 		header_cache_size: u64,
 		server: Server,
 	) -> std::io::Result<Peer> {
+		let max_msg_size = server.config.max_message_size();
+		let read_budget = server.peers.read_budget();
+		let traffic_totals = server.peers.traffic_totals();
 		let state = Arc::new(RwLock::new(State::Connected));
 		let state_sync_requested = Arc::new(AtomicBool::new(false));
 		let tracking_adapter = TrackingAdapter::new(adapter);
@@ -91,8 +101,17 @@ impl Peer {
 			header_cache_size,
 			server,
 		);
-		let tracker = Arc::new(conn::Tracker::new());
-		let (sendh, stoph) = conn::listen(conn, info.version, tracker.clone(), handler)?;
+		let tracker = Arc::new(conn::Tracker::new(traffic_totals));
+		let encrypted = noise.is_some();
+		let (sendh, stoph) = conn::listen(
+			conn,
+			noise,
+			info.version,
+			max_msg_size,
+			read_budget,
+			tracker.clone(),
+			handler,
+		)?;
 		let send_handle = Mutex::new(sendh);
 		let stop_handle = Mutex::new(stoph);
 		Ok(Peer {
@@ -103,6 +122,7 @@ impl Peer {
 			send_handle,
 			stop_handle,
 			state_sync_requested,
+			encrypted,
 		})
 	}
 
@@ -116,9 +136,16 @@ impl Peer {
 		server: Server,
 	) -> Result<Peer, Error> {
 		debug!("accept: handshaking from {:?}", conn.peer_addr());
-		let info = hs.accept(capab, total_difficulty, &mut conn);
+		let info = hs.accept(capab, total_difficulty, &mut conn, &*adapter);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter, header_cache_size, server)?),
+			Ok((info, noise)) => Ok(Peer::new(
+				info,
+				conn,
+				noise,
+				adapter,
+				header_cache_size,
+				server,
+			)?),
 			Err(e) => {
 				debug!(
 					"accept: handshaking from {:?} failed with error: {:?}",
@@ -153,12 +180,27 @@ impl Peer {
 				self_addr,
 				&mut conn,
 				Some(peer_addr.clone().unwrap()),
+				&*adapter,
 			)
 		} else {
-			hs.initiate(capab, total_difficulty, self_addr, &mut conn, None)
+			hs.initiate(
+				capab,
+				total_difficulty,
+				self_addr,
+				&mut conn,
+				None,
+				&*adapter,
+			)
 		};
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter, header_cache_size, server)?),
+			Ok((info, noise)) => Ok(Peer::new(
+				info,
+				conn,
+				noise,
+				adapter,
+				header_cache_size,
+				server,
+			)?),
 			Err(e) => {
 				if peer_addr.is_some() {
 					debug!(
@@ -212,6 +254,11 @@ impl Peer {
 		false
 	}
 
+	/// Whether our connection with this peer is encrypted with Noise.
+	pub fn is_encrypted(&self) -> bool {
+		self.encrypted
+	}
+
 	/// Whether this peer is currently connected.
 	pub fn is_connected(&self) -> bool {
 		State::Connected == *self.state.read()
@@ -266,20 +313,43 @@ impl Peer {
 
 	/// Send a msg with given msg_type to our peer via the connection.
 	fn send<T: Writeable>(&self, msg: T, msg_type: Type) -> Result<(), Error> {
+		if !self.info.supports(msg_type) {
+			return Err(Error::UnsupportedMessage {
+				msg_type,
+				version: self.info.version,
+			});
+		}
 		let msg = Msg::new(msg_type, msg, self.info.version)?;
 		self.send_handle.lock().send(msg)
 	}
 
+	/// Number of messages queued for this peer that weren't written yet.
+	pub fn send_queue_len(&self) -> usize {
+		self.send_handle.lock().queued()
+	}
+
 	/// Send a ping to the remote peer, providing our local difficulty and
 	/// height
 	pub fn send_ping(&self, total_difficulty: Difficulty, height: u64) -> Result<(), Error> {
 		let ping_msg = Ping {
 			total_difficulty,
 			height,
+			prune_height: Some(self.tracking_adapter.prune_height()),
 		};
+		self.info.ping_sent();
 		self.send(ping_msg, msg::Type::Ping)
 	}
 
+	/// Send an empty message, only keeping the connection path warm. Peers on
+	/// an older protocol version don't know the message and are skipped.
+	pub fn send_keepalive(&self) -> Result<bool, Error> {
+		if !self.info.supports(msg::Type::KeepAlive) {
+			return Ok(false);
+		}
+		self.send(KeepAlive, msg::Type::KeepAlive)?;
+		Ok(true)
+	}
+
 	/// Send the ban reason before banning
 	pub fn send_ban_reason(&self, ban_reason: ReasonForBan) -> Result<(), Error> {
 		let ban_reason_msg = BanReason { ban_reason };
@@ -339,7 +409,7 @@ impl Peer {
 
 		if self
 			.info
-			.capabilities
+			.current_capabilities()
 			.contains(Capabilities::TX_KERNEL_HASH)
 		{
 			return self.send_tx_kernel_hash(kernel.hash());
@@ -367,9 +437,35 @@ impl Peer {
 		self.send(tx, msg::Type::StemTransaction)
 	}
 
-	/// Sends a request for block headers from the provided block locator
-	pub fn send_header_request(&self, locator: Vec<Hash>) -> Result<(), Error> {
-		self.send(&Locator { hashes: locator }, msg::Type::GetHeaders)
+	/// Sends a request for block headers from the provided block locator, at
+	/// most count of them from peers supporting it.
+	pub fn send_header_request(&self, locator: Vec<Hash>, count: u32) -> Result<(), Error> {
+		self.info.set_busy(HEADERS_BUSY_TIMEOUT);
+		self.info.header_sync_request_sent();
+		self.send(
+			&Locator {
+				hashes: locator,
+				count: Some(count),
+			},
+			msg::Type::GetHeaders,
+		)
+	}
+
+	/// Sends a request for the block headers of a range of heights. Only peers
+	/// on a recent enough protocol version understand it.
+	pub fn send_headers_by_height_request(&self, start: u64, count: u32) -> Result<(), Error> {
+		if !self.info.supports(msg::Type::GetHeadersByHeight) {
+			return Err(Error::UnsupportedMessage {
+				msg_type: msg::Type::GetHeadersByHeight,
+				version: self.info.version,
+			});
+		}
+		self.info.set_busy(HEADERS_BUSY_TIMEOUT);
+		self.info.header_sync_request_sent();
+		self.send(
+			&GetHeadersByHeight { start, count },
+			msg::Type::GetHeadersByHeight,
+		)
 	}
 
 	pub fn send_tx_request(&self, h: Hash) -> Result<(), Error> {
@@ -394,6 +490,71 @@ impl Peer {
 		self.send(&h, msg::Type::GetCompactBlock)
 	}
 
+	/// Tells the peer our capabilities changed. Peers on an older protocol
+	/// version don't know the message and are skipped.
+	pub fn send_capabilities_update(&self, capabilities: Capabilities) -> Result<bool, Error> {
+		if !self.info.supports(msg::Type::CapabilitiesUpdate) {
+			return Ok(false);
+		}
+		debug!("Send capabilities {:?} to {}", capabilities, self.info.addr);
+		self.send(
+			&CapabilitiesUpdate { capabilities },
+			msg::Type::CapabilitiesUpdate,
+		)?;
+		Ok(true)
+	}
+
+	/// Asks the peer for the capabilities it offers now, in case they changed
+	/// without us being told. Peers on an older protocol version couldn't
+	/// answer and are skipped.
+	pub fn send_capabilities_probe(&self) -> Result<bool, Error> {
+		if !self.info.supports(msg::Type::GetCapabilities) {
+			return Ok(false);
+		}
+		trace!("Asking {} for its capabilities", self.info.addr);
+		self.send(GetCapabilities, msg::Type::GetCapabilities)?;
+		Ok(true)
+	}
+
+	/// Asks the peer to check it can connect to our listener on the provided
+	/// port, identifying the check by the nonce. Peers on an older protocol
+	/// version couldn't answer and are skipped.
+	pub fn send_reachability_request(&self, port: u16, nonce: u64) -> Result<bool, Error> {
+		if !self.info.supports(msg::Type::RequestReachabilityCheck) {
+			return Ok(false);
+		}
+		trace!(
+			"Asking {} whether port {} is reachable",
+			self.info.addr,
+			port
+		);
+		self.send(
+			RequestReachabilityCheck { port, nonce },
+			msg::Type::RequestReachabilityCheck,
+		)?;
+		Ok(true)
+	}
+
+	/// Tells the peer whether we could connect back to it for the check with
+	/// the provided nonce.
+	pub fn send_reachability_check(&self, nonce: u64, reachable: bool) -> Result<(), Error> {
+		self.send(
+			ReachabilityCheck { nonce, reachable },
+			msg::Type::ReachabilityCheck,
+		)
+	}
+
+	/// Asks the peer for its best header, answered with a Tip. Peers on an
+	/// older protocol version couldn't answer and are skipped.
+	pub fn send_tip_request(&self) -> Result<bool, Error> {
+		if !self.info.supports(msg::Type::GetTip) {
+			return Ok(false);
+		}
+		trace!("Asking {} for its tip", self.info.addr);
+		self.send(GetTip, msg::Type::GetTip)?;
+		Ok(true)
+	}
+
 	pub fn send_peer_request(&self, capab: Capabilities) -> Result<(), Error> {
 		trace!("Asking {} for more peers {:?}", self.info.addr, capab);
 		self.send(
@@ -404,12 +565,39 @@ impl Peer {
 		)
 	}
 
+	/// Pass on addresses of other peers without being asked, onion addresses
+	/// are left out for peers not supporting them.
+	pub fn send_peer_addrs(&self, peers: &[PeerAddr]) -> Result<(), Error> {
+		let tor = self
+			.info
+			.current_capabilities()
+			.contains(Capabilities::TOR_ADDRESS);
+		let peers: Vec<PeerAddr> = peers
+			.iter()
+			.filter(|addr| match addr {
+				PeerAddr::Onion(_) => tor,
+				PeerAddr::Ip(_) => true,
+			})
+			.cloned()
+			.collect();
+		if peers.is_empty() {
+			return Ok(());
+		}
+		trace!(
+			"Passing {} peer addrs on to {}",
+			peers.len(),
+			self.info.addr
+		);
+		self.send(&PeerAddrs { peers }, msg::Type::PeerAddrs)
+	}
+
 	pub fn send_txhashset_request(&self, height: u64, hash: Hash) -> Result<(), Error> {
 		info!(
 			"Asking {} for txhashset archive at {} {}.",
 			self.info.addr, height, hash
 		);
 		self.state_sync_requested.store(true, Ordering::Relaxed);
+		self.info.set_busy(TXHASHSET_BUSY_TIMEOUT);
 		self.send(
 			&TxHashSetRequest { hash, height },
 			msg::Type::TxHashSetRequest,
@@ -486,6 +674,14 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.get_transaction(kernel_hash)
 	}
 
+	fn min_relay_fee(&self) -> u64 {
+		self.adapter.min_relay_fee()
+	}
+
+	fn prune_height(&self) -> u64 {
+		self.adapter.prune_height()
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
@@ -515,7 +711,7 @@ impl ChainAdapter for TrackingAdapter {
 		b: core::Block,
 		peer_info: &PeerInfo,
 		opts: chain::Options,
-	) -> Result<bool, chain::Error> {
+	) -> Result<BlockAccept, chain::Error> {
 		let bh = b.hash();
 		self.push_recv(bh);
 
@@ -543,20 +739,12 @@ impl ChainAdapter for TrackingAdapter {
 	) -> Result<bool, chain::Error> {
 		trace!("peer = {:?}, set header sync = false", peer_info.addr);
 
-		let mut last_header = peer_info.last_header.lock().unwrap();
-		*last_header = Instant::now();
-		let mut last_header_reset = peer_info.last_header_reset.lock().unwrap();
-		*last_header_reset = Instant::now();
-
-		let val = peer_info
-			.header_sync_requested
-			.fetch_sub(1, Ordering::Relaxed);
-		// check for wrap
-		if val > 16 {
-			debug!("setting a sync to 0 for peer = {}", peer_info.addr);
-			peer_info.header_sync_requested.store(0, Ordering::Relaxed);
-		}
-		trace!("header sync for {} is {}", peer_info.addr, val);
+		peer_info.header_sync_received();
+		trace!(
+			"header sync for {} is {}",
+			peer_info.addr,
+			peer_info.header_sync_outstanding()
+		);
 		self.push_recv(bh.hash());
 		self.adapter.header_received(bh, peer_info)
 	}
@@ -572,20 +760,12 @@ impl ChainAdapter for TrackingAdapter {
 			peer_info.addr
 		);
 
-		let mut last_header = peer_info.last_header.lock().unwrap();
-		*last_header = Instant::now();
-
-		let mut last_header_reset = peer_info.last_header_reset.lock().unwrap();
-		*last_header_reset = Instant::now();
-
-		let val = peer_info
-			.header_sync_requested
-			.fetch_sub(1, Ordering::Relaxed);
-		// check for wrap
-		if val > 16 {
-			peer_info.header_sync_requested.store(0, Ordering::Relaxed);
-		}
-		trace!("header sync for {} is {}", peer_info.addr, val);
+		peer_info.header_sync_received();
+		trace!(
+			"header sync for {} is {}",
+			peer_info.addr,
+			peer_info.header_sync_outstanding()
+		);
 		self.adapter
 			.headers_received(bh, peer_info, header_sync_cache_size)
 	}
@@ -603,6 +783,14 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.locate_headers(locator)
 	}
 
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		self.adapter.headers_by_height(start, count)
+	}
+
 	fn get_block(&self, h: Hash, peer_info: &PeerInfo) -> Option<core::Block> {
 		self.adapter.get_block(h, peer_info)
 	}
@@ -619,6 +807,22 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.txhashset_receive_ready()
 	}
 
+	fn in_maintenance(&self) -> bool {
+		self.adapter.in_maintenance()
+	}
+
+	fn sync_status(&self) -> chain::SyncStatus {
+		self.adapter.sync_status()
+	}
+
+	fn knows_header(&self, hash: Hash) -> bool {
+		self.adapter.knows_header(hash)
+	}
+
+	fn load_factor(&self) -> f64 {
+		self.adapter.load_factor()
+	}
+
 	fn txhashset_write(
 		&self,
 		h: Hash,
@@ -645,6 +849,22 @@ impl ChainAdapter for TrackingAdapter {
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.adapter.get_tmpfile_pathname(tmpfile_name)
 	}
+
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		self.adapter.tmp_dir_free_space()
+	}
+
+	fn missing_block_ranges(&self, from: u64, to: u64) -> Vec<(u64, u64)> {
+		self.adapter.missing_block_ranges(from, to)
+	}
+
+	fn common_ancestor_height(&self, locator: &[Hash]) -> Option<u64> {
+		self.adapter.common_ancestor_height(locator)
+	}
+
+	fn header_head(&self) -> Option<chain::Tip> {
+		self.adapter.header_head()
+	}
 }
 
 impl NetAdapter for TrackingAdapter {
@@ -663,4 +883,16 @@ impl NetAdapter for TrackingAdapter {
 	fn is_banned(&self, addr: PeerAddr) -> bool {
 		self.adapter.is_banned(addr)
 	}
+
+	fn peer_ser_error(&self, addr: PeerAddr) {
+		self.adapter.peer_ser_error(addr)
+	}
+
+	fn txhashset_archive_acceptable(&self, height: u64) -> bool {
+		self.adapter.txhashset_archive_acceptable(height)
+	}
+
+	fn request_declined(&self, addr: PeerAddr, msg_type: msg::Type, reason: msg::DeclineReason) {
+		self.adapter.request_declined(addr, msg_type, reason)
+	}
 }
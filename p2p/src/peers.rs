@@ -12,25 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::util::RwLock;
-use std::collections::HashMap;
-use std::fs::File;
-use std::path::PathBuf;
+use crate::conn::TrafficTotals;
+use crate::log_throttle::LogThrottle;
+use crate::read_budget::ReadBudget;
+use crate::util::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{FromEntropy, Rng, SeedableRng};
 
 use crate::chain;
 use crate::core::core;
 use crate::core::core::hash::{Hash, Hashed};
 use crate::core::global;
 use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::msg::{self, DeclineReason};
 use crate::peer::Peer;
-use crate::store::{PeerData, PeerStore, State};
+use crate::store::{ExportedPeer, PeerData, PeerStore, State, SubnetBan};
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead, MAX_PEER_ADDRS,
+	distinct_nodes, estimate_block_difficulty, gossip_addrs_for, inbound_refusal_probability,
+	limit_onion_dials, rank_by_sync_priority, select_regossip, select_stable, select_useful,
+	BlockAccept, Capabilities, CapabilityRefusals, ChainAdapter, DuplicateConnectionPolicy, Error,
+	HeaderTimestamp, IpPrefix, NetAdapter, P2PConfig, PeerAddr, PeerInfo, PeerSetSnapshot,
+	PeerSnapshot, ReasonForBan, SeedSource, SilentPeerPolicy, SyncPriorityCtx, SyncPriorityWeights,
+	TxHashSetRead, DIFFICULTY_ESTIMATE_WINDOW, MAX_PEER_ADDRS,
 };
 use chrono::prelude::*;
 use chrono::Duration;
@@ -38,12 +51,155 @@ use grin_util::StopState;
 
 const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
+/// Number of recent connects and disconnects kept to compute the churn rate
+const CHURN_EVENTS_CAP: usize = 1024;
+
+/// Number of received peer addresses kept waiting to be re-gossiped, the
+/// oldest are dropped beyond that
+const REGOSSIP_PENDING_CAP: usize = 4 * MAX_PEER_ADDRS as usize;
+
+/// Number of disconnect times kept, the expired ones get pruned beyond that
+/// and the oldest dropped if none expired
+const DISCONNECTED_CAP: usize = 1024;
+
+/// Number of peers without a listener we remember, the ones we saw the
+/// longest ago are forgotten beyond that
+const UNREACHABLE_CAP: usize = 1024;
+
+/// Number of peers we count malformed messages for, the counts started the
+/// longest ago are dropped beyond that
+const SER_ERRORS_CAP: usize = 1024;
+
+/// Number of peers we count bad compact blocks for, the counts started the
+/// longest ago are dropped beyond that
+const BAD_COMPACT_BLOCKS_CAP: usize = 1024;
+
+/// Number of peers we count bans of for the ban log, the ones banned the
+/// longest ago are dropped beyond that
+const BAN_COUNTS_CAP: usize = 1024;
+
+/// Number of addresses we remember the source of, the ones noted the longest
+/// ago are forgotten beyond that
+const SEED_SOURCES_CAP: usize = 1024;
+
+/// Number of peers we remember the last peer addresses we sent, the ones we
+/// answered the longest ago are forgotten beyond that
+const PEER_ADDRS_SENT_CAP: usize = 1024;
+
+/// Once synced, how many blocks the median outbound peer may get ahead of us
+/// before we consider ourselves behind again
+const SYNCED_TOLERANCE_BLOCKS: u64 = 5;
+
+/// Number of blocks we track retries for, the counts are reset beyond that
+const BLOCK_RETRIES_CAP: usize = 1024;
+
+/// Number of blocks kept in memory to be processed again, newer ones aren't
+/// retried beyond that
+const DEFERRED_BLOCKS_CAP: usize = 32;
+
+/// How long we wait before processing again a block the chain failed on for
+/// a transient reason
+const BLOCK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Block the chain failed to process for a transient reason, kept to be
+/// processed again once it's due.
+struct DeferredBlock {
+	block: core::Block,
+	peer_info: PeerInfo,
+	opts: chain::Options,
+	due: Instant,
+}
+
+/// Make room for the provided peer in a map holding at most cap of them,
+/// dropping the entry with the oldest time if the peer is new to a full map.
+fn evict_oldest<V>(
+	map: &mut HashMap<PeerAddr, V>,
+	addr: &PeerAddr,
+	cap: usize,
+	time: impl Fn(&V) -> DateTime<Utc>,
+) {
+	if map.len() < cap || map.contains_key(addr) {
+		return;
+	}
+	let oldest = map
+		.iter()
+		.min_by_key(|(_, v)| time(v))
+		.map(|(oldest, _)| oldest.clone());
+	if let Some(oldest) = oldest {
+		map.remove(&oldest);
+	}
+}
+
 pub struct Peers {
 	pub adapter: Arc<dyn ChainAdapter>,
 	store: PeerStore,
 	peers: RwLock<HashMap<PeerAddr, Arc<Peer>>>,
 	config: P2PConfig,
 	stop_state: Arc<StopState>,
+	// malformed messages received per peer, with the start of the counting window
+	ser_errors: RwLock<HashMap<PeerAddr, (u32, DateTime<Utc>)>>,
+	// bad compact blocks received per peer, with the start of the counting window
+	bad_compact_blocks: RwLock<HashMap<PeerAddr, (u32, DateTime<Utc>)>>,
+	// blocks the chain failed on for a transient reason, with how many times
+	// we processed them again
+	block_retries: Mutex<HashMap<Hash, u32>>,
+	// blocks waiting to be processed again
+	deferred_blocks: Mutex<Vec<DeferredBlock>>,
+	// consecutive header batches per peer on a fork losing against ours
+	minority_forks: RwLock<HashMap<PeerAddr, u32>>,
+	// peers we won't talk to for a while, with the end of their cooldown
+	parked: RwLock<HashMap<PeerAddr, DateTime<Utc>>>,
+	// times of our most recent peer connects and disconnects, oldest first
+	churn_events: Mutex<VecDeque<DateTime<Utc>>>,
+	// peers that sent us a txhashset which proved corrupt, during state sync
+	corrupt_txhashsets: RwLock<HashSet<PeerAddr>>,
+	// per connected peer, requests relying on capabilities it advertised and how
+	// many it declined
+	capability_refusals: RwLock<HashMap<PeerAddr, HashMap<Capabilities, CapabilityRefusals>>>,
+	// number of bans we issued since we started
+	bans_issued: AtomicU64,
+	// with a ban log, number of times we banned each peer since we started and
+	// when we last did
+	ban_counts: RwLock<HashMap<PeerAddr, (u32, DateTime<Utc>)>>,
+	// received peer addresses not re-gossiped yet, oldest first
+	regossip_pending: Mutex<VecDeque<PeerAddr>>,
+	// addresses we successfully connected out to at least once
+	connected_once: RwLock<HashSet<PeerAddr>>,
+	// peers that connected to us without advertising a listener, with when
+	// they last did
+	unreachable: RwLock<HashMap<PeerAddr, DateTime<Utc>>>,
+	// when we last got disconnected from each peer
+	disconnected_at: RwLock<HashMap<PeerAddr, DateTime<Utc>>>,
+	// all our random peer selection draws from this, seeded from config if set
+	rng: Mutex<StdRng>,
+	// last answer of is_synced, to only leave the synced state once clearly behind
+	synced: AtomicBool,
+	// orphan blocks we recently handed to the chain with when, oldest first
+	known_orphans: Mutex<VecDeque<(Hash, DateTime<Utc>)>>,
+	// since when we're short of outbound peers, and whether we warned about it
+	outbound_deficit_since: RwLock<Option<DateTime<Utc>>>,
+	outbound_deficit_warned: AtomicBool,
+	// when we last dialed an onion peer
+	last_onion_dial: Mutex<Option<DateTime<Utc>>>,
+	// onion dials held back by the pacing, to be made first next time
+	deferred_onion_dials: Mutex<VecDeque<PeerAddr>>,
+	// onion addresses in our store, the ones we connected to the longest ago
+	// (or learned the earliest) first
+	onion_peers: Mutex<VecDeque<PeerAddr>>,
+	// collapses repeated error log lines about the same peer
+	log_throttle: LogThrottle,
+	// message bodies being read from all our peers
+	read_budget: Arc<ReadBudget>,
+	// bytes sent to and received from all our peers since we started
+	traffic_totals: Arc<TrafficTotals>,
+	// where we got the addresses we dial from, when not through gossip, with
+	// when we noted it
+	seed_sources: RwLock<HashMap<PeerAddr, (SeedSource, DateTime<Utc>)>>,
+	// last peer addresses we sent each peer, with when and the capabilities it
+	// asked for, served again to its repeated requests whatever the connection
+	peer_addrs_sent: RwLock<HashMap<PeerAddr, (DateTime<Utc>, Capabilities, Vec<PeerAddr>)>>,
+	// the subnet bans of our store, checked on every connection
+	subnet_bans: RwLock<HashMap<IpPrefix, SubnetBan>>,
 }
 
 impl Peers {
@@ -53,15 +209,74 @@ impl Peers {
 		config: P2PConfig,
 		stop_state: Arc<StopState>,
 	) -> Peers {
+		let rng = match config.rng_seed {
+			Some(seed) => StdRng::seed_from_u64(seed),
+			None => StdRng::from_entropy(),
+		};
+		let subnet_bans = match store.subnet_bans() {
+			Ok(bans) => bans.into_iter().map(|ban| (ban.prefix, ban)).collect(),
+			Err(e) => {
+				error!("Peers::new: failed to read subnet bans: {:?}", e);
+				HashMap::new()
+			}
+		};
+		let onion_peers = match store.all_peers() {
+			Ok(mut peers) => {
+				peers.retain(|p| match p.addr {
+					PeerAddr::Onion(_) => true,
+					PeerAddr::Ip(_) => false,
+				});
+				peers.sort_by_key(|p| p.last_connected);
+				peers.into_iter().map(|p| p.addr).collect()
+			}
+			Err(e) => {
+				error!("Peers::new: failed to read peers: {:?}", e);
+				VecDeque::new()
+			}
+		};
 		Peers {
 			adapter,
 			store,
 			config,
 			peers: RwLock::new(HashMap::new()),
 			stop_state,
+			ser_errors: RwLock::new(HashMap::new()),
+			bad_compact_blocks: RwLock::new(HashMap::new()),
+			block_retries: Mutex::new(HashMap::new()),
+			deferred_blocks: Mutex::new(vec![]),
+			minority_forks: RwLock::new(HashMap::new()),
+			parked: RwLock::new(HashMap::new()),
+			churn_events: Mutex::new(VecDeque::with_capacity(CHURN_EVENTS_CAP)),
+			corrupt_txhashsets: RwLock::new(HashSet::new()),
+			capability_refusals: RwLock::new(HashMap::new()),
+			bans_issued: AtomicU64::new(0),
+			ban_counts: RwLock::new(HashMap::new()),
+			regossip_pending: Mutex::new(VecDeque::new()),
+			connected_once: RwLock::new(HashSet::new()),
+			unreachable: RwLock::new(HashMap::new()),
+			disconnected_at: RwLock::new(HashMap::new()),
+			rng: Mutex::new(rng),
+			synced: AtomicBool::new(false),
+			known_orphans: Mutex::new(VecDeque::new()),
+			outbound_deficit_since: RwLock::new(None),
+			outbound_deficit_warned: AtomicBool::new(false),
+			last_onion_dial: Mutex::new(None),
+			deferred_onion_dials: Mutex::new(VecDeque::new()),
+			onion_peers: Mutex::new(onion_peers),
+			log_throttle: LogThrottle::default(),
+			read_budget: Arc::new(ReadBudget::new(config.max_inbound_buffer_bytes())),
+			traffic_totals: Arc::new(TrafficTotals::default()),
+			seed_sources: RwLock::new(HashMap::new()),
+			peer_addrs_sent: RwLock::new(HashMap::new()),
+			subnet_bans: RwLock::new(subnet_bans),
 		}
 	}
 
+	/// Shuffle the provided items using our (possibly seeded) rng.
+	pub fn shuffle<T>(&self, items: &mut [T]) {
+		items.shuffle(&mut *self.rng.lock());
+	}
+
 	/// Adds the peer to our internal peer mapping. Note that the peer is still
 	/// returned so the server can run it.
 	pub fn add_connected(&self, peer: Arc<Peer>) -> Result<(), Error> {
@@ -73,6 +288,33 @@ impl Peers {
 		if self.is_banned(peer.info.addr.clone()) {
 			return Err(Error::Banned);
 		}
+
+		// A peer opening another inbound connection while we already have one
+		// from it. Loopback addresses keep their port in PeerAddr equality, so
+		// they never collide here (local tests run several peers per ip).
+		if peer.info.is_inbound() && !peer.info.addr.is_loopback() {
+			if let Some(existing) = peers.get(&peer.info.addr) {
+				match self.config.duplicate_inbound_policy() {
+					DuplicateConnectionPolicy::ReplaceOld
+						if existing.info.is_inbound() && !existing.info.is_busy() =>
+					{
+						debug!(
+							"add_connected: replacing previous connection from {}",
+							peer.info.addr
+						);
+						existing.stop();
+					}
+					_ => {
+						debug!(
+							"add_connected: already connected to {}, refusing new connection",
+							peer.info.addr
+						);
+						return Err(Error::DuplicateConnection);
+					}
+				}
+			}
+		}
+
 		let peer_data = PeerData {
 			addr: peer.info.addr.clone(),
 			capabilities: peer.info.capabilities,
@@ -81,14 +323,240 @@ impl Peers {
 			last_banned: 0,
 			ban_reason: ReasonForBan::None,
 			last_connected: Utc::now().timestamp(),
+			score: self.stored_score(&peer.info.addr),
 		};
 		debug!("Saving newly connected peer {}.", peer_data.addr);
 		self.save_peer(&peer_data)?;
-		peers.insert(peer_data.addr, peer);
+		if peer.info.is_outbound() {
+			self.connected_once.write().insert(peer_data.addr.clone());
+			peer.info.set_seed_source(self.seed_source(&peer_data.addr));
+		}
+		if peer.info.inbound_reachable {
+			self.unreachable.write().remove(&peer_data.addr);
+		} else {
+			let mut unreachable = self.unreachable.write();
+			evict_oldest(&mut unreachable, &peer_data.addr, UNREACHABLE_CAP, |at| *at);
+			unreachable.insert(peer_data.addr.clone(), Utc::now());
+		}
+		if peers.insert(peer_data.addr, peer).is_some() {
+			// replaced a previous connection
+			self.record_churn();
+		}
+		self.record_churn();
 
 		Ok(())
 	}
 
+	/// Record a peer connecting or disconnecting, the oldest event is dropped
+	/// once we hold CHURN_EVENTS_CAP of them.
+	fn record_churn(&self) {
+		let mut events = self.churn_events.lock();
+		if events.len() >= CHURN_EVENTS_CAP {
+			events.pop_front();
+		}
+		events.push_back(Utc::now());
+	}
+
+	// A peer got disconnected, remember when so we don't redial it too soon,
+	// log the errors we held back about it and forget its capability refusals.
+	fn record_disconnect(&self, addr: &PeerAddr) {
+		self.record_churn();
+		self.capability_refusals.write().remove(addr);
+		let now = Utc::now();
+		for line in self.log_throttle.flush_peer(addr, now) {
+			debug!("peers: {}", line);
+		}
+		let mut disconnected = self.disconnected_at.write();
+		if disconnected.len() >= DISCONNECTED_CAP {
+			let interval = Duration::seconds(std::cmp::max(
+				self.config.peer_min_reconnect_interval(),
+				self.config.peer_min_preferred_reconnect_interval(),
+			));
+			disconnected.retain(|_, at| *at + interval > now);
+		}
+		evict_oldest(&mut disconnected, addr, DISCONNECTED_CAP, |at| *at);
+		disconnected.insert(addr.clone(), now);
+	}
+
+	/// Whether we may dial the peer again at the provided time, some minimum
+	/// interval after we last got disconnected from it (a shorter one for our
+	/// preferred peers). Avoids flapping connections to unstable peers.
+	pub fn reconnect_allowed(&self, addr: &PeerAddr, now: DateTime<Utc>) -> bool {
+		let preferred = match self.config.peers_preferred {
+			Some(ref preferred) => preferred.peers.contains(addr),
+			None => false,
+		};
+		let interval = if preferred {
+			self.config.peer_min_preferred_reconnect_interval()
+		} else {
+			self.config.peer_min_reconnect_interval()
+		};
+		match self.disconnected_at.read().get(addr) {
+			Some(at) => *at + Duration::seconds(interval) <= now,
+			None => true,
+		}
+	}
+
+	/// The addresses to dial out of the provided ones, the onion dials we
+	/// deferred first, with at most max_onion_peers onion addresses among
+	/// them.
+	pub fn dial_candidates(&self, mut addrs: Vec<PeerAddr>) -> Vec<PeerAddr> {
+		let mut candidates: Vec<_> = self.deferred_onion_dials.lock().drain(..).collect();
+		addrs.retain(|addr| !candidates.contains(addr));
+		candidates.append(&mut addrs);
+		limit_onion_dials(candidates, self.config.max_onion_peers())
+	}
+
+	/// Whether we may dial the provided address at the provided time. Onion
+	/// dials are paced, at least onion_dial_interval apart, and a permitted
+	/// one counts as made. The others are deferred, dial_candidates returns
+	/// them again. Clearnet dials are always allowed.
+	pub fn dial_allowed(&self, addr: &PeerAddr, now: DateTime<Utc>) -> bool {
+		if let PeerAddr::Ip(_) = addr {
+			return true;
+		}
+		let mut last = self.last_onion_dial.lock();
+		if let Some(at) = *last {
+			if at + Duration::seconds(self.config.onion_dial_interval()) > now {
+				let mut deferred = self.deferred_onion_dials.lock();
+				if !deferred.contains(addr) && deferred.len() < self.config.max_onion_peers() {
+					deferred.push_back(addr.clone());
+				}
+				return false;
+			}
+		}
+		*last = Some(now);
+		true
+	}
+
+	// Keeps track of an onion address we just stored. Past max_onion_peers
+	// the one we connected to the longest ago gets forgotten, unless we're
+	// connected to it or it's banned.
+	fn onion_peer_stored(&self, addr: PeerAddr) {
+		let mut onions = self.onion_peers.lock();
+		onions.push_back(addr);
+		if onions.len() <= self.config.max_onion_peers() {
+			return;
+		}
+		let oldest = onions.iter().position(|addr| {
+			self.get_connected_peer(addr.clone()).is_none() && !self.is_banned(addr.clone())
+		});
+		if let Some(addr) = oldest.and_then(|i| onions.remove(i)) {
+			debug!(
+				"onion_peer_stored: {} onion peers, forgetting {}",
+				onions.len(),
+				addr
+			);
+			if let Err(e) = self.store.delete_peer(addr) {
+				error!("onion_peer_stored: failed to delete peer: {:?}", e);
+			}
+		}
+	}
+
+	/// Peer connects and disconnects per minute over the provided window,
+	/// constant reconnects hint at network trouble (a misconfigured firewall
+	/// for example). Only the last CHURN_EVENTS_CAP events are accounted for.
+	pub fn churn_rate(&self, window: Duration) -> f64 {
+		let window_ms = window.num_milliseconds();
+		if window_ms <= 0 {
+			return 0.0;
+		}
+		let since = Utc::now() - window;
+		let count = self
+			.churn_events
+			.lock()
+			.iter()
+			.rev()
+			.take_while(|t| **t > since)
+			.count();
+		count as f64 * 60_000.0 / window_ms as f64
+	}
+
+	/// Send a keepalive to the connected peers whose direction has them
+	/// enabled, that understand them and that didn't get one for a keepalive
+	/// interval. Returns the number of keepalives sent.
+	pub fn send_keepalives(&self, now: Instant) -> usize {
+		let interval = self.config.keepalive_interval();
+		let mut sent = 0;
+		for peer in self.connected_peers() {
+			if !self.config.keepalive_enabled(peer.info.direction) {
+				continue;
+			}
+			let last_keepalive = peer.info.live_info.read().last_keepalive;
+			if now.saturating_duration_since(last_keepalive) < interval {
+				continue;
+			}
+			match peer.send_keepalive() {
+				Ok(true) => {
+					peer.info.live_info.write().last_keepalive = now;
+					sent += 1;
+				}
+				Ok(false) => {}
+				Err(e) => debug!(
+					"send_keepalives: failed to send to {}: {:?}",
+					peer.info.addr, e
+				),
+			}
+		}
+		sent
+	}
+
+	/// Ask the connected peers we haven't asked for capabilities_probe_interval
+	/// for the capabilities they offer now, their answer updates what we know
+	/// of them. Returns the number of peers asked.
+	pub fn probe_capabilities(&self, now: Instant) -> usize {
+		let interval = match self.config.capabilities_probe_interval() {
+			Some(interval) => interval,
+			None => return 0,
+		};
+		let mut sent = 0;
+		for peer in self.connected_peers() {
+			let last_probe = peer.info.live_info.read().last_capabilities_probe;
+			if now.saturating_duration_since(last_probe) < interval {
+				continue;
+			}
+			match peer.send_capabilities_probe() {
+				Ok(probed) => {
+					// older peers cannot answer, only try them again next interval
+					peer.info.live_info.write().last_capabilities_probe = now;
+					if probed {
+						sent += 1;
+					}
+				}
+				Err(e) => debug!(
+					"probe_capabilities: failed to send to {}: {:?}",
+					peer.info.addr, e
+				),
+			}
+		}
+		sent
+	}
+
+	/// Pass on a batch of the peer addresses we received to all our connected
+	/// peers, at most peer_addrs_regossip_cap of them and only those we
+	/// could connect to ourselves. Returns the addresses passed on.
+	pub fn regossip_peer_addrs(&self) -> Vec<PeerAddr> {
+		let addrs = select_regossip(
+			&mut self.regossip_pending.lock(),
+			&self.connected_once.read(),
+			self.config.peer_addrs_regossip_cap(),
+		);
+		if addrs.is_empty() {
+			return addrs;
+		}
+		debug!("regossip_peer_addrs: passing on {} addrs", addrs.len());
+		for peer in self.connected_peers() {
+			let addrs = gossip_addrs_for(&peer.info.addr, addrs.clone(), self.config.bridge_mode());
+			if let Err(e) = peer.send_peer_addrs(&addrs) {
+				debug!(
+					"regossip_peer_addrs: failed to send to {}: {:?}",
+					peer.info.addr, e
+				);
+			}
+		}
+		addrs
+	}
+
 	/// Add a peer as banned to block future connections, usually due to failed
 	/// handshake
 	pub fn add_banned(&self, addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
@@ -100,9 +568,48 @@ impl Peers {
 			last_banned: Utc::now().timestamp(),
 			ban_reason,
 			last_connected: Utc::now().timestamp(),
+			score: self.stored_score(&addr),
 		};
 		debug!("Banning peer {}, ban_reason={:?}", addr, ban_reason);
-		self.save_peer(&peer_data)
+		self.save_peer(&peer_data)?;
+		self.bans_issued.fetch_add(1, Ordering::Relaxed);
+		self.log_ban(&addr, ban_reason);
+		Ok(())
+	}
+
+	/// Append the ban to the ban log, if one is configured. A ban is never
+	/// failed because it couldn't be logged.
+	fn log_ban(&self, addr: &PeerAddr, ban_reason: ReasonForBan) {
+		let path = match self.config.ban_log_path {
+			Some(ref path) => path,
+			None => return,
+		};
+		let ban_count = {
+			let now = Utc::now();
+			let mut ban_counts = self.ban_counts.write();
+			evict_oldest(&mut ban_counts, addr, BAN_COUNTS_CAP, |c| c.1);
+			let entry = ban_counts.entry(addr.clone()).or_insert((0, now));
+			*entry = (entry.0 + 1, now);
+			entry.0
+		};
+		let line = format!(
+			"{} addr={} reason={} ban_count={}\n",
+			Utc::now().to_rfc3339(),
+			addr,
+			ban_reason.as_str(),
+			ban_count
+		);
+		let res = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.and_then(|mut file| {
+				file.write_all(line.as_bytes())?;
+				file.flush()
+			});
+		if let Err(e) = res {
+			warn!("log_ban: failed to write to {}: {}", path.display(), e);
+		}
 	}
 
 	/// Check if this peer address is already known (are we already connected to it)?
@@ -134,7 +641,11 @@ impl Peers {
 			.filter(|p| p.is_connected())
 			.cloned()
 			.collect::<Vec<_>>();
-		res.shuffle(&mut thread_rng());
+		if self.config.rng_seed.is_some() {
+			// map order is random, make it stable so the seeded shuffle is reproducible
+			res.sort_by_key(|p| p.info.addr.as_key());
+		}
+		self.shuffle(&mut res);
 		res
 	}
 
@@ -154,6 +665,35 @@ impl Peers {
 			.collect()
 	}
 
+	/// Whether we're caught up with the network: we have outbound peers and our
+	/// total difficulty is at least the median of theirs. Once synced we stay
+	/// so until the median peer gets more than a few blocks ahead, blocks
+	/// propagating at the tip would otherwise make us flap.
+	pub fn is_synced(&self) -> bool {
+		let mut peers: Vec<(Difficulty, u64)> = self
+			.outgoing_connected_peers()
+			.iter()
+			.map(|p| (p.info.total_difficulty(), p.info.height()))
+			.collect();
+		if peers.is_empty() {
+			self.synced.store(false, Ordering::Relaxed);
+			return false;
+		}
+		let (height, total_difficulty) =
+			match (self.adapter.total_height(), self.adapter.total_difficulty()) {
+				(Ok(height), Ok(total_difficulty)) => (height, total_difficulty),
+				_ => return self.synced.load(Ordering::Relaxed),
+			};
+
+		peers.sort();
+		let (median_difficulty, median_height) = peers[peers.len() / 2];
+		let synced = total_difficulty >= median_difficulty
+			|| (self.synced.load(Ordering::Relaxed)
+				&& median_height <= height + SYNCED_TOLERANCE_BLOCKS);
+		self.synced.store(synced, Ordering::Relaxed);
+		synced
+	}
+
 	/// Get a peer we're connected to by address.
 	pub fn get_connected_peer(&self, addr: PeerAddr) -> Option<Arc<Peer>> {
 		if self.stop_state.is_stopped() {
@@ -183,11 +723,33 @@ impl Peers {
 		self.outgoing_connected_peers().len() as u32
 	}
 
-	/// Number of inbound peers currently connected to.
+	/// Number of inbound peers currently connected to. Limits are enforced on
+	/// this raw count, the node nonce being the peer's own pick.
 	pub fn peer_inbound_count(&self) -> u32 {
 		self.incoming_connected_peers().len() as u32
 	}
 
+	/// Number of distinct nodes among our connected peers, the ones sharing a
+	/// node nonce counting once. Only meant for diversity stats.
+	pub fn distinct_node_count(&self) -> u32 {
+		distinct_nodes(self.connected_peers().iter().map(|p| &p.info)) as u32
+	}
+
+	/// Advisory estimate of the current per-block network difficulty, from
+	/// what our outbound peers advertise past a header of ours
+	/// DIFFICULTY_ESTIMATE_WINDOW blocks back. None while none of them is
+	/// past it.
+	pub fn estimated_network_difficulty(&self) -> Option<Difficulty> {
+		let head = self.adapter.header_head()?;
+		let height = head.height.saturating_sub(DIFFICULTY_ESTIMATE_WINDOW);
+		let header = self.adapter.headers_by_height(height, 1).ok()?.pop()?;
+		let peers = self.outgoing_connected_peers();
+		estimate_block_difficulty(
+			peers.iter().map(|p| &p.info),
+			(header.height, header.total_difficulty()),
+		)
+	}
+
 	// Return vec of connected peers that currently advertise more work
 	// (total_difficulty) than we do.
 	pub fn more_work_peers(&self) -> Result<Vec<Arc<Peer>>, chain::Error> {
@@ -203,7 +765,7 @@ impl Peers {
 			.filter(|x| x.info.total_difficulty() > total_difficulty)
 			.collect::<Vec<_>>();
 
-		max_peers.shuffle(&mut thread_rng());
+		self.shuffle(&mut max_peers);
 		Ok(max_peers)
 	}
 
@@ -234,6 +796,137 @@ impl Peers {
 		}
 	}
 
+	/// Number of connected peers per negotiated protocol version, to follow
+	/// how far the network got upgrading (and when dropping support for an
+	/// old version becomes safe).
+	pub fn version_distribution(&self) -> HashMap<ProtocolVersion, u32> {
+		let mut distribution = HashMap::new();
+		for peer in self.connected_peers() {
+			*distribution.entry(peer.info.version).or_insert(0) += 1;
+		}
+		distribution
+	}
+
+	/// Record a compact block from the peer we failed to reconstruct or
+	/// validate, banning the peer once it sent bad_compact_block_ban_threshold
+	/// of them within bad_compact_block_window. Returns whether it got banned.
+	pub fn bad_compact_block(&self, addr: PeerAddr) -> bool {
+		let now = Utc::now();
+		let window = Duration::seconds(self.config.bad_compact_block_window());
+		let count = {
+			let mut bad_compact_blocks = self.bad_compact_blocks.write();
+			evict_oldest(
+				&mut bad_compact_blocks,
+				&addr,
+				BAD_COMPACT_BLOCKS_CAP,
+				|e| e.1,
+			);
+			let entry = bad_compact_blocks.entry(addr.clone()).or_insert((0, now));
+			if now - entry.1 > window {
+				*entry = (0, now);
+			}
+			entry.0 += 1;
+			entry.0
+		};
+
+		if count < self.config.bad_compact_block_ban_threshold() {
+			debug!(
+				"bad_compact_block: bad compact block #{} from peer {} in the current window",
+				count, addr
+			);
+			return false;
+		}
+		debug!(
+			"bad_compact_block: peer {} sent {} bad compact blocks, banning",
+			addr, count
+		);
+		self.bad_compact_blocks.write().remove(&addr);
+		if let Err(e) = self.ban_peer(addr, ReasonForBan::BadCompactBlock) {
+			debug!("bad_compact_block: failed to ban peer: {:?}", e);
+		}
+		true
+	}
+
+	/// Whether we run as a headers-only relay, never requesting full blocks,
+	/// txhashsets or transactions.
+	pub fn headers_only(&self) -> bool {
+		self.config.headers_only()
+	}
+
+	/// Ask our connected peers for the addresses of other peers, skipping the
+	/// ones not advertising PEER_LIST. Returns how many peers we asked.
+	pub fn request_peer_addrs(&self) -> usize {
+		let mut asked = 0;
+		for peer in self.connected_peers() {
+			if !peer
+				.info
+				.current_capabilities()
+				.contains(Capabilities::PEER_LIST)
+			{
+				trace!("request_peer_addrs: {} has no PEER_LIST", peer.info.addr);
+				continue;
+			}
+			if peer.send_peer_request(Capabilities::PEER_LIST).is_ok() {
+				asked += 1;
+			}
+		}
+		asked
+	}
+
+	/// Bytes sent to and received from all our peers since we started,
+	/// counted by each of their connections.
+	pub fn traffic_totals(&self) -> Arc<TrafficTotals> {
+		self.traffic_totals.clone()
+	}
+
+	/// Rate limiter for the log lines about errors of our peers.
+	pub fn log_throttle(&self) -> &LogThrottle {
+		&self.log_throttle
+	}
+
+	/// Budget shared by all our connections for the message bodies they read.
+	pub fn read_budget(&self) -> Arc<ReadBudget> {
+		self.read_budget.clone()
+	}
+
+	/// Number of bans we issued since we started.
+	pub fn ban_count(&self) -> u64 {
+		self.bans_issued.load(Ordering::Relaxed)
+	}
+
+	/// Oldest protocol version among connected peers, None without peers.
+	/// Newer message types are only safe to use broadly once it's recent enough.
+	pub fn min_connected_version(&self) -> Option<ProtocolVersion> {
+		self.connected_peers().iter().map(|p| p.info.version).min()
+	}
+
+	/// Newest protocol version among connected peers, None without peers.
+	pub fn max_connected_version(&self) -> Option<ProtocolVersion> {
+		self.connected_peers().iter().map(|p| p.info.version).max()
+	}
+
+	/// Capture the currently connected peers and their key attributes, to be
+	/// compared with a later snapshot.
+	pub fn snapshot(&self) -> PeerSetSnapshot {
+		let peers = self
+			.connected_peers()
+			.iter()
+			.map(|p| {
+				let peer = PeerSnapshot {
+					capabilities: p.info.current_capabilities(),
+					user_agent: p.info.user_agent.clone(),
+					version: p.info.version,
+					direction: p.info.direction,
+				};
+				(p.info.addr.clone(), peer)
+			})
+			.collect();
+		PeerSetSnapshot {
+			taken_at: Utc::now(),
+			peers,
+		}
+	}
+
 	/// Return vec of connected peers that currently have the most worked
 	/// branch, showing the highest total difficulty.
 	pub fn most_work_peers(&self) -> Vec<Arc<Peer>> {
@@ -242,52 +935,595 @@ impl Peers {
 			return vec![];
 		}
 
-		let max_total_difficulty = match peers.iter().map(|x| x.info.total_difficulty()).max() {
-			Some(v) => v,
-			None => return vec![],
+		let max_total_difficulty = match peers.iter().map(|x| x.info.total_difficulty()).max() {
+			Some(v) => v,
+			None => return vec![],
+		};
+
+		let mut max_peers = peers
+			.into_iter()
+			.filter(|x| x.info.total_difficulty() == max_total_difficulty)
+			.collect::<Vec<_>>();
+
+		self.shuffle(&mut max_peers);
+		max_peers
+	}
+
+	/// Highest total difficulty advertised by at least min_corroborating_peers
+	/// distinct nodes among our connected peers, None if we don't have that
+	/// many. Connections sharing a node nonce or a host only count once.
+	pub fn corroborated_difficulty(&self) -> Option<Difficulty> {
+		let mut peers = self.connected_peers();
+		peers.sort_unstable_by(|a, b| b.info.total_difficulty().cmp(&a.info.total_difficulty()));
+
+		let mut nonces = HashSet::new();
+		let mut hosts = HashSet::new();
+		let mut difficulties = vec![];
+		for p in peers {
+			let host = match &p.info.addr {
+				PeerAddr::Ip(addr) => addr.ip().to_string(),
+				PeerAddr::Onion(onion) => onion.clone(),
+			};
+			let new_node = p.info.node_nonce.map_or(true, |n| nonces.insert(n));
+			if hosts.insert(host) && new_node {
+				difficulties.push(p.info.total_difficulty());
+			}
+		}
+		difficulties
+			.get(self.config.min_corroborating_peers() as usize - 1)
+			.cloned()
+	}
+
+	/// Number of connected peers we're still waiting on for headers.
+	pub fn header_sync_peer_count(&self) -> u32 {
+		self.connected_peers()
+			.iter()
+			.filter(|p| p.info.header_sync_outstanding() > 0)
+			.count() as u32
+	}
+
+	/// Narrow the peers we'd like headers from down to the ones we may ask
+	/// right now, at most max_header_sync_peers peers have header requests
+	/// outstanding at any time. Peers we're already waiting on can be asked
+	/// again, the other candidates queue until a slot frees up.
+	pub fn header_sync_candidates(&self, candidates: Vec<Arc<Peer>>) -> Vec<Arc<Peer>> {
+		let max = match self.config.max_header_sync_peers {
+			Some(max) => max,
+			None => return candidates,
+		};
+		let mut free = max.saturating_sub(self.header_sync_peer_count());
+		candidates
+			.into_iter()
+			.filter(|p| {
+				if p.info.header_sync_outstanding() > 0 {
+					true
+				} else if free > 0 {
+					free -= 1;
+					true
+				} else {
+					false
+				}
+			})
+			.collect()
+	}
+
+	/// Returns single peer with the most worked branch, showing the highest
+	/// total difficulty. Random among those, preferring the ones advertising
+	/// the longest uptime.
+	pub fn most_work_peer(&self) -> Option<Arc<Peer>> {
+		self.select_sync_peer(&self.most_work_peers())
+	}
+
+	/// Peers we may sync from: the most worked ones, or all peers with more
+	/// work than us by decreasing sync priority when sync_priority_weights is
+	/// set. Silent peers are left out unless the policy is to ignore them.
+	pub fn sync_peers(&self) -> Vec<Arc<Peer>> {
+		let mut peers = match self.config.sync_priority_weights {
+			Some(weights) => {
+				let more_work = self.more_work_peers().unwrap_or_else(|_| vec![]);
+				self.rank_sync_peers(more_work, weights)
+			}
+			None => self.most_work_peers(),
+		};
+		if self.config.silent_peer_policy() != SilentPeerPolicy::Ignore {
+			let grace = self.config.silent_peer_grace();
+			let now = Utc::now();
+			peers.retain(|p| !p.info.is_silent(grace, now));
+		}
+		peers
+	}
+
+	/// Connected peers that never reported their difficulty nor height within
+	/// the silent_peer_grace of connecting.
+	pub fn silent_peers(&self, now: DateTime<Utc>) -> Vec<PeerAddr> {
+		let grace = self.config.silent_peer_grace();
+		self.connected_peers()
+			.iter()
+			.filter(|p| p.info.is_silent(grace, now))
+			.map(|p| p.info.addr.clone())
+			.collect()
+	}
+
+	/// Sort peers by decreasing sync priority with the provided weights, their
+	/// reputation being the share of capability requests they didn't refuse.
+	pub fn rank_sync_peers(
+		&self,
+		mut peers: Vec<Arc<Peer>>,
+		weights: SyncPriorityWeights,
+	) -> Vec<Arc<Peer>> {
+		let mut ctx = SyncPriorityCtx::new(weights, peers.iter().map(|p| &p.info));
+		ctx.refusal_rates = self
+			.capability_refusals
+			.read()
+			.iter()
+			.map(|(addr, counts)| {
+				let total = counts
+					.values()
+					.fold(CapabilityRefusals::default(), |acc, c| CapabilityRefusals {
+						requested: acc.requested + c.requested,
+						refused: acc.refused + c.refused,
+					});
+				(addr.clone(), total.refusal_rate())
+			})
+			.collect();
+		rank_by_sync_priority(&mut peers, &ctx, |p| &p.info);
+		peers
+	}
+
+	/// Pick the peer to sync from among candidates, the one with the highest
+	/// sync priority when sync_priority_weights is set. Otherwise candidates
+	/// are of equal difficulty and it's the one that served us valid data
+	/// most recently if prefer_useful_peers is set, or the most stable
+	/// looking one.
+	pub fn select_sync_peer(&self, candidates: &[Arc<Peer>]) -> Option<Arc<Peer>> {
+		if let Some(weights) = self.config.sync_priority_weights {
+			return self
+				.rank_sync_peers(candidates.to_vec(), weights)
+				.into_iter()
+				.next();
+		}
+		if self.config.prefer_useful_peers() {
+			select_useful(candidates, |p| &p.info).cloned()
+		} else {
+			select_stable(candidates, |p| &p.info).cloned()
+		}
+	}
+
+	/// Peer to request the txhashset from during state sync, the most worked
+	/// one that didn't already send us a corrupt archive. None once we've
+	/// downloaded it again max_txhashset_redownloads times, we give up then.
+	pub fn txhashset_peer(&self) -> Option<Arc<Peer>> {
+		let corrupt = self.corrupt_txhashsets.read();
+		if corrupt.len() > self.config.max_txhashset_redownloads() as usize {
+			return None;
+		}
+		let peers = self
+			.most_work_peers()
+			.into_iter()
+			.filter(|p| !corrupt.contains(&p.info.addr))
+			.collect::<Vec<_>>();
+		self.select_sync_peer(&peers)
+	}
+
+	pub fn is_banned(&self, peer_addr: PeerAddr) -> bool {
+		if self.config.is_never_ban(&peer_addr) {
+			return false;
+		}
+		if self.is_subnet_banned(&peer_addr) {
+			return true;
+		}
+		if let Ok(peer) = self.store.get_peer(peer_addr) {
+			return peer.flags == State::Banned;
+		}
+		false
+	}
+
+	/// Whether the address is in a subnet we currently ban.
+	pub fn is_subnet_banned(&self, peer_addr: &PeerAddr) -> bool {
+		let ip = match peer_addr {
+			PeerAddr::Ip(addr) => addr.ip(),
+			PeerAddr::Onion(_) => return false,
+		};
+		let now = Utc::now().timestamp();
+		self.subnet_bans
+			.read()
+			.values()
+			.any(|ban| !ban.expired(now) && ban.prefix.contains(&ip))
+	}
+
+	/// Bans all the provided subnets (from a threat feed for example) for
+	/// `duration` seconds, disconnecting the connected peers in them. Subnets
+	/// already banned keep their ban, so importing the same list again changes
+	/// nothing. Returns the number of subnets newly banned.
+	pub fn import_ban_list(
+		&self,
+		ranges: &[IpPrefix],
+		reason: ReasonForBan,
+		duration: i64,
+	) -> Result<usize, Error> {
+		let now = Utc::now().timestamp();
+		let mut bans: Vec<SubnetBan> = vec![];
+		for prefix in ranges {
+			if bans.iter().any(|ban| ban.prefix == *prefix) {
+				continue;
+			}
+			if let Some(ban) = self.subnet_bans.read().get(prefix) {
+				if !ban.expired(now) {
+					continue;
+				}
+			}
+			bans.push(SubnetBan {
+				prefix: *prefix,
+				ban_reason: reason,
+				banned_at: now,
+				duration,
+			});
+		}
+		if bans.is_empty() {
+			return Ok(0);
+		}
+		self.store.save_subnet_bans(&bans)?;
+		self.subnet_bans
+			.write()
+			.extend(bans.iter().map(|ban| (ban.prefix, ban.clone())));
+		info!(
+			"import_ban_list: banned {} new subnets, ban_reason {:?}",
+			bans.len(),
+			reason
+		);
+
+		for peer in self.connected_peers() {
+			let ip = match peer.info.addr {
+				PeerAddr::Ip(addr) => addr.ip(),
+				PeerAddr::Onion(_) => continue,
+			};
+			if self.config.is_never_ban(&peer.info.addr)
+				|| !bans.iter().any(|ban| ban.prefix.contains(&ip))
+			{
+				continue;
+			}
+			debug!(
+				"import_ban_list: disconnecting {} in a banned subnet",
+				peer.info.addr
+			);
+			let _ = peer.send_ban_reason(reason);
+			peer.stop();
+			if let Some(mut peers) = self.peers.try_write_for(LOCK_TIMEOUT) {
+				if peers.remove(&peer.info.addr).is_some() {
+					self.record_disconnect(&peer.info.addr);
+				}
+			}
+		}
+		Ok(bans.len())
+	}
+
+	/// All the subnet bans we have, expired ones included until pruned.
+	pub fn subnet_bans(&self) -> Result<Vec<SubnetBan>, Error> {
+		Ok(self.subnet_bans.read().values().cloned().collect())
+	}
+	/// Ban a peer, disconnecting it if we're currently connected
+	pub fn ban_peer(&self, peer_addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
+		if self.config.is_never_ban(&peer_addr) {
+			info!(
+				"Skipping ban of peer {}, ban_reason {:?}, peer is on the never ban list",
+				peer_addr, ban_reason
+			);
+			return Ok(());
+		}
+		self.update_state(peer_addr.clone(), State::Banned)?;
+		self.log_ban(&peer_addr, ban_reason);
+
+		match self.get_connected_peer(peer_addr.clone()) {
+			Some(peer) => {
+				info!("Banning peer {}, ban_reason {:?}", peer_addr, ban_reason);
+				// setting peer status will get it removed at the next clean_peer
+				peer.send_ban_reason(ban_reason)?;
+				peer.set_banned();
+				peer.stop();
+				let mut peers = self.peers.try_write_for(LOCK_TIMEOUT).ok_or_else(|| {
+					error!("ban_peer: failed to get peers lock");
+					Error::PeerException("ban_peer: failed to get peers lock".to_string())
+				})?;
+				if peers.remove(&peer.info.addr).is_some() {
+					self.record_disconnect(&peer.info.addr);
+				}
+				Ok(())
+			}
+			None => Err(Error::PeerNotFound),
+		}
+	}
+
+	/// Disconnect a connected peer without banning it, we may connect to it
+	/// again later.
+	pub fn disconnect_peer(&self, peer_addr: PeerAddr) -> Result<(), Error> {
+		let peer = match self.get_connected_peer(peer_addr.clone()) {
+			Some(peer) => peer,
+			None => return Err(Error::PeerNotFound),
+		};
+		info!("Disconnecting peer {}", peer_addr);
+		peer.stop();
+		let mut peers = self.peers.try_write_for(LOCK_TIMEOUT).ok_or_else(|| {
+			error!("disconnect_peer: failed to get peers lock");
+			Error::PeerException("disconnect_peer: failed to get peers lock".to_string())
+		})?;
+		if peers.remove(&peer.info.addr).is_some() {
+			self.record_disconnect(&peer.info.addr);
+		}
+		Ok(())
+	}
+
+	/// Park a peer for the provided cooldown, disconnecting it. We neither
+	/// connect to a parked peer nor accept its connections until the cooldown
+	/// is over, but unlike a ban it's not persisted.
+	pub fn park_peer(&self, peer_addr: PeerAddr, cooldown: Duration) {
+		info!("Parking peer {} for {}s", peer_addr, cooldown.num_seconds());
+		self.parked
+			.write()
+			.insert(peer_addr.clone(), Utc::now() + cooldown);
+
+		if let Some(peer) = self.get_connected_peer(peer_addr) {
+			peer.stop();
+			match self.peers.try_write_for(LOCK_TIMEOUT) {
+				Some(mut peers) => {
+					if peers.remove(&peer.info.addr).is_some() {
+						self.record_disconnect(&peer.info.addr);
+					}
+				}
+				None => error!("park_peer: failed to get peers lock"),
+			}
+		}
+	}
+
+	/// Whether the peer is parked and its cooldown isn't over yet.
+	pub fn is_parked(&self, peer_addr: PeerAddr) -> bool {
+		let mut parked = self.parked.write();
+		match parked.get(&peer_addr) {
+			Some(until) if *until > Utc::now() => true,
+			Some(_) => {
+				parked.remove(&peer_addr);
+				false
+			}
+			None => false,
+		}
+	}
+
+	/// Record a request sent to the peer relying on a capability it
+	/// advertised. Requests to peers not advertising it aren't counted.
+	pub fn capability_requested(&self, peer_addr: PeerAddr, capability: Capabilities) {
+		if !self.advertises(&peer_addr, capability) {
+			return;
+		}
+		let mut refusals = self.capability_refusals.write();
+		let entry = refusals
+			.entry(peer_addr)
+			.or_insert_with(HashMap::new)
+			.entry(capability)
+			.or_insert_with(CapabilityRefusals::default);
+		entry.requested += 1;
+	}
+
+	/// Record the peer declining (or never answering) a request relying on a
+	/// capability it advertised. Once flagged the peer is parked, if so
+	/// configured.
+	pub fn capability_refused(&self, peer_addr: PeerAddr, capability: Capabilities) {
+		if !self.advertises(&peer_addr, capability) {
+			return;
+		}
+		let counts = {
+			let mut refusals = self.capability_refusals.write();
+			let entry = refusals
+				.entry(peer_addr.clone())
+				.or_insert_with(HashMap::new)
+				.entry(capability)
+				.or_insert_with(CapabilityRefusals::default);
+			entry.refused += 1;
+			entry.requested = entry.requested.max(entry.refused);
+			*entry
+		};
+		if !self.refusals_flagged(&counts) {
+			debug!(
+				"capability_refused: peer {} declined {:?} ({} of {})",
+				peer_addr, capability, counts.refused, counts.requested
+			);
+			return;
+		}
+		warn!(
+			"capability_refused: peer {} advertises {:?} but declined {} of {} requests",
+			peer_addr, capability, counts.refused, counts.requested
+		);
+		if self.config.penalize_capability_refusals() {
+			self.park_peer(peer_addr, Duration::seconds(self.config.ban_window()));
+		}
+	}
+
+	/// Requests and refusals per advertised capability for the peer, for
+	/// diagnostics.
+	pub fn capability_refusals(
+		&self,
+		peer_addr: PeerAddr,
+	) -> HashMap<Capabilities, CapabilityRefusals> {
+		self.capability_refusals
+			.read()
+			.get(&peer_addr)
+			.cloned()
+			.unwrap_or_default()
+	}
+
+	/// Peers declining most requests for a capability they advertise, with
+	/// the capability and their counts.
+	pub fn refusing_peers(&self) -> Vec<(PeerAddr, Capabilities, CapabilityRefusals)> {
+		let refusals = self.capability_refusals.read();
+		let mut flagged = vec![];
+		for (addr, capabilities) in refusals.iter() {
+			for (capability, counts) in capabilities.iter() {
+				if self.refusals_flagged(counts) {
+					flagged.push((addr.clone(), *capability, *counts));
+				}
+			}
+		}
+		flagged
+	}
+
+	fn refusals_flagged(&self, counts: &CapabilityRefusals) -> bool {
+		counts.refused >= self.config.capability_refusal_threshold() && counts.refusal_rate() > 0.5
+	}
+
+	// Whether the connected peer currently advertises the capability.
+	fn advertises(&self, peer_addr: &PeerAddr, capability: Capabilities) -> bool {
+		match self.get_connected_peer(peer_addr.clone()) {
+			Some(peer) => peer.info.current_capabilities().contains(capability),
+			None => false,
+		}
+	}
+
+	/// Whether the block is an orphan we handed to the chain within the orphan
+	/// tracking window, forgetting the expired ones.
+	fn is_known_orphan(&self, hash: &Hash) -> bool {
+		let since = Utc::now() - Duration::seconds(self.config.orphan_tracking_window());
+		let mut orphans = self.known_orphans.lock();
+		while let Some(&(_, at)) = orphans.front() {
+			if at > since {
+				break;
+			}
+			orphans.pop_front();
+		}
+		orphans.iter().any(|(h, _)| h == hash)
+	}
+
+	/// Remember an orphan block, the oldest one is forgotten once we track
+	/// max_tracked_orphans of them.
+	fn orphan_received(&self, hash: Hash) {
+		let max = self.config.max_tracked_orphans();
+		if max == 0 {
+			return;
+		}
+		let mut orphans = self.known_orphans.lock();
+		while orphans.len() >= max {
+			orphans.pop_front();
+		}
+		orphans.push_back((hash, Utc::now()));
+	}
+
+	/// Keep a block the chain failed to process for a transient reason to
+	/// process it again shortly, up to block_error_retries times.
+	fn defer_block(&self, block: core::Block, peer_info: &PeerInfo, opts: chain::Options) {
+		let hash = block.hash();
+		let attempts = {
+			let mut retries = self.block_retries.lock();
+			if retries.len() >= BLOCK_RETRIES_CAP && !retries.contains_key(&hash) {
+				retries.clear();
+			}
+			let attempts = retries.entry(hash).or_insert(0);
+			*attempts += 1;
+			*attempts
+		};
+		if attempts > self.config.block_error_retries() {
+			debug!(
+				"Giving up on block {} from {} after {} transient errors",
+				hash,
+				peer_info.addr,
+				attempts - 1
+			);
+			self.block_retries.lock().remove(&hash);
+			return;
+		}
+		let mut deferred = self.deferred_blocks.lock();
+		if deferred.len() >= DEFERRED_BLOCKS_CAP {
+			debug!("Too many deferred blocks, not retrying block {}", hash);
+			return;
+		}
+		deferred.push(DeferredBlock {
+			block,
+			peer_info: peer_info.clone(),
+			opts,
+			due: Instant::now() + BLOCK_RETRY_DELAY,
+		});
+	}
+
+	/// Process again the blocks the chain failed on for a transient reason
+	/// that are due by now, from the copy we kept rather than downloading
+	/// them again. Returns how many we processed.
+	pub fn retry_deferred_blocks(&self, now: Instant) -> usize {
+		let due = {
+			let mut deferred = self.deferred_blocks.lock();
+			let (due, later): (Vec<_>, Vec<_>) = deferred.drain(..).partition(|d| d.due <= now);
+			*deferred = later;
+			due
 		};
-
-		let mut max_peers = peers
-			.into_iter()
-			.filter(|x| x.info.total_difficulty() == max_total_difficulty)
-			.collect::<Vec<_>>();
-
-		max_peers.shuffle(&mut thread_rng());
-		max_peers
+		let count = due.len();
+		for d in due {
+			let hash = d.block.hash();
+			if let Err(e) = self.block_received(d.block, &d.peer_info, d.opts) {
+				debug!("Processing block {} again failed: {:?}", hash, e);
+			}
+		}
+		count
 	}
 
-	/// Returns single random peer with the most worked branch, showing the
-	/// highest total difficulty.
-	pub fn most_work_peer(&self) -> Option<Arc<Peer>> {
-		self.most_work_peers().pop()
+	/// Whether these headers put the peer on a fork losing against ours, a
+	/// header at or above our height with less cumulative difficulty can't be
+	/// on our chain.
+	fn on_losing_fork(&self, headers: &[core::BlockHeader]) -> bool {
+		let last = match headers.last() {
+			Some(header) => header,
+			None => return false,
+		};
+		match (self.adapter.total_height(), self.adapter.total_difficulty()) {
+			(Ok(height), Ok(total_difficulty)) => {
+				last.height >= height && last.total_difficulty() < total_difficulty
+			}
+			_ => false,
+		}
 	}
 
-	pub fn is_banned(&self, peer_addr: PeerAddr) -> bool {
-		if let Ok(peer) = self.store.get_peer(peer_addr) {
-			return peer.flags == State::Banned;
+	/// Number of headers of the batch we take from the peer. Past
+	/// max_uncorroborated_headers beyond our validated tip they're only taken
+	/// when another connected peer claims at least as much total difficulty as
+	/// they carry, so a single peer can't feed us an endless fake chain.
+	fn corroborated_headers(&self, headers: &[core::BlockHeader], peer_addr: &PeerAddr) -> usize {
+		let last = match headers.last() {
+			Some(header) => header,
+			None => return 0,
+		};
+		let tip = match self.adapter.total_height() {
+			Ok(height) => height,
+			Err(_) => return headers.len(),
+		};
+		let limit = tip.saturating_add(self.config.max_uncorroborated_headers());
+		if last.height <= limit {
+			return headers.len();
+		}
+		let corroborated = self.connected_peers().iter().any(|p| {
+			p.info.addr != *peer_addr && p.info.total_difficulty() >= last.total_difficulty()
+		});
+		if corroborated {
+			headers.len()
+		} else {
+			headers.iter().take_while(|h| h.height <= limit).count()
 		}
-		false
 	}
-	/// Ban a peer, disconnecting it if we're currently connected
-	pub fn ban_peer(&self, peer_addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
-		self.update_state(peer_addr.clone(), State::Banned)?;
 
-		match self.get_connected_peer(peer_addr.clone()) {
-			Some(peer) => {
-				info!("Banning peer {}, ban_reason {:?}", peer_addr, ban_reason);
-				// setting peer status will get it removed at the next clean_peer
-				peer.send_ban_reason(ban_reason)?;
-				peer.set_banned();
-				peer.stop();
-				let mut peers = self.peers.try_write_for(LOCK_TIMEOUT).ok_or_else(|| {
-					error!("ban_peer: failed to get peers lock");
-					Error::PeerException("ban_peer: failed to get peers lock".to_string())
-				})?;
-				peers.remove(&peer.info.addr);
-				Ok(())
-			}
-			None => Err(Error::PeerNotFound),
+	/// Count a batch of valid headers from a peer on a losing fork, parking the
+	/// peer once it persists (an honest peer we just can't agree with).
+	fn minority_fork_received(&self, peer_addr: PeerAddr) {
+		let count = {
+			let mut forks = self.minority_forks.write();
+			let count = forks.entry(peer_addr.clone()).or_insert(0);
+			*count += 1;
+			*count
+		};
+		if count < self.config.minority_fork_threshold() {
+			debug!(
+				"minority_fork_received: peer {} on a losing fork ({} in a row)",
+				peer_addr, count
+			);
+			return;
 		}
+		self.minority_forks.write().remove(&peer_addr);
+		self.park_peer(
+			peer_addr,
+			Duration::seconds(self.config.minority_fork_cooldown()),
+		);
 	}
 
 	/// Unban a peer, checks if it exists and banned then unban
@@ -326,17 +1562,41 @@ impl Peers {
 						}
 					};
 					p.stop();
-					peers.remove(&p.info.addr);
+					if peers.remove(&p.info.addr).is_some() {
+						self.record_disconnect(&p.info.addr);
+					}
 				}
 			}
 		}
 		count
 	}
 
+	/// Broadcast a block (or its header) skipping, this round, peers whose
+	/// send queue is already backed up. Sends never block, but a slow peer
+	/// would otherwise still delay propagation to the fast ones, it will get
+	/// the block through sync anyway.
+	fn broadcast_block<F>(&self, obj_name: &str, inner: F) -> u32
+	where
+		F: Fn(&Peer) -> Result<bool, Error>,
+	{
+		let max_queue = self.config.max_relay_queue();
+		self.broadcast(obj_name, |p| {
+			let queued = p.send_queue_len();
+			if queued > max_queue {
+				debug!(
+					"broadcast_block: skipping {} for {:?}, {} messages queued",
+					obj_name, p.info.addr, queued
+				);
+				return Ok(false);
+			}
+			inner(p)
+		})
+	}
+
 	/// Broadcast a compact block to all our connected peers.
 	/// This is only used when initially broadcasting a newly mined block.
 	pub fn broadcast_compact_block(&self, b: &core::CompactBlock) {
-		let count = self.broadcast("compact block", |p| p.send_compact_block(b));
+		let count = self.broadcast_block("compact block", |p| p.send_compact_block(b));
 		debug!(
 			"broadcast_compact_block: {}, {} at {}, to {} peers, done.",
 			b.hash(),
@@ -350,7 +1610,7 @@ impl Peers {
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the header.
 	pub fn broadcast_header(&self, bh: &core::BlockHeader) {
-		let count = self.broadcast("header", |p| p.send_header(bh));
+		let count = self.broadcast_block("header", |p| p.send_header(bh));
 		debug!(
 			"broadcast_header: {}, {} at {}, to {} peers, done.",
 			bh.hash(),
@@ -364,6 +1624,16 @@ impl Peers {
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
 	pub fn broadcast_transaction(&self, tx: &core::Transaction) {
+		let min_relay_fee = self.adapter.min_relay_fee();
+		if tx.fee() < min_relay_fee {
+			debug!(
+				"broadcast_transaction: {} fee {} below min relay fee {}, not relaying.",
+				tx.hash(),
+				tx.fee(),
+				min_relay_fee,
+			);
+			return;
+		}
 		let count = self.broadcast("transaction", |p| p.send_transaction(tx));
 		debug!(
 			"broadcast_transaction: {} to {} peers, done.",
@@ -372,6 +1642,16 @@ impl Peers {
 		);
 	}
 
+	/// Tells all our connected peers (the ones that understand it) about a
+	/// change of our capabilities.
+	pub fn broadcast_capabilities(&self, capabilities: Capabilities) {
+		let count = self.broadcast("capabilities", |p| p.send_capabilities_update(capabilities));
+		debug!(
+			"broadcast_capabilities: {:?} to {} peers, done.",
+			capabilities, count,
+		);
+	}
+
 	/// Ping all our connected peers. Always automatically expects a pong back
 	/// or disconnects. This acts as a liveness test.
 	pub fn check_all(&self, total_difficulty: Difficulty, height: u64) {
@@ -386,7 +1666,9 @@ impl Peers {
 					}
 				};
 				p.stop();
-				peers.remove(&p.info.addr);
+				if peers.remove(&p.info.addr).is_some() {
+					self.record_disconnect(&p.info.addr);
+				}
 			}
 		}
 	}
@@ -402,9 +1684,53 @@ impl Peers {
 		}
 	}
 
+	/// Write every peer in our store to a portable json file, so another node
+	/// can import them. Returns the number of peers written.
+	pub fn export_json(&self, path: &Path) -> Result<usize, Error> {
+		let peers: Vec<ExportedPeer> = self.store.all_peers()?.iter().map(From::from).collect();
+		let file = File::create(path)?;
+		serde_json::to_writer_pretty(file, &peers)
+			.map_err(|e| Error::Internal(format!("failed to export peers: {}", e)))?;
+		Ok(peers.len())
+	}
+
+	/// Load the peers from a json file written by export_json. Merging keeps
+	/// the peers we already know as they are and only adds new ones, otherwise
+	/// our store is replaced by the file content. Entries with an invalid
+	/// address are skipped. Returns the number of peers imported.
+	pub fn import_json(&self, path: &Path, merge: bool) -> Result<usize, Error> {
+		let file = File::open(path)?;
+		let peers: Vec<ExportedPeer> = serde_json::from_reader(file)
+			.map_err(|e| Error::Internal(format!("failed to import peers: {}", e)))?;
+
+		if !merge {
+			self.store.delete_peers(|_| true)?;
+		}
+		let mut imported = 0;
+		for peer in peers {
+			let peer_data = match peer.to_peer_data() {
+				Some(peer_data) => peer_data,
+				None => {
+					debug!("import_json: skipping invalid address {}", peer.addr);
+					continue;
+				}
+			};
+			if merge && self.store.exists_peer(peer_data.addr.clone())? {
+				continue;
+			}
+			self.store.save_peer(&peer_data)?;
+			imported += 1;
+		}
+		info!("import_json: imported {} peers from {:?}", imported, path);
+		Ok(imported)
+	}
+
 	/// Find peers in store (not necessarily connected) and return their data
 	pub fn find_peers(&self, state: State, cap: Capabilities, count: usize) -> Vec<PeerData> {
-		match self.store.find_peers(state, cap, count) {
+		match self
+			.store
+			.find_peers(state, cap, count, &mut *self.rng.lock())
+		{
 			Ok(peers) => peers,
 			Err(e) => {
 				error!("failed to find peers: {:?}", e);
@@ -428,6 +1754,20 @@ impl Peers {
 		self.store.save_peer(p).map_err(From::from)
 	}
 
+	// The score we keep for the peer, so saving it anew doesn't reset it. 0 if
+	// we don't know the peer.
+	fn stored_score(&self, addr: &PeerAddr) -> i32 {
+		match self.store.get_peer(addr.clone()) {
+			Ok(peer) => peer.score,
+			Err(_) => 0,
+		}
+	}
+
+	/// Deletes a peer from store
+	pub fn delete_peer(&self, peer_addr: PeerAddr) -> Result<(), Error> {
+		self.store.delete_peer(peer_addr).map_err(From::from)
+	}
+
 	/// Updates the state of a peer in store
 	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
 		self.store
@@ -445,6 +1785,13 @@ impl Peers {
 		preferred_peers: &[PeerAddr],
 	) {
 		let mut rm = vec![];
+		let now = Utc::now();
+		let policy = self.config.silent_peer_policy();
+
+		for line in self.log_throttle.flush(now) {
+			debug!("clean_peers: {}", line);
+		}
+		let grace = self.config.silent_peer_grace();
 
 		// build a list of peers to be cleaned up
 		{
@@ -471,6 +1818,9 @@ impl Peers {
 					}
 					let _ = self.update_state(peer.info.addr.clone(), State::Banned);
 					rm.push(peer.info.addr.clone());
+				} else if policy == SilentPeerPolicy::Evict && peer.info.is_silent(grace, now) {
+					debug!("clean_peers {:?}, silent peer", peer.info.addr);
+					rm.push(peer.info.addr.clone());
 				} else {
 					let (stuck, diff) = peer.is_stuck();
 					match self.adapter.total_difficulty() {
@@ -487,14 +1837,22 @@ impl Peers {
 			}
 		}
 
+		// silent peers are the first to go when we're over our limits
+		let by_silence = |mut peers: Vec<Arc<Peer>>| {
+			if policy != SilentPeerPolicy::Ignore {
+				peers.sort_by_key(|p| !p.info.is_silent(grace, now));
+			}
+			peers
+		};
+
 		// check here to make sure we don't have too many outgoing connections
+		// (peers busy serving us data are kept until done)
 		let excess_outgoing_count =
 			(self.peer_outbound_count() as usize).saturating_sub(max_outbound_count);
 		if excess_outgoing_count > 0 {
-			let mut addrs: Vec<_> = self
-				.outgoing_connected_peers()
+			let mut addrs: Vec<_> = by_silence(self.outgoing_connected_peers())
 				.iter()
-				.filter(|x| !preferred_peers.contains(&x.info.addr))
+				.filter(|x| !preferred_peers.contains(&x.info.addr) && !x.info.is_busy())
 				.take(excess_outgoing_count)
 				.map(|x| x.info.addr.clone())
 				.collect();
@@ -505,10 +1863,9 @@ impl Peers {
 		let excess_incoming_count =
 			(self.peer_inbound_count() as usize).saturating_sub(max_inbound_count);
 		if excess_incoming_count > 0 {
-			let mut addrs: Vec<_> = self
-				.incoming_connected_peers()
+			let mut addrs: Vec<_> = by_silence(self.incoming_connected_peers())
 				.iter()
-				.filter(|x| !preferred_peers.contains(&x.info.addr))
+				.filter(|x| !preferred_peers.contains(&x.info.addr) && !x.info.is_busy())
 				.take(excess_incoming_count)
 				.map(|x| x.info.addr.clone())
 				.collect();
@@ -525,13 +1882,19 @@ impl Peers {
 				}
 			};
 			for addr in rm {
-				let _ = peers.get(&addr).map(|peer| peer.stop());
-				peers.remove(&addr);
+				if let Some(peer) = peers.remove(&addr) {
+					peer.stop();
+					self.record_disconnect(&addr);
+				}
 			}
 		}
 	}
 
 	pub fn stop(&self) {
+		if let Err(e) = self.save_anchors() {
+			error!("failed to save anchor peers: {:?}", e);
+		}
+
 		let mut peers = self.peers.write();
 		for peer in peers.values() {
 			peer.stop();
@@ -541,11 +1904,175 @@ impl Peers {
 		}
 	}
 
+	/// Persist our longest lived outbound peers as anchors so we reconnect to
+	/// them first on the next startup. Previously saved anchors are kept if we
+	/// have no outbound peers right now.
+	pub fn save_anchors(&self) -> Result<(), Error> {
+		let mut peers = self.outgoing_connected_peers();
+		peers.sort_by_key(|p| p.info.first_seen());
+
+		let anchors = peers
+			.iter()
+			.take(self.config.anchor_peer_count() as usize)
+			.map(|p| p.info.addr.clone())
+			.collect::<Vec<_>>();
+		if anchors.is_empty() {
+			return Ok(());
+		}
+
+		debug!("Saving {} anchor peers.", anchors.len());
+		self.store.save_anchors(&anchors).map_err(From::from)
+	}
+
+	/// Remember where we got an address we're about to dial from. The first
+	/// source noted for an address is kept.
+	pub fn note_seed_source(&self, addr: PeerAddr, source: SeedSource) {
+		let mut seed_sources = self.seed_sources.write();
+		evict_oldest(&mut seed_sources, &addr, SEED_SOURCES_CAP, |s| s.1);
+		seed_sources.entry(addr).or_insert((source, Utc::now()));
+	}
+
+	/// The peer addresses to answer the peer asking for them again within
+	/// peer_addrs_request_interval, our previous answer if it asked for the
+	/// same capabilities and nothing otherwise. None when it may get a fresh
+	/// answer.
+	pub fn recent_peer_addrs(
+		&self,
+		addr: &PeerAddr,
+		capabilities: Capabilities,
+	) -> Option<Vec<PeerAddr>> {
+		let interval = self.config.peer_addrs_request_interval().as_secs() as i64;
+		match self.peer_addrs_sent.read().get(addr) {
+			Some((sent_at, sent_capabilities, sent))
+				if *sent_at + Duration::seconds(interval) > Utc::now() =>
+			{
+				if *sent_capabilities == capabilities {
+					Some(sent.clone())
+				} else {
+					Some(vec![])
+				}
+			}
+			_ => None,
+		}
+	}
+
+	/// Remember the peer addresses we sent the peer, for its repeated
+	/// requests.
+	pub fn peer_addrs_sent(
+		&self,
+		addr: PeerAddr,
+		capabilities: Capabilities,
+		peers: Vec<PeerAddr>,
+	) {
+		let mut sent = self.peer_addrs_sent.write();
+		evict_oldest(&mut sent, &addr, PEER_ADDRS_SENT_CAP, |s| s.0);
+		sent.insert(addr, (Utc::now(), capabilities, peers));
+	}
+
+	/// Where we got the address from, through gossip unless noted otherwise.
+	pub fn seed_source(&self, addr: &PeerAddr) -> SeedSource {
+		self.seed_sources
+			.read()
+			.get(addr)
+			.map(|s| s.0.clone())
+			.unwrap_or(SeedSource::Gossip)
+	}
+
+	/// Number of our connected outbound peers per source we got their
+	/// address from.
+	pub fn seed_source_breakdown(&self) -> HashMap<SeedSource, u32> {
+		let mut breakdown = HashMap::new();
+		for peer in self.connected_peers() {
+			if let Some(source) = peer.info.seed_source() {
+				*breakdown.entry(source).or_insert(0) += 1;
+			}
+		}
+		breakdown
+	}
+
+	/// Anchor peers persisted on our previous run, excluding the banned ones.
+	pub fn anchors(&self) -> Vec<PeerAddr> {
+		match self.store.anchors() {
+			Ok(anchors) => anchors
+				.into_iter()
+				.filter(|addr| !self.is_banned(addr.clone()))
+				.take(self.config.anchor_peer_count() as usize)
+				.collect(),
+			Err(e) => {
+				error!("failed to get anchor peers: {:?}", e);
+				vec![]
+			}
+		}
+	}
+
+	/// Whether to refuse a new inbound connection because of our current load,
+	/// randomly and more likely the higher the load.
+	pub fn refuse_inbound_under_load(&self) -> bool {
+		let probability = inbound_refusal_probability(
+			self.adapter.load_factor(),
+			self.config.inbound_load_threshold(),
+		);
+		probability > 0.0 && self.rng.lock().gen_bool(probability)
+	}
+
 	/// We have enough outbound connected peers
 	pub fn enough_outbound_peers(&self) -> bool {
 		self.peer_outbound_count() >= self.config.peer_min_preferred_outbound_count()
 	}
 
+	/// How many outbound peers we're short of our preferred minimum.
+	pub fn outbound_deficit(&self) -> u32 {
+		self.config
+			.peer_min_preferred_outbound_count()
+			.saturating_sub(self.peer_outbound_count())
+	}
+
+	/// Follow how long we've been short of outbound peers, warning once it
+	/// lasts longer than outbound_deficit_warn_after. Returns whether the
+	/// deficit lasted long enough to relax our selection of peers to connect
+	/// to (see outbound_candidates).
+	pub fn check_outbound_deficit(&self, now: DateTime<Utc>) -> bool {
+		let deficit = self.outbound_deficit();
+		let since = {
+			let mut since = self.outbound_deficit_since.write();
+			if deficit == 0 {
+				*since = None;
+				self.outbound_deficit_warned.store(false, Ordering::Relaxed);
+				return false;
+			}
+			*since.get_or_insert(now)
+		};
+		let elapsed = (now - since).num_seconds();
+		if elapsed >= self.config.outbound_deficit_warn_after()
+			&& !self.outbound_deficit_warned.swap(true, Ordering::Relaxed)
+		{
+			warn!(
+				"check_outbound_deficit: {} outbound peers short of {} for {}s",
+				deficit,
+				self.config.peer_min_preferred_outbound_count(),
+				elapsed
+			);
+		}
+		match self.config.outbound_deficit_relax_after {
+			Some(relax_after) => elapsed >= relax_after,
+			None => false,
+		}
+	}
+
+	/// Peers from our db to try connecting to, the healthy ones. With relaxed
+	/// selection the defunct ones (we failed to connect to before) are
+	/// candidates too, any peer beats staying under-connected.
+	pub fn outbound_candidates(&self, count: usize, relaxed: bool) -> Vec<PeerData> {
+		if !relaxed {
+			return self.find_peers(State::Healthy, Capabilities::UNKNOWN, count);
+		}
+		let mut candidates = self.find_peers(State::Healthy, Capabilities::UNKNOWN, count);
+		candidates.extend(self.find_peers(State::Defunct, Capabilities::UNKNOWN, count));
+		self.shuffle(&mut candidates);
+		candidates.truncate(count);
+		candidates
+	}
+
 	/// Removes those peers that seem to have expired
 	pub fn remove_expired(&self) {
 		let now = Utc::now();
@@ -570,6 +2097,39 @@ impl Peers {
 			should_remove
 		});
 	}
+
+	/// Deletes the ban records that expired by `now` from storage, returns the
+	/// number of records removed.
+	pub fn prune_expired_bans(&self, now: DateTime<Utc>) -> usize {
+		let ban_window = Duration::seconds(self.config.ban_window());
+		let res = self
+			.store
+			.delete_peers(|peer| {
+				peer.flags == State::Banned
+					&& now - Utc.timestamp(peer.last_banned, 0) >= ban_window
+			})
+			.and_then(|count| {
+				let subnets = self
+					.store
+					.delete_subnet_bans(|ban| ban.expired(now.timestamp()))?;
+				self.subnet_bans
+					.write()
+					.retain(|_, ban| !ban.expired(now.timestamp()));
+				Ok(count + subnets)
+			});
+		match res {
+			Ok(count) => {
+				if count > 0 {
+					debug!("prune_expired_bans: removed {} expired bans", count);
+				}
+				count
+			}
+			Err(e) => {
+				error!("prune_expired_bans: failed to delete expired bans: {:?}", e);
+				0
+			}
+		}
+	}
 }
 
 impl ChainAdapter for Peers {
@@ -585,6 +2145,14 @@ impl ChainAdapter for Peers {
 		self.adapter.get_transaction(kernel_hash)
 	}
 
+	fn min_relay_fee(&self) -> u64 {
+		self.adapter.min_relay_fee()
+	}
+
+	fn prune_height(&self) -> u64 {
+		self.adapter.prune_height()
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
@@ -606,22 +2174,64 @@ impl ChainAdapter for Peers {
 		b: core::Block,
 		peer_info: &PeerInfo,
 		opts: chain::Options,
-	) -> Result<bool, chain::Error> {
+	) -> Result<BlockAccept, chain::Error> {
 		let hash = b.hash();
-		if !self.adapter.block_received(b, peer_info, opts)? {
+		// while syncing we only ask for blocks on top of headers we have, one
+		// with an unknown parent wasn't asked for and isn't worth validating
+		if self.adapter.sync_status() != chain::SyncStatus::NoSync
+			&& !self.adapter.knows_header(b.header.prev_hash)
+		{
+			debug!(
+				"Dropping block {} from {} during sync, unknown parent {}",
+				hash, peer_info.addr, b.header.prev_hash
+			);
+			return Ok(BlockAccept::Orphan);
+		}
+		if self.is_known_orphan(&hash) {
+			debug!(
+				"Dropping orphan block {} from {}, already received",
+				hash, peer_info.addr
+			);
+			return Ok(BlockAccept::Orphan);
+		}
+		// keep a copy to process again should the chain fail transiently
+		let retry = if self.config.block_error_retries() > 0 {
+			Some(b.clone())
+		} else {
+			None
+		};
+		let accept = match self.adapter.block_received(b, peer_info, opts) {
+			Ok(accept) => accept,
+			// chain errors the block is to blame for make it invalid
+			Err(e) if e.is_bad_data() => BlockAccept::Invalid(e.kind().to_string()),
+			// the others are on our side, the peer isn't penalized for them
+			Err(e) => {
+				if let (true, Some(b)) = (e.is_transient(), retry) {
+					self.defer_block(b, peer_info, opts);
+				}
+				return Err(e);
+			}
+		};
+		self.block_retries.lock().remove(&hash);
+		if accept == BlockAccept::Orphan {
+			self.orphan_received(hash);
+		}
+		if let BlockAccept::Invalid(ref reason) = accept {
 			// if the peer sent us a block that's intrinsically bad
 			// they are either mistaken or malevolent, both of which require a ban
 			debug!(
-				"Received a bad block {} from  {}, the peer will be banned",
+				"Received a bad block {} from  {} ({}), the peer will be banned",
 				hash,
 				peer_info.addr.clone(),
+				reason,
 			);
 			self.ban_peer(peer_info.addr.clone(), ReasonForBan::BadBlock)
 				.map_err(|e| chain::ErrorKind::Other(format!("ban peer error {}", e)))?;
-			Ok(false)
-		} else {
-			Ok(true)
+		} else if accept == BlockAccept::Accepted {
+			// replaying blocks we have (or can't attach yet) isn't progress
+			peer_info.note_progress();
 		}
+		Ok(accept)
 	}
 
 	fn compact_block_received(
@@ -631,17 +2241,17 @@ impl ChainAdapter for Peers {
 	) -> Result<bool, chain::Error> {
 		let hash = cb.hash();
 		if !self.adapter.compact_block_received(cb, peer_info)? {
-			// if the peer sent us a block that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
+			// reconstruction can fail for benign reasons, only peers repeatedly
+			// sending bad compact blocks are mistaken or malevolent and banned
 			debug!(
-				"Received a bad compact block {} from  {}, the peer will be banned",
+				"Received a bad compact block {} from {}",
 				hash,
 				peer_info.addr.clone()
 			);
-			self.ban_peer(peer_info.addr.clone(), ReasonForBan::BadCompactBlock)
-				.map_err(|e| chain::ErrorKind::Other(format!("ban peer error {}", e)))?;
+			self.bad_compact_block(peer_info.addr.clone());
 			Ok(false)
 		} else {
+			peer_info.note_progress();
 			Ok(true)
 		}
 	}
@@ -658,6 +2268,7 @@ impl ChainAdapter for Peers {
 				.map_err(|e| chain::ErrorKind::Other(format!("ban peer error {}", e)))?;
 			Ok(false)
 		} else {
+			peer_info.note_progress();
 			Ok(true)
 		}
 	}
@@ -668,16 +2279,66 @@ impl ChainAdapter for Peers {
 		peer_info: &PeerInfo,
 		header_sync_cache_size: u64,
 	) -> Result<bool, chain::Error> {
+		// Headers from the future are more likely a clock problem on the peer side than
+		// a malicious peer, so we drop the connection instead of banning the peer.
+		if let Some(header) = headers
+			.iter()
+			.find(|h| peer_info.header_timestamp(h) == HeaderTimestamp::Future)
+		{
+			debug!(
+				"Received header {} at {} from {} with future timestamp {} (clock skew {}s), disconnecting",
+				header.hash(),
+				header.height,
+				peer_info.addr,
+				header.timestamp,
+				peer_info.clock_skew(),
+			);
+			if let Some(peer) = self.get_connected_peer(peer_info.addr.clone()) {
+				peer.stop();
+			}
+			return Ok(false);
+		}
+
+		// Not a reason to ban, an honest peer may just be ahead of the others.
+		let taken = self.corroborated_headers(headers, &peer_info.addr);
+		if taken < headers.len() {
+			debug!(
+				"Throttling {} headers from {} up to height {}, not corroborated by other peers",
+				headers.len() - taken,
+				peer_info.addr,
+				headers.last().map(|h| h.height).unwrap_or(0),
+			);
+			if taken == 0 {
+				return Ok(true);
+			}
+		}
+		let headers = &headers[..taken];
+
+		let minority_fork =
+			self.config.minority_fork_threshold() > 0 && self.on_losing_fork(headers);
+
 		if !self
 			.adapter
 			.headers_received(headers, peer_info, header_sync_cache_size)?
 		{
 			// if the peer sent us a block header that's intrinsically bad
 			// they are either mistaken or malevolent, both of which require a ban
-			self.ban_peer(peer_info.addr.clone(), ReasonForBan::BadBlockHeader)
+			// (pushing an invalid fork is fraud rather than a mistake)
+			let ban_reason = if minority_fork {
+				ReasonForBan::FraudHeight
+			} else {
+				ReasonForBan::BadBlockHeader
+			};
+			self.ban_peer(peer_info.addr.clone(), ban_reason)
 				.map_err(|e| chain::ErrorKind::Other(format!("ban peer error {}", e)))?;
 			Ok(false)
 		} else {
+			if minority_fork {
+				self.minority_fork_received(peer_info.addr.clone());
+			} else {
+				self.minority_forks.write().remove(&peer_info.addr);
+				peer_info.note_progress();
+			}
 			Ok(true)
 		}
 	}
@@ -695,6 +2356,14 @@ impl ChainAdapter for Peers {
 		self.adapter.locate_headers(hs)
 	}
 
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		self.adapter.headers_by_height(start, count)
+	}
+
 	fn get_block(&self, h: Hash, peer_info: &PeerInfo) -> Option<core::Block> {
 		self.adapter.get_block(h, peer_info)
 	}
@@ -711,22 +2380,57 @@ impl ChainAdapter for Peers {
 		self.adapter.txhashset_receive_ready()
 	}
 
+	fn in_maintenance(&self) -> bool {
+		self.adapter.in_maintenance()
+	}
+
+	fn sync_status(&self) -> chain::SyncStatus {
+		self.adapter.sync_status()
+	}
+
+	fn knows_header(&self, hash: Hash) -> bool {
+		self.adapter.knows_header(hash)
+	}
+
+	fn load_factor(&self) -> f64 {
+		self.adapter.load_factor()
+	}
+
 	fn txhashset_write(
 		&self,
 		h: Hash,
 		txhashset_data: File,
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		if self.adapter.txhashset_write(h, txhashset_data, peer_info)? {
-			debug!(
-				"Received a bad txhashset data from {}, the peer will be banned",
-				peer_info.addr.clone()
-			);
-			self.ban_peer(peer_info.addr.clone(), ReasonForBan::BadTxHashSet)
-				.map_err(|e| chain::ErrorKind::Other(format!("ban peer error {}", e)))?;
-			Ok(true)
-		} else {
-			Ok(false)
+		match self.adapter.txhashset_write(h, txhashset_data, peer_info) {
+			Ok(true) => {
+				debug!(
+					"Received a bad txhashset data from {}, the peer will be banned",
+					peer_info.addr.clone()
+				);
+				self.ban_peer(peer_info.addr.clone(), ReasonForBan::BadTxHashSet)
+					.map_err(|e| chain::ErrorKind::Other(format!("ban peer error {}", e)))?;
+				Ok(true)
+			}
+			Ok(false) => {
+				self.corrupt_txhashsets.write().clear();
+				Ok(false)
+			}
+			Err(e) => match e.kind() {
+				// could be the peer's disk as much as malice, we just won't
+				// ask it again and state sync restarts with somebody else
+				chain::ErrorKind::CorruptTxHashSet(_) => {
+					warn!(
+						"Txhashset from {} proved corrupt, we'll download it again elsewhere",
+						peer_info.addr
+					);
+					self.corrupt_txhashsets
+						.write()
+						.insert(peer_info.addr.clone());
+					Ok(false)
+				}
+				_ => Err(e),
+			},
 		}
 	}
 
@@ -747,13 +2451,34 @@ impl ChainAdapter for Peers {
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.adapter.get_tmpfile_pathname(tmpfile_name)
 	}
+
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		self.adapter.tmp_dir_free_space()
+	}
+
+	fn missing_block_ranges(&self, from: u64, to: u64) -> Vec<(u64, u64)> {
+		self.adapter.missing_block_ranges(from, to)
+	}
+
+	fn common_ancestor_height(&self, locator: &[Hash]) -> Option<u64> {
+		self.adapter.common_ancestor_height(locator)
+	}
+
+	fn header_head(&self) -> Option<chain::Tip> {
+		self.adapter.header_head()
+	}
 }
 
 impl NetAdapter for Peers {
 	/// Find good peers we know with the provided capability and return their
 	/// addresses.
 	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr> {
-		let peers = self.find_peers(State::Healthy, capab, MAX_PEER_ADDRS as usize);
+		let mut peers = self.find_peers(State::Healthy, capab, MAX_PEER_ADDRS as usize);
+		if self.config.gossip_reachable_only() {
+			// peers not accepting connections are of no use to whoever we tell
+			let unreachable = self.unreachable.read();
+			peers.retain(|p| !unreachable.contains_key(&p.addr));
+		}
 		trace!("find_peer_addrs: {} healthy peers picked", peers.len());
 		map_vec!(peers, |p| p.addr.clone())
 	}
@@ -767,32 +2492,115 @@ impl NetAdapter for Peers {
 					continue;
 				}
 			}
+			if pa.is_routable() {
+				let mut pending = self.regossip_pending.lock();
+				if pending.len() >= REGOSSIP_PENDING_CAP {
+					pending.pop_front();
+				}
+				pending.push_back(pa.clone());
+			}
 			let peer = PeerData {
-				addr: pa,
+				addr: pa.clone(),
 				capabilities: Capabilities::UNKNOWN,
 				user_agent: "".to_string(),
 				flags: State::Healthy,
 				last_banned: 0,
 				ban_reason: ReasonForBan::None,
 				last_connected: Utc::now().timestamp(),
+				score: 0,
 			};
 			if let Err(e) = self.save_peer(&peer) {
 				error!("Could not save received peer address: {:?}", e);
+			} else if let PeerAddr::Onion(_) = pa {
+				self.onion_peer_stored(pa);
 			}
 		}
 	}
 
 	fn peer_difficulty(&self, addr: PeerAddr, diff: Difficulty, height: u64) {
-		if let Some(peer) = self.get_connected_peer(addr) {
-			peer.info.update(height, diff);
+		if let Some(peer) = self.get_connected_peer(addr.clone()) {
+			let tolerance = self.config.height_regression_tolerance();
+			if peer.info.update(height, diff, tolerance) {
+				warn!(
+					"peer_difficulty: peer {} height dropped from {} to {}, parking",
+					addr,
+					peer.info.height(),
+					height
+				);
+				self.park_peer(addr, Duration::seconds(self.config.ban_window()));
+			}
 		}
 	}
 
 	fn is_banned(&self, addr: PeerAddr) -> bool {
-		if let Ok(peer) = self.get_peer(addr) {
-			peer.flags == State::Banned
-		} else {
+		Peers::is_banned(self, addr)
+	}
+
+	fn peer_ser_error(&self, addr: PeerAddr) {
+		let now = Utc::now();
+		let window = Duration::seconds(self.config.ser_error_window());
+		let count = {
+			let mut ser_errors = self.ser_errors.write();
+			evict_oldest(&mut ser_errors, &addr, SER_ERRORS_CAP, |e| e.1);
+			let entry = ser_errors.entry(addr.clone()).or_insert((0, now));
+			if now - entry.1 > window {
+				*entry = (0, now);
+			}
+			entry.0 += 1;
+			entry.0
+		};
+
+		if count < self.config.ser_error_ban_threshold() {
+			debug!(
+				"peer_ser_error: malformed message #{} from peer {} in the current window",
+				count, addr
+			);
+			return;
+		}
+		debug!(
+			"peer_ser_error: peer {} sent {} malformed messages, banning",
+			addr, count
+		);
+		self.ser_errors.write().remove(&addr);
+		if let Err(e) = self.ban_peer(addr, ReasonForBan::BadMessage) {
+			debug!("peer_ser_error: failed to ban peer: {:?}", e);
+		}
+	}
+
+	/// An archive is too old when it's anchored more than txhashset_max_age
+	/// blocks below the highest height reported by our peers.
+	fn txhashset_archive_acceptable(&self, height: u64) -> bool {
+		let max_age = match self.config.txhashset_max_age {
+			Some(n) => n,
+			None => return true,
+		};
+		let tip = self
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.height())
+			.max()
+			.unwrap_or(0);
+		if tip > height.saturating_add(max_age) {
+			debug!(
+				"txhashset_archive_acceptable: archive at {} is {} blocks behind network tip {}",
+				height,
+				tip - height,
+				tip
+			);
 			false
+		} else {
+			true
+		}
+	}
+
+	fn request_declined(&self, addr: PeerAddr, msg_type: msg::Type, reason: DeclineReason) {
+		debug!(
+			"request_declined: peer {} declined {:?}, {:?}",
+			addr, msg_type, reason
+		);
+		// advertising archives it won't serve is what capability refusals track
+		if msg_type == msg::Type::TxHashSetRequest && reason == DeclineReason::NotServed {
+			self.capability_refused(addr, Capabilities::TXHASHSET_HIST);
 		}
 	}
 }
@@ -15,25 +15,40 @@
 use crate::chain;
 use crate::conn::{Message, MessageHandler, Tracker};
 use crate::core::core::{self, hash::Hash, hash::Hashed, CompactBlock};
+use crate::core::ser;
 use crate::serv::Server;
-use crate::types::PeerAddr::Onion;
 
 use crate::msg::{
-	BanReason, GetPeerAddrs, Headers, Locator, Msg, PeerAddrs, Ping, Pong, TorAddress,
-	TxHashSetArchive, TxHashSetRequest, Type,
+	BanReason, CapabilitiesUpdate, DeclineReason, Declined, GetHeadersByHeight, GetPeerAddrs,
+	Headers, Msg, PeerAddrs, Ping, Pong, ReachabilityCheck, RequestReachabilityCheck, Tip,
+	TorAddress, TxHashSetArchive, TxHashSetRequest, Type,
 };
 
 use crate::types::Capabilities;
 use crate::types::PeerAddr;
-use crate::types::{Error, NetAdapter, PeerInfo};
+use crate::types::{
+	gossip_addrs_for, txhashset_fits, Error, NetAdapter, PeerInfo, MAX_BLOCK_HEADERS,
+};
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::cmp;
 use std::fs::{self, File, OpenOptions};
-use std::io::{BufWriter, Read};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two reachability checks we do for the same peer ip
+const REACHABILITY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long we try connecting back to a peer checking its reachability
+const REACHABILITY_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimum time between two writes of the capabilities a peer updated to our
+/// peer store, the store catches up with the next update after that.
+const CAPABILITIES_SAVE_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct Protocol {
 	adapter: Arc<dyn NetAdapter>,
@@ -41,6 +56,8 @@ pub struct Protocol {
 	state_sync_requested: Arc<AtomicBool>,
 	header_cache_size: u64,
 	server: Server,
+	// when we last saved capabilities the peer updated to our peer store
+	capabilities_saved: Option<Instant>,
 }
 
 impl Protocol {
@@ -57,12 +74,74 @@ impl Protocol {
 			state_sync_requested,
 			header_cache_size,
 			server,
+			capabilities_saved: None,
 		}
 	}
-}
 
-impl MessageHandler for Protocol {
-	fn consume<R: Read>(
+	/// Sync-heavy requests are not served while the node is in maintenance,
+	/// the peer is told to try later and may ask someone else meanwhile.
+	fn defer_in_maintenance(&self, msg_type: Type) -> bool {
+		if self.adapter.in_maintenance() {
+			debug!(
+				"handle_payload: in maintenance, deferring {:?} from {}",
+				msg_type, self.peer_info.addr
+			);
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Reply declining a request of the peer, so it doesn't wait on us. Peers
+	/// on an older protocol version don't know the message and get nothing.
+	fn decline(&self, msg_type: Type, reason: DeclineReason) -> Result<Option<Msg>, Error> {
+		if !self.peer_info.supports(Type::Declined) {
+			return Ok(None);
+		}
+		Ok(Some(Msg::new(
+			Type::Declined,
+			Declined { msg_type, reason },
+			self.peer_info.version,
+		)?))
+	}
+
+	/// Reads and drops the attachment of a message we won't keep, so the
+	/// connection stays usable for whatever the peer sends next.
+	fn skip_attachment<R: Read>(
+		msg: &mut Message<R>,
+		bytes: u64,
+		stopped: &AtomicBool,
+		tracker: &Tracker,
+	) -> Result<(), Error> {
+		let mut remaining = bytes;
+		while remaining > 0 {
+			let size =
+				msg.copy_attachment(cmp::min(48_000, remaining) as usize, &mut io::sink())?;
+			remaining -= size as u64;
+			// not held against the peer, we asked for it
+			tracker.inc_quiet_received(size as u64);
+			if stopped.load(Ordering::Relaxed) {
+				return Err(Error::ConnectionClose);
+			}
+		}
+		Ok(())
+	}
+
+	/// Headers-only relays decline requests for blocks, txhashsets and
+	/// transactions, the peer gets no response and will ask someone else.
+	fn decline_headers_only(&self, msg_type: Type) -> bool {
+		if self.server.config.headers_only() {
+			debug!(
+				"handle_payload: headers only, declining {:?} from {}",
+				msg_type, self.peer_info.addr
+			);
+			true
+		} else {
+			false
+		}
+	}
+
+	fn handle_payload<R: Read>(
 		&mut self,
 		mut msg: Message<R>,
 		stopped: Arc<AtomicBool>,
@@ -90,12 +169,16 @@ impl MessageHandler for Protocol {
 					ping.total_difficulty,
 					ping.height,
 				);
+				if let Some(prune_height) = ping.prune_height {
+					self.peer_info.set_prune_height(prune_height);
+				}
 
 				Ok(Some(Msg::new(
 					Type::Pong,
 					Pong {
 						total_difficulty: adapter.total_difficulty()?,
 						height: adapter.total_height()?,
+						prune_height: Some(adapter.prune_height()),
 					},
 					self.peer_info.version,
 				)?))
@@ -103,11 +186,15 @@ impl MessageHandler for Protocol {
 
 			Type::Pong => {
 				let pong: Pong = msg.body()?;
+				self.peer_info.pong_received();
 				adapter.peer_difficulty(
 					self.peer_info.addr.clone(),
 					pong.total_difficulty,
 					pong.height,
 				);
+				if let Some(prune_height) = pong.prune_height {
+					self.peer_info.set_prune_height(prune_height);
+				}
 				Ok(None)
 			}
 
@@ -133,6 +220,9 @@ impl MessageHandler for Protocol {
 					"handle_payload: GetTransaction: {}, msg_len: {}",
 					h, msg.header.msg_len,
 				);
+				if self.decline_headers_only(msg.header.msg_type) {
+					return Ok(None);
+				}
 				let tx = adapter.get_transaction(h);
 				if let Some(tx) = tx {
 					Ok(Some(Msg::new(
@@ -172,6 +262,12 @@ impl MessageHandler for Protocol {
 					h,
 					msg.header.msg_len,
 				);
+				if self.decline_headers_only(msg.header.msg_type) {
+					return Ok(None);
+				}
+				if self.defer_in_maintenance(msg.header.msg_type) {
+					return self.decline(msg.header.msg_type, DeclineReason::TryLater);
+				}
 
 				let bo = adapter.get_block(h, &self.peer_info);
 				if let Some(b) = bo {
@@ -197,6 +293,9 @@ impl MessageHandler for Protocol {
 
 			Type::GetCompactBlock => {
 				let h: Hash = msg.body()?;
+				if self.decline_headers_only(msg.header.msg_type) {
+					return Ok(None);
+				}
 				if let Some(b) = adapter.get_block(h, &self.peer_info) {
 					let cb: CompactBlock = b.into();
 					Ok(Some(Msg::new(
@@ -228,24 +327,203 @@ impl MessageHandler for Protocol {
 
 				let new_peer_addr = PeerAddr::Onion(tor_address.address.clone());
 				error!("new peer = {:?}", new_peer_addr);
+				if self.server.is_self(&new_peer_addr) {
+					debug!(
+						"handle_payload: {} is our own onion address, closing",
+						new_peer_addr
+					);
+					return Err(Error::PeerWithSelf);
+				}
+				let peer_addr = self.peer_info.addr.clone();
 				if self.server.peers.is_banned(new_peer_addr.clone()) {
-					let peer = self.server.peers.get_peer(self.peer_info.addr.clone())?;
+					let peer = self.server.peers.get_peer(peer_addr)?;
 					warn!("banned peer tried to connect! {:?}", peer);
 				} else {
-					let peer = self.server.peers.get_peer(self.peer_info.addr.clone());
+					let peer = self.server.peers.get_peer(peer_addr.clone());
 					if peer.is_ok() {
 						let mut peer = peer.unwrap();
 						peer.addr = new_peer_addr;
 						self.server.peers.save_peer(&peer)?;
+
+						// Inbound tor connections come from the local proxy, don't keep
+						// (and gossip) that loopback address as if it was a real peer.
+						if peer_addr.is_loopback() {
+							self.server.peers.delete_peer(peer_addr)?;
+						}
+					}
+				}
+				Ok(None)
+			}
+
+			Type::CapabilitiesUpdate => {
+				let update: CapabilitiesUpdate = msg.body()?;
+				if !self.peer_info.supports(Type::CapabilitiesUpdate) {
+					debug!(
+						"handle_payload: capabilities update from {} on protocol version {}, ignoring",
+						self.peer_info.addr, self.peer_info.version
+					);
+					return Ok(None);
+				}
+				if update.capabilities == self.peer_info.current_capabilities() {
+					return Ok(None);
+				}
+				debug!(
+					"handle_payload: {} now offers {:?}",
+					self.peer_info.addr, update.capabilities
+				);
+				self.peer_info.set_capabilities(update.capabilities);
+
+				// a peer flapping its capabilities doesn't get to hammer the store
+				if let Some(saved) = self.capabilities_saved {
+					if saved.elapsed() < CAPABILITIES_SAVE_INTERVAL {
+						return Ok(None);
+					}
+				}
+				if let Ok(mut peer) = self.server.peers.get_peer(self.peer_info.addr.clone()) {
+					if peer.capabilities != update.capabilities {
+						peer.capabilities = update.capabilities;
+						self.server.peers.save_peer(&peer)?;
+						self.capabilities_saved = Some(Instant::now());
 					}
 				}
 				Ok(None)
 			}
 
+			Type::GetCapabilities => {
+				if !self.peer_info.supports(Type::CapabilitiesUpdate) {
+					return Ok(None);
+				}
+				Ok(Some(Msg::new(
+					Type::CapabilitiesUpdate,
+					CapabilitiesUpdate {
+						capabilities: self.server.effective_capabilities(),
+					},
+					self.peer_info.version,
+				)?))
+			}
+
+			Type::GetTip => {
+				if !self.peer_info.supports(Type::Tip) {
+					return Ok(None);
+				}
+				match adapter.header_head() {
+					Some(head) => Ok(Some(Msg::new(
+						Type::Tip,
+						Tip {
+							hash: head.last_block_h,
+							height: head.height,
+							total_difficulty: head.total_difficulty,
+						},
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::Tip => {
+				let tip: Tip = msg.body()?;
+				trace!(
+					"handle_payload: {} is at {} ({}) with difficulty {}",
+					self.peer_info.addr,
+					tip.height,
+					tip.hash,
+					tip.total_difficulty
+				);
+				self.peer_info
+					.set_header_tip(tip.hash, tip.height, tip.total_difficulty);
+				Ok(None)
+			}
+
+			Type::RequestReachabilityCheck => {
+				let request: RequestReachabilityCheck = msg.body()?;
+				// only ever dial the ip the peer is connected from, and not
+				// when our clearnet connections go through tor
+				let ip = match self.peer_info.addr {
+					PeerAddr::Ip(addr) if self.server.socks_port == 0 => addr.ip(),
+					_ => return Ok(None),
+				};
+				// once per ip, reconnecting doesn't get the peer another check
+				if !self.server.reachability_check_due(
+					ip,
+					REACHABILITY_CHECK_INTERVAL,
+					Instant::now(),
+				) {
+					debug!(
+						"handle_payload: reachability check from {} too soon, ignoring",
+						self.peer_info.addr
+					);
+					return Ok(None);
+				}
+				let target = SocketAddr::new(ip, request.port);
+				let addr = self.peer_info.addr.clone();
+				let peers = self.server.peers.clone();
+				let spawned = thread::Builder::new()
+					.name("reachability_check".to_string())
+					.spawn(move || {
+						let reachable =
+							match TcpStream::connect_timeout(&target, REACHABILITY_CHECK_TIMEOUT) {
+								Ok(mut stream) => {
+									let _ =
+										stream.set_write_timeout(Some(REACHABILITY_CHECK_TIMEOUT));
+									stream.write_all(&request.nonce.to_be_bytes()).is_ok()
+								}
+								Err(_) => false,
+							};
+						debug!("reachability_check: {} reachable: {}", target, reachable);
+						if let Some(peer) = peers.get_connected_peer(addr) {
+							if let Err(e) = peer.send_reachability_check(request.nonce, reachable) {
+								debug!("reachability_check: failed to answer {}: {:?}", target, e);
+							}
+						}
+					});
+				if let Err(e) = spawned {
+					error!(
+						"handle_payload: failed to spawn reachability check: {:?}",
+						e
+					);
+				}
+				Ok(None)
+			}
+
+			Type::Declined => {
+				let declined: Declined = msg.body()?;
+				debug!(
+					"handle_payload: {} declined our {:?}, {:?}",
+					self.peer_info.addr, declined.msg_type, declined.reason
+				);
+				// nothing is coming back, the peer is free for other requests
+				if declined.msg_type == Type::TxHashSetRequest {
+					self.state_sync_requested.store(false, Ordering::Relaxed);
+				}
+				self.peer_info.clear_busy();
+				adapter.request_declined(
+					self.peer_info.addr.clone(),
+					declined.msg_type,
+					declined.reason,
+				);
+				Ok(None)
+			}
+
+			Type::ReachabilityCheck => {
+				let check: ReachabilityCheck = msg.body()?;
+				self.server.reachability_checked(
+					&self.peer_info.addr,
+					check.nonce,
+					check.reachable,
+				);
+				Ok(None)
+			}
+
 			Type::GetHeaders => {
 				// load headers from the locator
-				let loc: Locator = msg.body()?;
-				let headers = adapter.locate_headers(&loc.hashes)?;
+				let loc = msg.locator()?;
+				if self.defer_in_maintenance(msg.header.msg_type) {
+					return self.decline(msg.header.msg_type, DeclineReason::TryLater);
+				}
+				let mut headers = adapter.locate_headers(&loc.hashes)?;
+				if let Some(count) = loc.count {
+					headers.truncate(count as usize);
+				}
 
 				// serialize and send all the headers over
 				Ok(Some(Msg::new(
@@ -255,6 +533,28 @@ impl MessageHandler for Protocol {
 				)?))
 			}
 
+			Type::GetHeadersByHeight => {
+				let req: GetHeadersByHeight = msg.body()?;
+				if !self.peer_info.supports(Type::GetHeadersByHeight) {
+					debug!(
+						"handle_payload: headers by height from {} on protocol version {}, ignoring",
+						self.peer_info.addr, self.peer_info.version
+					);
+					return Ok(None);
+				}
+				if self.defer_in_maintenance(msg.header.msg_type) {
+					return self.decline(msg.header.msg_type, DeclineReason::TryLater);
+				}
+				let count = cmp::min(req.count, MAX_BLOCK_HEADERS);
+				let headers = adapter.headers_by_height(req.start, count)?;
+
+				Ok(Some(Msg::new(
+					Type::Headers,
+					Headers { headers },
+					self.peer_info.version,
+				)?))
+			}
+
 			// "header first" block propagation - if we have not yet seen this block
 			// we can go request it from some of our peers
 			Type::Header => {
@@ -284,6 +584,8 @@ impl MessageHandler for Protocol {
 					}
 				}
 
+				self.peer_info.clear_busy();
+
 				// Now check we read the correct total number of bytes off the stream.
 				if total_bytes_read != msg.header.msg_len {
 					return Err(Error::MsgLen);
@@ -294,6 +596,25 @@ impl MessageHandler for Protocol {
 
 			Type::GetPeerAddrs => {
 				let get_peers: GetPeerAddrs = msg.body()?;
+
+				// peers asking for addresses too often are served the previous
+				// response again (or nothing if asking for something else)
+				if let Some(peers) = self
+					.server
+					.peers
+					.recent_peer_addrs(&self.peer_info.addr, get_peers.capabilities)
+				{
+					debug!(
+						"handle_payload: throttling peer addrs request from {}",
+						self.peer_info.addr
+					);
+					return Ok(Some(Msg::new(
+						Type::PeerAddrs,
+						PeerAddrs { peers },
+						self.peer_info.version,
+					)?));
+				}
+
 				let peers =
 					adapter.find_peer_addrs(get_peers.capabilities & !Capabilities::TOR_ADDRESS);
 
@@ -313,6 +634,16 @@ impl MessageHandler for Protocol {
 				} else {
 					peers
 				};
+				let peers = gossip_addrs_for(
+					&self.peer_info.addr,
+					peers,
+					self.server.config.bridge_mode(),
+				);
+				self.server.peers.peer_addrs_sent(
+					self.peer_info.addr.clone(),
+					get_peers.capabilities,
+					peers.clone(),
+				);
 
 				Ok(Some(Msg::new(
 					Type::PeerAddrs,
@@ -325,22 +656,10 @@ impl MessageHandler for Protocol {
 				let peer_addrs: PeerAddrs = msg.body()?;
 				let mut peers: Vec<PeerAddr> = Vec::new();
 				for peer in peer_addrs.peers {
-					match peer.clone() {
-						Onion(address) => {
-							let self_address = self.server.self_onion_address.as_ref();
-							if self_address.is_none() {
-								peers.push(peer);
-							} else {
-								if &address != self_address.unwrap() {
-									peers.push(peer);
-								} else {
-									debug!("Not pushing self onion address = {}", address);
-								}
-							}
-						}
-						_ => {
-							peers.push(peer);
-						}
+					if self.server.is_self(&peer) {
+						debug!("Not pushing self address = {}", peer);
+					} else {
+						peers.push(peer);
 					}
 				}
 				adapter.peer_addrs_received(peers);
@@ -352,6 +671,19 @@ impl MessageHandler for Protocol {
 					"handle_payload: txhashset req for {} at {}",
 					sm_req.hash, sm_req.height
 				);
+				if self.decline_headers_only(msg.header.msg_type) {
+					return Ok(None);
+				}
+				if !self.server.config.serve_txhashset() {
+					debug!(
+						"handle_payload: not serving txhashset archives, declining request from {}",
+						self.peer_info.addr
+					);
+					return self.decline(msg.header.msg_type, DeclineReason::NotServed);
+				}
+				if self.defer_in_maintenance(msg.header.msg_type) {
+					return self.decline(msg.header.msg_type, DeclineReason::TryLater);
+				}
 
 				let txhashset_header = self.adapter.txhashset_archive_header()?;
 				let txhashset_header_hash = txhashset_header.hash();
@@ -394,6 +726,32 @@ impl MessageHandler for Protocol {
 				// Update the sync state requested status
 				self.state_sync_requested.store(false, Ordering::Relaxed);
 
+				if !self.adapter.txhashset_archive_acceptable(sm_arch.height) {
+					error!(
+						"handle_payload: txhashset archive at {} from {} is too old, not keeping it",
+						sm_arch.height, self.peer_info.addr
+					);
+					Protocol::skip_attachment(&mut msg, sm_arch.bytes, &stopped, &tracker)?;
+					self.peer_info.clear_busy();
+					return self.decline(msg.header.msg_type, DeclineReason::Unwanted);
+				}
+
+				// not the peer's fault, just drop the archive and keep the peer
+				if let Some(free) = self.adapter.tmp_dir_free_space() {
+					if !txhashset_fits(sm_arch.bytes, free) {
+						error!(
+							"handle_payload: not enough disk space for txhashset archive of {} bytes from {}, {} bytes free in {:?}",
+							sm_arch.bytes,
+							self.peer_info.addr,
+							free,
+							self.adapter.get_tmp_dir()
+						);
+						Protocol::skip_attachment(&mut msg, sm_arch.bytes, &stopped, &tracker)?;
+						self.peer_info.clear_busy();
+						return self.decline(msg.header.msg_type, DeclineReason::Unwanted);
+					}
+				}
+
 				let download_start_time = Utc::now();
 				self.adapter
 					.txhashset_download_update(download_start_time, 0, sm_arch.bytes);
@@ -450,7 +808,9 @@ impl MessageHandler for Protocol {
 					Ok(())
 				};
 
-				if let Err(e) = save_txhashset_to_file(tmp.clone()) {
+				let saved = save_txhashset_to_file(tmp.clone());
+				self.peer_info.clear_busy();
+				if let Err(e) = saved {
 					error!(
 						"handle_payload: txhashset archive save to file fail. err={:?}",
 						e
@@ -479,6 +839,10 @@ impl MessageHandler for Protocol {
 
 				Ok(None)
 			}
+			Type::KeepAlive => {
+				trace!("handle_payload: keepalive from {}", self.peer_info.addr);
+				Ok(None)
+			}
 			Type::Error | Type::Hand | Type::Shake => {
 				debug!("Received an unexpected msg: {:?}", msg.header.msg_type);
 				Ok(None)
@@ -486,3 +850,42 @@ impl MessageHandler for Protocol {
 		}
 	}
 }
+
+impl MessageHandler for Protocol {
+	fn consume<R: Read>(
+		&mut self,
+		msg: Message<R>,
+		stopped: Arc<AtomicBool>,
+		tracker: Arc<Tracker>,
+	) -> Result<Option<Msg>, Error> {
+		let res = self.handle_payload(msg, stopped, tracker);
+		// A peer sending malformed messages over and over gets banned, the
+		// adapter keeps track of how often it happens.
+		match res {
+			Err(Error::Serialization(ser::Error::IOErr(..))) => {}
+			Err(Error::Serialization(ref e)) => {
+				if let Some(line) = self.server.peers.log_throttle().note(
+					&self.peer_info.addr,
+					"malformed message",
+					Utc::now(),
+				) {
+					debug!("handler: consume: {}: {:?}", line, e);
+				}
+				self.adapter.peer_ser_error(self.peer_info.addr.clone());
+			}
+			_ => {}
+		}
+		res
+	}
+
+	fn unknown(&mut self, msg_type: u8, msg_len: u64) -> Result<(), Error> {
+		if self.server.config.strict_protocol() {
+			debug!(
+				"handler: unknown msg type {} (len {}) from {}, disconnecting",
+				msg_type, msg_len, self.peer_info.addr
+			);
+			return Err(Error::BadMessage);
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,138 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Global bound on the memory buffered reading message bodies from all our
+//! peers at once. Past it, connections reading large messages pause (leaving
+//! the bytes in the socket, the sender blocks) while small messages still
+//! flow. Room given back goes to the smallest paused reads first, so the
+//! largest ones are those kept waiting.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::util::Mutex;
+
+/// Message bodies up to this size are always read, whatever the budget left.
+pub const LIGHT_READ_BYTES: u64 = 64 * 1024;
+
+/// How long a paused reader waits before trying again.
+const PAUSE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Accounting of the bytes of message bodies being read across all peers.
+pub struct ReadBudget {
+	// 0 for no limit
+	limit: u64,
+	state: Mutex<BudgetState>,
+}
+
+struct BudgetState {
+	used: u64,
+	// sizes of the reads currently paused, waiting for room
+	waiting: Vec<u64>,
+}
+
+/// Room reserved for a message body, given back once dropped.
+pub struct ReadReservation<'a> {
+	budget: &'a ReadBudget,
+	bytes: u64,
+}
+
+impl Drop for ReadReservation<'_> {
+	fn drop(&mut self) {
+		let mut state = self.budget.state.lock();
+		state.used = state.used.saturating_sub(self.bytes);
+	}
+}
+
+impl ReadBudget {
+	/// Budget of `limit` bytes, 0 for no limit.
+	pub fn new(limit: u64) -> ReadBudget {
+		ReadBudget {
+			limit,
+			state: Mutex::new(BudgetState {
+				used: 0,
+				waiting: vec![],
+			}),
+		}
+	}
+
+	/// Bytes currently reserved by readers.
+	pub fn used(&self) -> u64 {
+		self.state.lock().used
+	}
+
+	/// Number of readers currently paused, waiting for room.
+	pub fn paused(&self) -> usize {
+		self.state.lock().waiting.len()
+	}
+
+	/// Whether a body of `bytes` fits. Light bodies always do, as does any
+	/// body when nothing else is being read, even one larger than the limit.
+	fn fits(&self, state: &BudgetState, bytes: u64) -> bool {
+		self.limit == 0
+			|| bytes <= LIGHT_READ_BYTES
+			|| state.used == 0
+			|| state.used.saturating_add(bytes) <= self.limit
+	}
+
+	fn grant(&self, state: &mut BudgetState, bytes: u64) -> ReadReservation<'_> {
+		state.used = state.used.saturating_add(bytes);
+		ReadReservation {
+			budget: self,
+			bytes,
+		}
+	}
+
+	/// Reserves room for a message body of `bytes` about to be read, None if
+	/// it doesn't fit in what's left.
+	pub fn try_reserve(&self, bytes: u64) -> Option<ReadReservation<'_>> {
+		let mut state = self.state.lock();
+		if self.fits(&state, bytes) {
+			Some(self.grant(&mut state, bytes))
+		} else {
+			None
+		}
+	}
+
+	/// Reserves room for a message body of `bytes`, pausing until there is
+	/// some and no smaller read is waiting for it. None if the connection got
+	/// stopped meanwhile.
+	pub fn reserve(&self, bytes: u64, stopped: &AtomicBool) -> Option<ReadReservation<'_>> {
+		let mut state = self.state.lock();
+		if self.fits(&state, bytes) && state.waiting.iter().all(|&w| w >= bytes) {
+			return Some(self.grant(&mut state, bytes));
+		}
+		debug!(
+			"read_budget: pausing read of {} bytes, {} of {} in use",
+			bytes, state.used, self.limit
+		);
+		state.waiting.push(bytes);
+		loop {
+			drop(state);
+			thread::sleep(PAUSE_INTERVAL);
+			state = self.state.lock();
+			let stop = stopped.load(Ordering::Relaxed);
+			let ready = self.fits(&state, bytes) && state.waiting.iter().all(|&w| w >= bytes);
+			if stop || ready {
+				if let Some(pos) = state.waiting.iter().position(|&w| w == bytes) {
+					state.waiting.swap_remove(pos);
+				}
+				if stop {
+					return None;
+				}
+				return Some(self.grant(&mut state, bytes));
+			}
+		}
+	}
+}
@@ -13,13 +13,15 @@
 // limitations under the License.
 
 use crate::types::PeerAddr::Onion;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::chain;
 use crate::core::core;
@@ -27,15 +29,37 @@ use crate::core::core::hash::Hash;
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::handshake::Handshake;
+use crate::msg;
 use crate::peer::Peer;
 use crate::peers::Peers;
 use crate::store::PeerStore;
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	BlockAccept, Capabilities, ChainAdapter, DuplicateConnectionPolicy, Error, NetAdapter,
+	P2PConfig, PeerAddr, PeerInfo, ReasonForBan, TxHashSetRead,
 };
-use crate::util::StopState;
+use crate::util::{Mutex, RwLock, StopState};
 use chrono::prelude::{DateTime, Utc};
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Peers asked to check our listener is reachable on each probe.
+const REACHABILITY_PROBE_PEERS: usize = 3;
+
+/// Answers that must agree before we consider our listener reachable, or not.
+const REACHABILITY_AGREEING_PEERS: usize = 2;
+
+/// How long we wait for a peer we asked to check our listener to connect back
+/// and answer.
+const REACHABILITY_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a connection from a peer checking our listener has to send the
+/// nonce of the check.
+const REACHABILITY_NONCE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Connections we read the nonce of a reachability check from at once, the
+/// ones beyond that go straight to the handshake.
+const MAX_REACHABILITY_PEEKS: usize = 8;
 
 /// P2P server implementation, handling bootstrapping to find and connect to
 /// peers, receiving connections from other peers and keep track of all of them.
@@ -43,11 +67,23 @@ use chrono::prelude::{DateTime, Utc};
 pub struct Server {
 	pub config: P2PConfig,
 	pub socks_port: u16,
-	capabilities: Capabilities,
+	capabilities: Arc<RwLock<Capabilities>>,
+	// effective capabilities our connected peers were last told about
+	advertised_capabilities: Arc<RwLock<Capabilities>>,
 	handshake: Arc<Handshake>,
 	pub peers: Arc<Peers>,
 	stop_state: Arc<StopState>,
 	pub self_onion_address: Option<String>,
+	advertised_addr: Option<PeerAddr>,
+	dialing_enabled: Arc<AtomicBool>,
+	// whether a peer confirmed our listener is reachable from outside
+	self_reachable: Arc<RwLock<Option<bool>>>,
+	// the reachability checks we asked peers for, and their answers
+	reachability_probes: Arc<Mutex<ReachabilityProbes>>,
+	// when we last dialed back each peer ip checking its reachability
+	reachability_checks: Arc<Mutex<HashMap<IpAddr, Instant>>>,
+	// connections we're reading the nonce of a reachability check from
+	reachability_peeks: Arc<AtomicUsize>,
 }
 
 // TODO TLS
@@ -63,9 +99,12 @@ impl Server {
 		socks_port: u16,
 		onion_address: Option<String>,
 	) -> Result<Server, Error> {
-		Ok(Server {
+		let advertised_addr = config.advertised_addr(onion_address.clone());
+		let dialing_enabled = Arc::new(AtomicBool::new(config.dialing_enabled()));
+		let server = Server {
 			config: config.clone(),
-			capabilities: capab,
+			capabilities: Arc::new(RwLock::new(capab)),
+			advertised_capabilities: Arc::new(RwLock::new(capab)),
 			handshake: Arc::new(Handshake::new(
 				genesis,
 				config.clone(),
@@ -80,7 +119,15 @@ impl Server {
 			stop_state,
 			socks_port,
 			self_onion_address: onion_address,
-		})
+			advertised_addr,
+			dialing_enabled,
+			self_reachable: Arc::new(RwLock::new(None)),
+			reachability_probes: Arc::new(Mutex::new(ReachabilityProbes::default())),
+			reachability_checks: Arc::new(Mutex::new(HashMap::new())),
+			reachability_peeks: Arc::new(AtomicUsize::new(0)),
+		};
+		*server.advertised_capabilities.write() = server.effective_capabilities();
+		Ok(server)
 	}
 
 	/// Starts a new TCP server and listen to incoming connections. This is a
@@ -89,6 +136,12 @@ impl Server {
 		// start TCP listener and handle incoming connections
 		let addr = SocketAddr::new(self.config.host, self.config.port);
 		let listener = TcpListener::bind(addr)?;
+		self.listen_on(listener, header_cache_size)
+	}
+
+	/// Listen to incoming connections on an already bound TCP listener. This
+	/// is a blocking call until the TCP server stops.
+	pub fn listen_on(&self, listener: TcpListener, header_cache_size: u64) -> Result<(), Error> {
 		listener.set_nonblocking(true)?;
 
 		let sleep_time = Duration::from_millis(5);
@@ -129,20 +182,13 @@ impl Server {
 						_ => {}
 					}
 
-					if self.check_undesirable(&stream) {
-						// Shutdown the incoming TCP connection if it is not desired
-						if let Err(e) = stream.shutdown(Shutdown::Both) {
-							debug!("Error shutting down conn: {:?}", e);
-						}
-						continue;
-					}
-					match self.handle_new_peer(stream, header_cache_size) {
-						Err(Error::ConnectionClose) => debug!("shutting down, ignoring a new peer"),
-						Err(e) => {
-							debug!("Error accepting peer {}: {:?}", peer_addr.to_string(), e);
-							let _ = self.peers.add_banned(peer_addr, ReasonForBan::BadHandshake);
-						}
-						Ok(_) => {}
+					// A peer checking our listener is reachable only connects and
+					// sends a nonce, which may take a while to come: it's read off
+					// the listener thread so other connections aren't held up.
+					if self.awaits_reachability_check(&peer_addr) {
+						self.accept_reachability_check(stream, peer_addr, header_cache_size);
+					} else {
+						self.accept_peer(stream, peer_addr, header_cache_size);
 					}
 				}
 				Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -160,6 +206,18 @@ impl Server {
 		Ok(())
 	}
 
+	/// The local tor socks proxy we reach onion peers through, None if we
+	/// don't have one.
+	fn socks_proxy(&self) -> Option<SocketAddr> {
+		if self.socks_port == 0 {
+			return None;
+		}
+		Some(SocketAddr::new(
+			IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+			self.socks_port,
+		))
+	}
+
 	/// Asks the server to connect to a new peer. Directly returns the peer if
 	/// we're already connected to the provided address.
 	pub fn connect(&self, addr: PeerAddr, header_cache_size: u64) -> Result<Arc<Peer>, Error> {
@@ -167,32 +225,20 @@ impl Server {
 			return Err(Error::ConnectionClose);
 		}
 
+		// Checked first, our own address being denied doesn't make us a peer.
+		if self.is_self(&addr) {
+			debug!("connect: ignore connecting to PeerWithSelf, addr: {}", addr);
+			return Err(Error::PeerWithSelf);
+		}
+
 		if Peer::is_denied(&self.config, addr.clone()) {
 			debug!("connect_peer: peer {:?} denied, not connecting.", addr);
 			return Err(Error::ConnectionClose);
 		}
 
-		if global::is_production_mode() {
-			let hs = self.handshake.clone();
-			let addrs = hs.addrs.read();
-			if addrs.contains(&addr) {
-				debug!("connect: ignore connecting to PeerWithSelf, addr: {}", addr);
-				return Err(Error::PeerWithSelf);
-			}
-		}
-
-		// check if the onion address is self
-		if global::is_production_mode() && self.self_onion_address.is_some() {
-			match addr.clone() {
-				Onion(address) => {
-					if self.self_onion_address.as_ref().unwrap() == &address {
-						debug!("error trying to connect with self: {}", address);
-						return Err(Error::PeerWithSelf);
-					}
-					debug!("not self, connecting to {}", address);
-				}
-				_ => {}
-			}
+		if self.peers.is_parked(addr.clone()) {
+			debug!("connect_peer: peer {:?} parked, not connecting.", addr);
+			return Err(Error::ConnectionClose);
 		}
 
 		if let Some(p) = self.peers.get_connected_peer(addr.clone()) {
@@ -214,11 +260,14 @@ impl Server {
 		let stream = match addr.clone() {
 			PeerAddr::Ip(address) => {
 				// we do this, not a good solution, but for now, we'll use it. Other side usually detects with ip.
-				self_addr = PeerAddr::Ip(SocketAddr::new(self.config.host, self.config.port));
-				if self.socks_port != 0 {
+				// until our listener is known to be reachable the handshake tells
+				// peers we only dial out, older peers still get our real port
+				self_addr = match self.advertised_addr {
+					Some(PeerAddr::Ip(addr)) => PeerAddr::Ip(addr),
+					_ => PeerAddr::Ip(SocketAddr::new(self.config.host, self.config.port)),
+				};
+				if let Some(proxy_addr) = self.socks_proxy() {
 					peer_addr = Some(PeerAddr::Ip(address));
-					let proxy_addr =
-						SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), self.socks_port);
 					let socks5_stream_ref =
 						tor_stream::TorStream::connect_with_address(proxy_addr, address);
 					match socks5_stream_ref {
@@ -229,33 +278,38 @@ impl Server {
 					}
 				} else {
 					peer_addr = Some(PeerAddr::Ip(address));
-					TcpStream::connect_timeout(&address, Duration::from_secs(10))?
+					match self.config.outbound_bind {
+						Some(local) => connect_from(local, address, Duration::from_secs(10))?,
+						None => TcpStream::connect_timeout(&address, Duration::from_secs(10))?,
+					}
 				}
 			}
 			PeerAddr::Onion(onion_address) => {
-				if self.socks_port != 0 {
-					self_addr = PeerAddr::Onion(
-						self.self_onion_address
-							.as_ref()
-							.unwrap_or(&"unknown".to_string())
-							.to_string(),
-					);
-					peer_addr = Some(PeerAddr::Onion(onion_address.clone()));
-					let proxy_addr =
-						SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), self.socks_port);
-					let onion_target: socks::TargetAddr =
-						socks::TargetAddr::Domain(onion_address, 80);
-					let socks5_stream_ref =
-						tor_stream::TorStream::connect_with_address(proxy_addr, onion_target);
-					match socks5_stream_ref {
-						Ok(socks5_stream) => socks5_stream.unwrap(),
-						Err(e) => {
-							return Err(Error::Connection(e));
-						}
+				// can't connect to this if we don't have a socks proxy.
+				let proxy_addr = match addr.transport_addr(self.socks_proxy()) {
+					Some(proxy_addr) => proxy_addr,
+					None => return Err(Error::ConnectionClose),
+				};
+				self_addr = PeerAddr::Onion(
+					self.self_onion_address
+						.as_ref()
+						.unwrap_or(&"unknown".to_string())
+						.to_string(),
+				);
+				peer_addr = Some(PeerAddr::Onion(onion_address.clone()));
+				let (onion_host, onion_port) = match addr.onion_target() {
+					Some(target) => target,
+					None => return Err(Error::ConnectionClose),
+				};
+				let onion_target: socks::TargetAddr =
+					socks::TargetAddr::Domain(onion_host, onion_port);
+				let socks5_stream_ref =
+					tor_stream::TorStream::connect_with_address(proxy_addr, onion_target);
+				match socks5_stream_ref {
+					Ok(socks5_stream) => socks5_stream.unwrap(),
+					Err(e) => {
+						return Err(Error::Connection(e));
 					}
-				} else {
-					// can't connect to this because we don't have a socks proxy.
-					return Err(Error::ConnectionClose);
 				}
 			}
 		};
@@ -264,9 +318,9 @@ impl Server {
 			Ok(stream) => {
 				let total_diff = self.peers.total_difficulty()?;
 
-				let peer = Peer::connect(
+				let peer = match Peer::connect(
 					stream,
-					self.capabilities,
+					self.effective_capabilities(),
 					total_diff,
 					self_addr,
 					&self.handshake,
@@ -274,7 +328,16 @@ impl Server {
 					header_cache_size,
 					peer_addr,
 					(*self).clone(),
-				)?;
+				) {
+					Err(e @ Error::ImpossibleDifficulty { .. }) => {
+						debug!("connect_peer: {} refused, {}", addr, e);
+						let _ = self
+							.peers
+							.add_banned(addr.clone(), ReasonForBan::ImpossibleDifficulty);
+						return Err(e);
+					}
+					res => res?,
+				};
 				let peer = Arc::new(peer);
 				self.peers.add_connected(peer.clone())?;
 				Ok(peer)
@@ -292,6 +355,79 @@ impl Server {
 		}
 	}
 
+	/// Goes through the handshake with a new inbound connection, unless we
+	/// don't want it.
+	fn accept_peer(&self, stream: TcpStream, peer_addr: PeerAddr, header_cache_size: u64) {
+		if self.check_undesirable(&stream) {
+			// Shutdown the incoming TCP connection if it is not desired
+			if let Err(e) = stream.shutdown(Shutdown::Both) {
+				debug!("Error shutting down conn: {:?}", e);
+			}
+			return;
+		}
+		match self.handle_new_peer(stream, header_cache_size) {
+			Err(Error::ConnectionClose) => debug!("shutting down, ignoring a new peer"),
+			Err(Error::DuplicateConnection) => {
+				debug!("Refused duplicate connection from {}", peer_addr)
+			}
+			// Connecting to ourselves isn't the fault of any peer.
+			Err(Error::PeerWithSelf) => {
+				debug!("Refused connection with self from {}", peer_addr)
+			}
+			Err(Error::HandshakeTimeout) if !self.config.penalize_handshake_timeout() => {
+				debug!("Handshake with {} timed out, dropping it", peer_addr)
+			}
+			Err(Error::ImpossibleDifficulty { claimed, bound }) => {
+				debug!(
+					"Peer {} claims impossible difficulty {}, at most {}",
+					peer_addr, claimed, bound
+				);
+				let _ = self
+					.peers
+					.add_banned(peer_addr, ReasonForBan::ImpossibleDifficulty);
+			}
+			Err(e) => {
+				debug!("Error accepting peer {}: {:?}", peer_addr.to_string(), e);
+				let _ = self.peers.add_banned(peer_addr, ReasonForBan::BadHandshake);
+			}
+			Ok(_) => {}
+		}
+	}
+
+	/// Reads the nonce of the reachability check the connection may be on a
+	/// thread of its own, the connections that aren't one then go through
+	/// the handshake. Past MAX_REACHABILITY_PEEKS connections being read
+	/// from, they go through the handshake right away.
+	fn accept_reachability_check(
+		&self,
+		stream: TcpStream,
+		peer_addr: PeerAddr,
+		header_cache_size: u64,
+	) {
+		if self.reachability_peeks.fetch_add(1, Ordering::SeqCst) >= MAX_REACHABILITY_PEEKS {
+			self.reachability_peeks.fetch_sub(1, Ordering::SeqCst);
+			self.accept_peer(stream, peer_addr, header_cache_size);
+			return;
+		}
+		let server = self.clone();
+		let spawned = thread::Builder::new()
+			.name("reachability_check".to_string())
+			.spawn(move || {
+				let is_check = server.is_reachability_check(&stream, &peer_addr);
+				server.reachability_peeks.fetch_sub(1, Ordering::SeqCst);
+				if is_check {
+					debug!("Reachability check from {}", peer_addr);
+					let _ = stream.shutdown(Shutdown::Both);
+				} else {
+					server.accept_peer(stream, peer_addr, header_cache_size);
+				}
+			});
+		if let Err(e) = spawned {
+			self.reachability_peeks.fetch_sub(1, Ordering::SeqCst);
+			debug!("Couldn't start reading a reachability check: {:?}", e);
+		}
+	}
+
 	fn handle_new_peer(&self, stream: TcpStream, header_cache_size: u64) -> Result<(), Error> {
 		if self.stop_state.is_stopped() {
 			return Err(Error::ConnectionClose);
@@ -301,23 +437,306 @@ impl Server {
 		// accept the peer and add it to the server map
 		let peer = Peer::accept(
 			stream,
-			self.capabilities,
+			self.effective_capabilities(),
 			total_diff,
 			&self.handshake,
 			self.peers.clone(),
 			header_cache_size,
 			self.clone(),
 		)?;
-		self.peers.add_connected(Arc::new(peer))?;
+		let peer = Arc::new(peer);
+		if let Err(e) = self.peers.add_connected(peer.clone()) {
+			peer.stop();
+			return Err(e);
+		}
 		Ok(())
 	}
 
+	/// Our own capabilities, as configured at startup or set since, before
+	/// any of the reductions of effective_capabilities.
+	pub fn capabilities(&self) -> Capabilities {
+		*self.capabilities.read()
+	}
+
+	/// Changes our capabilities at runtime. New peers see them in the
+	/// handshake, connected ones get notified of what we now advertise.
+	pub fn set_capabilities(&self, capabilities: Capabilities) {
+		*self.capabilities.write() = capabilities;
+		self.refresh_capabilities();
+	}
+
+	/// Notifies our connected peers if our effective capabilities changed
+	/// since we last told them, entering or leaving maintenance or catching
+	/// up with the network for example. Returns whether they changed.
+	pub fn refresh_capabilities(&self) -> bool {
+		let capabilities = self.effective_capabilities();
+		{
+			let mut advertised = self.advertised_capabilities.write();
+			if *advertised == capabilities {
+				return false;
+			}
+			*advertised = capabilities;
+		}
+		self.peers.broadcast_capabilities(capabilities);
+		true
+	}
+
+	/// Capabilities we advertise to new peers. We stop offering header and
+	/// txhashset history while in maintenance so peers look elsewhere to sync,
+	/// txhashset history is never offered if we don't serve archives. While
+	/// still far from synced we only offer our peer list (and keep exchanging
+	/// tor addresses), peers connecting once we caught up see everything.
+	/// Noise encryption is offered whenever a pre-shared key is configured.
+	pub fn effective_capabilities(&self) -> Capabilities {
+		let noise = if self.config.noise_psk().is_some() {
+			Capabilities::NOISE
+		} else {
+			Capabilities::UNKNOWN
+		};
+		let capabilities = self.capabilities() - Capabilities::NOISE;
+		if self.config.headers_only() {
+			return capabilities & (Capabilities::HEADER_HIST | Capabilities::PEER_LIST) | noise;
+		}
+		if !self.synced_enough() {
+			return capabilities & (Capabilities::PEER_LIST | Capabilities::TOR_ADDRESS) | noise;
+		}
+		let capabilities = if self.config.serve_txhashset() {
+			capabilities
+		} else {
+			capabilities - Capabilities::TXHASHSET_HIST
+		};
+		if self.peers.in_maintenance() {
+			capabilities - (Capabilities::HEADER_HIST | Capabilities::TXHASHSET_HIST) | noise
+		} else {
+			capabilities | noise
+		}
+	}
+
+	/// Whether our chain is close enough to the most advanced peer's to serve
+	/// history. Before we know whether to sync at all we assume we're fine, a
+	/// node alone on its network would otherwise never advertise anything.
+	fn synced_enough(&self) -> bool {
+		let max_lag = self.config.full_capabilities_max_lag();
+		match self.peers.sync_status() {
+			chain::SyncStatus::HeaderSync {
+				current_height,
+				highest_height,
+			}
+			| chain::SyncStatus::BodySync {
+				current_height,
+				highest_height,
+			} => highest_height.saturating_sub(current_height) <= max_lag,
+			chain::SyncStatus::TxHashsetDownload(_)
+			| chain::SyncStatus::TxHashsetSetup
+			| chain::SyncStatus::TxHashsetKernelsValidation { .. }
+			| chain::SyncStatus::TxHashsetRangeProofsValidation { .. }
+			| chain::SyncStatus::TxHashsetSave
+			| chain::SyncStatus::TxHashsetDone => false,
+			chain::SyncStatus::Initial
+			| chain::SyncStatus::NoSync
+			| chain::SyncStatus::AwaitingPeers(_)
+			| chain::SyncStatus::Shutdown => true,
+		}
+	}
+
+	/// Our externally reachable address as advertised to peers, if we know it.
+	/// With reachability probes enabled only once a peer confirmed it.
+	pub fn advertised_addr(&self) -> Option<PeerAddr> {
+		match self.advertised_addr {
+			Some(PeerAddr::Ip(_)) if !self.advertises_listener() => None,
+			ref addr => addr.clone(),
+		}
+	}
+
+	/// Whether a peer confirmed our listener can be reached from outside,
+	/// None until one answered a reachability probe.
+	pub fn self_reachable(&self) -> Option<bool> {
+		*self.self_reachable.read()
+	}
+
+	/// Whether we tell peers we accept connections. Always unless we probe
+	/// our reachability, then only once confirmed.
+	pub fn advertises_listener(&self) -> bool {
+		self.config.reachability_probe_interval().is_none() || self.self_reachable() == Some(true)
+	}
+
+	/// Asks a few connected peers to check our listener is reachable from
+	/// outside if we didn't for reachability_probe_interval, forgetting the
+	/// checks that went unanswered. Returns whether we asked.
+	pub fn probe_reachability(&self, now: Instant) -> bool {
+		let interval = match self.config.reachability_probe_interval() {
+			Some(interval) => interval,
+			None => return false,
+		};
+		let mut probes = self.reachability_probes.lock();
+		probes.expire(now);
+		if let Some(outcome) = probes.outcome() {
+			self.set_self_reachable(outcome);
+		}
+		if let Some(at) = probes.last_round {
+			if now.saturating_duration_since(at) < interval {
+				return false;
+			}
+		}
+		let port = match self.advertised_addr {
+			Some(PeerAddr::Ip(addr)) => addr.port(),
+			_ => self.config.port,
+		};
+		let mut peers = self.peers.connected_peers();
+		peers.shuffle(&mut thread_rng());
+		let mut asked = vec![];
+		for peer in peers {
+			if asked.len() >= REACHABILITY_PROBE_PEERS {
+				break;
+			}
+			if let PeerAddr::Onion(_) = peer.info.addr {
+				continue;
+			}
+			let nonce = thread_rng().gen();
+			match peer.send_reachability_request(port, nonce) {
+				Ok(true) => {
+					debug!("probe_reachability: asked {}", peer.info.addr);
+					asked.push(PendingProbe {
+						nonce,
+						peer: peer.info.addr.clone(),
+						asked_at: now,
+						answer: None,
+					});
+				}
+				Ok(false) => {}
+				Err(e) => debug!(
+					"probe_reachability: failed to ask {}: {:?}",
+					peer.info.addr, e
+				),
+			}
+		}
+		if asked.is_empty() {
+			return false;
+		}
+		probes.last_round = Some(now);
+		probes.pending = asked;
+		probes.answers.clear();
+		true
+	}
+
+	/// Records the answer of a peer to one of our reachability checks,
+	/// ignored if we didn't ask that peer for it. A peer saying it could
+	/// connect only counts once we saw it connect.
+	pub fn reachability_checked(&self, addr: &PeerAddr, nonce: u64, reachable: bool) {
+		let mut probes = self.reachability_probes.lock();
+		let index = probes
+			.pending
+			.iter()
+			.position(|p| p.nonce == nonce && p.peer == *addr);
+		let index = match index {
+			Some(index) => index,
+			None => {
+				debug!(
+					"reachability_checked: no pending check {} for {}",
+					nonce, addr
+				);
+				return;
+			}
+		};
+		if reachable {
+			probes.pending[index].answer = Some(true);
+			return;
+		}
+		probes.pending.remove(index);
+		probes.answers.push(false);
+		if let Some(outcome) = probes.outcome() {
+			self.set_self_reachable(outcome);
+		}
+	}
+
+	fn set_self_reachable(&self, reachable: bool) {
+		let mut self_reachable = self.self_reachable.write();
+		if *self_reachable != Some(reachable) {
+			info!(
+				"Our listener is {} according to our peers",
+				if reachable {
+					"reachable"
+				} else {
+					"not reachable"
+				}
+			);
+		}
+		*self_reachable = Some(reachable);
+		self.handshake.set_listening(self.advertises_listener());
+	}
+
+	// Whether we wait for a peer at the ip of the incoming connection to
+	// connect back to us for a reachability check.
+	fn awaits_reachability_check(&self, addr: &PeerAddr) -> bool {
+		let ip = match ip_of(addr) {
+			Some(ip) => ip,
+			None => return false,
+		};
+		let mut probes = self.reachability_probes.lock();
+		probes.expire(Instant::now());
+		probes.pending.iter().any(|p| ip_of(&p.peer) == Some(ip))
+	}
+
+	// Whether the incoming connection is a peer we asked for a reachability
+	// check connecting back to us, it only sends the nonce of the check.
+	// Only called for the ips awaits_reachability_check expects.
+	fn is_reachability_check(&self, stream: &TcpStream, addr: &PeerAddr) -> bool {
+		let ip = match ip_of(addr) {
+			Some(ip) => ip,
+			None => return false,
+		};
+		let nonce = match peek_nonce(stream) {
+			Some(nonce) => nonce,
+			None => return false,
+		};
+		let mut probes = self.reachability_probes.lock();
+		let index = probes
+			.pending
+			.iter()
+			.position(|p| p.nonce == nonce && ip_of(&p.peer) == Some(ip));
+		match index {
+			Some(index) => {
+				probes.pending.remove(index);
+				probes.answers.push(true);
+				if let Some(outcome) = probes.outcome() {
+					self.set_self_reachable(outcome);
+				}
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Whether we may dial back the peer ip to check its reachability, at
+	/// most once per interval whatever the connection it asks from.
+	pub fn reachability_check_due(&self, ip: IpAddr, interval: Duration, now: Instant) -> bool {
+		let mut checks = self.reachability_checks.lock();
+		checks.retain(|_, at| now.saturating_duration_since(*at) < interval);
+		if checks.contains_key(&ip) {
+			return false;
+		}
+		checks.insert(ip, now);
+		true
+	}
+
+	/// Whether the address is our own: our onion address, or (in production)
+	/// one we detected a connection to ourselves on.
+	pub fn is_self(&self, addr: &PeerAddr) -> bool {
+		if let Onion(ref address) = addr {
+			if self.self_onion_address.as_ref() == Some(address) {
+				return true;
+			}
+		}
+		global::is_production_mode() && self.handshake.addrs.read().contains(addr)
+	}
+
 	/// Checks whether there's any reason we don't want to accept an incoming peer
 	/// connection. There can be a few of them:
 	/// 1. Accepting the peer connection would exceed the configured maximum allowed
 	/// inbound peer count. Note that seed nodes may wish to increase the default
 	/// value for PEER_LISTENER_BUFFER_COUNT to help with network bootstrapping.
-	/// A default buffer of 8 peers is allowed to help with network growth.
+	/// A default buffer of 8 peers is allowed to help with network growth,
+	/// but none when the maximum is 0 (outbound only).
 	/// 2. The peer has been previously banned and the ban period hasn't
 	/// expired yet.
 	/// 3. We're already connected to a peer at the same IP. While there are
@@ -326,22 +745,33 @@ impl Server {
 	/// different sets of peers themselves. In addition, it prevent potential
 	/// duplicate connections, malicious or not.
 	fn check_undesirable(&self, stream: &TcpStream) -> bool {
-		if self.peers.peer_inbound_count()
-			>= self.config.peer_max_inbound_count() + self.config.peer_listener_buffer_count()
-		{
+		if self.peers.peer_inbound_count() >= self.config.inbound_accept_limit() {
 			debug!("Accepting new connection will exceed peer limit, refusing connection.");
 			return true;
 		}
+		if self.peers.refuse_inbound_under_load() {
+			debug!("Under load, refusing connection.");
+			return true;
+		}
 		if let Ok(peer_addr) = stream.peer_addr() {
 			let peer_addr = PeerAddr::Ip(peer_addr.clone());
 			if self.peers.is_banned(peer_addr.clone()) {
 				debug!("Peer {} banned, refusing connection.", peer_addr);
 				return true;
 			}
+			if self.peers.is_parked(peer_addr.clone()) {
+				debug!("Peer {} parked, refusing connection.", peer_addr);
+				return true;
+			}
 			// The call to is_known() can fail due to contention on the peers map.
 			// If it fails we want to default to refusing the connection.
 			match self.peers.is_known(peer_addr.clone()) {
-				Ok(true) => {
+				// Under replace_old the handshake decides whether this connection
+				// takes over from the existing one.
+				Ok(true)
+					if self.config.duplicate_inbound_policy()
+						== DuplicateConnectionPolicy::RejectNew =>
+				{
 					debug!("Peer {} already known, refusing connection.", peer_addr);
 					return true;
 				}
@@ -358,6 +788,22 @@ impl Server {
 		false
 	}
 
+	/// Enables or disables dialing of new outbound peers by the peer
+	/// maintenance. Existing connections and inbound ones aren't affected.
+	pub fn set_dialing_enabled(&self, enabled: bool) {
+		if self.dialing_enabled.swap(enabled, Ordering::Relaxed) != enabled {
+			info!(
+				"Outbound peer dialing {}",
+				if enabled { "enabled" } else { "disabled" }
+			);
+		}
+	}
+
+	/// Whether the peer maintenance dials new outbound peers.
+	pub fn is_dialing_enabled(&self) -> bool {
+		self.dialing_enabled.load(Ordering::Relaxed)
+	}
+
 	pub fn stop(&self) {
 		self.stop_state.stop();
 		self.peers.stop();
@@ -372,6 +818,95 @@ impl Server {
 	}
 }
 
+/// A reachability check we asked a peer for, until it connected back or
+/// answered it couldn't.
+struct PendingProbe {
+	nonce: u64,
+	peer: PeerAddr,
+	asked_at: Instant,
+	// whether the peer said it could connect, while we didn't see it yet
+	answer: Option<bool>,
+}
+
+/// Our reachability checks in flight and the answers of the latest round.
+#[derive(Default)]
+struct ReachabilityProbes {
+	last_round: Option<Instant>,
+	pending: Vec<PendingProbe>,
+	answers: Vec<bool>,
+}
+
+impl ReachabilityProbes {
+	/// Forgets the checks that timed out, a peer that said it connected
+	/// without us ever seeing it counts as not reaching us.
+	fn expire(&mut self, now: Instant) {
+		let answers = &mut self.answers;
+		self.pending.retain(|p| {
+			if now.saturating_duration_since(p.asked_at) < REACHABILITY_PROBE_TIMEOUT {
+				return true;
+			}
+			if p.answer == Some(true) {
+				answers.push(false);
+			}
+			false
+		});
+	}
+
+	/// Whether enough answers of the round agree on our listener being
+	/// reachable, or not.
+	fn outcome(&self) -> Option<bool> {
+		let reachable = self.answers.iter().filter(|a| **a).count();
+		let unreachable = self.answers.len() - reachable;
+		if reachable >= REACHABILITY_AGREEING_PEERS && reachable > unreachable {
+			Some(true)
+		} else if unreachable >= REACHABILITY_AGREEING_PEERS && unreachable > reachable {
+			Some(false)
+		} else {
+			None
+		}
+	}
+}
+
+fn ip_of(addr: &PeerAddr) -> Option<IpAddr> {
+	match addr {
+		PeerAddr::Ip(addr) => Some(addr.ip()),
+		PeerAddr::Onion(_) => None,
+	}
+}
+
+/// Reads the nonce a peer checking our listener sends first, without
+/// consuming anything so any other connection can go through the handshake.
+/// The stream is left without a read timeout, as accepted.
+fn peek_nonce(stream: &TcpStream) -> Option<u64> {
+	let deadline = Instant::now() + REACHABILITY_NONCE_TIMEOUT;
+	let mut buf = [0u8; 8];
+	let _ = stream.set_read_timeout(Some(REACHABILITY_NONCE_TIMEOUT));
+	let nonce = loop {
+		match stream.peek(&mut buf) {
+			Ok(8) => break Some(u64::from_be_bytes(buf)),
+			Ok(0) | Err(_) => break None,
+			Ok(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+			Ok(_) => break None,
+		}
+	};
+	let _ = stream.set_read_timeout(None);
+	nonce
+}
+
+/// Connect to the address from the provided local one (on any port), so the
+/// connection egresses through the matching interface.
+fn connect_from(local: IpAddr, address: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+	let domain = if address.is_ipv4() {
+		Domain::ipv4()
+	} else {
+		Domain::ipv6()
+	};
+	let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+	socket.bind(&SocketAddr::new(local, 0).into())?;
+	socket.connect_timeout(&address.into(), timeout)?;
+	Ok(socket.into_tcp_stream())
+}
+
 /// A no-op network adapter used for testing.
 pub struct DummyAdapter {}
 
@@ -415,8 +950,8 @@ impl ChainAdapter for DummyAdapter {
 		_: core::Block,
 		_: &PeerInfo,
 		_: chain::Options,
-	) -> Result<bool, chain::Error> {
-		Ok(true)
+	) -> Result<BlockAccept, chain::Error> {
+		Ok(BlockAccept::Accepted)
 	}
 	fn headers_received(
 		&self,
@@ -429,6 +964,9 @@ impl ChainAdapter for DummyAdapter {
 	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::BlockHeader>, chain::Error> {
 		Ok(vec![])
 	}
+	fn headers_by_height(&self, _: u64, _: u32) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
 	fn get_block(&self, _: Hash, _: &PeerInfo) -> Option<core::Block> {
 		None
 	}
@@ -452,6 +990,10 @@ impl ChainAdapter for DummyAdapter {
 		false
 	}
 
+	fn in_maintenance(&self) -> bool {
+		false
+	}
+
 	fn txhashset_write(
 		&self,
 		_h: Hash,
@@ -488,4 +1030,9 @@ impl NetAdapter for DummyAdapter {
 	fn is_banned(&self, _: PeerAddr) -> bool {
 		false
 	}
+	fn peer_ser_error(&self, _: PeerAddr) {}
+	fn txhashset_archive_acceptable(&self, _: u64) -> bool {
+		true
+	}
+	fn request_declined(&self, _: PeerAddr, _: msg::Type, _: msg::DeclineReason) {}
 }
@@ -17,16 +17,22 @@
 use chrono::Utc;
 use num::FromPrimitive;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::Rng;
 
 use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
-use crate::types::{Capabilities, PeerAddr, ReasonForBan};
+use crate::types::{Capabilities, IpPrefix, PeerAddr, ReasonForBan};
 use grin_store::{self, option_to_not_found, to_key, Error};
 
 const DB_NAME: &str = "peerV2";
 const STORE_SUBPATH: &str = "peers";
 
 const PEER_PREFIX: u8 = b'P';
+const ANCHOR_PREFIX: u8 = b'A';
+const SUBNET_BAN_PREFIX: u8 = b'S';
+
+/// Version of the PeerData layout we write. Records from before versioning
+/// end at last_connected (or earlier) and count as version 0.
+pub const PEER_DATA_VERSION: u8 = 1;
 
 // Types of messages
 enum_from_primitive! {
@@ -56,6 +62,60 @@ pub struct PeerData {
 	pub ban_reason: ReasonForBan,
 	/// Time when we last connected to this peer.
 	pub last_connected: i64,
+	/// Reputation score kept for the peer, 0 when unknown.
+	pub score: i32,
+}
+
+/// Portable form of the data we keep on a peer, as exported to and imported
+/// from json by Peers::export_json and Peers::import_json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedPeer {
+	/// Address of the peer, as displayed (ip:port or onion address).
+	pub addr: String,
+	/// Bits of the capabilities the peer advertises.
+	pub capabilities: u32,
+	pub user_agent: String,
+	pub state: State,
+	pub last_banned: i64,
+	pub ban_reason: ReasonForBan,
+	/// Time when we last connected to this peer.
+	pub last_connected: i64,
+	/// Reputation score, 0 if missing from the import.
+	#[serde(default)]
+	pub score: i32,
+}
+
+impl From<&PeerData> for ExportedPeer {
+	fn from(peer: &PeerData) -> ExportedPeer {
+		ExportedPeer {
+			addr: peer.addr.to_string(),
+			capabilities: peer.capabilities.bits(),
+			user_agent: peer.user_agent.clone(),
+			state: peer.flags,
+			last_banned: peer.last_banned,
+			ban_reason: peer.ban_reason,
+			last_connected: peer.last_connected,
+			score: peer.score,
+		}
+	}
+}
+
+impl ExportedPeer {
+	/// Back to the data we store, None if the address doesn't parse.
+	/// Capabilities we don't know about are dropped.
+	pub fn to_peer_data(&self) -> Option<PeerData> {
+		let addr = PeerAddr::parse_checked(&self.addr)?;
+		Some(PeerData {
+			addr,
+			capabilities: Capabilities::from_bits_truncate(self.capabilities),
+			user_agent: self.user_agent.clone(),
+			flags: self.state,
+			last_banned: self.last_banned,
+			ban_reason: self.ban_reason,
+			last_connected: self.last_connected,
+			score: self.score,
+		})
+	}
 }
 
 impl Writeable for PeerData {
@@ -74,7 +134,9 @@ impl Writeable for PeerData {
 			[write_u8, self.flags as u8],
 			[write_i64, self.last_banned],
 			[write_i32, self.ban_reason as i32],
-			[write_i64, self.last_connected]
+			[write_i64, self.last_connected],
+			[write_u8, PEER_DATA_VERSION],
+			[write_i32, self.score]
 		);
 		Ok(())
 	}
@@ -87,13 +149,14 @@ impl Readable for PeerData {
 		let ua = reader.read_bytes_len_prefix()?;
 		let (fl, lb, br) = ser_multiread!(reader, read_u8, read_i64, read_i32);
 
-		let lc = reader.read_i64();
-		// this only works because each PeerData is read in its own vector and this
-		// is the last data element
-		let last_connected = match lc {
-			Err(_) => Utc::now().timestamp(),
-			Ok(lc) => lc,
+		// this only works because each PeerData is read in its own vector, older
+		// records simply end early and get defaults for what they lack
+		let (last_connected, version) = match reader.read_i64() {
+			Err(_) => (Utc::now().timestamp(), 0),
+			Ok(lc) => (lc, reader.read_u8().unwrap_or(0)),
 		};
+		// fields added by later versions we don't know about are left unread
+		let score = if version >= 1 { reader.read_i32()? } else { 0 };
 
 		let user_agent = String::from_utf8(ua)
 			.map_err(|e| ser::Error::CorruptedData(format!("Fail to read user agent, {}", e)))?;
@@ -111,6 +174,7 @@ impl Readable for PeerData {
 				last_banned: lb,
 				ban_reason,
 				last_connected,
+				score,
 			}),
 			None => Err(ser::Error::CorruptedData(
 				"Unable to read PeerData State".to_string(),
@@ -119,6 +183,54 @@ impl Readable for PeerData {
 	}
 }
 
+/// A whole subnet we don't accept connections from, nor connect to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubnetBan {
+	/// The banned network.
+	pub prefix: IpPrefix,
+	pub ban_reason: ReasonForBan,
+	/// When the ban started.
+	pub banned_at: i64,
+	/// How long (in seconds) the ban lasts.
+	pub duration: i64,
+}
+
+impl SubnetBan {
+	/// Whether the ban is over at the provided time (in seconds).
+	pub fn expired(&self, now: i64) -> bool {
+		now >= self.banned_at.saturating_add(self.duration)
+	}
+}
+
+impl Writeable for SubnetBan {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.prefix.write(writer)?;
+		ser_multiwrite!(
+			writer,
+			[write_i32, self.ban_reason as i32],
+			[write_i64, self.banned_at],
+			[write_i64, self.duration]
+		);
+		Ok(())
+	}
+}
+
+impl Readable for SubnetBan {
+	fn read<R: Reader>(reader: &mut R) -> Result<SubnetBan, ser::Error> {
+		let prefix = IpPrefix::read(reader)?;
+		let (br, banned_at, duration) = ser_multiread!(reader, read_i32, read_i64, read_i64);
+		let ban_reason = ReasonForBan::from_i32(br).ok_or(ser::Error::CorruptedData(
+			"Unable to read SubnetBan ban reason".to_string(),
+		))?;
+		Ok(SubnetBan {
+			prefix,
+			ban_reason,
+			banned_at,
+			duration,
+		})
+	}
+}
+
 /// Storage facility for peer data.
 pub struct PeerStore {
 	db: grin_store::Store,
@@ -149,19 +261,18 @@ impl PeerStore {
 		self.db.exists(&peer_key(peer_addr)[..])
 	}
 
-	/// TODO - allow below added to avoid github issue reports
-	#[allow(dead_code)]
 	pub fn delete_peer(&self, peer_addr: PeerAddr) -> Result<(), Error> {
 		let batch = self.db.batch()?;
 		batch.delete(&peer_key(peer_addr)[..])?;
 		batch.commit()
 	}
 
-	pub fn find_peers(
+	pub fn find_peers<R: Rng + ?Sized>(
 		&self,
 		state: State,
 		cap: Capabilities,
 		count: usize,
+		rng: &mut R,
 	) -> Result<Vec<PeerData>, Error> {
 		let mut peers = self
 			.db
@@ -169,7 +280,7 @@ impl PeerStore {
 			.map(|(_, v)| v)
 			.filter(|p| p.flags == state && p.capabilities.contains(cap))
 			.collect::<Vec<_>>();
-		peers[..].shuffle(&mut thread_rng());
+		peers[..].shuffle(rng);
 		Ok(peers.iter().take(count).cloned().collect())
 	}
 
@@ -184,6 +295,75 @@ impl PeerStore {
 			.collect::<Vec<_>>())
 	}
 
+	/// List the anchor peers persisted on our previous run.
+	pub fn anchors(&self) -> Result<Vec<PeerAddr>, Error> {
+		let key = to_key(ANCHOR_PREFIX, "");
+		Ok(self
+			.db
+			.iter::<PeerAddr>(&key)?
+			.map(|(_, v)| v)
+			.collect::<Vec<_>>())
+	}
+
+	/// Replaces the persisted anchor peers with the provided addresses.
+	pub fn save_anchors(&self, anchors: &[PeerAddr]) -> Result<(), Error> {
+		let old_anchors = self.anchors()?;
+
+		let batch = self.db.batch()?;
+		for addr in old_anchors {
+			batch.delete(&anchor_key(addr)[..])?;
+		}
+		for addr in anchors {
+			batch.put_ser(&anchor_key(addr.clone())[..], addr)?;
+		}
+		batch.commit()
+	}
+
+	/// List all the subnet bans, expired ones included.
+	pub fn subnet_bans(&self) -> Result<Vec<SubnetBan>, Error> {
+		let key = to_key(SUBNET_BAN_PREFIX, "");
+		Ok(self
+			.db
+			.iter::<SubnetBan>(&key)?
+			.map(|(_, v)| v)
+			.collect::<Vec<_>>())
+	}
+
+	pub fn get_subnet_ban(&self, prefix: &IpPrefix) -> Result<Option<SubnetBan>, Error> {
+		self.db.get_ser(&subnet_ban_key(prefix)[..])
+	}
+
+	/// Saves the provided subnet bans in a single batch, replacing the ones of
+	/// the same subnets.
+	pub fn save_subnet_bans(&self, bans: &[SubnetBan]) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+		for ban in bans {
+			batch.put_ser(&subnet_ban_key(&ban.prefix)[..], ban)?;
+		}
+		batch.commit()
+	}
+
+	/// Deletes the subnet bans satisfying `predicate`, returns the number of
+	/// bans deleted.
+	pub fn delete_subnet_bans<F>(&self, predicate: F) -> Result<usize, Error>
+	where
+		F: Fn(&SubnetBan) -> bool,
+	{
+		let to_remove = self
+			.subnet_bans()?
+			.into_iter()
+			.filter(|ban| predicate(ban))
+			.collect::<Vec<_>>();
+		if !to_remove.is_empty() {
+			let batch = self.db.batch()?;
+			for ban in &to_remove {
+				batch.delete(&subnet_ban_key(&ban.prefix)[..])?;
+			}
+			batch.commit()?;
+		}
+		Ok(to_remove.len())
+	}
+
 	/// Convenience method to load a peer data, update its status and save it
 	/// back. If new state is Banned its last banned time will be updated too.
 	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
@@ -202,8 +382,9 @@ impl PeerStore {
 		batch.commit()
 	}
 
-	/// Deletes peers from the storage that satisfy some condition `predicate`
-	pub fn delete_peers<F>(&self, predicate: F) -> Result<(), Error>
+	/// Deletes peers from the storage that satisfy some condition `predicate`,
+	/// returns the number of peers deleted
+	pub fn delete_peers<F>(&self, predicate: F) -> Result<usize, Error>
 	where
 		F: Fn(&PeerData) -> bool,
 	{
@@ -216,6 +397,7 @@ impl PeerStore {
 		}
 
 		// Delete peers in single batch
+		let count = to_remove.len();
 		if !to_remove.is_empty() {
 			let batch = self.db.batch()?;
 
@@ -226,7 +408,7 @@ impl PeerStore {
 			batch.commit()?;
 		}
 
-		Ok(())
+		Ok(count)
 	}
 }
 
@@ -234,3 +416,11 @@ impl PeerStore {
 fn peer_key(peer_addr: PeerAddr) -> Vec<u8> {
 	to_key(PEER_PREFIX, &peer_addr.as_key())
 }
+
+fn anchor_key(peer_addr: PeerAddr) -> Vec<u8> {
+	to_key(ANCHOR_PREFIX, &peer_addr.as_key())
+}
+
+fn subnet_ban_key(prefix: &IpPrefix) -> Vec<u8> {
+	to_key(SUBNET_BAN_PREFIX, &prefix.to_string())
+}
@@ -15,6 +15,7 @@
 use crate::types::PeerAddr::Ip;
 use crate::types::PeerAddr::Onion;
 use failure::Fail;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::From;
 use std::fmt;
 use std::fs::File;
@@ -28,19 +29,19 @@ use std::sync::Mutex;
 use chrono::prelude::*;
 use serde::de::{SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer};
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use grin_store;
 
 use crate::chain;
 use crate::core::core;
 use crate::core::core::hash::Hash;
-use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
-use crate::msg::PeerAddrs;
-use crate::util::RwLock;
-use std::time::Instant;
+use crate::core::{consensus, global};
+use crate::msg::{self, PeerAddrs};
+use crate::util::{self, RwLock};
+use std::time::{Duration, Instant};
 
 /// Maximum number of block headers a peer should ever send
 pub const MAX_BLOCK_HEADERS: u32 = 512;
@@ -55,18 +56,166 @@ pub const MAX_PEER_ADDRS: u32 = 256;
 /// Maximum number of block header hashes to send as part of a locator
 pub const MAX_LOCATORS: u32 = 20;
 
+/// Number of headers we first ask a peer for during sync, grown by the same
+/// amount with every prompt answer up to MAX_BLOCK_HEADERS
+pub const HEADER_BATCH_START: u32 = 32;
+
+/// Smallest header batch we back off to on timeouts
+pub const HEADER_BATCH_MIN: u32 = 8;
+
+/// How far (in seconds) a header timestamp may be ahead of our clock before we
+/// consider it to be coming from the future, same as UntrustedBlockHeader.
+pub const FUTURE_TIMESTAMP_ALLOWANCE: i64 = 12 * consensus::BLOCK_TIME_SEC as i64;
+
+/// How long a peer is kept from eviction after we ask it for headers, unless
+/// they arrive earlier
+pub const HEADERS_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a peer is kept from eviction after we ask it for a txhashset
+/// archive, unless the download completes earlier
+pub const TXHASHSET_BUSY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Disk space needed to unzip a txhashset archive, in multiples of the archive
+/// size. Kept on top of the archive itself while it's being extracted.
+pub const TXHASHSET_UNZIP_OVERHEAD: u64 = 2;
+
+/// Longest uptime we believe a peer claims, anything above is capped to it
+pub const MAX_PEER_UPTIME: Duration = Duration::from_secs(10 * 365 * 24 * 3600);
+
 /// How long a banned peer should be banned for
 const BAN_WINDOW: i64 = 10800;
 
+/// Number of malformed messages within SER_ERROR_WINDOW that gets a peer banned
+const SER_ERROR_BAN_THRESHOLD: u32 = 5;
+
+/// Window (in seconds) over which malformed messages from a peer are counted
+const SER_ERROR_WINDOW: i64 = 600;
+
+/// Number of header batches on a losing minority fork after which a peer gets
+/// parked
+const MINORITY_FORK_THRESHOLD: u32 = 3;
+
+/// How long (in seconds) a peer on a losing minority fork stays parked
+const MINORITY_FORK_COOLDOWN: i64 = 1800;
+
+/// How many blocks behind the most advanced peer we may be while syncing and
+/// still advertise our full capabilities
+const FULL_CAPABILITIES_MAX_LAG: u64 = 60;
+
+/// Number of messages queued for a peer beyond which we skip it when relaying
+/// blocks
+const MAX_RELAY_QUEUE: u32 = 10;
+
+/// How many times we download the txhashset again after one proved corrupt
+const MAX_TXHASHSET_REDOWNLOADS: u32 = 3;
+
+/// Number of declined requests for a capability a peer advertised before we
+/// flag it, as long as it declines most of them
+const CAPABILITY_REFUSAL_THRESHOLD: u32 = 3;
+
+/// Minimum interval (in seconds) between fresh answers to a peer asking for
+/// peer addresses
+const PEER_ADDRS_REQUEST_INTERVAL: u64 = 60;
+
+/// Interval (in seconds) between batches of received peer addresses passed on
+/// to our peers
+const PEER_ADDRS_REGOSSIP_INTERVAL: u64 = 600;
+
+/// Maximum number of received peer addresses passed on per batch
+const PEER_ADDRS_REGOSSIP_CAP: u32 = 32;
+
+/// How long (in seconds) we may stay short of our preferred outbound peer
+/// count before warning about it
+const OUTBOUND_DEFICIT_WARN_AFTER: i64 = 300;
+
+/// Load factor above which we start refusing some new inbound connections
+const INBOUND_LOAD_THRESHOLD: f64 = 0.5;
+
+/// How long (in seconds) we wait before dialing a peer we got disconnected
+/// from again
+const PEER_MIN_RECONNECT_INTERVAL: i64 = 60;
+
+/// Same as PEER_MIN_RECONNECT_INTERVAL, for our preferred peers
+const PEER_MIN_PREFERRED_RECONNECT_INTERVAL: i64 = 10;
+
+/// Interval (in seconds) between keepalives on the connections they're
+/// enabled for
+const KEEPALIVE_INTERVAL: u64 = 30;
+
+/// Interval (in seconds) between asking long-lived connections for their
+/// current capabilities
+const CAPABILITIES_PROBE_INTERVAL: u64 = 6 * 3600;
+
+/// Number of headers past our validated tip we take from a single peer
+/// without another peer claiming as much work
+const MAX_UNCORROBORATED_HEADERS: u64 = 100_000;
+
+/// How many times we process again a block the chain failed on for a
+/// transient reason
+const BLOCK_ERROR_RETRIES: u32 = 2;
+
+/// Peers that served us valid data within this many seconds of the most
+/// recent one are equally useful when picking a sync peer
+const USEFUL_PEER_WINDOW: i64 = 60;
+
+/// Number of blocks back from our header head the network difficulty is
+/// estimated over, so it reflects the current one
+pub const DIFFICULTY_ESTIMATE_WINDOW: u64 = 60;
+
+/// How many blocks the height a peer advertises may drop by, as on a reorg,
+/// before the peer gets flagged
+const HEIGHT_REGRESSION_TOLERANCE: u64 = 60;
+
+/// Number of gossiped onion addresses we store, and consider dialing at once
+const MAX_ONION_PEERS: u32 = 256;
+
+/// Minimum interval (in seconds) between two onion peer dials, circuit setup
+/// being costly
+const ONION_DIAL_INTERVAL: i64 = 10;
+
+/// Number of bad compact blocks within BAD_COMPACT_BLOCK_WINDOW that gets a
+/// peer banned
+const BAD_COMPACT_BLOCK_BAN_THRESHOLD: u32 = 3;
+
+/// Window (in seconds) over which bad compact blocks from a peer are counted
+const BAD_COMPACT_BLOCK_WINDOW: i64 = 3600;
+
+/// Time (in seconds) a peer has to complete the handshake before we drop it
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Time (in seconds) a peer has after connecting to report its difficulty
+/// and height before we consider it silent
+const SILENT_PEER_GRACE_SECS: i64 = 120;
+
+/// Total size (in bytes) of the message bodies we read from all peers at once
+/// before large reads pause
+const MAX_INBOUND_BUFFER_BYTES: u64 = 256 * 1024 * 1024;
+
+/// How long (in seconds) an orphan block stays known, its deliveries by other
+/// peers meanwhile aren't handed to the chain again
+const ORPHAN_TRACKING_WINDOW: i64 = 60;
+
+/// Maximum number of orphan blocks tracked at once
+const MAX_TRACKED_ORPHANS: u32 = 256;
+
 /// The max inbound peer count
 const PEER_MAX_INBOUND_COUNT: u32 = 128;
 
 /// The max outbound peer count
 const PEER_MAX_OUTBOUND_COUNT: u32 = 8;
 
+/// Lower bound of the recommended outbound peer count
+pub const MIN_RECOMMENDED_OUTBOUND_COUNT: u32 = 4;
+
+/// Upper bound of the recommended outbound peer count
+pub const MAX_RECOMMENDED_OUTBOUND_COUNT: u32 = 32;
+
 /// The min preferred outbound peer count
 const PEER_MIN_PREFERRED_OUTBOUND_COUNT: u32 = 8;
 
+/// The number of outbound peers we persist as anchors across restarts
+const ANCHOR_PEER_COUNT: u32 = 2;
+
 /// The peer listener buffer count. Allows temporarily accepting more connections
 /// than allowed by PEER_MAX_INBOUND_COUNT to encourage network bootstrapping.
 const PEER_LISTENER_BUFFER_COUNT: u32 = 8;
@@ -88,16 +237,28 @@ pub enum Error {
 	ConnectionClose,
 	#[fail(display = "p2p timeout")]
 	Timeout,
+	#[fail(display = "p2p handshake timeout")]
+	HandshakeTimeout,
 	#[fail(display = "p2p store error, {}", _0)]
 	Store(grin_store::Error),
 	#[fail(display = "p2p chain error, {}", _0)]
 	Chain(chain::Error),
 	#[fail(display = "peer with self")]
 	PeerWithSelf,
+	#[fail(display = "p2p duplicate connection")]
+	DuplicateConnection,
 	#[fail(display = "p2p no dandelion relay")]
 	NoDandelionRelay,
 	#[fail(display = "p2p genesis mismatch: {} vs peer {}", us, peer)]
 	GenesisMismatch { us: Hash, peer: Hash },
+	#[fail(
+		display = "p2p impossible difficulty: peer claims {}, at most {}",
+		claimed, bound
+	)]
+	ImpossibleDifficulty {
+		claimed: Difficulty,
+		bound: Difficulty,
+	},
 	#[fail(display = "p2p send error, {}", _0)]
 	Send(String),
 	#[fail(display = "peer not found")]
@@ -110,6 +271,20 @@ pub enum Error {
 	Internal(String),
 	#[fail(display = "libp2p error: {}", _0)]
 	Libp2pError(String),
+	#[fail(display = "p2p noise error: {}", _0)]
+	Noise(String),
+	#[fail(display = "p2p admin action not authorized")]
+	Unauthorized,
+	#[fail(
+		display = "p2p {:?} unsupported on protocol version {}",
+		msg_type, version
+	)]
+	UnsupportedMessage {
+		msg_type: msg::Type,
+		version: ProtocolVersion,
+	},
+	#[fail(display = "p2p invalid ip prefix: {}", _0)]
+	InvalidIpPrefix(String),
 }
 
 impl From<ser::Error> for Error {
@@ -133,6 +308,42 @@ impl From<io::Error> for Error {
 	}
 }
 
+/// Scheme prefixed to onion addresses when displayed.
+const TOR_SCHEME: &str = "tor://";
+
+/// Port onion peers accept p2p connections on, our hidden service maps it to
+/// the p2p listener.
+pub const ONION_P2P_PORT: u16 = 80;
+
+/// Where we got the address of an outbound peer from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SeedSource {
+	/// Resolved from the DNS seed with this name
+	Dns(String),
+	/// Configured seed or preferred peer
+	List,
+	/// Outbound peer of our previous run
+	Anchor,
+	/// Learned from other peers
+	Gossip,
+}
+
+/// Kind of network a peer address belongs to, see PeerAddr::network_class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkClass {
+	Ipv4Public,
+	/// Private (RFC 1918) and link local ranges
+	Ipv4Private,
+	Ipv6Public,
+	/// Unique local addresses
+	Ipv6Private,
+	Ipv6LinkLocal,
+	Loopback,
+	Onion,
+	/// Unspecified, broadcast, multicast, documentation or otherwise unusable
+	Reserved,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PeerAddr {
 	Ip(SocketAddr),
@@ -234,13 +445,33 @@ impl<'de> Deserialize<'de> for PeerAddrs {
 	}
 }
 
+/// Whether peers at this address are told apart by port as well as ip. Only
+/// loopback ones are, several local nodes on different ports being distinct
+/// peers. An IPv4-mapped IPv6 loopback counts as loopback too. Hash and Eq of
+/// PeerAddr must both go by this to stay consistent.
+fn is_port_sensitive(addr: &SocketAddr) -> bool {
+	match addr.ip() {
+		IpAddr::V4(ip) => ip.is_loopback(),
+		IpAddr::V6(ip) => {
+			ip.is_loopback()
+				|| match ip.segments() {
+					[0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+						Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8)
+							.is_loopback()
+					}
+					_ => false,
+				}
+		}
+	}
+}
+
 impl std::hash::Hash for PeerAddr {
 	/// If loopback address then we care about ip and port.
 	/// If regular address then we only care about the ip and ignore the port.
 	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
 		match self {
 			Ip(ip) => {
-				if ip.ip().is_loopback() {
+				if is_port_sensitive(ip) {
 					ip.hash(state);
 				} else {
 					ip.ip().hash(state);
@@ -256,16 +487,15 @@ impl std::hash::Hash for PeerAddr {
 impl PartialEq for PeerAddr {
 	/// If loopback address then we care about ip and port.
 	/// If regular address then we only care about the ip and ignore the port.
+	/// A loopback address never equals a regular one, whichever side it is on.
 	fn eq(&self, other: &PeerAddr) -> bool {
 		match self {
 			Ip(ip) => match other {
-				Ip(other_ip) => {
-					if ip.ip().is_loopback() {
-						ip == other_ip
-					} else {
-						ip.ip() == other_ip.ip()
-					}
-				}
+				Ip(other_ip) => match (is_port_sensitive(ip), is_port_sensitive(other_ip)) {
+					(true, true) => ip == other_ip,
+					(false, false) => ip.ip() == other_ip.ip(),
+					_ => false,
+				},
 				_ => false,
 			},
 			Onion(onion) => match other {
@@ -282,11 +512,132 @@ impl std::fmt::Display for PeerAddr {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
 			Ip(ip) => write!(f, "{}", ip),
-			Onion(onion) => {
-				let onion_address = &onion.to_string();
-				write!(f, "tor://{}", onion_address)
+			Onion(onion) => write!(f, "{}{}", TOR_SCHEME, onion),
+		}
+	}
+}
+
+/// A network of IP addresses, given by an address and a prefix length
+/// ("10.0.0.0/8", "2001:db8::/32"), used to ban whole subnets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpPrefix {
+	addr: IpAddr,
+	len: u8,
+}
+
+impl IpPrefix {
+	/// Prefix of the provided length, the host bits of the address are
+	/// cleared so equal networks compare equal.
+	pub fn new(addr: IpAddr, len: u8) -> Result<IpPrefix, Error> {
+		let addr = match addr {
+			IpAddr::V4(ip) if len <= 32 => {
+				let mask = u32::max_value().checked_shl(32 - len as u32).unwrap_or(0);
+				IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+			}
+			IpAddr::V6(ip) if len <= 128 => {
+				let mask = u128::max_value().checked_shl(128 - len as u32).unwrap_or(0);
+				IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+			}
+			_ => {
+				return Err(Error::InvalidIpPrefix(format!(
+					"prefix length {} too long for {}",
+					len, addr
+				)))
+			}
+		};
+		Ok(IpPrefix { addr, len })
+	}
+
+	/// Network address, without host bits.
+	pub fn addr(&self) -> IpAddr {
+		self.addr
+	}
+
+	/// Number of leading bits of the network.
+	pub fn prefix_len(&self) -> u8 {
+		self.len
+	}
+
+	/// Whether the address is in this network. IPv4-mapped IPv6 addresses
+	/// belong to the IPv4 networks.
+	pub fn contains(&self, ip: &IpAddr) -> bool {
+		let ip = match ip {
+			IpAddr::V6(v6) => match v6.segments() {
+				[0, 0, 0, 0, 0, 0xffff, ..] => IpAddr::V4(Ipv4Addr::from(
+					(u128::from(*v6) & u128::from(u32::max_value())) as u32,
+				)),
+				_ => *ip,
+			},
+			_ => *ip,
+		};
+		match IpPrefix::new(ip, self.len) {
+			Ok(prefix) => prefix == *self,
+			Err(_) => false,
+		}
+	}
+}
+
+impl std::fmt::Display for IpPrefix {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}/{}", self.addr, self.len)
+	}
+}
+
+impl std::str::FromStr for IpPrefix {
+	type Err = Error;
+
+	/// Parses "addr/len", a plain address being a network of its own.
+	fn from_str(s: &str) -> Result<IpPrefix, Error> {
+		let invalid = || Error::InvalidIpPrefix(s.to_string());
+		let mut parts = s.trim().splitn(2, '/');
+		let addr: IpAddr = parts.next().unwrap_or("").parse().map_err(|_| invalid())?;
+		let len = match parts.next() {
+			Some(len) => len.parse().map_err(|_| invalid())?,
+			None if addr.is_ipv4() => 32,
+			None => 128,
+		};
+		IpPrefix::new(addr, len)
+	}
+}
+
+impl Writeable for IpPrefix {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		match self.addr {
+			IpAddr::V4(ip) => {
+				writer.write_u8(4)?;
+				writer.write_fixed_bytes(&ip.octets().to_vec())?;
+			}
+			IpAddr::V6(ip) => {
+				writer.write_u8(6)?;
+				writer.write_fixed_bytes(&ip.octets().to_vec())?;
 			}
 		}
+		writer.write_u8(self.len)
+	}
+}
+
+impl Readable for IpPrefix {
+	fn read<R: Reader>(reader: &mut R) -> Result<IpPrefix, ser::Error> {
+		let addr = match reader.read_u8()? {
+			4 => {
+				let bytes = reader.read_fixed_bytes(4)?;
+				IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+			}
+			6 => {
+				let bytes = reader.read_fixed_bytes(16)?;
+				let mut octets = [0u8; 16];
+				octets.copy_from_slice(&bytes);
+				IpAddr::V6(Ipv6Addr::from(octets))
+			}
+			_ => {
+				return Err(ser::Error::CorruptedData(
+					"Unknown ip prefix family".to_string(),
+				))
+			}
+		};
+		let len = reader.read_u8()?;
+		IpPrefix::new(addr, len)
+			.map_err(|e| ser::Error::CorruptedData(format!("Invalid ip prefix, {}", e)))
 	}
 }
 
@@ -294,25 +645,186 @@ impl PeerAddr {
 	/// Convenient way of constructing a new peer_addr from an ip_addr
 	/// defaults to port 3414 on mainnet and 13414 on floonet.
 	pub fn from_ip(addr: IpAddr) -> PeerAddr {
-		let port = if global::is_floonet() { 13414 } else { 3414 };
-		PeerAddr::Ip(SocketAddr::new(addr, port))
+		PeerAddr::Ip(SocketAddr::new(addr, PeerAddr::default_port()))
+	}
+
+	/// Default p2p port of the network we're on.
+	fn default_port() -> u16 {
+		if global::is_floonet() {
+			13414
+		} else {
+			3414
+		}
+	}
+
+	/// Split an onion address from the optional ":port" it carries, the port
+	/// being the network default when missing. Returns None if this isn't an
+	/// onion address.
+	pub fn split_onion_port(addr: &str) -> Option<(String, u16)> {
+		let (host, port) = match addr.rfind(':') {
+			Some(idx) => (&addr[..idx], addr[idx + 1..].parse::<u16>().ok()?),
+			None => (addr, PeerAddr::default_port()),
+		};
+		if host.ends_with(".onion") {
+			Some((host.to_string(), port))
+		} else {
+			None
+		}
+	}
+
+	/// Onion address for the provided host and port. The network default port
+	/// many configs carry means our usual hidden service port and is left out,
+	/// so the same peer always has the same address, other ports are kept.
+	fn onion_with_port(host: String, port: u16) -> String {
+		if port == PeerAddr::default_port() || port == ONION_P2P_PORT {
+			host
+		} else {
+			format!("{}:{}", host, port)
+		}
 	}
 
+	/// Parse an ip address, host name or onion address. Onion addresses are
+	/// kept without the tor:// scheme Display adds, and without their port
+	/// when it's the default one. They are never resolved through DNS.
 	pub fn from_str(addr: &str) -> PeerAddr {
+		PeerAddr::from_str_preferring(addr, AddressFamilyPreference::Any)
+	}
+
+	/// Same as from_str, picking an address of the preferred family when the
+	/// host name resolves to both.
+	pub fn from_str_preferring(addr: &str, preference: AddressFamilyPreference) -> PeerAddr {
+		let addr = if addr.starts_with(TOR_SCHEME) {
+			&addr[TOR_SCHEME.len()..]
+		} else {
+			addr
+		};
+		if let Some((onion, port)) = PeerAddr::split_onion_port(addr) {
+			return PeerAddr::Onion(PeerAddr::onion_with_port(onion, port));
+		}
 		let socket_addr = SocketAddr::from_str(addr);
 		if socket_addr.is_err() {
-			let socket_addrs = addr.to_socket_addrs();
-			if socket_addrs.is_ok() {
-				let vec: Vec<SocketAddr> = socket_addrs.unwrap().collect();
-				PeerAddr::Ip(vec[0])
-			} else {
-				PeerAddr::Onion(addr.to_string())
+			match addr.to_socket_addrs() {
+				Ok(socket_addrs) => PeerAddr::from_resolved(addr, socket_addrs, preference),
+				Err(_) => PeerAddr::Onion(addr.to_string()),
 			}
 		} else {
 			PeerAddr::Ip(socket_addr.unwrap())
 		}
 	}
 
+	/// The first of the addresses a host name resolved to, of the preferred
+	/// family if there's any. A name resolving to nothing is kept as an onion
+	/// candidate, like one failing to resolve.
+	pub fn from_resolved<I>(
+		addr: &str,
+		resolved: I,
+		preference: AddressFamilyPreference,
+	) -> PeerAddr
+	where
+		I: IntoIterator<Item = SocketAddr>,
+	{
+		let resolved = preference.order(resolved.into_iter().collect());
+		match resolved.into_iter().next() {
+			Some(socket_addr) => PeerAddr::Ip(socket_addr),
+			None => PeerAddr::Onion(addr.to_string()),
+		}
+	}
+
+	/// Parse an ip address with its port or an onion address, without ever
+	/// resolving host names. None if the address is neither.
+	pub fn parse_checked(addr: &str) -> Option<PeerAddr> {
+		let addr = addr.trim();
+		let addr = if addr.starts_with(TOR_SCHEME) {
+			&addr[TOR_SCHEME.len()..]
+		} else {
+			addr
+		};
+		if let Some((onion, port)) = PeerAddr::split_onion_port(addr) {
+			if onion.len() > ".onion".len() && onion.len() <= 100 {
+				return Some(PeerAddr::Onion(PeerAddr::onion_with_port(onion, port)));
+			}
+			return None;
+		}
+		SocketAddr::from_str(addr).ok().map(PeerAddr::Ip)
+	}
+
+	/// The kind of network the address belongs to, for bucketing peers.
+	pub fn network_class(&self) -> NetworkClass {
+		match self {
+			Ip(ip) => match ip.ip() {
+				IpAddr::V4(ip) => {
+					if ip.is_loopback() {
+						NetworkClass::Loopback
+					} else if ip.is_private() || ip.is_link_local() {
+						NetworkClass::Ipv4Private
+					} else if ip.is_unspecified()
+						|| ip.is_broadcast()
+						|| ip.is_documentation()
+						|| ip.is_multicast()
+					{
+						NetworkClass::Reserved
+					} else {
+						NetworkClass::Ipv4Public
+					}
+				}
+				IpAddr::V6(ip) => {
+					let first = ip.segments()[0];
+					if ip.is_loopback() {
+						NetworkClass::Loopback
+					} else if first & 0xffc0 == 0xfe80 {
+						// fe80::/10
+						NetworkClass::Ipv6LinkLocal
+					} else if first & 0xfe00 == 0xfc00 {
+						// unique local fc00::/7
+						NetworkClass::Ipv6Private
+					} else if ip.is_unspecified() || ip.is_multicast() {
+						NetworkClass::Reserved
+					} else {
+						NetworkClass::Ipv6Public
+					}
+				}
+			},
+			Onion(onion) => {
+				if PeerAddr::split_onion_port(onion).is_some() {
+					NetworkClass::Onion
+				} else {
+					NetworkClass::Reserved
+				}
+			}
+		}
+	}
+
+	/// Whether other nodes could reach this address, onion addresses or
+	/// public ip addresses (no loopback, private, link local or otherwise
+	/// reserved ranges).
+	pub fn is_routable(&self) -> bool {
+		if let Ip(ip) = self {
+			if ip.port() == 0 {
+				return false;
+			}
+		}
+		match self.network_class() {
+			NetworkClass::Ipv4Public | NetworkClass::Ipv6Public | NetworkClass::Onion => true,
+			_ => false,
+		}
+	}
+
+	/// Whether this is a loopback ip address (several local peers may share it).
+	pub fn is_loopback(&self) -> bool {
+		self.network_class() == NetworkClass::Loopback
+	}
+
+	/// The exact bytes this address is sent as on the wire (the encoding
+	/// doesn't depend on the protocol version).
+	pub fn to_wire_bytes(&self) -> Result<Vec<u8>, ser::Error> {
+		ser::ser_vec(self, ProtocolVersion::local())
+	}
+
+	/// Read an address back from its on-wire bytes.
+	pub fn from_wire_bytes(bytes: &[u8]) -> Result<PeerAddr, ser::Error> {
+		ser::deserialize(&mut &bytes[..], ProtocolVersion::local())
+	}
+
 	/// If the ip is loopback then our key is "ip:port" (mainly for local usernet testing).
 	/// Otherwise we only care about the ip (we disallow multiple peers on the same ip address).
 	pub fn as_key(&self) -> String {
@@ -328,6 +840,30 @@ impl PeerAddr {
 		}
 	}
 
+	/// The socket we actually connect to in order to reach this peer, the tor
+	/// proxy for onion addresses or the address itself for ip addresses.
+	/// None for onion peers without a proxy, they can't be reached.
+	pub fn transport_addr(&self, proxy: Option<SocketAddr>) -> Option<SocketAddr> {
+		match self {
+			Ip(ip) => Some(*ip),
+			Onion(_) => proxy,
+		}
+	}
+
+	/// Host and port to ask the tor proxy for to reach this onion peer, the
+	/// hidden service port unless the address carries another one. None for
+	/// ip addresses.
+	pub fn onion_target(&self) -> Option<(String, u16)> {
+		match self {
+			Ip(_) => None,
+			Onion(onion) => match PeerAddr::split_onion_port(onion) {
+				Some((host, port)) if port != PeerAddr::default_port() => Some((host, port)),
+				Some((host, _)) => Some((host, ONION_P2P_PORT)),
+				None => Some((onion.clone(), ONION_P2P_PORT)),
+			},
+		}
+	}
+
 	pub fn tor_address(&self) -> Result<String, Error> {
 		match self {
 			Ip(_ip) => {
@@ -336,6 +872,10 @@ impl PeerAddr {
 				))
 			}
 			Onion(onion) => {
+				let onion = match PeerAddr::split_onion_port(onion) {
+					Some((host, _)) => host,
+					None => onion.clone(),
+				};
 				if onion.ends_with(".onion") {
 					let onion = &onion[..(onion.len() - ".onion".len())];
 					return Ok(onion.to_string());
@@ -371,6 +911,9 @@ pub struct P2PConfig {
 	/// The list of preferred peers that we will try to connect to
 	pub peers_preferred: Option<PeerAddrs>,
 
+	/// Trusted peers (own infrastructure, monitoring) that are never banned
+	pub peers_never_ban: Option<PeerAddrs>,
+
 	pub ban_window: Option<i64>,
 
 	pub peer_max_inbound_count: Option<u32>,
@@ -382,6 +925,249 @@ pub struct P2PConfig {
 	pub peer_listener_buffer_count: Option<u32>,
 
 	pub dandelion_peer: Option<PeerAddr>,
+
+	/// How many of our outbound peers are persisted as anchors and dialed
+	/// first on the next startup (0 disables anchors)
+	pub anchor_peer_count: Option<u32>,
+
+	/// Largest message body (in bytes) we accept from a peer, messages
+	/// declaring a larger length are refused before reading them
+	pub max_message_size: Option<u64>,
+
+	/// How many malformed messages a peer may send within ser_error_window
+	/// before it gets banned
+	pub ser_error_ban_threshold: Option<u32>,
+
+	/// Window (in seconds) over which malformed messages from a peer are counted
+	pub ser_error_window: Option<i64>,
+
+	/// Minimum interval (in seconds) between two peer address requests from
+	/// the same peer we answer with a fresh list (0 disables the limit)
+	pub peer_addrs_request_interval: Option<u64>,
+
+	/// How many header batches on a fork losing against ours a peer may send
+	/// before it gets parked (0 disables minority fork handling)
+	pub minority_fork_threshold: Option<u32>,
+
+	/// How long (in seconds) a peer on a losing minority fork stays parked,
+	/// we don't connect to it nor accept its connections meanwhile
+	pub minority_fork_cooldown: Option<i64>,
+
+	/// Serve txhashset archives to our peers (default true), when disabled we
+	/// don't advertise TXHASHSET_HIST and decline archive requests
+	pub serve_txhashset: Option<bool>,
+
+	/// How many blocks behind the most advanced peer we may be while syncing
+	/// and still advertise full capabilities, further behind (or while
+	/// rebuilding the txhashset) we only advertise PEER_LIST
+	pub full_capabilities_max_lag: Option<u64>,
+
+	/// How many times we download the txhashset again, each time from a
+	/// different peer, after the one we got proved corrupt
+	pub max_txhashset_redownloads: Option<u32>,
+
+	/// Number of messages queued for a peer beyond which it's skipped when we
+	/// relay a block, so slow peers don't hold up propagation
+	pub max_relay_queue: Option<u32>,
+
+	/// How many blocks behind the highest peer a txhashset archive may be
+	/// anchored and still be accepted (no limit by default)
+	pub txhashset_max_age: Option<u64>,
+
+	/// Seed for the rng used in peer selection, for reproducible tests only
+	/// (defaults to OS entropy)
+	pub rng_seed: Option<u64>,
+
+	/// Maximum number of peers we concurrently wait on for headers during
+	/// sync, to throttle low-power nodes (unlimited by default)
+	pub max_header_sync_peers: Option<u32>,
+
+	/// What to do when a peer we're already connected to opens another
+	/// inbound connection (defaults to reject_new)
+	pub duplicate_inbound_policy: Option<DuplicateConnectionPolicy>,
+
+	/// Advertise an older protocol version during handshakes, for interop
+	/// testing only (bounded to the versions we support)
+	pub protocol_version: Option<u32>,
+
+	/// Externally reachable address we advertise to peers, when it can't be
+	/// derived from the bind address (behind NAT, bound to 0.0.0.0)
+	pub advertised_addr: Option<PeerAddr>,
+
+	/// Pre-shared key (32 bytes, hex encoded) of a private network, when set
+	/// connections with peers having it too are encrypted with Noise
+	pub noise_psk: Option<String>,
+
+	/// Number of declined requests for a capability a peer advertised after
+	/// which the peer is flagged, if it declines most of them
+	pub capability_refusal_threshold: Option<u32>,
+
+	/// Park peers flagged for declining what they advertise, instead of only
+	/// reporting them (defaults to false)
+	pub penalize_capability_refusals: Option<bool>,
+
+	/// Interval (in seconds) between batches of received peer addresses we
+	/// pass on to our peers
+	pub peer_addrs_regossip_interval: Option<u64>,
+
+	/// Maximum number of received peer addresses passed on per batch
+	pub peer_addrs_regossip_cap: Option<u32>,
+
+	/// File every ban gets appended to, one line per ban, for forensics
+	/// (no ban log by default)
+	pub ban_log_path: Option<PathBuf>,
+
+	/// How long (in seconds) we may stay short of our preferred outbound peer
+	/// count before a warning is logged
+	pub outbound_deficit_warn_after: Option<i64>,
+
+	/// How long (in seconds) we may stay short of our preferred outbound peer
+	/// count before also trying peers we previously failed to connect to
+	/// (never by default)
+	pub outbound_deficit_relax_after: Option<i64>,
+
+	/// Load factor (0.0 to 1.0, as reported by the chain adapter) above which
+	/// new inbound connections get refused more and more often, up to all of
+	/// them at full load
+	pub inbound_load_threshold: Option<f64>,
+
+	/// How long (in seconds) an orphan block stays known, further deliveries
+	/// of it are dropped meanwhile
+	pub orphan_tracking_window: Option<i64>,
+
+	/// Maximum number of orphan blocks tracked at once, the oldest are
+	/// forgotten beyond that
+	pub max_tracked_orphans: Option<u32>,
+
+	/// Gossip clearnet addresses to Tor peers and onion addresses to clearnet
+	/// peers, otherwise each only learns addresses of its own kind (defaults
+	/// to false)
+	pub bridge_mode: Option<bool>,
+
+	/// Only pass on addresses of peers accepting connections, leaving out the
+	/// ones that connected to us without advertising a listener (defaults to
+	/// true)
+	pub gossip_reachable_only: Option<bool>,
+
+	/// How long (in seconds) we wait before dialing a peer we got
+	/// disconnected from again
+	pub peer_min_reconnect_interval: Option<i64>,
+
+	/// How long (in seconds) we wait before dialing one of our preferred
+	/// peers again after getting disconnected from it
+	pub peer_min_preferred_reconnect_interval: Option<i64>,
+
+	/// Connection directions we send keepalives on, to keep NAT mappings and
+	/// tor circuits from silently dropping (none by default)
+	pub keepalive_directions: Option<Vec<Direction>>,
+
+	/// Interval (in seconds) between keepalives
+	pub keepalive_interval: Option<u64>,
+
+	/// Among sync peers of equal difficulty, prefer the ones that served us
+	/// valid headers or blocks most recently (defaults to true)
+	pub prefer_useful_peers: Option<bool>,
+
+	/// Interval (in seconds) between asking connected peers for their current
+	/// capabilities, to notice changes they didn't tell us about (0 disables)
+	pub capabilities_probe_interval: Option<u64>,
+
+	/// Number of headers past our validated tip we take from a peer before
+	/// another peer has to claim at least as much total difficulty as they
+	/// carry
+	pub max_uncorroborated_headers: Option<u64>,
+
+	/// Local address our outbound connections are made from, for hosts with
+	/// several interfaces. Connections through the tor proxy don't use it.
+	pub outbound_bind: Option<IpAddr>,
+
+	/// Send the same handshake nonce over all our connections, so peers we
+	/// reach at several addresses (onion and clearnet) can tell it's one node.
+	/// This links our addresses together for them (defaults to false)
+	pub share_node_nonce: Option<bool>,
+
+	/// Close connections sending us messages of a type we don't know, instead
+	/// of skipping them as sent by a newer peer (defaults to false)
+	pub strict_protocol: Option<bool>,
+
+	/// How many blocks the height advertised by a peer may drop by before it
+	/// gets parked, larger drops aren't reorgs but buggy, restarting or
+	/// malicious peers
+	pub height_regression_tolerance: Option<u64>,
+
+	/// Number of onion addresses we store (the oldest making room for newly
+	/// gossiped ones), and consider dialing at once, apart from the clearnet
+	/// ones
+	pub max_onion_peers: Option<u32>,
+
+	/// Minimum interval (in seconds) between two onion peer dials
+	pub onion_dial_interval: Option<i64>,
+
+	/// How many bad compact blocks a peer may send within
+	/// bad_compact_block_window before it gets banned, reconstruction can
+	/// fail for benign reasons
+	pub bad_compact_block_ban_threshold: Option<u32>,
+
+	/// Window (in seconds) over which bad compact blocks from a peer are
+	/// counted
+	pub bad_compact_block_window: Option<i64>,
+
+	/// Run as a headers-only relay (defaults to false): only advertise
+	/// HEADER_HIST and PEER_LIST, decline block, txhashset and transaction
+	/// requests and never request those ourselves
+	pub headers_only: Option<bool>,
+
+	/// Address family tried first when a DNS seed resolves to both IPv4 and
+	/// IPv6 addresses (defaults to the resolver order)
+	pub preferred_address_family: Option<AddressFamilyPreference>,
+
+	/// Weights of the signals combined into the sync priority of peers, when
+	/// set the sync peer is the highest ranked one instead of a most worked one
+	pub sync_priority_weights: Option<SyncPriorityWeights>,
+
+	/// Time (in seconds) a peer has to complete the handshake, however slowly
+	/// it keeps sending, before the connection is dropped (0 for the default)
+	pub handshake_timeout_secs: Option<u64>,
+
+	/// Ban peers not completing the handshake in time, like the ones failing
+	/// it (defaults to true)
+	pub penalize_handshake_timeout: Option<bool>,
+
+	/// Whether we dial new outbound peers on startup (defaults to true), can
+	/// be toggled at runtime to freeze the peer set
+	pub dialing_enabled: Option<bool>,
+
+	/// Time (in seconds) a peer has after connecting to report its difficulty
+	/// and height through ping or pong before it's considered silent
+	pub silent_peer_grace_secs: Option<i64>,
+
+	/// What to do with silent peers (defaults to deprioritize)
+	pub silent_peer_policy: Option<SilentPeerPolicy>,
+
+	/// Interval (in seconds) between asking a peer to check our listener can
+	/// be reached from outside, when set we only advertise ourselves as
+	/// reachable once a peer confirmed it (0 disables, the default)
+	pub reachability_probe_interval: Option<u64>,
+
+	/// Total size (in bytes) of the message bodies being read from all peers
+	/// at once, past it connections reading large messages pause until
+	/// there's room again (0 disables)
+	pub max_inbound_buffer_bytes: Option<u64>,
+
+	/// How many connected peers must advertise a tip at least as worked as
+	/// the one we sync to before we download its blocks or txhashset, below
+	/// it we only sync headers (1, the default, trusts any single peer)
+	pub min_corroborating_peers: Option<u32>,
+
+	/// How many times we process again a block the chain failed on for a
+	/// transient reason (busy store), peers are never penalized for those
+	/// (0 disables retries)
+	pub block_error_retries: Option<u32>,
+
+	/// Refuse peers not offering Noise when we do, instead of falling back
+	/// to plaintext (defaults to false, the capability isn't authenticated
+	/// so anyone in the middle can strip it)
+	pub require_noise: Option<bool>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -397,12 +1183,71 @@ impl Default for P2PConfig {
 			peers_allow: None,
 			peers_deny: None,
 			peers_preferred: None,
+			peers_never_ban: None,
 			ban_window: None,
 			peer_max_inbound_count: None,
 			peer_max_outbound_count: None,
 			peer_min_preferred_outbound_count: None,
 			peer_listener_buffer_count: None,
 			dandelion_peer: None,
+			anchor_peer_count: None,
+			max_message_size: None,
+			ser_error_ban_threshold: None,
+			ser_error_window: None,
+			peer_addrs_request_interval: None,
+			minority_fork_threshold: None,
+			minority_fork_cooldown: None,
+			serve_txhashset: None,
+			full_capabilities_max_lag: None,
+			max_txhashset_redownloads: None,
+			max_relay_queue: None,
+			txhashset_max_age: None,
+			rng_seed: None,
+			max_header_sync_peers: None,
+			duplicate_inbound_policy: None,
+			protocol_version: None,
+			advertised_addr: None,
+			noise_psk: None,
+			capability_refusal_threshold: None,
+			penalize_capability_refusals: None,
+			peer_addrs_regossip_interval: None,
+			peer_addrs_regossip_cap: None,
+			ban_log_path: None,
+			outbound_deficit_warn_after: None,
+			outbound_deficit_relax_after: None,
+			inbound_load_threshold: None,
+			orphan_tracking_window: None,
+			max_tracked_orphans: None,
+			bridge_mode: None,
+			gossip_reachable_only: None,
+			peer_min_reconnect_interval: None,
+			peer_min_preferred_reconnect_interval: None,
+			keepalive_directions: None,
+			keepalive_interval: None,
+			prefer_useful_peers: None,
+			capabilities_probe_interval: None,
+			max_uncorroborated_headers: None,
+			outbound_bind: None,
+			share_node_nonce: None,
+			strict_protocol: None,
+			height_regression_tolerance: None,
+			max_onion_peers: None,
+			onion_dial_interval: None,
+			bad_compact_block_ban_threshold: None,
+			bad_compact_block_window: None,
+			headers_only: None,
+			preferred_address_family: None,
+			sync_priority_weights: None,
+			handshake_timeout_secs: None,
+			penalize_handshake_timeout: None,
+			dialing_enabled: None,
+			silent_peer_grace_secs: None,
+			silent_peer_policy: None,
+			reachability_probe_interval: None,
+			max_inbound_buffer_bytes: None,
+			min_corroborating_peers: None,
+			block_error_retries: None,
+			require_noise: None,
 		}
 	}
 }
@@ -449,91 +1294,1073 @@ impl P2PConfig {
 			None => PEER_LISTENER_BUFFER_COUNT,
 		}
 	}
-}
 
-/// Type of seeding the server will use to find other peers on the network.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
-pub enum Seeding {
-	/// No seeding, mostly for tests that programmatically connect
-	None,
-	/// A list of seeds provided to the server (can be addresses or DNS names)
-	List,
-	/// Automatically get a list of seeds from multiple DNS
-	DNSSeed,
-	/// Mostly for tests, where connections are initiated programmatically
-	Programmatic,
-}
+	/// return how many inbound connections the listener accepts, the buffer
+	/// on top of a positive peer_max_inbound_count. None at all when that's 0
+	/// (outbound only), whatever the buffer.
+	pub fn inbound_accept_limit(&self) -> u32 {
+		match self.peer_max_inbound_count() {
+			0 => 0,
+			n => n.saturating_add(self.peer_listener_buffer_count()),
+		}
+	}
 
-impl Default for Seeding {
-	fn default() -> Seeding {
-		Seeding::DNSSeed
+	/// return the number of malformed messages that gets a peer banned
+	pub fn ser_error_ban_threshold(&self) -> u32 {
+		match self.ser_error_ban_threshold {
+			Some(n) => n,
+			None => SER_ERROR_BAN_THRESHOLD,
+		}
 	}
-}
 
-bitflags! {
-	/// Options for what type of interaction a peer supports
-	#[derive(Serialize, Deserialize)]
-	pub struct Capabilities: u32 {
-		/// We don't know (yet) what the peer can do.
-		const UNKNOWN = 0b0000_0000;
-		/// Can provide full history of headers back to genesis
-		/// (for at least one arbitrary fork).
-		const HEADER_HIST = 0b0000_0001;
-		/// Can provide block headers and the TxHashSet for some recent-enough
-		/// height.
-		const TXHASHSET_HIST = 0b0000_0010;
-		/// Can provide a list of healthy peers
-		const PEER_LIST = 0b0000_0100;
-		/// Can broadcast and request txs by kernel hash.
-		const TX_KERNEL_HASH = 0b0000_1000;
-		/// Can send/receive tor addresses
-		const TOR_ADDRESS = 0b0001_0000;
+	/// return the window (in seconds) malformed messages are counted over
+	pub fn ser_error_window(&self) -> i64 {
+		match self.ser_error_window {
+			Some(n) => n,
+			None => SER_ERROR_WINDOW,
+		}
+	}
 
-		/// All nodes right now are "full nodes".
-		/// Some nodes internally may maintain longer block histories (archival_mode)
-		/// but we do not advertise this to other nodes.
-		/// All nodes by default will accept lightweight "kernel first" tx broadcast.
-		const FULL_NODE = Capabilities::HEADER_HIST.bits
-			| Capabilities::TXHASHSET_HIST.bits
-			| Capabilities::PEER_LIST.bits
-			| Capabilities::TX_KERNEL_HASH.bits
-			| Capabilities::TOR_ADDRESS.bits;
+	/// return the number of losing minority fork header batches that gets a
+	/// peer parked
+	pub fn minority_fork_threshold(&self) -> u32 {
+		match self.minority_fork_threshold {
+			Some(n) => n,
+			None => MINORITY_FORK_THRESHOLD,
+		}
 	}
-}
 
-// Types of connection
-enum_from_primitive! {
-	#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
-	pub enum Direction {
-		Inbound = 0,
-		Outbound = 1,
-		InboundTor = 2,
-		OutboundTor = 3,
+	/// return how long (in seconds) a peer on a losing minority fork is parked
+	pub fn minority_fork_cooldown(&self) -> i64 {
+		match self.minority_fork_cooldown {
+			Some(n) => n,
+			None => MINORITY_FORK_COOLDOWN,
+		}
 	}
-}
 
-// Ban reason
-enum_from_primitive! {
-	#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
-	pub enum ReasonForBan {
-		None = 0,
-		BadBlock = 1,
-		BadCompactBlock = 2,
-		BadBlockHeader = 3,
-		BadTxHashSet = 4,
-		ManualBan = 5,
-		FraudHeight = 6,
-		BadHandshake = 7,
+	/// return the minimum interval between fresh answers to peer address requests
+	pub fn peer_addrs_request_interval(&self) -> Duration {
+		Duration::from_secs(
+			self.peer_addrs_request_interval
+				.unwrap_or(PEER_ADDRS_REQUEST_INTERVAL),
+		)
 	}
-}
 
-#[derive(Clone, Debug)]
-pub struct PeerLiveInfo {
-	pub total_difficulty: Difficulty,
+	/// return how long an outbound peer deficit lasts before we warn about it
+	pub fn outbound_deficit_warn_after(&self) -> i64 {
+		match self.outbound_deficit_warn_after {
+			Some(n) => n,
+			None => OUTBOUND_DEFICIT_WARN_AFTER,
+		}
+	}
+
+	/// return how long (in seconds) an orphan block stays known
+	pub fn orphan_tracking_window(&self) -> i64 {
+		match self.orphan_tracking_window {
+			Some(n) => n,
+			None => ORPHAN_TRACKING_WINDOW,
+		}
+	}
+
+	/// return how many orphan blocks we track at once
+	pub fn max_tracked_orphans(&self) -> usize {
+		match self.max_tracked_orphans {
+			Some(n) => n as usize,
+			None => MAX_TRACKED_ORPHANS as usize,
+		}
+	}
+
+	/// return whether we gossip addresses across Tor and clearnet
+	pub fn bridge_mode(&self) -> bool {
+		self.bridge_mode.unwrap_or(false)
+	}
+
+	/// return whether we leave unreachable peers out of the addresses we send
+	pub fn gossip_reachable_only(&self) -> bool {
+		self.gossip_reachable_only.unwrap_or(true)
+	}
+
+	/// return how long we wait before redialing a peer after a disconnect
+	pub fn peer_min_reconnect_interval(&self) -> i64 {
+		match self.peer_min_reconnect_interval {
+			Some(n) => n,
+			None => PEER_MIN_RECONNECT_INTERVAL,
+		}
+	}
+
+	/// return how long we wait before redialing a preferred peer after a
+	/// disconnect
+	pub fn peer_min_preferred_reconnect_interval(&self) -> i64 {
+		match self.peer_min_preferred_reconnect_interval {
+			Some(n) => n,
+			None => PEER_MIN_PREFERRED_RECONNECT_INTERVAL,
+		}
+	}
+
+	/// return whether we send keepalives on connections of this direction
+	pub fn keepalive_enabled(&self, direction: Direction) -> bool {
+		match self.keepalive_directions {
+			Some(ref directions) => directions.contains(&direction),
+			None => false,
+		}
+	}
+
+	/// return the interval between keepalives
+	pub fn keepalive_interval(&self) -> Duration {
+		Duration::from_secs(self.keepalive_interval.unwrap_or(KEEPALIVE_INTERVAL))
+	}
+
+	/// return whether sync peers that recently served us valid data are
+	/// preferred
+	pub fn prefer_useful_peers(&self) -> bool {
+		self.prefer_useful_peers.unwrap_or(true)
+	}
+
+	/// return whether we send the same nonce over all our connections
+	pub fn share_node_nonce(&self) -> bool {
+		self.share_node_nonce.unwrap_or(false)
+	}
+
+	/// return whether unknown message types close the connection
+	pub fn strict_protocol(&self) -> bool {
+		self.strict_protocol.unwrap_or(false)
+	}
+
+	/// return how many blocks the height advertised by a peer may drop by
+	pub fn height_regression_tolerance(&self) -> u64 {
+		match self.height_regression_tolerance {
+			Some(n) => n,
+			None => HEIGHT_REGRESSION_TOLERANCE,
+		}
+	}
+
+	/// return how many onion addresses we store and consider dialing at once
+	pub fn max_onion_peers(&self) -> usize {
+		match self.max_onion_peers {
+			Some(n) => n as usize,
+			None => MAX_ONION_PEERS as usize,
+		}
+	}
+
+	/// return the minimum interval (in seconds) between two onion peer dials
+	pub fn onion_dial_interval(&self) -> i64 {
+		match self.onion_dial_interval {
+			Some(n) => n,
+			None => ONION_DIAL_INTERVAL,
+		}
+	}
+
+	/// return the number of bad compact blocks that gets a peer banned
+	pub fn bad_compact_block_ban_threshold(&self) -> u32 {
+		match self.bad_compact_block_ban_threshold {
+			Some(n) => n,
+			None => BAD_COMPACT_BLOCK_BAN_THRESHOLD,
+		}
+	}
+
+	/// return the window (in seconds) bad compact blocks are counted over
+	pub fn bad_compact_block_window(&self) -> i64 {
+		match self.bad_compact_block_window {
+			Some(n) => n,
+			None => BAD_COMPACT_BLOCK_WINDOW,
+		}
+	}
+
+	/// return whether we run as a headers-only relay
+	pub fn headers_only(&self) -> bool {
+		self.headers_only.unwrap_or(false)
+	}
+
+	/// return the address family tried first when a name resolves to both
+	pub fn preferred_address_family(&self) -> AddressFamilyPreference {
+		self.preferred_address_family.unwrap_or_default()
+	}
+
+	/// return the time (in seconds) a peer has to complete the handshake, no
+	/// peer could make it in 0 so the default applies then
+	pub fn handshake_timeout_secs(&self) -> u64 {
+		match self.handshake_timeout_secs {
+			Some(n) if n > 0 => n,
+			_ => HANDSHAKE_TIMEOUT_SECS,
+		}
+	}
+
+	/// return whether peers not completing the handshake in time get banned
+	pub fn penalize_handshake_timeout(&self) -> bool {
+		self.penalize_handshake_timeout.unwrap_or(true)
+	}
+
+	/// return whether we dial new outbound peers on startup
+	pub fn dialing_enabled(&self) -> bool {
+		self.dialing_enabled.unwrap_or(true)
+	}
+
+	/// return the time a peer has to report its difficulty and height
+	pub fn silent_peer_grace(&self) -> chrono::Duration {
+		chrono::Duration::seconds(
+			self.silent_peer_grace_secs
+				.unwrap_or(SILENT_PEER_GRACE_SECS),
+		)
+	}
+
+	/// return what we do with peers never reporting difficulty nor height
+	pub fn silent_peer_policy(&self) -> SilentPeerPolicy {
+		self.silent_peer_policy.unwrap_or_default()
+	}
+
+	/// return the interval between reachability probes, None if disabled
+	pub fn reachability_probe_interval(&self) -> Option<Duration> {
+		match self.reachability_probe_interval.unwrap_or(0) {
+			0 => None,
+			n => Some(Duration::from_secs(n)),
+		}
+	}
+
+	/// return the total size of message bodies read from all peers at once,
+	/// 0 for no limit
+	pub fn max_inbound_buffer_bytes(&self) -> u64 {
+		self.max_inbound_buffer_bytes
+			.unwrap_or(MAX_INBOUND_BUFFER_BYTES)
+	}
+
+	/// return how many peers must advertise a tip before we fully sync to it
+	pub fn min_corroborating_peers(&self) -> u32 {
+		self.min_corroborating_peers.unwrap_or(1).max(1)
+	}
+
+	/// return how many times we process again a block the chain failed on
+	/// for a transient reason
+	pub fn block_error_retries(&self) -> u32 {
+		self.block_error_retries.unwrap_or(BLOCK_ERROR_RETRIES)
+	}
+
+	/// return how many headers past our tip a single peer may feed us
+	pub fn max_uncorroborated_headers(&self) -> u64 {
+		self.max_uncorroborated_headers
+			.unwrap_or(MAX_UNCORROBORATED_HEADERS)
+	}
+
+	/// return the interval between capability probes, None if disabled
+	pub fn capabilities_probe_interval(&self) -> Option<Duration> {
+		match self
+			.capabilities_probe_interval
+			.unwrap_or(CAPABILITIES_PROBE_INTERVAL)
+		{
+			0 => None,
+			n => Some(Duration::from_secs(n)),
+		}
+	}
+
+	/// return the load factor above which we refuse some inbound connections
+	pub fn inbound_load_threshold(&self) -> f64 {
+		self.inbound_load_threshold
+			.unwrap_or(INBOUND_LOAD_THRESHOLD)
+	}
+
+	/// return the interval between batches of re-gossiped peer addresses
+	pub fn peer_addrs_regossip_interval(&self) -> Duration {
+		Duration::from_secs(
+			self.peer_addrs_regossip_interval
+				.unwrap_or(PEER_ADDRS_REGOSSIP_INTERVAL),
+		)
+	}
+
+	/// return how many peer addresses we re-gossip per batch, never more than
+	/// fit in a single message
+	pub fn peer_addrs_regossip_cap(&self) -> usize {
+		let cap = self
+			.peer_addrs_regossip_cap
+			.unwrap_or(PEER_ADDRS_REGOSSIP_CAP);
+		std::cmp::min(cap, MAX_PEER_ADDRS) as usize
+	}
+
+	/// return whether we serve txhashset archives to our peers
+	pub fn serve_txhashset(&self) -> bool {
+		self.serve_txhashset.unwrap_or(true)
+	}
+
+	/// return how far behind we may be while syncing and still advertise
+	/// full capabilities
+	pub fn full_capabilities_max_lag(&self) -> u64 {
+		match self.full_capabilities_max_lag {
+			Some(n) => n,
+			None => FULL_CAPABILITIES_MAX_LAG,
+		}
+	}
+
+	/// return how many times we download a corrupt txhashset again
+	pub fn max_txhashset_redownloads(&self) -> u32 {
+		match self.max_txhashset_redownloads {
+			Some(n) => n,
+			None => MAX_TXHASHSET_REDOWNLOADS,
+		}
+	}
+
+	/// return how many declined requests for an advertised capability get a
+	/// peer flagged
+	pub fn capability_refusal_threshold(&self) -> u32 {
+		match self.capability_refusal_threshold {
+			Some(n) => n,
+			None => CAPABILITY_REFUSAL_THRESHOLD,
+		}
+	}
+
+	/// return whether peers flagged for declining what they advertise are parked
+	pub fn penalize_capability_refusals(&self) -> bool {
+		self.penalize_capability_refusals.unwrap_or(false)
+	}
+
+	/// return how many queued messages get a peer skipped when relaying blocks
+	pub fn max_relay_queue(&self) -> usize {
+		match self.max_relay_queue {
+			Some(n) => n as usize,
+			None => MAX_RELAY_QUEUE as usize,
+		}
+	}
+
+	/// return the policy applied to duplicate inbound connections
+	pub fn duplicate_inbound_policy(&self) -> DuplicateConnectionPolicy {
+		self.duplicate_inbound_policy.unwrap_or_default()
+	}
+
+	/// return the number of anchor peers to persist across restarts
+	pub fn anchor_peer_count(&self) -> u32 {
+		match self.anchor_peer_count {
+			Some(n) => n,
+			None => ANCHOR_PEER_COUNT,
+		}
+	}
+
+	/// return the max message body size accepted from peers
+	pub fn max_message_size(&self) -> u64 {
+		match self.max_message_size {
+			Some(n) => n,
+			None => msg::max_message_size(),
+		}
+	}
+
+	/// whether this peer is on the never ban list
+	pub fn is_never_ban(&self, peer_addr: &PeerAddr) -> bool {
+		match self.peers_never_ban {
+			Some(ref never_ban) => never_ban.peers.contains(peer_addr),
+			None => false,
+		}
+	}
+
+	/// return the address we advertise to peers for gossip: our onion address
+	/// when tor is enabled, else the configured advertised_addr, else the bind
+	/// address if it is reachable at all (never unspecified or loopback)
+	pub fn advertised_addr(&self, onion_address: Option<String>) -> Option<PeerAddr> {
+		if let Some(onion) = onion_address {
+			return Some(PeerAddr::Onion(onion));
+		}
+		if let Some(ref addr) = self.advertised_addr {
+			return Some(addr.clone());
+		}
+		if self.host.is_unspecified() || self.host.is_loopback() {
+			None
+		} else {
+			Some(PeerAddr::Ip(SocketAddr::new(self.host, self.port)))
+		}
+	}
+
+	/// return the pre-shared key encrypting connections with Noise, if a
+	/// valid one is configured
+	pub fn noise_psk(&self) -> Option<[u8; 32]> {
+		let hex = self.noise_psk.as_ref()?;
+		match util::from_hex(hex) {
+			Ok(ref bytes) if bytes.len() == 32 => {
+				let mut psk = [0u8; 32];
+				psk.copy_from_slice(bytes);
+				Some(psk)
+			}
+			_ => {
+				warn!("noise_psk must be 32 bytes hex encoded, ignoring it");
+				None
+			}
+		}
+	}
+
+	/// return whether peers not offering Noise are refused when we offer it
+	pub fn require_noise(&self) -> bool {
+		self.require_noise.unwrap_or(false)
+	}
+
+	/// return the protocol version advertised to peers during handshakes
+	pub fn protocol_version(&self) -> ProtocolVersion {
+		let local = ProtocolVersion::local();
+		match self.protocol_version {
+			Some(n) => ProtocolVersion(n.max(1).min(local.value())),
+			None => local,
+		}
+	}
+}
+
+/// Type of seeding the server will use to find other peers on the network.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Seeding {
+	/// No seeding, mostly for tests that programmatically connect
+	None,
+	/// A list of seeds provided to the server (can be addresses or DNS names)
+	List,
+	/// Automatically get a list of seeds from multiple DNS
+	DNSSeed,
+	/// Mostly for tests, where connections are initiated programmatically
+	Programmatic,
+}
+
+impl Default for Seeding {
+	fn default() -> Seeding {
+		Seeding::DNSSeed
+	}
+}
+
+/// How to handle a new inbound connection from a peer we're already connected
+/// to over an inbound connection.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateConnectionPolicy {
+	/// Keep the existing connection and refuse the new one
+	RejectNew,
+	/// Drop the existing connection in favor of the new one, unless it's
+	/// busy serving us data
+	ReplaceOld,
+}
+
+impl Default for DuplicateConnectionPolicy {
+	fn default() -> DuplicateConnectionPolicy {
+		DuplicateConnectionPolicy::RejectNew
+	}
+}
+
+/// Which address family to try first when a host name resolves to both IPv4
+/// and IPv6 addresses.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamilyPreference {
+	/// Keep the order the resolver returned
+	Any,
+	/// IPv4 addresses first
+	Ipv4,
+	/// IPv6 addresses first
+	Ipv6,
+}
+
+/// What to do with peers that never report their difficulty nor height, see
+/// PeerInfo::is_silent.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SilentPeerPolicy {
+	/// Treat them like any other peer
+	Ignore,
+	/// Don't sync from them and disconnect them first when over our limits
+	Deprioritize,
+	/// Disconnect them
+	Evict,
+}
+
+impl Default for SilentPeerPolicy {
+	fn default() -> SilentPeerPolicy {
+		SilentPeerPolicy::Deprioritize
+	}
+}
+
+impl Default for AddressFamilyPreference {
+	fn default() -> AddressFamilyPreference {
+		AddressFamilyPreference::Any
+	}
+}
+
+impl AddressFamilyPreference {
+	/// Orders resolved addresses with the preferred family first, the
+	/// resolver order being kept within each family.
+	pub fn order(&self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+		let (preferred, others): (Vec<SocketAddr>, Vec<SocketAddr>) =
+			addrs.into_iter().partition(|addr| match self {
+				AddressFamilyPreference::Any => true,
+				AddressFamilyPreference::Ipv4 => addr.is_ipv4(),
+				AddressFamilyPreference::Ipv6 => addr.is_ipv6(),
+			});
+		preferred.into_iter().chain(others).collect()
+	}
+}
+
+bitflags! {
+	/// Options for what type of interaction a peer supports
+	#[derive(Serialize, Deserialize)]
+	pub struct Capabilities: u32 {
+		/// We don't know (yet) what the peer can do.
+		const UNKNOWN = 0b0000_0000;
+		/// Can provide full history of headers back to genesis
+		/// (for at least one arbitrary fork).
+		const HEADER_HIST = 0b0000_0001;
+		/// Can provide block headers and the TxHashSet for some recent-enough
+		/// height.
+		const TXHASHSET_HIST = 0b0000_0010;
+		/// Can provide a list of healthy peers
+		const PEER_LIST = 0b0000_0100;
+		/// Can broadcast and request txs by kernel hash.
+		const TX_KERNEL_HASH = 0b0000_1000;
+		/// Can send/receive tor addresses
+		const TOR_ADDRESS = 0b0001_0000;
+		/// Can encrypt the connection with Noise, using the pre-shared key of
+		/// a private network. Not part of FULL_NODE, only offered when a key
+		/// is configured.
+		const NOISE = 0b1_0000_0000;
+
+		/// All nodes right now are "full nodes".
+		/// Some nodes internally may maintain longer block histories (archival_mode)
+		/// but we do not advertise this to other nodes.
+		/// All nodes by default will accept lightweight "kernel first" tx broadcast.
+		const FULL_NODE = Capabilities::HEADER_HIST.bits
+			| Capabilities::TXHASHSET_HIST.bits
+			| Capabilities::PEER_LIST.bits
+			| Capabilities::TX_KERNEL_HASH.bits
+			| Capabilities::TOR_ADDRESS.bits;
+	}
+}
+
+// Types of connection
+enum_from_primitive! {
+	#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+	pub enum Direction {
+		Inbound = 0,
+		Outbound = 1,
+		InboundTor = 2,
+		OutboundTor = 3,
+	}
+}
+
+// Ban reason
+enum_from_primitive! {
+	#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+	pub enum ReasonForBan {
+		None = 0,
+		BadBlock = 1,
+		BadCompactBlock = 2,
+		BadBlockHeader = 3,
+		BadTxHashSet = 4,
+		ManualBan = 5,
+		FraudHeight = 6,
+		BadHandshake = 7,
+		BadMessage = 8,
+		ImpossibleDifficulty = 9,
+	}
+}
+
+impl ReasonForBan {
+	/// Stable name of the reason, as written to the ban log.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ReasonForBan::None => "none",
+			ReasonForBan::BadBlock => "bad_block",
+			ReasonForBan::BadCompactBlock => "bad_compact_block",
+			ReasonForBan::BadBlockHeader => "bad_block_header",
+			ReasonForBan::BadTxHashSet => "bad_txhashset",
+			ReasonForBan::ManualBan => "manual_ban",
+			ReasonForBan::FraudHeight => "fraud_height",
+			ReasonForBan::BadHandshake => "bad_handshake",
+			ReasonForBan::BadMessage => "bad_message",
+			ReasonForBan::ImpossibleDifficulty => "impossible_difficulty",
+		}
+	}
+}
+
+/// Requests we sent a peer relying on a capability it advertised, and how many
+/// of those it declined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CapabilityRefusals {
+	pub requested: u32,
+	pub refused: u32,
+}
+
+impl CapabilityRefusals {
+	/// Share of the requests the peer declined.
+	pub fn refusal_rate(&self) -> f64 {
+		if self.requested == 0 {
+			0.0
+		} else {
+			self.refused as f64 / self.requested as f64
+		}
+	}
+}
+
+/// Key attributes of a connected peer, as captured in a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerSnapshot {
+	pub capabilities: Capabilities,
+	pub user_agent: String,
+	pub version: ProtocolVersion,
+	pub direction: Direction,
+}
+
+/// The connected peers at a given time, see Peers::snapshot.
+#[derive(Debug, Clone)]
+pub struct PeerSetSnapshot {
+	pub taken_at: DateTime<Utc>,
+	pub peers: HashMap<PeerAddr, PeerSnapshot>,
+}
+
+impl PeerSetSnapshot {
+	/// Peers added, removed or with changed attributes going from this
+	/// snapshot to the other (usually later) one.
+	pub fn diff(&self, other: &PeerSetSnapshot) -> PeerSetDiff {
+		let mut diff = PeerSetDiff::default();
+		for (addr, peer) in &other.peers {
+			match self.peers.get(addr) {
+				None => diff.added.push(addr.clone()),
+				Some(previous) if previous != peer => diff.changed.push(addr.clone()),
+				Some(_) => {}
+			}
+		}
+		for addr in self.peers.keys() {
+			if !other.peers.contains_key(addr) {
+				diff.removed.push(addr.clone());
+			}
+		}
+		diff.added.sort_by_key(|addr| addr.to_string());
+		diff.removed.sort_by_key(|addr| addr.to_string());
+		diff.changed.sort_by_key(|addr| addr.to_string());
+		diff
+	}
+}
+
+/// Differences between two snapshots of the connected peers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerSetDiff {
+	pub added: Vec<PeerAddr>,
+	pub removed: Vec<PeerAddr>,
+	pub changed: Vec<PeerAddr>,
+}
+
+impl PeerSetDiff {
+	/// Whether the connected peers stayed the same.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+	}
+}
+
+/// Outcome of handing a block received from a peer over to the chain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockAccept {
+	/// The block was accepted, or otherwise handled without issue.
+	Accepted,
+	/// The block is valid as far as we can tell but we miss its parent.
+	Orphan,
+	/// We already have (or already processed) this block.
+	Duplicate,
+	/// The block will never be valid, with the reason the chain gave for it.
+	Invalid(String),
+}
+
+impl BlockAccept {
+	/// Whether the block is intrinsically bad, which is the only outcome
+	/// the sending peer should be banned for.
+	pub fn is_invalid(&self) -> bool {
+		match self {
+			BlockAccept::Invalid(_) => true,
+			_ => false,
+		}
+	}
+}
+
+/// Compatibility with the former boolean result, false meaning the block
+/// will never be valid.
+impl From<bool> for BlockAccept {
+	fn from(valid: bool) -> BlockAccept {
+		if valid {
+			BlockAccept::Accepted
+		} else {
+			BlockAccept::Invalid("rejected by chain".to_string())
+		}
+	}
+}
+
+impl From<BlockAccept> for bool {
+	fn from(accept: BlockAccept) -> bool {
+		!accept.is_invalid()
+	}
+}
+
+/// Assign each block height to a candidate able to serve it, going round
+/// robin over the candidates to spread the load. Heights no candidate can
+/// serve get None.
+pub fn route_block_requests<'a, T, F>(
+	candidates: &'a [T],
+	heights: &[u64],
+	info: F,
+) -> Vec<Option<&'a T>>
+where
+	F: Fn(&T) -> &PeerInfo,
+{
+	let mut next = 0;
+	heights
+		.iter()
+		.map(|&height| {
+			for i in 0..candidates.len() {
+				let idx = (next + i) % candidates.len();
+				if info(&candidates[idx]).can_serve_block(height) {
+					next = idx + 1;
+					return Some(&candidates[idx]);
+				}
+			}
+			None
+		})
+		.collect()
+}
+
+/// Pick the most stable looking of otherwise equal candidates, the one with
+/// the longest uptime (the first one on ties, peers not telling us their
+/// uptime come last). Uptimes are whatever peers claim, so this is only ever
+/// a soft preference.
+pub fn select_stable<T, F>(candidates: &[T], info: F) -> Option<&T>
+where
+	F: Fn(&T) -> &PeerInfo,
+{
+	let mut best: Option<(&T, Option<Duration>)> = None;
+	for candidate in candidates {
+		let uptime = info(candidate).uptime();
+		match best {
+			Some((_, best_uptime)) if best_uptime >= uptime => {}
+			_ => best = Some((candidate, uptime)),
+		}
+	}
+	best.map(|(candidate, _)| candidate)
+}
+
+/// Number of distinct nodes among the provided peers, the ones sharing a node
+/// nonce are the same node reached at different addresses.
+pub fn distinct_nodes<'a, I>(peers: I) -> usize
+where
+	I: IntoIterator<Item = &'a PeerInfo>,
+{
+	let mut nonces = HashSet::new();
+	let mut count = 0;
+	for info in peers {
+		match info.node_nonce {
+			Some(nonce) => {
+				if nonces.insert(nonce) {
+					count += 1;
+				}
+			}
+			None => count += 1,
+		}
+	}
+	count
+}
+
+/// Rough per-block difficulty of the network from what the provided peers
+/// advertise: the work each peer claims past the reference (height and total
+/// difficulty of a recent header of ours) averaged over the blocks since, the
+/// median of those (the lower one of the two middle ones on an even count).
+/// Peers not past the reference tell nothing recent. None without any peer
+/// past it.
+pub fn estimate_block_difficulty<'a, I>(
+	peers: I,
+	reference: (u64, Difficulty),
+) -> Option<Difficulty>
+where
+	I: IntoIterator<Item = &'a PeerInfo>,
+{
+	let (ref_height, ref_difficulty) = reference;
+	let mut estimates = peers
+		.into_iter()
+		.filter(|info| info.height() > ref_height)
+		.map(|info| {
+			let work = info
+				.total_difficulty()
+				.to_num()
+				.saturating_sub(ref_difficulty.to_num());
+			work / (info.height() - ref_height)
+		})
+		.collect::<Vec<_>>();
+	if estimates.is_empty() {
+		return None;
+	}
+	estimates.sort();
+	Some(Difficulty::from_num(estimates[(estimates.len() - 1) / 2]))
+}
+
+/// Pick among the candidates that most recently served us valid headers or
+/// blocks, peers that never did come last. The ones that did within
+/// USEFUL_PEER_WINDOW of the most recent one are as useful, select_stable
+/// picks among those (or among all untested ones if none served us yet).
+pub fn select_useful<T, F>(candidates: &[T], info: F) -> Option<&T>
+where
+	F: Fn(&T) -> &PeerInfo,
+{
+	let last_useful = match candidates.iter().map(|c| info(c).last_useful()).max() {
+		Some(v) => v,
+		None => return None,
+	};
+	let useful = candidates
+		.iter()
+		.filter(|c| match (info(c).last_useful(), last_useful) {
+			(Some(at), Some(last)) => at + chrono::Duration::seconds(USEFUL_PEER_WINDOW) >= last,
+			(at, last) => at == last,
+		})
+		.collect::<Vec<_>>();
+	select_stable(&useful, |c| info(c)).cloned()
+}
+
+/// Weights of the signals making the sync priority of a peer, see
+/// PeerInfo::sync_priority. A zero weight leaves the signal out.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct SyncPriorityWeights {
+	pub difficulty: f64,
+	pub rtt: f64,
+	pub reputation: f64,
+	pub recency: f64,
+	pub uptime: f64,
+}
+
+impl Default for SyncPriorityWeights {
+	fn default() -> SyncPriorityWeights {
+		SyncPriorityWeights {
+			difficulty: 1.0,
+			rtt: 0.1,
+			reputation: 0.1,
+			recency: 0.1,
+			uptime: 0.05,
+		}
+	}
+}
+
+/// What peers get compared against when computing their sync priority.
+#[derive(Debug, Clone)]
+pub struct SyncPriorityCtx {
+	pub weights: SyncPriorityWeights,
+	/// Highest total difficulty among the ranked peers
+	pub max_difficulty: Difficulty,
+	/// Share of the requests each peer refused, peers missing refused none
+	pub refusal_rates: HashMap<PeerAddr, f64>,
+	pub now: DateTime<Utc>,
+}
+
+impl SyncPriorityCtx {
+	/// Context ranking the provided peers, without any refusals.
+	pub fn new<'a, I>(weights: SyncPriorityWeights, peers: I) -> SyncPriorityCtx
+	where
+		I: IntoIterator<Item = &'a PeerInfo>,
+	{
+		SyncPriorityCtx {
+			weights,
+			max_difficulty: peers
+				.into_iter()
+				.map(|info| info.total_difficulty())
+				.max()
+				.unwrap_or(Difficulty::zero()),
+			refusal_rates: HashMap::new(),
+			now: Utc::now(),
+		}
+	}
+}
+
+/// Sort candidates by decreasing sync priority, the first one on ties.
+pub fn rank_by_sync_priority<T, F>(candidates: &mut Vec<T>, ctx: &SyncPriorityCtx, info: F)
+where
+	F: Fn(&T) -> &PeerInfo,
+{
+	candidates.sort_by(|a, b| {
+		info(b)
+			.sync_priority(ctx)
+			.partial_cmp(&info(a).sync_priority(ctx))
+			.unwrap_or(std::cmp::Ordering::Equal)
+	});
+}
+
+/// Keep at most cap onion addresses out of the ones to dial (the first ones),
+/// clearnet addresses are all kept.
+pub fn limit_onion_dials(addrs: Vec<PeerAddr>, cap: usize) -> Vec<PeerAddr> {
+	let mut onions = 0;
+	addrs
+		.into_iter()
+		.filter(|addr| match addr {
+			PeerAddr::Onion(_) => {
+				onions += 1;
+				onions <= cap
+			}
+			PeerAddr::Ip(_) => true,
+		})
+		.collect()
+}
+
+/// Take up to cap received addresses to pass on to our peers from the pending
+/// ones, oldest first. Only routable addresses we connected to at least once
+/// are taken, unroutable ones are dropped and the others stay pending until
+/// we manage to connect to them.
+pub fn select_regossip(
+	pending: &mut VecDeque<PeerAddr>,
+	connected: &HashSet<PeerAddr>,
+	cap: usize,
+) -> Vec<PeerAddr> {
+	let mut selected = vec![];
+	let mut kept = VecDeque::with_capacity(pending.len());
+	while let Some(addr) = pending.pop_front() {
+		if !addr.is_routable() {
+			continue;
+		}
+		if selected.len() < cap && connected.contains(&addr) {
+			selected.push(addr);
+		} else {
+			kept.push_back(addr);
+		}
+	}
+	*pending = kept;
+	selected
+}
+
+/// Addresses we may gossip to the provided peer. Unless bridging, Tor peers
+/// only learn onion addresses and clearnet peers only clearnet ones, so
+/// neither side of the network gets linked to the other through us.
+pub fn gossip_addrs_for(
+	recipient: &PeerAddr,
+	addrs: Vec<PeerAddr>,
+	bridge_mode: bool,
+) -> Vec<PeerAddr> {
+	if bridge_mode {
+		return addrs;
+	}
+	let is_onion = |addr: &PeerAddr| match addr {
+		Onion(_) => true,
+		Ip(_) => false,
+	};
+	let tor = is_onion(recipient);
+	addrs
+		.into_iter()
+		.filter(|addr| is_onion(addr) == tor)
+		.collect()
+}
+
+/// Probability of refusing a new inbound connection at the provided load
+/// factor: none up to the threshold, then rising linearly to always at full
+/// load. Degrades gracefully instead of only refusing at the hard limit.
+pub fn inbound_refusal_probability(load: f64, threshold: f64) -> f64 {
+	let load = load.max(0.0).min(1.0);
+	if load <= threshold {
+		0.0
+	} else if threshold >= 1.0 {
+		1.0
+	} else {
+		((load - threshold) / (1.0 - threshold)).min(1.0)
+	}
+}
+
+/// Whether a txhashset archive of the provided size can be downloaded and
+/// unzipped with the provided free space (in bytes) left in our tmp dir.
+pub fn txhashset_fits(archive_bytes: u64, free_space: u64) -> bool {
+	let needed = archive_bytes.saturating_mul(TXHASHSET_UNZIP_OVERHEAD + 1);
+	needed <= free_space
+}
+
+/// Classification of a header timestamp relative to our own clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderTimestamp {
+	/// Timestamp is within the allowed drift from our clock.
+	Valid,
+	/// Timestamp is too far in the future, even accounting for the peer clock skew.
+	Future,
+}
+
+/// Slack (in seconds) added to the age of our header head when bounding the
+/// difficulty peers can claim, covering our own clock and late headers.
+const PLAUSIBLE_DIFFICULTY_SLACK_SECS: u64 = 3_600;
+
+/// Loose upper bound on the total difficulty a peer can plausibly claim, given
+/// the total and last block difficulty of our header head and how long ago it
+/// was mined. Nobody can have mined more than twice the blocks expected since
+/// (plus an hour of slack) on top of our head, and clamping keeps the
+/// difficulty from growing much more than threefold per adjustment window, we
+/// let it quadruple (plus one window of slack) so honest peers never hit the
+/// bound. None if our head is so old that any difficulty is plausible.
+pub fn max_plausible_difficulty(
+	total_difficulty: Difficulty,
+	block_difficulty: Difficulty,
+	head_age_secs: u64,
+) -> Option<Difficulty> {
+	let blocks = head_age_secs.saturating_add(PLAUSIBLE_DIFFICULTY_SLACK_SECS)
+		/ consensus::BLOCK_TIME_SEC
+		* 2 + 1;
+	let windows = blocks / consensus::DIFFICULTY_ADJUST_WINDOW + 2;
+	let growth =
+		(consensus::CLAMP_FACTOR * consensus::CLAMP_FACTOR).checked_pow(windows.min(64) as u32)?;
+	let work = block_difficulty
+		.to_num()
+		.max(consensus::MIN_DIFFICULTY)
+		.checked_mul(growth)?
+		.checked_mul(blocks)?;
+	total_difficulty
+		.to_num()
+		.checked_add(work)
+		.map(Difficulty::from_num)
+}
+
+/// Advisory outbound peer count for a network of the provided (estimated)
+/// size. The chance of being eclipsed drops quickly with every extra honest
+/// outbound connection, so we scale with the log of the network size (one
+/// more peer each time the network doubles) rather than with the size itself.
+/// A network of about a hundred nodes gets the default of 8. Bounded to
+/// MIN/MAX_RECOMMENDED_OUTBOUND_COUNT, it's never applied automatically.
+pub fn recommended_outbound_count(network_size_estimate: usize) -> u32 {
+	let bits = (std::mem::size_of::<usize>() * 8) as u32 - network_size_estimate.leading_zeros();
+	(bits + 1)
+		.max(MIN_RECOMMENDED_OUTBOUND_COUNT)
+		.min(MAX_RECOMMENDED_OUTBOUND_COUNT)
+}
+
+#[derive(Clone, Debug)]
+pub struct PeerLiveInfo {
+	pub total_difficulty: Difficulty,
 	pub height: u64,
 	pub last_seen: DateTime<Utc>,
 	pub stuck_detector: DateTime<Utc>,
 	pub first_seen: DateTime<Utc>,
+	/// Estimate (in seconds) of how far ahead of ours the peer clock is.
+	pub clock_skew: i64,
+	/// Set while the peer is serving us a significant transfer, until when
+	/// we give up waiting for it.
+	pub busy_until: Option<Instant>,
+	/// Lowest height the peer still holds full blocks for, 0 until we learn
+	/// it pruned older ones.
+	pub prune_height: u64,
+	/// Capabilities the peer announced since the handshake, if it changed them.
+	pub capabilities: Option<Capabilities>,
+	/// Number of headers we ask the peer for at once during sync.
+	pub header_batch_size: u32,
+	/// When we last sent the peer a keepalive (or connected to it).
+	pub last_keepalive: Instant,
+	/// When the peer last served us valid headers or blocks.
+	pub last_useful: Option<DateTime<Utc>>,
+	/// When we last asked the peer for its capabilities (or connected to it).
+	pub last_capabilities_probe: Instant,
+	/// Best header (hash, height, total difficulty) the peer last reported
+	/// in answer to a GetTip.
+	pub header_tip: Option<(Hash, u64, Difficulty)>,
+	/// When we sent the ping we're still waiting the pong of.
+	pub ping_sent: Option<Instant>,
+	/// Round trip time of the last ping answered by the peer.
+	pub rtt: Option<Duration>,
+	/// Where we got the address from, for outbound peers.
+	pub seed_source: Option<SeedSource>,
+	/// Whether the peer reported its difficulty and height (ping or pong)
+	/// since the handshake.
+	pub difficulty_reported: bool,
+}
+
+/// What each side advertised during the handshake and what got agreed on,
+/// kept to diagnose interop issues.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolNegotiation {
+	/// Capabilities we advertised.
+	pub our_capabilities: Capabilities,
+	/// Highest protocol version we advertised.
+	pub our_version_max: ProtocolVersion,
+	/// Capabilities the peer advertised.
+	pub advertised_capabilities: Capabilities,
+	/// Highest protocol version the peer advertised.
+	pub advertised_version_max: ProtocolVersion,
+	/// Version both sides talk, the lowest of the two maximums.
+	pub negotiated_version: ProtocolVersion,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -541,9 +2368,20 @@ pub struct PeerLiveInfo {
 pub struct PeerInfo {
 	pub capabilities: Capabilities,
 	pub user_agent: String,
+	/// Uptime the peer claimed during the handshake (untrusted, only from
+	/// protocol version 4 on)
+	pub peer_uptime: Option<Duration>,
 	pub version: ProtocolVersion,
 	pub addr: PeerAddr,
 	pub direction: Direction,
+	/// Whether the peer accepts connections: we connected to it, or it
+	/// advertised a listening port in its hand.
+	pub inbound_reachable: bool,
+	/// Nonce the peer sent in its hand, when it connected to us. Peers sharing
+	/// their node nonce send the same one over all their connections.
+	pub node_nonce: Option<u64>,
+	/// Details of the handshake, None for peers we didn't shake hands with.
+	pub negotiation: Option<ProtocolNegotiation>,
 	pub live_info: Arc<RwLock<PeerLiveInfo>>,
 	pub header_sync_requested: Arc<AtomicUsize>,
 	pub last_header: Arc<Mutex<Instant>>,
@@ -558,11 +2396,30 @@ impl PeerLiveInfo {
 			first_seen: Utc::now(),
 			last_seen: Utc::now(),
 			stuck_detector: Utc::now(),
+			clock_skew: 0,
+			busy_until: None,
+			prune_height: 0,
+			capabilities: None,
+			header_batch_size: HEADER_BATCH_START,
+			last_keepalive: Instant::now(),
+			last_useful: None,
+			last_capabilities_probe: Instant::now(),
+			header_tip: None,
+			ping_sent: None,
+			rtt: None,
+			seed_source: None,
+			difficulty_reported: false,
 		}
 	}
 }
 
 impl PeerInfo {
+	/// Whether the peer protocol version is recent enough for it to know
+	/// messages of this type.
+	pub fn supports(&self, msg_type: msg::Type) -> bool {
+		self.version >= msg::min_version(msg_type)
+	}
+
 	/// The current total_difficulty of the peer.
 	pub fn total_difficulty(&self) -> Difficulty {
 		self.live_info.read().total_difficulty
@@ -581,6 +2438,37 @@ impl PeerInfo {
 		self.live_info.read().height
 	}
 
+	/// Lowest height the peer still holds full blocks for.
+	pub fn prune_height(&self) -> u64 {
+		self.live_info.read().prune_height
+	}
+
+	/// Update the lowest height the peer still holds full blocks for.
+	pub fn set_prune_height(&self, prune_height: u64) {
+		self.live_info.write().prune_height = prune_height;
+	}
+
+	/// Capabilities the peer currently offers, the ones from the handshake
+	/// unless it updated them since.
+	pub fn current_capabilities(&self) -> Capabilities {
+		self.live_info
+			.read()
+			.capabilities
+			.unwrap_or(self.capabilities)
+	}
+
+	pub fn set_capabilities(&self, capabilities: Capabilities) {
+		self.live_info.write().capabilities = Some(capabilities);
+	}
+
+	/// Whether the peer can serve us the full block at this height: not pruned
+	/// yet and not above its tip. The tip is unknown (0) until the first ping
+	/// after the handshake, we don't hold that against the peer.
+	pub fn can_serve_block(&self, height: u64) -> bool {
+		let live_info = self.live_info.read();
+		height >= live_info.prune_height && (live_info.height == 0 || height <= live_info.height)
+	}
+
 	/// Time of last_seen for this peer (via ping/pong).
 	pub fn last_seen(&self) -> DateTime<Utc> {
 		self.live_info.read().last_seen
@@ -591,16 +2479,235 @@ impl PeerInfo {
 		self.live_info.read().first_seen
 	}
 
+	/// Estimate (in seconds) of how far ahead of ours the peer clock is.
+	pub fn clock_skew(&self) -> i64 {
+		self.live_info.read().clock_skew
+	}
+
+	/// Update the clock skew estimate of the peer.
+	pub fn set_clock_skew(&self, clock_skew: i64) {
+		self.live_info.write().clock_skew = clock_skew;
+	}
+
+	/// How long the peer has been running: the uptime it claimed during the
+	/// handshake (capped to MAX_PEER_UPTIME) plus how long we've been
+	/// connected since.
+	pub fn uptime(&self) -> Option<Duration> {
+		let connected = (Utc::now() - self.first_seen())
+			.to_std()
+			.unwrap_or(Duration::from_secs(0));
+		self.peer_uptime
+			.map(|uptime| uptime.min(MAX_PEER_UPTIME).saturating_add(connected))
+	}
+
+	/// Mark the peer as busy serving us data for at most the provided time,
+	/// busy peers aren't evicted.
+	pub fn set_busy(&self, timeout: Duration) {
+		self.live_info.write().busy_until = Some(Instant::now() + timeout);
+	}
+
+	/// The transfer from this peer is done (or failed), it can be evicted again.
+	pub fn clear_busy(&self) {
+		self.live_info.write().busy_until = None;
+	}
+
+	/// Whether the peer is in the middle of serving us a transfer that didn't
+	/// time out yet.
+	pub fn is_busy(&self) -> bool {
+		match self.live_info.read().busy_until {
+			Some(until) => Instant::now() < until,
+			None => false,
+		}
+	}
+
+	/// Number of header requests sent to this peer during sync that are still
+	/// waiting for a response.
+	pub fn header_sync_outstanding(&self) -> usize {
+		self.header_sync_requested.load(Ordering::Relaxed)
+	}
+
+	/// Record a header request sent to this peer during sync.
+	pub fn header_sync_request_sent(&self) {
+		self.header_sync_requested.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Record headers received from this peer. One outstanding request is
+	/// accounted for (never going below zero, unsolicited headers are fine)
+	/// and the timers are restarted.
+	pub fn header_sync_received(&self) {
+		let requested = &self.header_sync_requested;
+		let _ = requested.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |val| {
+			Some(val.saturating_sub(1))
+		});
+		let now = Instant::now();
+		*self.last_header.lock().unwrap() = now;
+		*self.last_header_reset.lock().unwrap() = now;
+	}
+
+	/// Number of headers to ask this peer for in our next sync request.
+	pub fn header_batch_size(&self) -> u32 {
+		self.live_info.read().header_batch_size
+	}
+
+	/// The peer promptly answered our last header request, ask it for a few
+	/// more next time (slow start, never more than MAX_BLOCK_HEADERS).
+	pub fn header_batch_answered(&self) {
+		let mut live_info = self.live_info.write();
+		live_info.header_batch_size = std::cmp::min(
+			live_info.header_batch_size + HEADER_BATCH_START,
+			MAX_BLOCK_HEADERS,
+		);
+	}
+
+	/// Our last header request to the peer timed out, halve what we ask it
+	/// for (down to HEADER_BATCH_MIN).
+	pub fn header_batch_timed_out(&self) {
+		let mut live_info = self.live_info.write();
+		live_info.header_batch_size =
+			std::cmp::max(live_info.header_batch_size / 2, HEADER_BATCH_MIN);
+	}
+
+	/// Time elapsed since we last received headers from this peer (or since
+	/// we connected, if it never sent us any).
+	pub fn time_since_last_header(&self) -> Duration {
+		self.last_header.lock().unwrap().elapsed()
+	}
+
+	/// Forget about any outstanding header requests to this peer, when giving
+	/// up on them. Restarts the reset timer but not the last header one.
+	pub fn reset_header_sync(&self) {
+		self.header_sync_requested.store(0, Ordering::Relaxed);
+		*self.last_header_reset.lock().unwrap() = Instant::now();
+	}
+
+	/// Check whether the header timestamp is further ahead of our clock than
+	/// the allowance plus the known clock skew of the peer.
+	pub fn header_timestamp(&self, header: &core::BlockHeader) -> HeaderTimestamp {
+		let max_ahead = FUTURE_TIMESTAMP_ALLOWANCE + self.clock_skew().max(0);
+		if header.timestamp > Utc::now() + chrono::Duration::seconds(max_ahead) {
+			HeaderTimestamp::Future
+		} else {
+			HeaderTimestamp::Valid
+		}
+	}
+
 	/// Update the total_difficulty, height and last_seen of the peer.
-	/// Takes a write lock on the live_info.
-	pub fn update(&self, height: u64, total_difficulty: Difficulty) {
+	/// Takes a write lock on the live_info. A height more than reorg_tolerance
+	/// below the one we know isn't taken, returns true to flag the peer.
+	pub fn update(&self, height: u64, total_difficulty: Difficulty, reorg_tolerance: u64) -> bool {
 		let mut live_info = self.live_info.write();
+		if height.saturating_add(reorg_tolerance) < live_info.height {
+			return true;
+		}
 		if total_difficulty != live_info.total_difficulty {
 			live_info.stuck_detector = Utc::now();
 		}
 		live_info.height = height;
 		live_info.total_difficulty = total_difficulty;
-		live_info.last_seen = Utc::now()
+		live_info.last_seen = Utc::now();
+		live_info.difficulty_reported = true;
+		false
+	}
+
+	/// Whether the peer, connected for longer than the grace period, still
+	/// never reported a difficulty nor height, only having the difficulty of
+	/// its handshake. Such peers don't take part in the network, they only
+	/// hold a connection slot.
+	pub fn is_silent(&self, grace: chrono::Duration, now: DateTime<Utc>) -> bool {
+		let live_info = self.live_info.read();
+		!live_info.difficulty_reported
+			&& live_info.height == 0
+			&& now - live_info.first_seen > grace
+	}
+
+	/// The peer served us something valid (headers, a block), so it isn't
+	/// stuck even if its advertised difficulty didn't change since.
+	pub fn note_progress(&self) {
+		let mut live_info = self.live_info.write();
+		live_info.stuck_detector = Utc::now();
+		live_info.last_useful = Some(live_info.stuck_detector);
+	}
+
+	/// When the peer last served us valid headers or blocks, None if it
+	/// never did.
+	pub fn last_useful(&self) -> Option<DateTime<Utc>> {
+		self.live_info.read().last_useful
+	}
+
+	/// Where we got the address of this outbound peer from, None for inbound
+	/// peers.
+	pub fn seed_source(&self) -> Option<SeedSource> {
+		self.live_info.read().seed_source.clone()
+	}
+
+	pub fn set_seed_source(&self, source: SeedSource) {
+		self.live_info.write().seed_source = Some(source);
+	}
+
+	/// We just sent the peer a ping, its pong will tell the round trip time.
+	pub fn ping_sent(&self) {
+		self.live_info.write().ping_sent = Some(Instant::now());
+	}
+
+	/// The peer answered our ping, updates the round trip time.
+	pub fn pong_received(&self) {
+		let mut live_info = self.live_info.write();
+		if let Some(sent) = live_info.ping_sent.take() {
+			live_info.rtt = Some(sent.elapsed());
+		}
+	}
+
+	/// Round trip time of the last ping the peer answered, None before the
+	/// first one.
+	pub fn rtt(&self) -> Option<Duration> {
+		self.live_info.read().rtt
+	}
+
+	/// Score of the peer as a sync peer, the weighted sum of its signals each
+	/// scaled to [0, 1]: total difficulty (relative to the best one), round
+	/// trip time, share of the requests it didn't refuse, how recently it was
+	/// useful and its uptime. Unknown signals score 0.
+	pub fn sync_priority(&self, ctx: &SyncPriorityCtx) -> f64 {
+		let weights = &ctx.weights;
+		let difficulty = if ctx.max_difficulty.to_num() == 0 {
+			0.0
+		} else {
+			consensus::difficulty_ratio(self.total_difficulty(), ctx.max_difficulty)
+		};
+		let rtt = match self.rtt() {
+			Some(rtt) => 1.0 / (1.0 + rtt.as_secs_f64()),
+			None => 0.0,
+		};
+		let reputation = 1.0 - ctx.refusal_rates.get(&self.addr).cloned().unwrap_or(0.0);
+		let recency = match self.last_useful() {
+			Some(t) => 1.0 / (1.0 + (ctx.now - t).num_seconds().max(0) as f64 / 60.0),
+			None => 0.0,
+		};
+		let uptime = match self.uptime() {
+			Some(uptime) => {
+				let hours = uptime.as_secs_f64() / 3600.0;
+				hours / (1.0 + hours)
+			}
+			None => 0.0,
+		};
+		weights.difficulty * difficulty
+			+ weights.rtt * rtt
+			+ weights.reputation * reputation
+			+ weights.recency * recency
+			+ weights.uptime * uptime
+	}
+
+	/// Best header the peer last reported as its tip, None until it answered
+	/// a GetTip.
+	pub fn header_tip(&self) -> Option<(Hash, u64, Difficulty)> {
+		self.live_info.read().header_tip
+	}
+
+	/// Records the tip the peer reported, which also tells us it's alive.
+	pub fn set_header_tip(&self, hash: Hash, height: u64, total_difficulty: Difficulty) {
+		let mut live_info = self.live_info.write();
+		live_info.header_tip = Some((hash, height, total_difficulty));
+		live_info.last_seen = Utc::now();
 	}
 }
 
@@ -627,18 +2734,23 @@ pub struct PeerInfoDisplay {
 	pub direction: Direction,
 	pub total_difficulty: Difficulty,
 	pub height: u64,
+	/// What each side advertised during the handshake, missing from older
+	/// nodes.
+	#[serde(default)]
+	pub negotiation: Option<ProtocolNegotiation>,
 }
 
 impl From<PeerInfo> for PeerInfoDisplay {
 	fn from(info: PeerInfo) -> PeerInfoDisplay {
 		PeerInfoDisplay {
-			capabilities: info.capabilities,
+			capabilities: info.current_capabilities(),
 			user_agent: info.user_agent.clone(),
 			version: info.version,
 			addr: info.clone().addr,
 			direction: info.direction,
 			total_difficulty: info.total_difficulty(),
 			height: info.height(),
+			negotiation: info.negotiation,
 		}
 	}
 }
@@ -670,22 +2782,33 @@ pub trait ChainAdapter: Sync + Send {
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction>;
 
+	/// Minimum fee a transaction must pay for us to relay it to our peers,
+	/// anything below would be dropped by the local policy anyway.
+	fn min_relay_fee(&self) -> u64 {
+		0
+	}
+
+	/// Lowest height we still hold full blocks for, told to our peers so
+	/// they don't ask us for older ones.
+	fn prune_height(&self) -> u64 {
+		0
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error>;
 
-	/// A block has been received from one of our peers. Returns true if the
-	/// block could be handled properly and is not deemed defective by the
-	/// chain. Returning false means the block will never be valid and
-	/// may result in the peer being banned.
+	/// A block has been received from one of our peers. Returns how the chain
+	/// handled it, only `BlockAccept::Invalid` means the block will never be
+	/// valid and results in the peer being banned.
 	fn block_received(
 		&self,
 		b: core::Block,
 		peer_info: &PeerInfo,
 		opts: chain::Options,
-	) -> Result<bool, chain::Error>;
+	) -> Result<BlockAccept, chain::Error>;
 
 	fn compact_block_received(
 		&self,
@@ -720,6 +2843,14 @@ pub trait ChainAdapter: Sync + Send {
 	/// immediately.
 	fn locate_headers(&self, locator: &[Hash]) -> Result<Vec<core::BlockHeader>, chain::Error>;
 
+	/// Gets the headers of our header chain from the start height on, at most
+	/// count of them (fewer when we don't have them all).
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::BlockHeader>, chain::Error>;
+
 	/// Gets a full block by its hash.
 	/// Converts block to v2 compatibility if necessary (based on peer protocol version).
 	fn get_block(&self, h: Hash, peer_info: &PeerInfo) -> Option<core::Block>;
@@ -738,6 +2869,29 @@ pub trait ChainAdapter: Sync + Send {
 	/// state data.
 	fn txhashset_receive_ready(&self) -> bool;
 
+	/// Whether the node is busy with maintenance work (compaction or a
+	/// txhashset write). Sync-heavy requests from peers are deferred meanwhile.
+	fn in_maintenance(&self) -> bool;
+
+	/// Where our own chain sync stands, we advertise reduced capabilities
+	/// while far from synced.
+	fn sync_status(&self) -> chain::SyncStatus {
+		chain::SyncStatus::NoSync
+	}
+
+	/// Whether we have the header with this hash. While syncing, blocks whose
+	/// parent we don't know are dropped without further validation.
+	fn knows_header(&self, _hash: Hash) -> bool {
+		true
+	}
+
+	/// Current resource pressure of the node, from 0.0 (idle) to 1.0
+	/// (saturated). New inbound connections get refused more often as it
+	/// rises.
+	fn load_factor(&self) -> f64 {
+		0.0
+	}
+
 	/// Update txhashset downloading progress
 	fn txhashset_download_update(
 		&self,
@@ -763,6 +2917,30 @@ pub trait ChainAdapter: Sync + Send {
 	/// Get a tmp file path in above specific tmp dir (create tmp dir if not exist)
 	/// Delete file if tmp file already exists
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf;
+
+	/// Free space (in bytes) available in the tmp dir, None if it can't be
+	/// told. Checked before accepting a txhashset archive.
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		None
+	}
+
+	/// Contiguous (inclusive) ranges of heights between from and to on our
+	/// header chain we still lack the full block for, to be requested.
+	fn missing_block_ranges(&self, _from: u64, _to: u64) -> Vec<(u64, u64)> {
+		vec![]
+	}
+
+	/// Height of our common ancestor with the peer that sent the locator,
+	/// None if we share none of its headers.
+	fn common_ancestor_height(&self, _locator: &[Hash]) -> Option<u64> {
+		None
+	}
+
+	/// Tip of our header chain, answered to peers asking for our tip. None if
+	/// it can't be read.
+	fn header_head(&self) -> Option<chain::Tip> {
+		None
+	}
 }
 
 /// Additional methods required by the protocol that don't need to be
@@ -780,4 +2958,14 @@ pub trait NetAdapter: ChainAdapter {
 
 	/// Is this peer currently banned?
 	fn is_banned(&self, addr: PeerAddr) -> bool;
+
+	/// A peer sent us a message we couldn't deserialize.
+	fn peer_ser_error(&self, addr: PeerAddr);
+
+	/// Is a txhashset archive anchored at this height recent enough to be
+	/// worth downloading?
+	fn txhashset_archive_acceptable(&self, height: u64) -> bool;
+
+	/// A peer declined a request of ours, telling us why.
+	fn request_declined(&self, addr: PeerAddr, msg_type: msg::Type, reason: msg::DeclineReason);
 }
@@ -0,0 +1,117 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::p2p::types::{Capabilities, Error, PeerAddr};
+use crate::p2p::{AdminAction, AdminControl, AuthorizedAdmin};
+
+// Admin controls recording the actions they performed.
+#[derive(Default)]
+struct RecordingAdmin {
+	actions: Mutex<Vec<AdminAction>>,
+}
+
+impl AdminControl for RecordingAdmin {
+	fn ban(&self, addr: PeerAddr, _token: Option<&str>) -> Result<(), Error> {
+		self.actions.lock().unwrap().push(AdminAction::Ban(addr));
+		Ok(())
+	}
+
+	fn unban(&self, addr: PeerAddr, _token: Option<&str>) -> Result<(), Error> {
+		self.actions.lock().unwrap().push(AdminAction::Unban(addr));
+		Ok(())
+	}
+
+	fn set_capabilities(
+		&self,
+		capabilities: Capabilities,
+		_token: Option<&str>,
+	) -> Result<(), Error> {
+		self.actions
+			.lock()
+			.unwrap()
+			.push(AdminAction::SetCapabilities(capabilities));
+		Ok(())
+	}
+
+	fn disconnect(&self, addr: PeerAddr, _token: Option<&str>) -> Result<(), Error> {
+		self.actions
+			.lock()
+			.unwrap()
+			.push(AdminAction::Disconnect(addr));
+		Ok(())
+	}
+}
+
+fn addr() -> PeerAddr {
+	PeerAddr::Ip("10.0.0.1:3414".parse::<SocketAddr>().unwrap())
+}
+
+// Only callers presenting the right token get their ban through.
+#[test]
+fn unauthorized_ban_rejected() {
+	let inner = Arc::new(RecordingAdmin::default());
+	let admin = AuthorizedAdmin::new(inner.clone(), |_: &AdminAction, token: Option<&str>| {
+		token == Some("secret")
+	});
+
+	match admin.ban(addr(), None) {
+		Err(Error::Unauthorized) => {}
+		other => panic!("unexpected {:?}", other),
+	}
+	match admin.ban(addr(), Some("guess")) {
+		Err(Error::Unauthorized) => {}
+		other => panic!("unexpected {:?}", other),
+	}
+	assert!(inner.actions.lock().unwrap().is_empty());
+
+	admin.ban(addr(), Some("secret")).unwrap();
+	assert_eq!(
+		*inner.actions.lock().unwrap(),
+		vec![AdminAction::Ban(addr())]
+	);
+}
+
+// The check sees the action, so some can be allowed to everyone.
+#[test]
+fn authorize_per_action() {
+	let inner = Arc::new(RecordingAdmin::default());
+	let admin = AuthorizedAdmin::new(
+		inner.clone(),
+		|action: &AdminAction, token: Option<&str>| match action {
+			AdminAction::Disconnect(_) => true,
+			_ => token == Some("secret"),
+		},
+	);
+
+	admin.disconnect(addr(), None).unwrap();
+	assert!(admin.unban(addr(), None).is_err());
+	assert!(admin
+		.set_capabilities(Capabilities::PEER_LIST, None)
+		.is_err());
+	admin
+		.set_capabilities(Capabilities::PEER_LIST, Some("secret"))
+		.unwrap();
+	assert_eq!(
+		*inner.actions.lock().unwrap(),
+		vec![
+			AdminAction::Disconnect(addr()),
+			AdminAction::SetCapabilities(Capabilities::PEER_LIST),
+		]
+	);
+}
@@ -0,0 +1,80 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::p2p::types::PeerAddr;
+
+fn config(host: IpAddr, advertised_addr: Option<PeerAddr>) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host,
+		port: 3414,
+		advertised_addr,
+		..p2p::P2PConfig::default()
+	}
+}
+
+// With tor enabled we always advertise our onion service.
+#[test]
+fn advertised_addr_onion() {
+	let explicit = PeerAddr::Ip(SocketAddr::new(
+		IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5)),
+		3414,
+	));
+	let onion = "2a6at2obto3uvkpkitqp4wxcg6u36qf534eucbskqciturczzc5suyid".to_string();
+	let config = config(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), Some(explicit));
+
+	assert_eq!(
+		config.advertised_addr(Some(onion.clone())),
+		Some(PeerAddr::Onion(onion))
+	);
+}
+
+// Without tor, an explicitly configured address wins over the bind address.
+#[test]
+fn advertised_addr_explicit() {
+	let explicit = PeerAddr::Ip(SocketAddr::new(
+		IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5)),
+		13414,
+	));
+	let config = config(
+		IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+		Some(explicit.clone()),
+	);
+
+	assert_eq!(config.advertised_addr(None), Some(explicit));
+}
+
+// Otherwise we fall back to the bind address, but never advertise an
+// unspecified or loopback one.
+#[test]
+fn advertised_addr_bind_fallback() {
+	let host = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+	assert_eq!(
+		config(host, None).advertised_addr(None),
+		Some(PeerAddr::Ip(SocketAddr::new(host, 3414)))
+	);
+
+	let unusable = [
+		IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
+		IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+		IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+		IpAddr::V6(Ipv6Addr::LOCALHOST),
+	];
+	for host in unusable.iter() {
+		assert_eq!(config(*host, None).advertised_addr(None), None);
+	}
+}
@@ -0,0 +1,170 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use chrono::{Duration, Utc};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{IpPrefix, ReasonForBan};
+
+fn prefixes(ranges: &[&str]) -> Vec<IpPrefix> {
+	ranges.iter().map(|r| r.parse().unwrap()).collect()
+}
+
+#[test]
+fn ip_prefix() {
+	let prefix: IpPrefix = "10.1.2.3/8".parse().unwrap();
+	assert_eq!(prefix.to_string(), "10.0.0.0/8");
+	assert_eq!(prefix, "10.0.0.0/8".parse().unwrap());
+	assert!(prefix.contains(&"10.200.0.1".parse().unwrap()));
+	assert!(prefix.contains(&"::ffff:10.9.9.9".parse().unwrap()));
+	assert!(!prefix.contains(&"11.0.0.1".parse().unwrap()));
+
+	let single: IpPrefix = "192.168.1.1".parse().unwrap();
+	assert_eq!(single.prefix_len(), 32);
+	assert!(single.contains(&"192.168.1.1".parse().unwrap()));
+	assert!(!single.contains(&"192.168.1.2".parse().unwrap()));
+
+	let v6: IpPrefix = "2001:db8::1/32".parse().unwrap();
+	assert_eq!(v6.to_string(), "2001:db8::/32");
+	assert!(v6.contains(&"2001:db8:ffff::1".parse().unwrap()));
+	assert!(!v6.contains(&"10.0.0.1".parse().unwrap()));
+
+	assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+	assert!("not an ip/8".parse::<IpPrefix>().is_err());
+	assert!("::/0"
+		.parse::<IpPrefix>()
+		.unwrap()
+		.contains(&"::1".parse().unwrap()));
+}
+
+// Importing a ban list drops the connected peers in the listed subnets, and
+// importing it again doesn't add anything.
+#[test]
+fn import_ban_list() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("ban_list"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	// Clients connecting from distinct loopback addresses.
+	let server_addr = PeerAddr::Ip(SocketAddr::new(p2p_config.host, p2p_config.port));
+	let clients: Vec<_> = ["127.0.0.2", "127.0.0.3", "127.0.0.4"]
+		.iter()
+		.enumerate()
+		.map(|(i, ip)| {
+			let ip: IpAddr = ip.parse().unwrap();
+			let client = Arc::new(new_server(
+				test_dir(&format!("ban_list_client{}", i)),
+				p2p::P2PConfig {
+					host: ip,
+					port: open_port(),
+					outbound_bind: Some(ip),
+					..p2p::P2PConfig::default()
+				},
+			));
+			client.connect(server_addr.clone(), 100_000).unwrap();
+			client
+		})
+		.collect();
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(server.peers.peer_inbound_count(), 3);
+
+	let ban_list = prefixes(&["127.0.0.2/32", "127.0.0.3", "10.0.0.0/8"]);
+	let added = server
+		.peers
+		.import_ban_list(&ban_list, ReasonForBan::ManualBan, 3600)
+		.unwrap();
+	assert_eq!(added, 3);
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(server.peers.peer_inbound_count(), 1);
+	let remaining = server.peers.connected_peers();
+	assert_eq!(
+		remaining[0].info.addr,
+		PeerAddr::Ip(SocketAddr::new(
+			"127.0.0.4".parse().unwrap(),
+			clients[2].config.port
+		))
+	);
+	assert!(server
+		.peers
+		.is_banned(PeerAddr::Ip("127.0.0.2:1234".parse().unwrap())));
+	assert!(server
+		.peers
+		.is_banned(PeerAddr::Ip("10.1.2.3:3414".parse().unwrap())));
+	assert!(!server
+		.peers
+		.is_banned(PeerAddr::Ip("127.0.0.4:1234".parse().unwrap())));
+
+	// Importing again, duplicates included, adds nothing.
+	let mut again = ban_list.clone();
+	again.push("10.1.2.3/8".parse().unwrap());
+	assert_eq!(
+		server
+			.peers
+			.import_ban_list(&again, ReasonForBan::ManualBan, 3600)
+			.unwrap(),
+		0
+	);
+	assert_eq!(server.peers.subnet_bans().unwrap().len(), 3);
+
+	assert_eq!(
+		server
+			.peers
+			.import_ban_list(
+				&prefixes(&["10.0.0.0/8", "192.168.0.0/16"]),
+				ReasonForBan::ManualBan,
+				3600
+			)
+			.unwrap(),
+		1
+	);
+	assert_eq!(server.peers.subnet_bans().unwrap().len(), 4);
+
+	// Subnet bans expire like any other.
+	assert_eq!(server.peers.prune_expired_bans(Utc::now()), 0);
+	assert_eq!(
+		server
+			.peers
+			.prune_expired_bans(Utc::now() + Duration::seconds(3600)),
+		4
+	);
+	assert!(!server
+		.peers
+		.is_banned(PeerAddr::Ip("10.1.2.3:3414".parse().unwrap())));
+
+	server.stop();
+	for client in clients {
+		client.stop();
+	}
+}
@@ -0,0 +1,86 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{healthy_peer, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::ReasonForBan;
+
+// Every ban appends a line with the address, reason and how many times the
+// peer was banned, earlier lines are kept.
+#[test]
+fn bans_appended() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let db_root = test_dir("ban_log");
+	let _ = fs::remove_dir_all(db_root);
+	fs::create_dir_all(db_root).unwrap();
+	let log_path = PathBuf::from(db_root).join("bans.log");
+	let config = p2p::P2PConfig {
+		ban_log_path: Some(log_path.clone()),
+		..p2p::P2PConfig::default()
+	};
+	let server = p2p::Server::new(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let first = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let second = PeerAddr::Ip("10.0.0.2:3414".parse().unwrap());
+	server
+		.peers
+		.save_peer(&healthy_peer(first.clone()))
+		.unwrap();
+	server
+		.peers
+		.save_peer(&healthy_peer(second.clone()))
+		.unwrap();
+
+	// Not connected, the ban only updates the stored state (and the log).
+	let _ = server.peers.ban_peer(first.clone(), ReasonForBan::BadBlock);
+	let log = fs::read_to_string(&log_path).unwrap();
+	let lines: Vec<&str> = log.lines().collect();
+	assert_eq!(lines.len(), 1);
+	assert!(lines[0].ends_with(" addr=10.0.0.1:3414 reason=bad_block ban_count=1"));
+
+	let _ = server.peers.ban_peer(second, ReasonForBan::BadMessage);
+	let _ = server.peers.ban_peer(first, ReasonForBan::BadBlockHeader);
+	let log = fs::read_to_string(&log_path).unwrap();
+	let lines: Vec<&str> = log.lines().collect();
+	assert_eq!(lines.len(), 3);
+	assert!(lines[0].ends_with(" addr=10.0.0.1:3414 reason=bad_block ban_count=1"));
+	assert!(lines[1].ends_with(" addr=10.0.0.2:3414 reason=bad_message ban_count=1"));
+	assert!(lines[2].ends_with(" addr=10.0.0.1:3414 reason=bad_block_header ban_count=2"));
+}
@@ -0,0 +1,140 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{
+	healthy_peer, peer_info, server_with_adapter, test_dir, TestAdapter, TestChain,
+};
+use crate::core::core::Block;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{BlockAccept, ChainAdapter, PeerInfo};
+
+/// Adapter handing back a fixed outcome for every block it receives.
+struct OutcomeAdapter {
+	outcome: BlockAccept,
+}
+
+impl TestChain for OutcomeAdapter {
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		Ok(self.outcome.clone())
+	}
+}
+
+// Check whether a peer sending us a block with the given outcome is banned.
+fn banned_for(db_root: &str, outcome: BlockAccept) -> bool {
+	let server = server_with_adapter(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(TestAdapter(OutcomeAdapter {
+			outcome: outcome.clone(),
+		})),
+	);
+	let addr = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	server.peers.save_peer(&healthy_peer(addr.clone())).unwrap();
+	let info = peer_info(addr.clone());
+
+	let res = server
+		.peers
+		.block_received(Block::default(), &info, chain::Options::NONE)
+		.unwrap();
+	assert_eq!(res, outcome);
+	server.peers.is_banned(addr)
+}
+
+// Only intrinsically invalid blocks get their sender banned, orphans and
+// duplicates are a normal part of relay and the peer is kept.
+#[test]
+fn block_accept_outcomes() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	assert!(!banned_for(
+		test_dir("block_accept_ok"),
+		BlockAccept::Accepted
+	));
+	assert!(!banned_for(
+		test_dir("block_accept_orphan"),
+		BlockAccept::Orphan
+	));
+	assert!(!banned_for(
+		test_dir("block_accept_duplicate"),
+		BlockAccept::Duplicate
+	));
+	assert!(banned_for(
+		test_dir("block_accept_invalid"),
+		BlockAccept::Invalid("bad kernel sum".to_string())
+	));
+}
+
+// The boolean shim maps to the former "false means ban" semantics.
+#[test]
+fn block_accept_bool_shim() {
+	assert_eq!(BlockAccept::from(true), BlockAccept::Accepted);
+	assert!(BlockAccept::from(false).is_invalid());
+	assert!(bool::from(BlockAccept::Accepted));
+	assert!(bool::from(BlockAccept::Orphan));
+	assert!(bool::from(BlockAccept::Duplicate));
+	assert!(!bool::from(BlockAccept::Invalid("bad".to_string())));
+}
+
+// Only accepted blocks count as progress of the peer, replaying blocks we
+// have or can't attach yet doesn't.
+#[test]
+fn progress_on_accepted_only() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let progressed = |db_root, outcome| {
+		let server = server_with_adapter(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			p2p::P2PConfig::default(),
+			Arc::new(TestAdapter(OutcomeAdapter { outcome })),
+		);
+		let info = peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+		server
+			.peers
+			.block_received(Block::default(), &info, chain::Options::NONE)
+			.unwrap();
+		info.last_useful().is_some()
+	};
+	assert!(progressed(
+		test_dir("block_progress_ok"),
+		BlockAccept::Accepted
+	));
+	assert!(!progressed(
+		test_dir("block_progress_orphan"),
+		BlockAccept::Orphan
+	));
+	assert!(!progressed(
+		test_dir("block_progress_duplicate"),
+		BlockAccept::Duplicate
+	));
+}
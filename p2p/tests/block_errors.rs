@@ -0,0 +1,183 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_store as store;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use self::common::{
+	healthy_peer, peer_info, server_with_adapter, test_dir, TestAdapter, TestChain,
+};
+use crate::core::core::hash::Hash;
+use crate::core::core::Block;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{BlockAccept, ChainAdapter, PeerInfo};
+
+/// Adapter failing on every block it receives with the same chain error.
+struct ErrorAdapter {
+	error: chain::ErrorKind,
+}
+
+impl TestChain for ErrorAdapter {
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		Err(self.error.clone().into())
+	}
+}
+
+/// Adapter failing on blocks with a busy store a number of times before
+/// accepting them, counting the blocks it's handed.
+struct FlakyAdapter {
+	failures: AtomicUsize,
+	calls: AtomicUsize,
+}
+
+impl TestChain for FlakyAdapter {
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		self.calls.fetch_add(1, Ordering::Relaxed);
+		if self.failures.load(Ordering::Relaxed) > 0 {
+			self.failures.fetch_sub(1, Ordering::Relaxed);
+			return Err(store_error().into());
+		}
+		Ok(BlockAccept::Accepted)
+	}
+}
+
+fn store_error() -> chain::ErrorKind {
+	chain::ErrorKind::StoreErr(
+		store::Error::OtherErr("store busy".to_string()),
+		"block_exists".to_string(),
+	)
+}
+
+// Check whether a peer sending us a block the chain fails on with the given
+// error is banned, along with what we make of the block.
+fn banned_for(db_root: &str, error: chain::ErrorKind) -> (bool, Result<BlockAccept, chain::Error>) {
+	let server = server_with_adapter(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(TestAdapter(ErrorAdapter { error })),
+	);
+	let addr = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	server.peers.save_peer(&healthy_peer(addr.clone())).unwrap();
+	let info = peer_info(addr.clone());
+
+	let res = server
+		.peers
+		.block_received(Block::default(), &info, chain::Options::NONE);
+	(server.peers.is_banned(addr), res)
+}
+
+// A busy store is our problem and the peer is kept, a block failing
+// validation gets its sender banned.
+#[test]
+fn block_error_classification() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	assert_eq!(p2p::P2PConfig::default().block_error_retries(), 2);
+
+	let (banned, res) = banned_for(test_dir("block_errors_store"), store_error());
+	assert!(!banned);
+	assert_eq!(res.unwrap_err().kind(), store_error());
+
+	let (banned, res) = banned_for(
+		test_dir("block_errors_invalid"),
+		chain::ErrorKind::InvalidPow,
+	);
+	assert!(banned);
+	assert!(res.unwrap().is_invalid());
+}
+
+fn flaky_server(db_root: &str, failures: usize) -> (p2p::Server, Arc<TestAdapter<FlakyAdapter>>) {
+	let adapter = Arc::new(TestAdapter(FlakyAdapter {
+		failures: AtomicUsize::new(failures),
+		calls: AtomicUsize::new(0),
+	}));
+	let server = p2p::Server::new(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	(server, adapter)
+}
+
+// A block the store was too busy for is processed again from the copy we
+// kept once due, without asking the peer for it again.
+#[test]
+fn transient_block_error_reprocessed() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, adapter) = flaky_server(test_dir("block_errors_reprocessed"), 1);
+	let info = peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+	assert!(server
+		.peers
+		.block_received(Block::default(), &info, chain::Options::NONE)
+		.is_err());
+	assert_eq!(adapter.calls.load(Ordering::Relaxed), 1);
+
+	// not due yet
+	assert_eq!(server.peers.retry_deferred_blocks(Instant::now()), 0);
+
+	let later = Instant::now() + Duration::from_secs(60);
+	assert_eq!(server.peers.retry_deferred_blocks(later), 1);
+	assert_eq!(adapter.calls.load(Ordering::Relaxed), 2);
+	assert_eq!(server.peers.retry_deferred_blocks(later), 0);
+}
+
+// A block failing transiently every time is given up on after
+// block_error_retries attempts.
+#[test]
+fn transient_block_error_given_up() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, adapter) = flaky_server(test_dir("block_errors_given_up"), usize::MAX);
+	let info = peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+	let _ = server
+		.peers
+		.block_received(Block::default(), &info, chain::Options::NONE);
+
+	let later = Instant::now() + Duration::from_secs(60);
+	while server.peers.retry_deferred_blocks(later) > 0 {}
+	assert_eq!(adapter.calls.load(Ordering::Relaxed), 3);
+}
@@ -0,0 +1,115 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util::StopState;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{peer_info, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::core::Block;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{BlockAccept, ChainAdapter, PeerInfo};
+
+/// Adapter knowing the parent of every block or of none, counting the blocks
+/// it fully processes.
+struct ParentAdapter {
+	syncing: bool,
+	knows_parent: bool,
+	processed: AtomicUsize,
+}
+
+impl TestChain for ParentAdapter {
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		self.processed.fetch_add(1, Ordering::Relaxed);
+		Ok(BlockAccept::Accepted)
+	}
+	fn sync_status(&self) -> chain::SyncStatus {
+		if self.syncing {
+			chain::SyncStatus::BodySync {
+				current_height: 5,
+				highest_height: 10,
+			}
+		} else {
+			chain::SyncStatus::NoSync
+		}
+	}
+	fn knows_header(&self, _hash: Hash) -> bool {
+		self.knows_parent
+	}
+}
+
+// Hand a block over to a server with the provided adapter, returning the
+// outcome and whether the adapter processed the block.
+fn receive_block(db_root: &str, syncing: bool, knows_parent: bool) -> (BlockAccept, bool) {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	let adapter = Arc::new(TestAdapter(ParentAdapter {
+		syncing,
+		knows_parent,
+		processed: AtomicUsize::new(0),
+	}));
+	let server = p2p::Server::new(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	let info = peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+	let accept = server
+		.peers
+		.block_received(Block::default(), &info, chain::Options::NONE)
+		.unwrap();
+	(accept, adapter.processed.load(Ordering::Relaxed) == 1)
+}
+
+// While syncing, a block whose parent we don't know is dropped unprocessed.
+#[test]
+fn unknown_parent_dropped_during_sync() {
+	assert_eq!(
+		receive_block(test_dir("block_parent_unknown"), true, false),
+		(BlockAccept::Orphan, false)
+	);
+	assert_eq!(
+		receive_block(test_dir("block_parent_known"), true, true),
+		(BlockAccept::Accepted, true)
+	);
+}
+
+// Once synced orphans are still handed over, the chain keeps them around
+// until their parent shows up.
+#[test]
+fn unknown_parent_processed_when_synced() {
+	assert_eq!(
+		receive_block(test_dir("block_parent_synced"), false, false),
+		(BlockAccept::Accepted, true)
+	);
+}
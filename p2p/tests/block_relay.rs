@@ -0,0 +1,161 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, server_with_adapter, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::core::{Block, CompactBlock};
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer, PeerInfo};
+
+/// Adapter counting the compact blocks it receives, a slow one stalls on
+/// each of them.
+struct RelayAdapter {
+	slow: bool,
+	compact_blocks: AtomicUsize,
+}
+
+impl TestChain for RelayAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn compact_block_received(
+		&self,
+		_cb: core::core::CompactBlock,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.compact_blocks.fetch_add(1, Ordering::SeqCst);
+		if self.slow {
+			// stop reading from the connection for a while
+			thread::sleep(time::Duration::from_secs(60));
+		}
+		Ok(true)
+	}
+}
+
+// Connect a client to the server, announcing the provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+fn relay_adapter(slow: bool) -> Arc<TestAdapter<RelayAdapter>> {
+	Arc::new(TestAdapter(RelayAdapter {
+		slow,
+		compact_blocks: AtomicUsize::new(0),
+	}))
+}
+
+// A peer not keeping up with what we send gets skipped when relaying blocks,
+// the others still get them right away.
+#[test]
+fn block_relay_skips_backed_up_peer() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		max_relay_queue: Some(10),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(server_with_adapter(
+		test_dir("block_relay"),
+		Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		relay_adapter(false),
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let fast_adapter = relay_adapter(false);
+	let fast_client = server_with_adapter(
+		test_dir("block_relay_fast"),
+		Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		fast_adapter.clone(),
+	);
+	let slow_client = server_with_adapter(
+		test_dir("block_relay_slow"),
+		Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		relay_adapter(true),
+	);
+	let _fast = connect(&p2p_config, &fast_client, 5000);
+	let _slow = connect(&p2p_config, &slow_client, 5001);
+	thread::sleep(time::Duration::from_secs(1));
+
+	let slow = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.1:5001".parse().unwrap()))
+		.unwrap();
+	let fast = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()))
+		.unwrap();
+
+	// Keep relaying until the slow peer's socket buffers are full and its
+	// queue backs up.
+	let cb: CompactBlock = Block::default().into();
+	let mut rounds = 0;
+	while slow.send_queue_len() <= 10 && rounds < 1_000_000 {
+		server.peers.broadcast_compact_block(&cb);
+		rounds += 1;
+	}
+	let slow_queued = slow.send_queue_len();
+	assert!(slow_queued > 10);
+
+	// Let the fast peer catch up, then relay once more.
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(fast.send_queue_len(), 0);
+	let received = fast_adapter.compact_blocks.load(Ordering::SeqCst);
+
+	server.peers.broadcast_compact_block(&cb);
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(
+		fast_adapter.compact_blocks.load(Ordering::SeqCst),
+		received + 1
+	);
+	assert_eq!(slow.send_queue_len(), slow_queued);
+}
@@ -0,0 +1,101 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+mod common;
+
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::msg::{Ping, PRUNE_HEIGHT_VERSION};
+use crate::p2p::types::{route_block_requests, PeerAddr};
+use crate::p2p::PeerInfo;
+
+fn peer_info(addr: &str, height: u64, prune_height: u64) -> PeerInfo {
+	let info = common::peer_info(PeerAddr::Ip(addr.parse().unwrap()));
+	info.update(height, Difficulty::min(), std::u64::MAX);
+	info.set_prune_height(prune_height);
+	info
+}
+
+// A peer only serves blocks between its prune height and its tip.
+#[test]
+fn can_serve_block_bounds() {
+	let info = peer_info("10.0.0.1:3414", 100, 40);
+	assert!(!info.can_serve_block(39));
+	assert!(info.can_serve_block(40));
+	assert!(info.can_serve_block(100));
+	assert!(!info.can_serve_block(101));
+
+	// Tip not known yet (no ping since the handshake), only the floor applies.
+	let info = peer_info("10.0.0.2:3414", 0, 0);
+	assert!(info.can_serve_block(0));
+	assert!(info.can_serve_block(1_000));
+}
+
+// Each block goes to the next peer (round robin) holding it, blocks no peer
+// holds are left out.
+#[test]
+fn route_by_tip_and_prune_height() {
+	let behind = peer_info("10.0.0.1:3414", 50, 0);
+	let pruned = peer_info("10.0.0.2:3414", 200, 120);
+	let archive = peer_info("10.0.0.3:3414", 200, 0);
+	let low_pruned = peer_info("10.0.0.4:3414", 200, 300);
+	let candidates = vec![behind, pruned, archive, low_pruned];
+
+	let routed = |heights: &[u64]| -> Vec<Option<PeerAddr>> {
+		route_block_requests(&candidates, heights, |i| i)
+			.into_iter()
+			.map(|p| p.map(|p| p.addr.clone()))
+			.collect()
+	};
+	let addr = |i: usize| Some(candidates[i].addr.clone());
+
+	// Low blocks, only the peers holding history that far back.
+	assert_eq!(routed(&[10, 11, 12]), vec![addr(0), addr(2), addr(0)]);
+	// Past the tip of the lagging peer, pruned peer and archive alternate.
+	assert_eq!(
+		routed(&[150, 151, 152, 153]),
+		vec![addr(1), addr(2), addr(1), addr(2)]
+	);
+	// Above the lagging tip, the second peer pruned 60 but still holds 130.
+	assert_eq!(routed(&[60, 130]), vec![addr(2), addr(1)]);
+	// Beyond everybody's tip.
+	assert_eq!(routed(&[201]), vec![None]);
+
+	let empty: Vec<PeerInfo> = vec![];
+	assert!(route_block_requests(&empty, &[10], |i| i)[0].is_none());
+}
+
+// Peers tell their prune height in their pings and pongs, from a recent enough
+// protocol version on.
+#[test]
+fn ping_prune_height_gated_on_version() {
+	let ping = Ping {
+		total_difficulty: Difficulty::min(),
+		height: 100,
+		prune_height: Some(40),
+	};
+
+	let bytes = ser::ser_vec(&ping, PRUNE_HEIGHT_VERSION).unwrap();
+	let read: Ping = ser::deserialize(&mut &bytes[..], PRUNE_HEIGHT_VERSION).unwrap();
+	assert_eq!(read.prune_height, Some(40));
+
+	let old = ProtocolVersion(PRUNE_HEIGHT_VERSION.value() - 1);
+	let old_bytes = ser::ser_vec(&ping, old).unwrap();
+	assert_eq!(old_bytes.len() + 8, bytes.len());
+	let read: Ping = ser::deserialize(&mut &old_bytes[..], old).unwrap();
+	assert_eq!(read.prune_height, None);
+}
@@ -0,0 +1,72 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use crate::p2p::types::{gossip_addrs_for, PeerAddr};
+
+fn clearnet(addr: &str) -> PeerAddr {
+	PeerAddr::Ip(addr.parse().unwrap())
+}
+
+fn onion(addr: &str) -> PeerAddr {
+	PeerAddr::Onion(format!("{}.onion", addr))
+}
+
+fn known_addrs() -> Vec<PeerAddr> {
+	vec![
+		clearnet("8.8.8.8:3414"),
+		onion("maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd"),
+		clearnet("9.9.9.9:3414"),
+	]
+}
+
+#[test]
+fn bridge_mode_default_off() {
+	assert!(!p2p::P2PConfig::default().bridge_mode());
+}
+
+// Without bridging each side only learns addresses of its own kind.
+#[test]
+fn families_segregated() {
+	let tor_peer = onion("2a6at2obto3uvkpkitqp4wxcg6u36qf534eucbskqciturczzc5suyid");
+	assert_eq!(
+		gossip_addrs_for(&tor_peer, known_addrs(), false),
+		vec![onion(
+			"maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd"
+		)]
+	);
+
+	let clearnet_peer = clearnet("1.1.1.1:3414");
+	assert_eq!(
+		gossip_addrs_for(&clearnet_peer, known_addrs(), false),
+		vec![clearnet("8.8.8.8:3414"), clearnet("9.9.9.9:3414")]
+	);
+}
+
+// A bridge passes every address on, whatever the kind of the peer.
+#[test]
+fn bridge_mode_crosses_families() {
+	let tor_peer = onion("2a6at2obto3uvkpkitqp4wxcg6u36qf534eucbskqciturczzc5suyid");
+	assert_eq!(
+		gossip_addrs_for(&tor_peer, known_addrs(), true),
+		known_addrs()
+	);
+
+	let clearnet_peer = clearnet("1.1.1.1:3414");
+	assert_eq!(
+		gossip_addrs_for(&clearnet_peer, known_addrs(), true),
+		known_addrs()
+	);
+}
@@ -0,0 +1,91 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Peer;
+
+// A peer busy serving us data survives being over the inbound limit, once
+// the transfer is done (or timed out) it's evicted as usual.
+#[test]
+fn busy_peer_not_evicted() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("busy_peer"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(test_dir("busy_peer_client"), p2p::P2PConfig::default());
+	let client_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let _peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		client_addr.clone(),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_inbound_count(), 1);
+
+	// Over the limit of 0 inbound peers, but busy.
+	let inbound = server
+		.peers
+		.get_connected_peer(client_addr.clone())
+		.unwrap();
+	inbound.info.set_busy(time::Duration::from_secs(60));
+	assert!(inbound.info.is_busy());
+	server.peers.clean_peers(0, 8, &[]);
+	assert_eq!(server.peers.peer_inbound_count(), 1);
+
+	inbound.info.clear_busy();
+	assert!(!inbound.info.is_busy());
+
+	// The marker times out on its own if the transfer never completes.
+	inbound.info.set_busy(time::Duration::from_millis(200));
+	server.peers.clean_peers(0, 8, &[]);
+	assert_eq!(server.peers.peer_inbound_count(), 1);
+	thread::sleep(time::Duration::from_millis(400));
+	assert!(!inbound.info.is_busy());
+	server.peers.clean_peers(0, 8, &[]);
+	assert_eq!(server.peers.peer_inbound_count(), 0);
+	assert!(server.peers.get_connected_peer(client_addr).is_none());
+}
@@ -0,0 +1,118 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Capabilities;
+
+fn start_server(db_root: &str, port: u16, probe_interval: u64) -> Arc<p2p::Server> {
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		capabilities_probe_interval: Some(probe_interval),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			Capabilities::PEER_LIST | Capabilities::HEADER_HIST,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	server
+}
+
+// Connect the server to a fresh remote, returning the remote and its address.
+fn connect_remote(server: &p2p::Server, db_root: &str) -> (Arc<p2p::Server>, PeerAddr) {
+	let port = open_port();
+	let remote = start_server(db_root, port, 0);
+	thread::sleep(Duration::from_millis(500));
+	let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+	server.connect(addr.clone(), 100_000).unwrap();
+	thread::sleep(Duration::from_millis(500));
+	(remote, addr)
+}
+
+// A capability change we weren't told about gets noticed by the next probe,
+// updating both the live and the stored capabilities of the peer.
+#[test]
+fn probe_updates_capabilities() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = start_server(test_dir("capabilities_probe"), open_port(), 60);
+	let (remote, addr) = connect_remote(&server, test_dir("capabilities_probe_remote"));
+	let peer = server.peers.get_connected_peer(addr.clone()).unwrap();
+	let offered = remote.effective_capabilities();
+	assert_ne!(offered, Capabilities::UNKNOWN);
+	assert_eq!(peer.info.current_capabilities(), offered);
+
+	// what we know of the peer went stale over a long-lived connection
+	peer.info.set_capabilities(Capabilities::UNKNOWN);
+
+	// not due right after connecting
+	let start = Instant::now();
+	assert_eq!(server.peers.probe_capabilities(start), 0);
+
+	assert_eq!(
+		server
+			.peers
+			.probe_capabilities(start + Duration::from_secs(61)),
+		1
+	);
+	assert_eq!(
+		server
+			.peers
+			.probe_capabilities(start + Duration::from_secs(90)),
+		0
+	);
+	thread::sleep(Duration::from_millis(500));
+	assert_eq!(peer.info.current_capabilities(), offered);
+	assert_eq!(server.peers.get_peer(addr).unwrap().capabilities, offered);
+}
+
+// Probing can be disabled altogether.
+#[test]
+fn probe_disabled() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = start_server(test_dir("capabilities_probe_off"), open_port(), 0);
+	let (_remote, _) = connect_remote(&server, test_dir("capabilities_probe_off_remote"));
+	let later = Instant::now() + Duration::from_secs(365 * 24 * 3600);
+	assert_eq!(server.peers.probe_capabilities(later), 0);
+}
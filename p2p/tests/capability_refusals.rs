@@ -0,0 +1,173 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+fn start_server(db_root: &str, config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	let server = Arc::new(new_server(db_root, config));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	server
+}
+
+// Connect a client advertising TXHASHSET_HIST but configured not to serve
+// archives, so it declines every request.
+fn connect_refusing(server_config: &p2p::P2PConfig, client_root: &str, port: u16) -> Peer {
+	let client_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		serve_txhashset: Some(false),
+		..p2p::P2PConfig::default()
+	};
+	let client = new_server(client_root, client_config);
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::TXHASHSET_HIST,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap()
+}
+
+// Ask the peer for an archive it declines, recording both on the server.
+fn request_refused(server: &p2p::Server, addr: PeerAddr) {
+	let peer = server.peers.get_connected_peer(addr.clone()).unwrap();
+	server
+		.peers
+		.capability_requested(addr.clone(), Capabilities::TXHASHSET_HIST);
+	peer.send_txhashset_request(0, Hash::from_vec(&vec![]))
+		.unwrap();
+	thread::sleep(time::Duration::from_millis(500));
+	server
+		.peers
+		.capability_refused(addr, Capabilities::TXHASHSET_HIST);
+}
+
+fn server_config(capability_refusal_threshold: u32, penalize: bool) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		capability_refusal_threshold: Some(capability_refusal_threshold),
+		penalize_capability_refusals: Some(penalize),
+		..p2p::P2PConfig::default()
+	}
+}
+
+// A peer advertising TXHASHSET_HIST that declines every archive request gets
+// its refusals counted and is eventually flagged.
+#[test]
+fn refusing_peer_flagged() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let config = server_config(3, false);
+	let server = start_server(test_dir("refusal_flag"), config.clone());
+	let _peer = connect_refusing(&config, test_dir("refusal_flag_client"), 5000);
+	thread::sleep(time::Duration::from_millis(500));
+
+	let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), 5000));
+	for refused in 1..3 {
+		request_refused(&server, addr.clone());
+		let refusals = server.peers.capability_refusals(addr.clone());
+		let counts = refusals[&Capabilities::TXHASHSET_HIST];
+		assert_eq!(counts.requested, refused);
+		assert_eq!(counts.refused, refused);
+		assert!(server.peers.refusing_peers().is_empty());
+	}
+
+	request_refused(&server, addr.clone());
+	let flagged = server.peers.refusing_peers();
+	assert_eq!(flagged.len(), 1);
+	assert_eq!(flagged[0].0, addr);
+	assert_eq!(flagged[0].1, Capabilities::TXHASHSET_HIST);
+	assert_eq!(flagged[0].2.refused, 3);
+
+	// only reported, the peer stays connected
+	assert!(server.peers.get_connected_peer(addr.clone()).is_some());
+	assert!(!server.peers.is_parked(addr));
+
+	server.stop();
+}
+
+// Refusals of a capability the peer never advertised aren't held against it.
+#[test]
+fn unadvertised_capability_not_counted() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let config = server_config(1, true);
+	let server = start_server(test_dir("refusal_unadvertised"), config.clone());
+	let _peer = connect_refusing(&config, test_dir("refusal_unadvertised_client"), 5001);
+	thread::sleep(time::Duration::from_millis(500));
+
+	let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), 5001));
+	server
+		.peers
+		.capability_requested(addr.clone(), Capabilities::HEADER_HIST);
+	server
+		.peers
+		.capability_refused(addr.clone(), Capabilities::HEADER_HIST);
+
+	assert!(server.peers.capability_refusals(addr.clone()).is_empty());
+	assert!(server.peers.refusing_peers().is_empty());
+	assert!(server.peers.get_connected_peer(addr).is_some());
+
+	server.stop();
+}
+
+// With penalties on, a flagged peer is parked.
+#[test]
+fn refusing_peer_parked() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let config = server_config(2, true);
+	let server = start_server(test_dir("refusal_park"), config.clone());
+	let _peer = connect_refusing(&config, test_dir("refusal_park_client"), 5002);
+	thread::sleep(time::Duration::from_millis(500));
+
+	let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), 5002));
+	request_refused(&server, addr.clone());
+	assert!(!server.peers.is_parked(addr.clone()));
+
+	request_refused(&server, addr.clone());
+	assert!(server.peers.is_parked(addr.clone()));
+	assert!(server.peers.get_connected_peer(addr).is_none());
+
+	server.stop();
+}
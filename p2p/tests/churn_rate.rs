@@ -0,0 +1,88 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use chrono::Duration;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Peer;
+
+// Connect a client to the server, announcing the provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// Connects and disconnects within the window count towards the churn rate,
+// older ones don't.
+#[test]
+fn churn_rate_over_window() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("churn_rate"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.churn_rate(Duration::minutes(1)), 0.0);
+
+	let client = new_server(test_dir("churn_rate_client"), p2p::P2PConfig::default());
+	let _peers: Vec<Peer> = (5000..5003)
+		.map(|port| connect(&p2p_config, &client, port))
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_inbound_count(), 3);
+	assert_eq!(server.peers.churn_rate(Duration::minutes(1)), 3.0);
+
+	// Evict all of them a while after they connected.
+	thread::sleep(time::Duration::from_secs(1));
+	server.peers.clean_peers(0, 8, &[]);
+	assert_eq!(server.peers.peer_inbound_count(), 0);
+
+	// 3 connects and 3 disconnects over the last minute.
+	assert_eq!(server.peers.churn_rate(Duration::minutes(1)), 6.0);
+	// Only the 3 disconnects over the last second, 180 per minute.
+	assert_eq!(server.peers.churn_rate(Duration::seconds(1)), 180.0);
+	assert_eq!(server.peers.churn_rate(Duration::zero()), 0.0);
+}
@@ -0,0 +1,591 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the p2p integration tests. Simulated peers, with
+//! individual capabilities and protocol behaviors forced off to exercise our
+//! code against limited peers.
+
+#![allow(dead_code)]
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util::{RwLock, StopState};
+
+use chrono::prelude::{DateTime, Utc};
+use std::fs::{self, File};
+use std::net::{SocketAddr, TcpListener};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use self::core::core::hash::Hash;
+use self::core::global;
+use self::core::pow::Difficulty;
+use self::core::ser::ProtocolVersion;
+use self::p2p::msg::{DeclineReason, Type};
+use self::p2p::types::{NetAdapter, PeerLiveInfo, TxHashSetRead};
+use self::p2p::{
+	BlockAccept, Capabilities, ChainAdapter, P2PConfig, PeerAddr, PeerData, PeerInfo, ReasonForBan,
+	State,
+};
+
+/// Directory for a test's data, under the system temp dir rather than the
+/// working directory. Leaked to stand in for the string literals tests pass
+/// around, it's the same for every call with the same name in a test run.
+pub fn test_dir(name: &str) -> &'static str {
+	let root = std::env::temp_dir().join(format!("grin_p2p_tests_{}", std::process::id()));
+	fs::create_dir_all(&root).unwrap();
+	let path = root.join(name);
+	Box::leak(path.to_str().unwrap().to_string().into_boxed_str())
+}
+
+/// Info of a freshly connected outbound peer at the given address, that
+/// didn't tell us anything about itself yet. Tests override what they need.
+pub fn peer_info(addr: PeerAddr) -> PeerInfo {
+	PeerInfo {
+		capabilities: Capabilities::UNKNOWN,
+		user_agent: "test".to_string(),
+		peer_uptime: None,
+		version: ProtocolVersion::local(),
+		addr,
+		direction: p2p::Direction::Outbound,
+		inbound_reachable: true,
+		node_nonce: None,
+		negotiation: None,
+		live_info: Arc::new(RwLock::new(PeerLiveInfo::new(Difficulty::min()))),
+		header_sync_requested: Arc::new(AtomicUsize::new(0)),
+		last_header: Arc::new(Mutex::new(Instant::now())),
+		last_header_reset: Arc::new(Mutex::new(Instant::now())),
+	}
+}
+
+/// Local port free at the time of the call, for a test server to listen on.
+pub fn open_port() -> u16 {
+	// use port 0 to allow the OS to assign an open port, the listener
+	// unbinds it as soon as it goes out of scope
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+/// Server with the provided config, not following any chain.
+pub fn new_server(db_root: &str, config: P2PConfig) -> p2p::Server {
+	server_with_adapter(
+		db_root,
+		Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+	)
+}
+
+/// Server advertising the provided capabilities, on the chain behind the
+/// provided adapter.
+pub fn server_with_adapter(
+	db_root: &str,
+	capab: Capabilities,
+	config: P2PConfig,
+	adapter: Arc<dyn ChainAdapter>,
+) -> p2p::Server {
+	p2p::Server::new(
+		db_root,
+		capab,
+		config,
+		adapter,
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap()
+}
+
+/// Stored peer at the given address, healthy and never banned.
+pub fn healthy_peer(addr: PeerAddr) -> PeerData {
+	PeerData {
+		addr,
+		capabilities: Capabilities::UNKNOWN,
+		user_agent: "test".to_string(),
+		flags: State::Healthy,
+		last_banned: 0,
+		ban_reason: ReasonForBan::None,
+		last_connected: Utc::now().timestamp(),
+		score: 0,
+	}
+}
+
+/// Chain behind a test adapter. Answers like a small chain accepting
+/// everything, tests only override what they exercise.
+pub trait TestChain: Sync + Send {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::from_num(1000))
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(10)
+	}
+	fn transaction_received(
+		&self,
+		_: core::core::Transaction,
+		_stem: bool,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn get_transaction(&self, _h: Hash) -> Option<core::core::Transaction> {
+		None
+	}
+	fn min_relay_fee(&self) -> u64 {
+		0
+	}
+	fn tx_kernel_received(&self, _h: Hash, _peer_info: &PeerInfo) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		Ok(BlockAccept::Accepted)
+	}
+	fn compact_block_received(
+		&self,
+		_cb: core::core::CompactBlock,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn header_received(
+		&self,
+		_bh: core::core::BlockHeader,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn process_add_headers_sync(
+		&self,
+		_: &[core::core::BlockHeader],
+		_: u64,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn headers_received(
+		&self,
+		_: &[core::core::BlockHeader],
+		_: &PeerInfo,
+		_: u64,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
+	fn headers_by_height(
+		&self,
+		_: u64,
+		_: u32,
+	) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
+	fn get_block(&self, _: Hash, _: &PeerInfo) -> Option<core::core::Block> {
+		None
+	}
+	fn txhashset_read(&self, _h: Hash) -> Option<TxHashSetRead> {
+		None
+	}
+	fn txhashset_archive_header(&self) -> Result<core::core::BlockHeader, chain::Error> {
+		Err(chain::ErrorKind::Other("no archive".to_string()).into())
+	}
+	fn txhashset_receive_ready(&self) -> bool {
+		false
+	}
+	fn in_maintenance(&self) -> bool {
+		false
+	}
+	fn sync_status(&self) -> chain::SyncStatus {
+		chain::SyncStatus::NoSync
+	}
+	fn knows_header(&self, _hash: Hash) -> bool {
+		true
+	}
+	fn load_factor(&self) -> f64 {
+		0.0
+	}
+	fn txhashset_download_update(
+		&self,
+		_start_time: DateTime<Utc>,
+		_downloaded_size: u64,
+		_total_size: u64,
+	) -> bool {
+		false
+	}
+	fn txhashset_write(
+		&self,
+		_h: Hash,
+		_txhashset_data: File,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+	fn get_tmp_dir(&self) -> PathBuf {
+		std::env::temp_dir()
+	}
+	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
+		self.get_tmp_dir().join(tmpfile_name)
+	}
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		None
+	}
+	fn missing_block_ranges(&self, _from: u64, _to: u64) -> Vec<(u64, u64)> {
+		vec![]
+	}
+	fn common_ancestor_height(&self, _locator: &[Hash]) -> Option<u64> {
+		None
+	}
+	fn header_head(&self) -> Option<chain::Tip> {
+		None
+	}
+}
+
+/// Adapter handing everything the network layer asks of the chain over to
+/// a test chain, derefs to it so tests can inspect what it recorded.
+pub struct TestAdapter<T>(pub T);
+
+impl<T> Deref for TestAdapter<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T: TestChain> ChainAdapter for TestAdapter<T> {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		self.0.total_difficulty()
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		self.0.total_height()
+	}
+	fn transaction_received(
+		&self,
+		tx: core::core::Transaction,
+		stem: bool,
+	) -> Result<bool, chain::Error> {
+		self.0.transaction_received(tx, stem)
+	}
+	fn get_transaction(&self, h: Hash) -> Option<core::core::Transaction> {
+		self.0.get_transaction(h)
+	}
+	fn min_relay_fee(&self) -> u64 {
+		self.0.min_relay_fee()
+	}
+	fn tx_kernel_received(&self, h: Hash, peer_info: &PeerInfo) -> Result<bool, chain::Error> {
+		self.0.tx_kernel_received(h, peer_info)
+	}
+	fn block_received(
+		&self,
+		b: core::core::Block,
+		peer_info: &PeerInfo,
+		opts: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		self.0.block_received(b, peer_info, opts)
+	}
+	fn compact_block_received(
+		&self,
+		cb: core::core::CompactBlock,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.0.compact_block_received(cb, peer_info)
+	}
+	fn header_received(
+		&self,
+		bh: core::core::BlockHeader,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.0.header_received(bh, peer_info)
+	}
+	fn process_add_headers_sync(
+		&self,
+		bh: &[core::core::BlockHeader],
+		header_cache_size: u64,
+	) -> Result<bool, chain::Error> {
+		self.0.process_add_headers_sync(bh, header_cache_size)
+	}
+	fn headers_received(
+		&self,
+		bh: &[core::core::BlockHeader],
+		peer_info: &PeerInfo,
+		header_sync_cache_size: u64,
+	) -> Result<bool, chain::Error> {
+		self.0
+			.headers_received(bh, peer_info, header_sync_cache_size)
+	}
+	fn locate_headers(
+		&self,
+		locator: &[Hash],
+	) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.0.locate_headers(locator)
+	}
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.0.headers_by_height(start, count)
+	}
+	fn get_block(&self, h: Hash, peer_info: &PeerInfo) -> Option<core::core::Block> {
+		self.0.get_block(h, peer_info)
+	}
+	fn txhashset_read(&self, h: Hash) -> Option<TxHashSetRead> {
+		self.0.txhashset_read(h)
+	}
+	fn txhashset_archive_header(&self) -> Result<core::core::BlockHeader, chain::Error> {
+		self.0.txhashset_archive_header()
+	}
+	fn txhashset_receive_ready(&self) -> bool {
+		self.0.txhashset_receive_ready()
+	}
+	fn in_maintenance(&self) -> bool {
+		self.0.in_maintenance()
+	}
+	fn sync_status(&self) -> chain::SyncStatus {
+		self.0.sync_status()
+	}
+	fn knows_header(&self, hash: Hash) -> bool {
+		self.0.knows_header(hash)
+	}
+	fn load_factor(&self) -> f64 {
+		self.0.load_factor()
+	}
+	fn txhashset_download_update(
+		&self,
+		start_time: DateTime<Utc>,
+		downloaded_size: u64,
+		total_size: u64,
+	) -> bool {
+		self.0
+			.txhashset_download_update(start_time, downloaded_size, total_size)
+	}
+	fn txhashset_write(
+		&self,
+		h: Hash,
+		txhashset_data: File,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.0.txhashset_write(h, txhashset_data, peer_info)
+	}
+	fn get_tmp_dir(&self) -> PathBuf {
+		self.0.get_tmp_dir()
+	}
+	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
+		self.0.get_tmpfile_pathname(tmpfile_name)
+	}
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		self.0.tmp_dir_free_space()
+	}
+	fn missing_block_ranges(&self, from: u64, to: u64) -> Vec<(u64, u64)> {
+		self.0.missing_block_ranges(from, to)
+	}
+	fn common_ancestor_height(&self, locator: &[Hash]) -> Option<u64> {
+		self.0.common_ancestor_height(locator)
+	}
+	fn header_head(&self) -> Option<chain::Tip> {
+		self.0.header_head()
+	}
+}
+
+/// Client side adapter recording the requests its peers declined.
+#[derive(Default)]
+pub struct DeclineRecorder {
+	pub declined: Mutex<Vec<(Type, DeclineReason)>>,
+}
+
+impl TestChain for DeclineRecorder {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+}
+
+impl NetAdapter for TestAdapter<DeclineRecorder> {
+	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
+		vec![]
+	}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
+	fn is_banned(&self, _: PeerAddr) -> bool {
+		false
+	}
+	fn peer_ser_error(&self, _: PeerAddr) {}
+	fn txhashset_archive_acceptable(&self, _: u64) -> bool {
+		true
+	}
+	fn request_declined(&self, _: PeerAddr, msg_type: Type, reason: DeclineReason) {
+		self.declined.lock().unwrap().push((msg_type, reason));
+	}
+}
+
+/// Chain serving a txhashset archive of the given size, anchored at the
+/// given height.
+pub struct ArchiveSource {
+	pub height: u64,
+	pub bytes: usize,
+	pub dir: PathBuf,
+}
+
+impl TestChain for ArchiveSource {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(self.height)
+	}
+	fn txhashset_archive_header(&self) -> Result<core::core::BlockHeader, chain::Error> {
+		let mut header = core::core::BlockHeader::default();
+		header.height = self.height;
+		Ok(header)
+	}
+	fn txhashset_read(&self, _h: Hash) -> Option<TxHashSetRead> {
+		let path = self.dir.join("txhashset.zip");
+		fs::write(&path, vec![0u8; self.bytes]).unwrap();
+		Some(TxHashSetRead {
+			output_index: 0,
+			kernel_index: 0,
+			reader: File::open(&path).unwrap(),
+		})
+	}
+}
+
+/// Chain waiting on a txhashset archive, keeping it when recent enough and
+/// fitting in its free space. Counts the archives it writes.
+pub struct ArchiveTaker {
+	pub acceptable: AtomicBool,
+	pub free_space: Option<u64>,
+	pub writes: AtomicUsize,
+}
+
+impl TestChain for ArchiveTaker {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn txhashset_receive_ready(&self) -> bool {
+		true
+	}
+	fn txhashset_write(
+		&self,
+		_h: Hash,
+		_txhashset_data: File,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.writes.fetch_add(1, Ordering::SeqCst);
+		Ok(false)
+	}
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		self.free_space
+	}
+}
+
+impl NetAdapter for TestAdapter<ArchiveTaker> {
+	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
+		vec![]
+	}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
+	fn is_banned(&self, _: PeerAddr) -> bool {
+		false
+	}
+	fn peer_ser_error(&self, _: PeerAddr) {}
+	fn txhashset_archive_acceptable(&self, _: u64) -> bool {
+		self.acceptable.load(Ordering::SeqCst)
+	}
+	fn request_declined(&self, _: PeerAddr, _: Type, _: DeclineReason) {}
+}
+
+/// Builds a simulated peer, a full node unless told otherwise.
+pub struct MockPeerBuilder {
+	capabilities: Capabilities,
+	config: P2PConfig,
+	listener: TcpListener,
+}
+
+/// A running simulated peer, listening on addr.
+pub struct MockPeer {
+	pub server: Arc<p2p::Server>,
+	pub addr: PeerAddr,
+}
+
+impl Default for MockPeerBuilder {
+	fn default() -> MockPeerBuilder {
+		// use port 0 to allow the OS to assign an open port, keeping the
+		// listener bound until the peer starts so the port can't be taken
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		MockPeerBuilder {
+			capabilities: Capabilities::FULL_NODE,
+			config: P2PConfig {
+				host: "127.0.0.1".parse().unwrap(),
+				port,
+				..P2PConfig::default()
+			},
+			listener,
+		}
+	}
+}
+
+impl MockPeerBuilder {
+	/// Don't advertise (nor support) the provided capabilities.
+	pub fn without(mut self, capabilities: Capabilities) -> MockPeerBuilder {
+		self.capabilities = self.capabilities - capabilities;
+		self
+	}
+
+	/// Force protocol behaviors off through the config of the peer, serving
+	/// txhashsets or sharing its node nonce for example.
+	pub fn with_config<F>(mut self, f: F) -> MockPeerBuilder
+	where
+		F: FnOnce(&mut P2PConfig),
+	{
+		f(&mut self.config);
+		self
+	}
+
+	/// Start the peer, listening for connections.
+	pub fn start(self, db_root: &str) -> MockPeer {
+		global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+		let addr = PeerAddr::Ip(SocketAddr::new(self.config.host, self.config.port));
+		let server = Arc::new(
+			p2p::Server::new(
+				db_root,
+				self.capabilities,
+				self.config,
+				Arc::new(p2p::DummyAdapter {}),
+				Hash::from_vec(&vec![]),
+				Arc::new(StopState::new()),
+				0,
+				None,
+			)
+			.unwrap(),
+		);
+		let inner = server.clone();
+		let listener = self.listener;
+		let _ = thread::spawn(move || inner.listen_on(listener, 100_000));
+		MockPeer { server, addr }
+	}
+}
@@ -0,0 +1,123 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Peer;
+
+// Connect a client to the server from the provided local ip, announcing the
+// provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, ip: &str, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let local = SocketAddr::new(ip.parse().unwrap(), 0);
+	let socket = Socket::new(Domain::ipv4(), Type::stream(), Some(Protocol::tcp())).unwrap();
+	socket.bind(&local.into()).unwrap();
+	socket
+		.connect_timeout(&addr.into(), time::Duration::from_secs(10))
+		.unwrap();
+	Peer::connect(
+		socket.into_tcp_stream(),
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new(local.ip(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// A single node claiming a much higher tip isn't enough to commit to it when
+// two corroborating peers are required, however many connections it opens.
+// A second node advertising as much is.
+#[test]
+fn high_tip_needs_corroboration() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	assert_eq!(p2p::P2PConfig::default().min_corroborating_peers(), 1);
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		min_corroborating_peers: Some(2),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("corroborating"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(test_dir("corroborating_client"), p2p::P2PConfig::default());
+	let _claiming = connect(&p2p_config, &client, "127.0.0.1", 5000);
+	thread::sleep(time::Duration::from_millis(500));
+	let claiming = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()))
+		.unwrap();
+	claiming
+		.info
+		.update(1_000, Difficulty::from_num(100_000), 0);
+
+	// alone, the claimed tip isn't corroborated at all
+	assert_eq!(server.peers.corroborated_difficulty(), None);
+
+	// nor by a second connection from the same host
+	let _again = connect(&p2p_config, &client, "127.0.0.1", 5001);
+	thread::sleep(time::Duration::from_millis(500));
+	let again = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.1:5001".parse().unwrap()))
+		.unwrap();
+	again.info.update(1_000, Difficulty::from_num(100_000), 0);
+	assert_eq!(server.peers.corroborated_difficulty(), None);
+
+	let other_client = new_server(test_dir("corroborating_other"), p2p::P2PConfig::default());
+	let _other = connect(&p2p_config, &other_client, "127.0.0.2", 5002);
+	thread::sleep(time::Duration::from_millis(500));
+	let other = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.2:5002".parse().unwrap()))
+		.unwrap();
+	other.info.update(10, Difficulty::from_num(1_000), 0);
+
+	// a second node only backs the work it advertises itself
+	assert_eq!(
+		server.peers.corroborated_difficulty(),
+		Some(Difficulty::from_num(1_000))
+	);
+
+	other.info.update(1_000, Difficulty::from_num(100_000), 0);
+	assert_eq!(
+		server.peers.corroborated_difficulty(),
+		Some(Difficulty::from_num(100_000))
+	);
+}
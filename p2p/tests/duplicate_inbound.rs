@@ -0,0 +1,115 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{DuplicateConnectionPolicy, Peer};
+
+// Connects to the server twice, advertising the same (non loopback) onion
+// address both times. Returns the time the server first saw the peer after
+// each of the connections.
+fn connect_twice(db_root: &str, policy: DuplicateConnectionPolicy) -> (p2p::Server, i64, i64) {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		duplicate_inbound_policy: Some(policy),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(db_root, p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(&format!("{}_client", db_root), p2p::P2PConfig::default());
+	let client_addr = PeerAddr::Onion("duplicateinboundtest.onion".to_string());
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let connect = || {
+		let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+		Peer::connect(
+			socket,
+			p2p::Capabilities::UNKNOWN,
+			Difficulty::min(),
+			client_addr.clone(),
+			&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+			client.peers.clone(),
+			100_000,
+			None,
+			client.clone(),
+		)
+		.unwrap()
+	};
+
+	let _first = connect();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_count(), 1);
+	let first_seen = server
+		.peers
+		.get_connected_peer(client_addr.clone())
+		.unwrap()
+		.info
+		.first_seen()
+		.timestamp_millis();
+
+	let _second = connect();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_count(), 1);
+	let second_seen = server
+		.peers
+		.get_connected_peer(client_addr)
+		.unwrap()
+		.info
+		.first_seen()
+		.timestamp_millis();
+
+	((*server).clone(), first_seen, second_seen)
+}
+
+// The existing connection is kept and the new one refused.
+#[test]
+fn duplicate_inbound_reject_new() {
+	let (server, first_seen, second_seen) = connect_twice(
+		test_dir("duplicate_inbound_reject"),
+		DuplicateConnectionPolicy::RejectNew,
+	);
+	assert_eq!(first_seen, second_seen);
+	server.stop();
+}
+
+// The existing connection is dropped in favor of the new one.
+#[test]
+fn duplicate_inbound_replace_old() {
+	let (server, first_seen, second_seen) = connect_twice(
+		test_dir("duplicate_inbound_replace"),
+		DuplicateConnectionPolicy::ReplaceOld,
+	);
+	assert!(second_seen > first_seen);
+	server.stop();
+}
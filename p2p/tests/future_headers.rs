@@ -0,0 +1,144 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use chrono::{Duration, Utc};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, peer_info, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::msg::{self, Hand, MsgHeader, Shake, Type};
+use crate::p2p::types::{PeerAddr, FUTURE_TIMESTAMP_ALLOWANCE};
+use crate::p2p::{ChainAdapter, HeaderTimestamp};
+
+fn header_ahead(secs: i64) -> BlockHeader {
+	BlockHeader {
+		timestamp: Utc::now() + Duration::seconds(secs),
+		..BlockHeader::default()
+	}
+}
+
+// Headers from the future are classified against the clock skew of the peer
+// and result in a disconnect rather than a ban.
+#[test]
+fn future_headers_from_skewed_peer() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = p2p::Server::new(
+		test_dir("future_headers"),
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let info = peer_info(addr.clone());
+	info.set_clock_skew(600);
+
+	// Beyond the allowance, but covered by the measured clock skew.
+	let skewed = header_ahead(FUTURE_TIMESTAMP_ALLOWANCE + 300);
+	assert_eq!(info.header_timestamp(&skewed), HeaderTimestamp::Valid);
+	assert_eq!(
+		server.peers.headers_received(&[skewed], &info, 0).unwrap(),
+		true
+	);
+
+	// Beyond both the allowance and the measured clock skew.
+	let future = header_ahead(FUTURE_TIMESTAMP_ALLOWANCE + 900);
+	assert_eq!(info.header_timestamp(&future), HeaderTimestamp::Future);
+	assert_eq!(
+		server.peers.headers_received(&[future], &info, 0).unwrap(),
+		false
+	);
+	assert!(!server.peers.is_banned(addr));
+}
+
+// The clock skew of a peer is measured from the time it sends in its hand.
+#[test]
+fn clock_skew_from_hand() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let addr = SocketAddr::new(config.host, config.port);
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("clock_skew"),
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let inner = server.clone();
+	let _ = thread::spawn(move || inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let version = ProtocolVersion::local();
+	let mut stream = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let hand = Hand {
+		version,
+		capabilities: p2p::Capabilities::UNKNOWN,
+		nonce: 42,
+		genesis: Hash::from_vec(&vec![]),
+		total_difficulty: Difficulty::min(),
+		sender_addr: PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		receiver_addr: PeerAddr::Ip(addr),
+		user_agent: "test".to_string(),
+		uptime: None,
+		timestamp: Some((Utc::now() + Duration::seconds(600)).timestamp()),
+		listening: None,
+	};
+	let body = ser::ser_vec(&hand, version).unwrap();
+	let header = ser::ser_vec(&MsgHeader::new(Type::Hand, body.len() as u64), version).unwrap();
+	stream.write_all(&header).unwrap();
+	stream.write_all(&body).unwrap();
+	let _: Shake = msg::read_message(&mut stream, version, Type::Shake).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let peer = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()))
+		.unwrap();
+	let skew = peer.info.clock_skew();
+	assert!(skew >= 595 && skew <= 600, "skew {}", skew);
+}
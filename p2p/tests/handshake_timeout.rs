@@ -0,0 +1,121 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Capabilities;
+
+fn start_server(db_root: &str, penalize: Option<bool>) -> (Arc<p2p::Server>, SocketAddr) {
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		handshake_timeout_secs: Some(1),
+		penalize_handshake_timeout: penalize,
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(Duration::from_secs(1));
+	(server, SocketAddr::new(p2p_config.host, p2p_config.port))
+}
+
+// Connects and trickles a byte of a hand every 200ms without ever completing
+// it, returns the local address and how long until the server dropped us.
+fn stall_handshake(addr: SocketAddr) -> (SocketAddr, Duration) {
+	let mut stream = TcpStream::connect(addr).unwrap();
+	let local = stream.local_addr().unwrap();
+	stream
+		.set_read_timeout(Some(Duration::from_millis(200)))
+		.unwrap();
+	let start = Instant::now();
+	let mut buf = [0u8; 64];
+	while start.elapsed() < Duration::from_secs(10) {
+		if stream.write_all(&[0]).is_err() {
+			break;
+		}
+		match stream.read(&mut buf) {
+			Ok(0) => break,
+			Ok(_) => {}
+			Err(ref e)
+				if e.kind() == std::io::ErrorKind::WouldBlock
+					|| e.kind() == std::io::ErrorKind::TimedOut => {}
+			Err(_) => break,
+		}
+	}
+	(local, start.elapsed())
+}
+
+// A peer stalling mid-handshake is dropped once the configured timeout is
+// over, even though it keeps sending. It's banned unless configured not to.
+#[test]
+fn stalled_handshake_dropped() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, addr) = start_server(test_dir("handshake_timeout"), None);
+	let (local, elapsed) = stall_handshake(addr);
+	assert!(elapsed >= Duration::from_millis(900));
+	assert!(elapsed < Duration::from_secs(5));
+	thread::sleep(Duration::from_millis(200));
+	assert!(server.peers.is_banned(PeerAddr::Ip(local)));
+
+	let (server, addr) = start_server(test_dir("handshake_timeout_lenient"), Some(false));
+	let (local, elapsed) = stall_handshake(addr);
+	assert!(elapsed < Duration::from_secs(5));
+	thread::sleep(Duration::from_millis(200));
+	assert!(!server.peers.is_banned(PeerAddr::Ip(local)));
+}
+
+// A zero timeout would fail (and ban) every peer, the default applies instead.
+#[test]
+fn zero_handshake_timeout_ignored() {
+	let config = p2p::P2PConfig {
+		handshake_timeout_secs: Some(0),
+		..p2p::P2PConfig::default()
+	};
+	assert_eq!(
+		config.handshake_timeout_secs(),
+		p2p::P2PConfig::default().handshake_timeout_secs()
+	);
+	assert!(config.handshake_timeout_secs() > 0);
+}
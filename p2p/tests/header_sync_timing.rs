@@ -0,0 +1,112 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::thread;
+use std::time::Duration;
+
+use grin_p2p as p2p;
+
+mod common;
+
+use crate::p2p::types::{PeerAddr, HEADER_BATCH_MIN, HEADER_BATCH_START};
+use crate::p2p::{PeerInfo, MAX_BLOCK_HEADERS};
+
+fn peer_info() -> PeerInfo {
+	common::peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()))
+}
+
+// Requests sent and answered are tracked, the count never wraps below zero.
+#[test]
+fn header_sync_outstanding() {
+	let info = peer_info();
+	assert_eq!(info.header_sync_outstanding(), 0);
+
+	info.header_sync_request_sent();
+	info.header_sync_request_sent();
+	assert_eq!(info.header_sync_outstanding(), 2);
+
+	info.header_sync_received();
+	assert_eq!(info.header_sync_outstanding(), 1);
+	info.header_sync_received();
+	info.header_sync_received();
+	assert_eq!(info.header_sync_outstanding(), 0);
+}
+
+// Receiving headers restarts the last header timer.
+#[test]
+fn header_sync_timing() {
+	let info = peer_info();
+	thread::sleep(Duration::from_millis(50));
+	assert!(info.time_since_last_header() >= Duration::from_millis(50));
+
+	info.header_sync_received();
+	assert!(info.time_since_last_header() < Duration::from_millis(50));
+}
+
+// Resetting drops the outstanding requests but doesn't pretend we received
+// any headers.
+#[test]
+fn header_sync_reset() {
+	let info = peer_info();
+	info.header_sync_request_sent();
+	info.header_sync_request_sent();
+	thread::sleep(Duration::from_millis(50));
+
+	info.reset_header_sync();
+	assert_eq!(info.header_sync_outstanding(), 0);
+	assert!(info.time_since_last_header() >= Duration::from_millis(50));
+	assert!(info.last_header_reset.lock().unwrap().elapsed() < Duration::from_millis(50));
+}
+
+// A peer answering promptly is asked for more headers every time, up to the
+// maximum a peer may send.
+#[test]
+fn header_batch_grows() {
+	let info = peer_info();
+	assert_eq!(info.header_batch_size(), HEADER_BATCH_START);
+
+	let mut previous = info.header_batch_size();
+	for _ in 0..4 {
+		info.header_batch_answered();
+		assert!(info.header_batch_size() > previous);
+		previous = info.header_batch_size();
+	}
+
+	for _ in 0..100 {
+		info.header_batch_answered();
+	}
+	assert_eq!(info.header_batch_size(), MAX_BLOCK_HEADERS);
+}
+
+// A peer timing out is asked for fewer headers every time, down to a minimum.
+#[test]
+fn header_batch_shrinks() {
+	let info = peer_info();
+	for _ in 0..20 {
+		info.header_batch_answered();
+	}
+	assert_eq!(info.header_batch_size(), MAX_BLOCK_HEADERS);
+
+	let mut previous = info.header_batch_size();
+	for _ in 0..4 {
+		info.header_batch_timed_out();
+		assert!(info.header_batch_size() < previous);
+		previous = info.header_batch_size();
+	}
+
+	for _ in 0..100 {
+		info.header_batch_timed_out();
+	}
+	assert_eq!(info.header_batch_size(), HEADER_BATCH_MIN);
+}
@@ -0,0 +1,151 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::{Mutex, StopState};
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer, MAX_BLOCK_HEADERS};
+
+/// Adapter recording the header ranges it gets asked to serve.
+struct RangeAdapter {
+	requests: Mutex<Vec<(u64, u32)>>,
+}
+
+impl TestChain for RangeAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.requests.lock().push((start, count));
+		Ok(vec![])
+	}
+}
+
+fn connect(
+	db_root: &str,
+	server_config: &p2p::P2PConfig,
+	client_config: p2p::P2PConfig,
+) -> Result<Peer, p2p::Error> {
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let client = p2p::Server::new(
+		db_root,
+		Capabilities::UNKNOWN,
+		client_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config, None),
+		net_adapter,
+		100_000,
+		None,
+		client,
+	)
+}
+
+// Height ranges are passed through to the chain adapter as requested, counts
+// above MAX_BLOCK_HEADERS are clamped. Peers on an older protocol version
+// can't send the request.
+#[test]
+fn headers_by_height_request() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let adapter = Arc::new(TestAdapter(RangeAdapter {
+		requests: Mutex::new(vec![]),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("headers_by_height"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let peer = connect(
+		test_dir("headers_by_height_client"),
+		&p2p_config,
+		p2p_config.clone(),
+	)
+	.unwrap();
+	peer.send_headers_by_height_request(10, 5).unwrap();
+	peer.send_headers_by_height_request(20, MAX_BLOCK_HEADERS * 4)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(
+		*adapter.requests.lock(),
+		vec![(10, 5), (20, MAX_BLOCK_HEADERS)]
+	);
+
+	let old_config = p2p::P2PConfig {
+		protocol_version: Some(3),
+		..p2p_config.clone()
+	};
+	let old_peer = connect(
+		test_dir("headers_by_height_old_client"),
+		&p2p_config,
+		old_config,
+	)
+	.unwrap();
+	assert_eq!(old_peer.info.version, ProtocolVersion(3));
+	assert!(old_peer.send_headers_by_height_request(10, 5).is_err());
+}
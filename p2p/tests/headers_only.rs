@@ -0,0 +1,144 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::{Mutex, StopState};
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, PeerInfo};
+
+/// Adapter recording the requests it gets asked to serve.
+struct RecordingAdapter {
+	calls: Mutex<Vec<&'static str>>,
+}
+
+impl TestChain for RecordingAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn get_transaction(&self, _h: Hash) -> Option<core::core::Transaction> {
+		self.calls.lock().push("get_transaction");
+		None
+	}
+	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.calls.lock().push("locate_headers");
+		Ok(vec![])
+	}
+	fn get_block(&self, _: Hash, _: &PeerInfo) -> Option<core::core::Block> {
+		self.calls.lock().push("get_block");
+		None
+	}
+}
+
+fn start_server(
+	db_root: &str,
+	headers_only: bool,
+) -> (
+	Arc<p2p::Server>,
+	Arc<TestAdapter<RecordingAdapter>>,
+	PeerAddr,
+) {
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		headers_only: Some(headers_only),
+		..p2p::P2PConfig::default()
+	};
+	let addr = PeerAddr::Ip(SocketAddr::new(config.host, config.port));
+	let adapter = Arc::new(TestAdapter(RecordingAdapter {
+		calls: Mutex::new(vec![]),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			Capabilities::FULL_NODE,
+			config,
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let inner = server.clone();
+	let _ = thread::spawn(move || inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	(server, adapter, addr)
+}
+
+// Connect to the server and ask it for headers, then for a block, a compact
+// block and a transaction. Returns the capabilities it advertised and what
+// it asked its chain for.
+fn request_everything(db_root: &str, headers_only: bool) -> (Capabilities, Vec<&'static str>) {
+	let (_server, adapter, addr) = start_server(db_root, headers_only);
+	let (client, _, _) = start_server(&format!("{}_client", db_root), false);
+
+	let peer = client.connect(addr, 100_000).unwrap();
+	let h = Hash::from_vec(&vec![1]);
+	peer.send_header_request(vec![h], p2p::MAX_BLOCK_HEADERS)
+		.unwrap();
+	peer.send_block_request(h, chain::Options::NONE).unwrap();
+	peer.send_compact_block_request(h).unwrap();
+	peer.send_tx_request(h).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let calls = adapter.calls.lock().clone();
+	(peer.info.capabilities, calls)
+}
+
+// A headers-only relay only advertises HEADER_HIST and PEER_LIST, serves
+// headers and declines block and transaction requests. A full node serves
+// them all.
+#[test]
+fn headers_only_relay() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (capabilities, calls) = request_everything(test_dir("headers_only"), true);
+	assert_eq!(
+		capabilities,
+		Capabilities::HEADER_HIST | Capabilities::PEER_LIST
+	);
+	assert_eq!(calls, vec!["locate_headers"]);
+
+	let (capabilities, calls) = request_everything(test_dir("headers_only_full"), false);
+	assert!(capabilities.contains(Capabilities::TXHASHSET_HIST));
+	assert_eq!(
+		calls,
+		vec![
+			"locate_headers",
+			"get_block",
+			"get_block",
+			"get_transaction"
+		]
+	);
+}
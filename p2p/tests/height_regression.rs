@@ -0,0 +1,50 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+mod common;
+
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::PeerInfo;
+
+fn peer_info() -> PeerInfo {
+	common::peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()))
+}
+
+// A reorg within the tolerance is taken as is.
+#[test]
+fn small_reorg_accepted() {
+	let info = peer_info();
+	assert!(!info.update(1_000, Difficulty::from_num(1_000), 60));
+	assert!(!info.update(940, Difficulty::from_num(1_001), 60));
+	assert_eq!(info.height(), 940);
+	assert_eq!(info.total_difficulty(), Difficulty::from_num(1_001));
+}
+
+// A larger drop flags the peer and isn't taken.
+#[test]
+fn large_regression_flagged() {
+	let info = peer_info();
+	assert!(!info.update(1_000, Difficulty::from_num(1_000), 60));
+	assert!(info.update(939, Difficulty::from_num(939), 60));
+	assert_eq!(info.height(), 1_000);
+	assert_eq!(info.total_difficulty(), Difficulty::from_num(1_000));
+
+	// going up is always fine
+	assert!(!info.update(1_200, Difficulty::from_num(1_200), 60));
+	assert_eq!(info.height(), 1_200);
+}
@@ -0,0 +1,128 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, server_with_adapter, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{inbound_refusal_probability, PeerAddr};
+use crate::p2p::Peer;
+
+/// Adapter reporting a fixed load factor.
+struct LoadAdapter {
+	load: f64,
+}
+
+impl TestChain for LoadAdapter {
+	fn load_factor(&self) -> f64 {
+		self.load
+	}
+}
+
+fn loaded_server(db_root: &str, config: p2p::P2PConfig, load: f64) -> p2p::Server {
+	server_with_adapter(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(TestAdapter(LoadAdapter { load })),
+	)
+}
+
+fn seeded_config() -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		rng_seed: Some(42),
+		..p2p::P2PConfig::default()
+	}
+}
+
+// Number of refusals out of 1000 new inbound connections at this load.
+fn refusals(db_root: &str, load: f64) -> usize {
+	let server = loaded_server(db_root, seeded_config(), load);
+	(0..1000)
+		.filter(|_| server.peers.refuse_inbound_under_load())
+		.count()
+}
+
+#[test]
+fn refusal_probability() {
+	assert_eq!(inbound_refusal_probability(0.0, 0.5), 0.0);
+	assert_eq!(inbound_refusal_probability(0.5, 0.5), 0.0);
+	assert_eq!(inbound_refusal_probability(0.75, 0.5), 0.5);
+	assert_eq!(inbound_refusal_probability(1.0, 0.5), 1.0);
+	// out of range loads are clamped
+	assert_eq!(inbound_refusal_probability(-1.0, 0.5), 0.0);
+	assert_eq!(inbound_refusal_probability(2.0, 0.5), 1.0);
+	// a threshold of 1.0 disables it short of full load
+	assert_eq!(inbound_refusal_probability(0.99, 1.0), 0.0);
+}
+
+// Low load accepts everybody, high load refuses most new inbound connections.
+#[test]
+fn refusals_rise_with_load() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	assert_eq!(refusals(test_dir("inbound_load_low"), 0.2), 0);
+
+	// 80% refusal probability at 0.9 with the default 0.5 threshold
+	let high = refusals(test_dir("inbound_load_high"), 0.9);
+	assert!(high > 700 && high < 900, "{} refusals", high);
+}
+
+// At full load the listener refuses the connection before any handshake.
+#[test]
+fn full_load_refuses_connections() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let config = seeded_config();
+	let server = Arc::new(loaded_server(
+		test_dir("inbound_load_full"),
+		config.clone(),
+		1.0,
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = loaded_server(test_dir("inbound_load_client"), seeded_config(), 0.0);
+	let addr = SocketAddr::new(config.host, config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), 5000)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), config, None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	);
+	assert!(peer.is_err());
+	assert_eq!(server.peers.peer_inbound_count(), 0);
+}
@@ -0,0 +1,141 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+/// Adapter for a chain whose head we move around, one unit of difficulty per
+/// block.
+struct ChainHeadAdapter {
+	height: AtomicU64,
+}
+
+impl TestChain for ChainHeadAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::from_num(self.height.load(Ordering::SeqCst)))
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(self.height.load(Ordering::SeqCst))
+	}
+}
+
+// Our peers each see a chain at the provided height.
+fn set_peer_heights(peers: &[Arc<Peer>], heights: &[u64]) {
+	for (peer, height) in peers.iter().zip(heights) {
+		peer.info
+			.update(*height, Difficulty::from_num(*height), std::u64::MAX);
+	}
+}
+
+// We're synced when at least at the median of our outbound peers, once synced
+// we only stop being so when falling more than a few blocks behind.
+#[test]
+fn is_synced_against_outbound_peers() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let adapter = Arc::new(TestAdapter(ChainHeadAdapter {
+		height: AtomicU64::new(50),
+	}));
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = p2p::Server::new(
+		test_dir("is_synced"),
+		Capabilities::UNKNOWN,
+		p2p_config,
+		adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	// No peers, no way to tell.
+	assert!(!server.peers.is_synced());
+
+	let mut peers = vec![];
+	for i in 0..3 {
+		let remote_config = p2p::P2PConfig {
+			host: "127.0.0.1".parse().unwrap(),
+			port: open_port(),
+			..p2p::P2PConfig::default()
+		};
+		let remote = Arc::new(
+			p2p::Server::new(
+				test_dir(&format!("is_synced_remote{}", i)),
+				Capabilities::UNKNOWN,
+				remote_config.clone(),
+				Arc::new(p2p::DummyAdapter {}),
+				Hash::from_vec(&vec![]),
+				Arc::new(StopState::new()),
+				0,
+				None,
+			)
+			.unwrap(),
+		);
+		let _ = thread::spawn(move || remote.listen(100_000));
+		thread::sleep(time::Duration::from_millis(500));
+
+		let addr = PeerAddr::Ip(SocketAddr::new(remote_config.host, remote_config.port));
+		peers.push(server.connect(addr, 100_000).unwrap());
+	}
+	assert_eq!(server.peers.outgoing_connected_peers().len(), 3);
+
+	// Clearly behind.
+	set_peer_heights(&peers, &[100, 100, 100]);
+	assert!(!server.peers.is_synced());
+
+	// At parity, a single peer far ahead doesn't move the median.
+	adapter.height.store(100, Ordering::SeqCst);
+	assert!(server.peers.is_synced());
+	set_peer_heights(&peers, &[100, 100, 1000]);
+	assert!(server.peers.is_synced());
+
+	// Slightly behind at the tip, still synced.
+	set_peer_heights(&peers, &[103, 104, 1000]);
+	assert!(server.peers.is_synced());
+
+	// Too far behind.
+	set_peer_heights(&peers, &[110, 110, 1000]);
+	assert!(!server.peers.is_synced());
+
+	// Catching up within the tolerance isn't enough to be synced again, we
+	// need to reach the median.
+	adapter.height.store(107, Ordering::SeqCst);
+	assert!(!server.peers.is_synced());
+	adapter.height.store(110, Ordering::SeqCst);
+	assert!(server.peers.is_synced());
+}
@@ -0,0 +1,132 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Direction;
+
+fn config(port: u16) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		keepalive_directions: Some(vec![Direction::InboundTor, Direction::OutboundTor]),
+		keepalive_interval: Some(1),
+		..p2p::P2PConfig::default()
+	}
+}
+
+fn start_server(db_root: &str, port: u16, onion_address: Option<String>) -> Arc<p2p::Server> {
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config(port),
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			onion_address,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	server
+}
+
+// Connect the server to a fresh remote, returning the remote address.
+fn connect_remote(server: &p2p::Server, db_root: &str) -> PeerAddr {
+	let port = open_port();
+	start_server(db_root, port, None);
+	thread::sleep(Duration::from_millis(500));
+	let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+	server.connect(addr.clone(), 100_000).unwrap();
+	thread::sleep(Duration::from_millis(500));
+	addr
+}
+
+// With keepalives enabled for tor, our tor connections get one every interval.
+#[test]
+fn tor_connection_kept_alive() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let onion = "maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd".to_string();
+	let server = start_server(test_dir("keepalive_tor"), open_port(), Some(onion));
+	let remote = connect_remote(&server, test_dir("keepalive_tor_remote"));
+	let peer = server.peers.get_connected_peer(remote.clone()).unwrap();
+	assert_eq!(peer.info.direction, Direction::OutboundTor);
+
+	// not due right after connecting
+	let start = Instant::now();
+	assert_eq!(server.peers.send_keepalives(start), 0);
+
+	let sent = peer.last_min_sent_bytes();
+	assert_eq!(
+		server
+			.peers
+			.send_keepalives(start + Duration::from_millis(1100)),
+		1
+	);
+	assert_eq!(
+		server
+			.peers
+			.send_keepalives(start + Duration::from_millis(1500)),
+		0
+	);
+	thread::sleep(Duration::from_millis(2500));
+	assert_eq!(server.peers.send_keepalives(Instant::now()), 1);
+	thread::sleep(Duration::from_millis(200));
+	assert!(peer.last_min_sent_bytes() > sent);
+
+	// the remote discards them and stays connected
+	assert!(server.peers.get_connected_peer(remote).is_some());
+}
+
+// Clearnet connections get no keepalives when only enabled for tor.
+#[test]
+fn clearnet_connection_not_kept_alive() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = start_server(test_dir("keepalive_clearnet"), open_port(), None);
+	let remote = connect_remote(&server, test_dir("keepalive_clearnet_remote"));
+	let peer = server.peers.get_connected_peer(remote).unwrap();
+	assert_eq!(peer.info.direction, Direction::Outbound);
+
+	let start = Instant::now();
+	for secs in 0..5 {
+		assert_eq!(
+			server
+				.peers
+				.send_keepalives(start + Duration::from_secs(secs)),
+			0
+		);
+	}
+}
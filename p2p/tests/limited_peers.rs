@@ -0,0 +1,95 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+use grin_util as util;
+
+use std::fs;
+use std::{thread, time};
+
+mod common;
+
+use self::common::MockPeerBuilder;
+use crate::p2p::{Capabilities, PeerAddr};
+
+fn clean(db_roots: &[&str]) {
+	for db_root in db_roots {
+		let _ = fs::remove_dir_all(db_root);
+	}
+}
+
+fn onion() -> PeerAddr {
+	PeerAddr::Onion("maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd.onion".to_string())
+}
+
+fn ip() -> PeerAddr {
+	PeerAddr::Ip("8.8.8.8:3414".parse().unwrap())
+}
+
+// A v3 peer not supporting onion addresses only ever gets clearnet ones from
+// us, a full one gets both.
+#[test]
+fn peer_without_tor_address() {
+	util::init_test_logger();
+	let dirs = [
+		test_dir("limited_tor_ours"),
+		test_dir("limited_tor_limited"),
+		test_dir("limited_tor_full"),
+	];
+	clean(&dirs);
+
+	let ours = MockPeerBuilder::default().start(dirs[0]);
+	let limited = MockPeerBuilder::default()
+		.without(Capabilities::TOR_ADDRESS)
+		.start(dirs[1]);
+	let full = MockPeerBuilder::default().start(dirs[2]);
+
+	for mock in &[&limited, &full] {
+		let peer = ours.server.connect(mock.addr.clone(), 100_000).unwrap();
+		peer.send_peer_addrs(&[ip(), onion()]).unwrap();
+	}
+	thread::sleep(time::Duration::from_secs(1));
+
+	assert!(limited.server.peers.exists_peer(ip()).unwrap());
+	assert!(!limited.server.peers.exists_peer(onion()).unwrap());
+	assert!(full.server.peers.exists_peer(ip()).unwrap());
+	assert!(full.server.peers.exists_peer(onion()).unwrap());
+	clean(&dirs);
+}
+
+// We don't ask a peer not advertising PEER_LIST for addresses, only the
+// others.
+#[test]
+fn peer_without_peer_list() {
+	util::init_test_logger();
+	let dirs = [
+		test_dir("limited_list_ours"),
+		test_dir("limited_list_limited"),
+		test_dir("limited_list_full"),
+	];
+	clean(&dirs);
+
+	let ours = MockPeerBuilder::default().start(dirs[0]);
+	let limited = MockPeerBuilder::default()
+		.without(Capabilities::PEER_LIST)
+		.start(dirs[1]);
+	let full = MockPeerBuilder::default().start(dirs[2]);
+
+	ours.server.connect(limited.addr.clone(), 100_000).unwrap();
+	assert_eq!(ours.server.peers.request_peer_addrs(), 0);
+
+	ours.server.connect(full.addr.clone(), 100_000).unwrap();
+	assert_eq!(ours.server.peers.request_peer_addrs(), 1);
+	clean(&dirs);
+}
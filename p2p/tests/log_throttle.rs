@@ -0,0 +1,104 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use chrono::prelude::Utc;
+use chrono::Duration;
+
+use crate::p2p::{LogThrottle, PeerAddr};
+
+// Many identical errors within the window give a single line, the rest is
+// reported as one summary once the window is over.
+#[test]
+fn repeated_errors_are_summarized() {
+	let throttle = LogThrottle::new(Duration::seconds(60));
+	let peer = PeerAddr::Ip("1.2.3.4:3414".parse().unwrap());
+	let other = PeerAddr::Ip("5.6.7.8:3414".parse().unwrap());
+	let start = Utc::now();
+
+	let lines: Vec<String> = (0..100)
+		.filter_map(|i| {
+			throttle.note(
+				&peer,
+				"malformed message",
+				start + Duration::milliseconds(i * 100),
+			)
+		})
+		.collect();
+	assert_eq!(lines, vec![format!("malformed message from peer {}", peer)]);
+
+	// Other peers and other kinds of errors are counted on their own.
+	assert!(throttle.note(&other, "malformed message", start).is_some());
+	assert!(throttle.note(&peer, "bad compact block", start).is_some());
+
+	assert_eq!(
+		throttle.note(&peer, "malformed message", start + Duration::seconds(61)),
+		Some(format!(
+			"malformed message from peer {} (100 times in the last 60s)",
+			peer
+		))
+	);
+	assert_eq!(
+		throttle.note(&peer, "malformed message", start + Duration::seconds(62)),
+		None
+	);
+
+	// A single error in a window is logged as is.
+	assert_eq!(
+		throttle.note(&other, "malformed message", start + Duration::seconds(70)),
+		Some(format!("malformed message from peer {}", other))
+	);
+}
+
+// Summaries don't wait for a later error: they're logged once flushed, after
+// the window or when the peer goes away.
+#[test]
+fn summaries_flushed() {
+	let throttle = LogThrottle::new(Duration::seconds(60));
+	let peer = PeerAddr::Ip("1.2.3.4:3414".parse().unwrap());
+	let other = PeerAddr::Ip("5.6.7.8:3414".parse().unwrap());
+	let start = Utc::now();
+
+	for _ in 0..5 {
+		throttle.note(&peer, "malformed message", start);
+		throttle.note(&other, "malformed message", start);
+	}
+	throttle.note(&other, "bad compact block", start);
+
+	assert!(throttle.flush(start + Duration::seconds(30)).is_empty());
+	assert_eq!(
+		throttle.flush_peer(&peer, start + Duration::seconds(30)),
+		vec![format!(
+			"malformed message from peer {} (5 times in the last 30s)",
+			peer
+		)]
+	);
+
+	// A single error was logged already, nothing to summarize.
+	assert_eq!(
+		throttle.flush(start + Duration::seconds(61)),
+		vec![format!(
+			"malformed message from peer {} (5 times in the last 60s)",
+			other
+		)]
+	);
+	assert!(throttle.flush(start + Duration::seconds(120)).is_empty());
+
+	// Once flushed, the next error starts a new window.
+	assert_eq!(
+		throttle.note(&other, "malformed message", start + Duration::seconds(62)),
+		Some(format!("malformed message from peer {}", other))
+	);
+}
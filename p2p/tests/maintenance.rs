@@ -0,0 +1,234 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, DeclineRecorder, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::msg::{DeclineReason, Type};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+/// Adapter that can be flagged as in maintenance and counts the header
+/// requests it serves.
+struct MaintenanceAdapter {
+	maintenance: AtomicBool,
+	locate_calls: AtomicUsize,
+}
+
+impl TestChain for MaintenanceAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.locate_calls.fetch_add(1, Ordering::SeqCst);
+		Ok(vec![])
+	}
+	fn in_maintenance(&self) -> bool {
+		self.maintenance.load(Ordering::SeqCst)
+	}
+}
+
+// While in maintenance a server withholds its sync capabilities and tells
+// peers to try their header requests later, once maintenance is over it
+// serves them again.
+#[test]
+fn maintenance_defers_heavy_requests() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		..p2p::P2PConfig::default()
+	};
+	let adapter = Arc::new(TestAdapter(MaintenanceAdapter {
+		maintenance: AtomicBool::new(true),
+		locate_calls: AtomicUsize::new(0),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("maintenance"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let net_adapter = Arc::new(TestAdapter(DeclineRecorder::default()));
+	let client = p2p::Server::new(
+		test_dir("maintenance_client"),
+		Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		net_adapter.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+	assert!(!peer
+		.info
+		.capabilities
+		.contains(Capabilities::TXHASHSET_HIST));
+	assert!(!peer.info.capabilities.contains(Capabilities::HEADER_HIST));
+
+	peer.send_header_request(vec![Hash::from_vec(&vec![])], p2p::MAX_BLOCK_HEADERS)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(adapter.locate_calls.load(Ordering::SeqCst), 0);
+	assert_eq!(
+		*net_adapter.declined.lock().unwrap(),
+		vec![(Type::GetHeaders, DeclineReason::TryLater)]
+	);
+
+	adapter.maintenance.store(false, Ordering::SeqCst);
+	assert_eq!(server.effective_capabilities(), Capabilities::FULL_NODE);
+
+	peer.send_header_request(vec![Hash::from_vec(&vec![])], p2p::MAX_BLOCK_HEADERS)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(adapter.locate_calls.load(Ordering::SeqCst), 1);
+	assert_eq!(net_adapter.declined.lock().unwrap().len(), 1);
+}
+
+// Entering maintenance changes what we advertise without any call to
+// set_capabilities, connected peers get told and stop relying on us for it.
+#[test]
+fn maintenance_updates_connected_peers() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		..p2p::P2PConfig::default()
+	};
+	let adapter = Arc::new(TestAdapter(MaintenanceAdapter {
+		maintenance: AtomicBool::new(false),
+		locate_calls: AtomicUsize::new(0),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("maintenance_update"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = p2p::Server::new(
+		test_dir("maintenance_update_client"),
+		Capabilities::UNKNOWN,
+		p2p::P2PConfig {
+			host: "127.0.0.1".parse().unwrap(),
+			port: open_port(),
+			..p2p::P2PConfig::default()
+		},
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	let addr = PeerAddr::Ip(SocketAddr::new(p2p_config.host, p2p_config.port));
+	let peer = client.connect(addr, 100_000).unwrap();
+	assert_eq!(peer.info.current_capabilities(), Capabilities::FULL_NODE);
+
+	// Nothing changed, nothing to tell.
+	assert!(!server.refresh_capabilities());
+
+	adapter.maintenance.store(true, Ordering::SeqCst);
+	assert!(server.refresh_capabilities());
+	assert!(!server.refresh_capabilities());
+	thread::sleep(time::Duration::from_millis(500));
+	let reduced =
+		Capabilities::FULL_NODE - Capabilities::HEADER_HIST - Capabilities::TXHASHSET_HIST;
+	assert_eq!(peer.info.capabilities, Capabilities::FULL_NODE);
+	assert_eq!(peer.info.current_capabilities(), reduced);
+
+	// The updated set is what the client goes by from now on.
+	assert_eq!(client.peers.request_peer_addrs(), 1);
+	server.set_capabilities(Capabilities::FULL_NODE - Capabilities::PEER_LIST);
+	thread::sleep(time::Duration::from_millis(500));
+	assert!(!peer
+		.info
+		.current_capabilities()
+		.contains(Capabilities::PEER_LIST));
+	assert_eq!(client.peers.request_peer_addrs(), 0);
+
+	adapter.maintenance.store(false, Ordering::SeqCst);
+	assert!(server.refresh_capabilities());
+	thread::sleep(time::Duration::from_millis(500));
+	assert!(peer
+		.info
+		.current_capabilities()
+		.contains(Capabilities::HEADER_HIST));
+
+	server.stop();
+	client.stop();
+}
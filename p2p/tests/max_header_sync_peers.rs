@@ -0,0 +1,109 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Peer;
+
+// With 4 peers available and a cap of 2, requesting headers from every
+// candidate never leaves more than 2 peers with requests outstanding. Giving
+// up on one of them lets a queued peer in.
+#[test]
+fn header_sync_peers_capped() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		max_header_sync_peers: Some(2),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("max_header_sync"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(
+		test_dir("max_header_sync_client"),
+		p2p::P2PConfig::default(),
+	);
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let mut clients = vec![];
+	for port in 5000..5004 {
+		let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+		clients.push(
+			Peer::connect(
+				socket,
+				p2p::Capabilities::UNKNOWN,
+				Difficulty::min(),
+				PeerAddr::Ip(format!("127.0.0.1:{}", port).parse().unwrap()),
+				&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+				client.peers.clone(),
+				100_000,
+				None,
+				client.clone(),
+			)
+			.unwrap(),
+		);
+	}
+	thread::sleep(time::Duration::from_secs(1));
+	let peers = server.peers.connected_peers();
+	assert_eq!(peers.len(), 4);
+
+	// Several scheduling rounds, each asking every candidate.
+	for _ in 0..3 {
+		let candidates = server.peers.header_sync_candidates(peers.clone());
+		assert!(candidates.len() <= 2);
+		for peer in candidates {
+			peer.info.header_sync_request_sent();
+		}
+		assert_eq!(server.peers.header_sync_peer_count(), 2);
+	}
+
+	// Giving up on a peer frees its slot, the other one is still waited on.
+	let waited_on: Vec<_> = peers
+		.iter()
+		.filter(|p| p.info.header_sync_outstanding() > 0)
+		.collect();
+	assert_eq!(waited_on.len(), 2);
+	waited_on[0].info.reset_header_sync();
+	assert_eq!(server.peers.header_sync_peer_count(), 1);
+	let candidates = server.peers.header_sync_candidates(peers.clone());
+	assert_eq!(candidates.len(), 2);
+	assert!(candidates
+		.iter()
+		.any(|p| p.info.addr == waited_on[1].info.addr));
+	assert_eq!(
+		candidates
+			.iter()
+			.filter(|p| p.info.header_sync_outstanding() == 0)
+			.count(),
+		1
+	);
+}
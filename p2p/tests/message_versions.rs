@@ -0,0 +1,191 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::{Mutex, StopState};
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::msg::{self, Type};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+/// Adapter recording how it gets asked for headers, by height ranges or by
+/// locator.
+struct RequestAdapter {
+	by_height: Mutex<Vec<(u64, u32)>>,
+	by_locator: Mutex<Vec<Vec<Hash>>>,
+}
+
+impl TestChain for RequestAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn locate_headers(
+		&self,
+		locator: &[Hash],
+	) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.by_locator.lock().push(locator.to_vec());
+		Ok(vec![])
+	}
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		self.by_height.lock().push((start, count));
+		Ok(vec![])
+	}
+}
+
+fn connect(
+	db_root: &str,
+	server_config: &p2p::P2PConfig,
+	client_config: p2p::P2PConfig,
+) -> Result<Peer, p2p::Error> {
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let client = p2p::Server::new(
+		db_root,
+		Capabilities::UNKNOWN,
+		client_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config, None),
+		net_adapter,
+		100_000,
+		None,
+		client,
+	)
+}
+
+// Asks the peer for the headers following height 10, falling back to a
+// locator request when it's too old to know headers by height.
+fn request_headers(peer: &Peer) {
+	if peer.info.supports(Type::GetHeadersByHeight) {
+		peer.send_headers_by_height_request(11, 5).unwrap();
+	} else {
+		peer.send_header_request(vec![Hash::from_vec(&[10; 32])], p2p::MAX_BLOCK_HEADERS)
+			.unwrap();
+	}
+}
+
+// Message types newer than the peer protocol version are never sent to it,
+// it's asked the older way instead. Peers on a recent enough version get the
+// newer message.
+#[test]
+fn message_min_version() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	assert_eq!(msg::min_version(Type::Ping), ProtocolVersion(1));
+	assert_eq!(msg::min_version(Type::GetHeaders), ProtocolVersion(1));
+	assert_eq!(
+		msg::min_version(Type::GetHeadersByHeight),
+		msg::HEADERS_BY_HEIGHT_VERSION
+	);
+	assert_eq!(msg::min_version(Type::GetTip), msg::TIP_VERSION);
+	assert_eq!(msg::min_version(Type::KeepAlive), msg::KEEPALIVE_VERSION);
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let adapter = Arc::new(TestAdapter(RequestAdapter {
+		by_height: Mutex::new(vec![]),
+		by_locator: Mutex::new(vec![]),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("message_versions"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let old_config = p2p::P2PConfig {
+		protocol_version: Some(3),
+		..p2p_config.clone()
+	};
+	let old_peer = connect(test_dir("message_versions_old"), &p2p_config, old_config).unwrap();
+	assert_eq!(old_peer.info.version, ProtocolVersion(3));
+	assert!(!old_peer.info.supports(Type::GetHeadersByHeight));
+	match old_peer.send_headers_by_height_request(11, 5) {
+		Err(p2p::Error::UnsupportedMessage { msg_type, version }) => {
+			assert_eq!(msg_type, Type::GetHeadersByHeight);
+			assert_eq!(version, ProtocolVersion(3));
+		}
+		res => panic!("unexpected result {:?}", res),
+	}
+	assert!(!old_peer.send_tip_request().unwrap());
+	assert!(!old_peer.send_keepalive().unwrap());
+	request_headers(&old_peer);
+	thread::sleep(time::Duration::from_secs(1));
+	assert!(adapter.by_height.lock().is_empty());
+	assert_eq!(
+		*adapter.by_locator.lock(),
+		vec![vec![Hash::from_vec(&[10; 32])]]
+	);
+
+	let peer = connect(
+		test_dir("message_versions_new"),
+		&p2p_config,
+		p2p_config.clone(),
+	)
+	.unwrap();
+	assert!(peer.info.supports(Type::GetHeadersByHeight));
+	assert!(peer.send_keepalive().unwrap());
+	request_headers(&peer);
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(*adapter.by_height.lock(), vec![(11, 5)]);
+	assert_eq!(adapter.by_locator.lock().len(), 1);
+}
@@ -0,0 +1,161 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::core::{KernelFeatures, Transaction, TxKernel};
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::msg;
+use crate::p2p::types::{NetAdapter, PeerAddr};
+use crate::p2p::{Capabilities, Peer};
+
+/// Adapter with a configurable min relay fee, counting the txs it receives.
+struct RelayAdapter {
+	min_relay_fee: u64,
+	received: AtomicUsize,
+}
+
+impl RelayAdapter {
+	fn new(min_relay_fee: u64) -> RelayAdapter {
+		RelayAdapter {
+			min_relay_fee,
+			received: AtomicUsize::new(0),
+		}
+	}
+}
+
+impl TestChain for RelayAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn min_relay_fee(&self) -> u64 {
+		self.min_relay_fee
+	}
+	fn transaction_received(
+		&self,
+		_: core::core::Transaction,
+		_stem: bool,
+	) -> Result<bool, chain::Error> {
+		self.received.fetch_add(1, Ordering::SeqCst);
+		Ok(true)
+	}
+}
+
+impl NetAdapter for TestAdapter<RelayAdapter> {
+	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
+		vec![]
+	}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
+	fn is_banned(&self, _: PeerAddr) -> bool {
+		false
+	}
+	fn peer_ser_error(&self, _: PeerAddr) {}
+	fn txhashset_archive_acceptable(&self, _: u64) -> bool {
+		true
+	}
+	fn request_declined(&self, _: PeerAddr, _: msg::Type, _: msg::DeclineReason) {}
+}
+
+fn tx_with_fee(fee: u64) -> Transaction {
+	Transaction::empty().with_kernel(TxKernel::with_features(KernelFeatures::Plain { fee }))
+}
+
+// Txs paying less than the min relay fee are not forwarded to our peers,
+// txs paying enough are.
+#[test]
+fn below_min_relay_fee_not_forwarded() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("min_relay_fee"),
+			Capabilities::UNKNOWN,
+			p2p_config.clone(),
+			Arc::new(TestAdapter(RelayAdapter::new(10))),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let net_adapter = Arc::new(TestAdapter(RelayAdapter::new(0)));
+	let client = p2p::Server::new(
+		test_dir("min_relay_fee_client"),
+		Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let _peer = Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		net_adapter.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_count(), 1);
+
+	server.peers.broadcast_transaction(&tx_with_fee(5));
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(net_adapter.received.load(Ordering::SeqCst), 0);
+
+	server.peers.broadcast_transaction(&tx_with_fee(10));
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(net_adapter.received.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,116 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{
+	healthy_peer, peer_info, server_with_adapter, test_dir, TestAdapter, TestChain,
+};
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::pow::{Difficulty, ProofOfWork};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{ChainAdapter, PeerInfo};
+
+/// Adapter at height 10 with a total difficulty of 1000, accepting headers
+/// or refusing them as invalid.
+struct ForkAdapter {
+	valid: bool,
+}
+
+impl TestChain for ForkAdapter {
+	fn headers_received(
+		&self,
+		_: &[core::core::BlockHeader],
+		_: &PeerInfo,
+		_: u64,
+	) -> Result<bool, chain::Error> {
+		Ok(self.valid)
+	}
+}
+
+fn fork_server(db_root: &str, valid: bool) -> p2p::Server {
+	let config = p2p::P2PConfig {
+		minority_fork_threshold: Some(2),
+		..p2p::P2PConfig::default()
+	};
+	server_with_adapter(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(TestAdapter(ForkAdapter { valid })),
+	)
+}
+
+// A header at our height with less cumulative difficulty than ours.
+fn losing_fork_header() -> BlockHeader {
+	BlockHeader {
+		height: 10,
+		pow: ProofOfWork {
+			total_difficulty: Difficulty::from_num(500),
+			..ProofOfWork::default()
+		},
+		..BlockHeader::default()
+	}
+}
+
+// A peer persistently offering a valid fork that loses against ours is parked,
+// not banned.
+#[test]
+fn minority_fork_valid_parks() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = fork_server(test_dir("minority_fork_valid"), true);
+	let addr = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	server.peers.save_peer(&healthy_peer(addr.clone())).unwrap();
+	let info = peer_info(addr.clone());
+
+	let header = losing_fork_header();
+	assert!(server
+		.peers
+		.headers_received(&[header.clone()], &info, 0)
+		.unwrap());
+	assert!(!server.peers.is_parked(addr.clone()));
+
+	assert!(server.peers.headers_received(&[header], &info, 0).unwrap());
+	assert!(server.peers.is_parked(addr.clone()));
+	assert!(!server.peers.is_banned(addr));
+}
+
+// A peer offering an invalid fork is banned right away.
+#[test]
+fn minority_fork_invalid_bans() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = fork_server(test_dir("minority_fork_invalid"), false);
+	let addr = PeerAddr::Ip("10.0.0.2:3414".parse().unwrap());
+	server.peers.save_peer(&healthy_peer(addr.clone())).unwrap();
+	let info = peer_info(addr.clone());
+
+	let _ = server
+		.peers
+		.headers_received(&[losing_fork_header()], &info, 0);
+	assert!(server.peers.is_banned(addr.clone()));
+	assert!(!server.peers.is_parked(addr));
+}
@@ -0,0 +1,103 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util::StopState;
+
+mod common;
+
+use self::common::test_dir;
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{estimate_block_difficulty, PeerAddr};
+use crate::p2p::PeerInfo;
+
+fn peer_info(total_difficulty: u64, height: u64) -> PeerInfo {
+	let info = common::peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+	{
+		let mut live_info = info.live_info.write();
+		live_info.total_difficulty = Difficulty::from_num(total_difficulty);
+		live_info.height = height;
+	}
+	info
+}
+
+// The work peers claim past a recent header of ours is averaged over the
+// blocks since, the median of those is used and outliers either way don't
+// move it.
+#[test]
+fn median_peer_estimate() {
+	// most of the chain work is way older than the reference, and doesn't
+	// count
+	let reference = (1_000, Difficulty::from_num(5_000_000));
+	let peers = vec![
+		peer_info(5_001_000, 1_010),
+		peer_info(5_002_000, 1_010),
+		peer_info(999_999_999, 1_010),
+	];
+	assert_eq!(
+		estimate_block_difficulty(&peers, reference),
+		Some(Difficulty::from_num(200))
+	);
+
+	// the lower one of the two middle claims on an even count
+	let peers = vec![
+		peer_info(5_000_001, 1_001),
+		peer_info(5_003_000, 1_010),
+		peer_info(5_004_000, 1_010),
+		peer_info(999_999_999, 1_010),
+	];
+	assert_eq!(
+		estimate_block_difficulty(&peers, reference),
+		Some(Difficulty::from_num(300))
+	);
+
+	// peers not past the reference are left out
+	let peers = vec![peer_info(5_000_000, 1_000), peer_info(5_000_500, 1_005)];
+	assert_eq!(
+		estimate_block_difficulty(&peers, reference),
+		Some(Difficulty::from_num(100))
+	);
+	assert_eq!(
+		estimate_block_difficulty(&vec![peer_info(4_000_000, 900)], reference),
+		None
+	);
+}
+
+// No estimate at all without peers.
+#[test]
+fn no_peers_no_estimate() {
+	assert_eq!(
+		estimate_block_difficulty(&vec![], (0, Difficulty::min())),
+		None
+	);
+
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	let server = p2p::Server::new(
+		test_dir("network_difficulty"),
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	assert_eq!(server.peers.estimated_network_difficulty(), None);
+}
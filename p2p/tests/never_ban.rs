@@ -0,0 +1,95 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{healthy_peer, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::msg::PeerAddrs;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{ReasonForBan, State};
+
+// Banning a peer on the never ban list is a no-op, other peers are still
+// banned as usual.
+#[test]
+fn never_ban_peer() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let trusted = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let other = PeerAddr::Ip("10.0.0.2:3414".parse().unwrap());
+	let config = p2p::P2PConfig {
+		peers_never_ban: Some(PeerAddrs {
+			peers: vec![trusted.clone()],
+		}),
+		..p2p::P2PConfig::default()
+	};
+	let server = p2p::Server::new(
+		test_dir("never_ban"),
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	server
+		.peers
+		.save_peer(&healthy_peer(trusted.clone()))
+		.unwrap();
+	server
+		.peers
+		.save_peer(&healthy_peer(other.clone()))
+		.unwrap();
+
+	assert!(server
+		.peers
+		.ban_peer(trusted.clone(), ReasonForBan::BadBlock)
+		.is_ok());
+	assert!(!server.peers.is_banned(trusted.clone()));
+	assert_eq!(
+		server.peers.get_peer(trusted.clone()).unwrap().flags,
+		State::Healthy
+	);
+
+	// Not connected, so the ban only updates the stored state.
+	let _ = server.peers.ban_peer(other.clone(), ReasonForBan::BadBlock);
+	assert!(server.peers.is_banned(other));
+
+	// Nor does banning its subnet refuse it.
+	server
+		.peers
+		.import_ban_list(
+			&["10.0.0.0/8".parse().unwrap()],
+			ReasonForBan::ManualBan,
+			3600,
+		)
+		.unwrap();
+	assert!(!server.peers.is_banned(trusted));
+	assert!(server
+		.peers
+		.is_banned(PeerAddr::Ip("10.0.0.3:3414".parse().unwrap())));
+}
@@ -0,0 +1,57 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+mod common;
+
+use crate::p2p::types::{distinct_nodes, PeerAddr};
+use crate::p2p::PeerInfo;
+
+fn peer_info(addr: PeerAddr, node_nonce: Option<u64>) -> PeerInfo {
+	PeerInfo {
+		direction: p2p::Direction::Inbound,
+		node_nonce,
+		..common::peer_info(addr)
+	}
+}
+
+fn ip(addr: &str) -> PeerAddr {
+	PeerAddr::Ip(addr.parse().unwrap())
+}
+
+fn onion() -> PeerAddr {
+	PeerAddr::Onion("maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd".to_string())
+}
+
+// Connections sharing a node nonce are one node, whatever their addresses.
+#[test]
+fn shared_nonce_counts_once() {
+	let clearnet = peer_info(ip("10.0.0.1:3414"), Some(42));
+	let tor = peer_info(onion(), Some(42));
+	assert_eq!(distinct_nodes(vec![&clearnet, &tor]), 1);
+
+	let other = peer_info(ip("10.0.0.2:3414"), Some(43));
+	assert_eq!(distinct_nodes(vec![&clearnet, &tor, &other]), 2);
+}
+
+// Peers we don't know the nonce of (the ones we connected to) always count.
+#[test]
+fn unknown_nonce_counts_each() {
+	let first = peer_info(ip("10.0.0.1:3414"), None);
+	let second = peer_info(ip("10.0.0.2:3414"), None);
+	let nonced = peer_info(onion(), Some(42));
+	assert_eq!(distinct_nodes(vec![&first, &second, &nonced]), 3);
+	assert_eq!(distinct_nodes(vec![]), 0);
+}
@@ -0,0 +1,220 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::noise::{self, NoiseSession, NoiseWriter};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+const PSK: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+fn start_server(db_root: &str, require_noise: Option<bool>) -> (Arc<p2p::Server>, p2p::P2PConfig) {
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		noise_psk: Some(PSK.to_owned()),
+		require_noise,
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(db_root, p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	(server, p2p_config)
+}
+
+// Connect a client to the server, handshaking with the provided capabilities
+// and config.
+fn connect(
+	server_config: &p2p::P2PConfig,
+	client_config: p2p::P2PConfig,
+	capab: Capabilities,
+	client: &p2p::Server,
+) -> Result<Peer, p2p::Error> {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		capab,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config, None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+}
+
+// Ping the server and check it got it, and we got its pong back.
+fn ping_pong(server: &p2p::Server, peer: &Peer) {
+	peer.send_ping(Difficulty::from_num(10), 10).unwrap();
+	thread::sleep(time::Duration::from_millis(500));
+
+	let server_peer = server
+		.peers
+		.get_connected_peer(PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()))
+		.unwrap();
+	assert_eq!(server_peer.info.height(), 10);
+	assert_eq!(server_peer.is_encrypted(), peer.is_encrypted());
+	assert_eq!(peer.last_min_message_counts(), Some((1, 1)));
+}
+
+// Two peers sharing the key upgrade their connection to Noise and talk
+// through it.
+#[test]
+fn noise_encrypted_connection() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, p2p_config) = start_server(test_dir("noise"), None);
+	assert!(server
+		.effective_capabilities()
+		.contains(Capabilities::NOISE));
+
+	let client = new_server(test_dir("noise_client"), p2p::P2PConfig::default());
+	let peer = connect(
+		&p2p_config,
+		p2p_config.clone(),
+		Capabilities::NOISE,
+		&client,
+	)
+	.unwrap();
+	assert!(peer.is_encrypted());
+	ping_pong(&server, &peer);
+}
+
+// A peer offering Noise with another key can't complete the handshake.
+#[test]
+fn noise_wrong_key() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, p2p_config) = start_server(test_dir("noise_wrong_key"), None);
+
+	let client = new_server(
+		test_dir("noise_wrong_key_client"),
+		p2p::P2PConfig::default(),
+	);
+	let client_config = p2p::P2PConfig {
+		noise_psk: Some(PSK.replace("00", "ff")),
+		..p2p_config.clone()
+	};
+	assert!(connect(&p2p_config, client_config, Capabilities::NOISE, &client).is_err());
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(server.peers.peer_inbound_count(), 0);
+}
+
+// A peer not offering Noise still connects, in plaintext.
+#[test]
+fn noise_plaintext_fallback() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, p2p_config) = start_server(test_dir("noise_fallback"), None);
+
+	let client = new_server(test_dir("noise_fallback_client"), p2p::P2PConfig::default());
+	let peer = connect(
+		&p2p_config,
+		p2p::P2PConfig::default(),
+		Capabilities::UNKNOWN,
+		&client,
+	)
+	.unwrap();
+	assert!(!peer.is_encrypted());
+	ping_pong(&server, &peer);
+}
+
+// A peer not offering Noise is refused when we require it, it may have had
+// our offer stripped on the way.
+#[test]
+fn noise_required() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, p2p_config) = start_server(test_dir("noise_required"), Some(true));
+
+	let client = new_server(test_dir("noise_required_client"), p2p::P2PConfig::default());
+	assert!(connect(
+		&p2p_config,
+		p2p::P2PConfig::default(),
+		Capabilities::UNKNOWN,
+		&client,
+	)
+	.is_err());
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(server.peers.peer_inbound_count(), 0);
+}
+
+/// Writer taking the provided number of bytes, then timing out.
+struct StallingWriter {
+	room: usize,
+}
+
+impl Write for StallingWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.room == 0 {
+			return Err(io::ErrorKind::TimedOut.into());
+		}
+		let len = std::cmp::min(self.room, buf.len());
+		self.room -= len;
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+// Runs the Noise handshake over a local connection, returning the session of
+// the initiating side.
+fn noise_session() -> NoiseSession {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	let psk = [7u8; 32];
+	let responder = thread::spawn(move || {
+		let (mut conn, _) = listener.accept().unwrap();
+		noise::respond(&mut conn, &psk).unwrap();
+	});
+	let mut conn = TcpStream::connect(addr).unwrap();
+	let session = noise::initiate(&mut conn, &psk).unwrap();
+	responder.join().unwrap();
+	session
+}
+
+// A frame cut short by a write timeout breaks the writer for good, without
+// reporting a timeout the caller would retry the message on.
+#[test]
+fn noise_partial_write_fatal() {
+	let mut writer = NoiseWriter::new(StallingWriter { room: 10 }, Arc::new(noise_session()));
+	let err = writer.write_all(&[1u8; 100]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+	let err = writer.write(&[1u8; 10]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
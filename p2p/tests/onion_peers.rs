@@ -0,0 +1,128 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use chrono::{Duration, Utc};
+use std::fs;
+
+mod common;
+
+use self::common::{new_server, test_dir};
+use crate::core::global;
+use crate::p2p::types::{limit_onion_dials, NetAdapter, PeerAddr};
+
+fn addr(addr: &str) -> PeerAddr {
+	PeerAddr::Ip(addr.parse().unwrap())
+}
+
+fn onion(i: u8) -> PeerAddr {
+	PeerAddr::Onion(format!(
+		"maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nq{}.onion",
+		(b'a' + i) as char
+	))
+}
+
+fn onion_server(db_root: &str, max_onion_peers: u32) -> p2p::Server {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	let _ = fs::remove_dir_all(db_root);
+	let config = p2p::P2PConfig {
+		max_onion_peers: Some(max_onion_peers),
+		onion_dial_interval: Some(10),
+		..p2p::P2PConfig::default()
+	};
+	new_server(db_root, config)
+}
+
+// Onion addresses past the cap aren't dialed, clearnet ones all are.
+#[test]
+fn onion_dials_capped() {
+	let addrs = vec![
+		onion(0),
+		addr("8.8.8.8:3414"),
+		onion(1),
+		onion(2),
+		addr("9.9.9.9:3414"),
+		onion(3),
+	];
+	assert_eq!(
+		limit_onion_dials(addrs.clone(), 2),
+		vec![
+			onion(0),
+			addr("8.8.8.8:3414"),
+			onion(1),
+			addr("9.9.9.9:3414")
+		]
+	);
+	assert_eq!(
+		limit_onion_dials(addrs.clone(), 0),
+		vec![addr("8.8.8.8:3414"), addr("9.9.9.9:3414")]
+	);
+	assert_eq!(limit_onion_dials(addrs.clone(), 10), addrs);
+}
+
+// Past the cap, the oldest stored onion addresses make room for the gossiped
+// ones, clearnet ones are all kept.
+#[test]
+fn onion_storage_capped() {
+	let server = onion_server(test_dir("onion_peers"), 2);
+	server.peers.peer_addrs_received(vec![
+		onion(0),
+		onion(1),
+		addr("8.8.8.8:3414"),
+		onion(2),
+		addr("9.9.9.9:3414"),
+	]);
+	// a later batch is capped together with what's stored already
+	server
+		.peers
+		.peer_addrs_received(vec![onion(3), addr("1.1.1.1:3414")]);
+
+	let stored = server.peers.all_peers();
+	let mut onions: Vec<_> = stored
+		.iter()
+		.filter(|p| match p.addr {
+			PeerAddr::Onion(_) => true,
+			PeerAddr::Ip(_) => false,
+		})
+		.map(|p| p.addr.to_string())
+		.collect();
+	onions.sort();
+	assert_eq!(onions, vec![onion(2).to_string(), onion(3).to_string()]);
+	assert_eq!(stored.len(), 5);
+}
+
+// Onion dials are paced, clearnet ones never are. The held back ones are
+// dialed first next time.
+#[test]
+fn onion_dials_paced() {
+	let server = onion_server(test_dir("onion_pacing"), 2);
+	let now = Utc::now();
+	assert!(server.peers.dial_allowed(&onion(0), now));
+	assert!(!server
+		.peers
+		.dial_allowed(&onion(1), now + Duration::seconds(5)));
+	assert!(server.peers.dial_allowed(&addr("8.8.8.8:3414"), now));
+	assert_eq!(
+		server
+			.peers
+			.dial_candidates(vec![addr("8.8.8.8:3414"), onion(1)]),
+		vec![onion(1), addr("8.8.8.8:3414")]
+	);
+	assert!(server.peers.dial_candidates(vec![]).is_empty());
+	assert!(server
+		.peers
+		.dial_allowed(&onion(1), now + Duration::seconds(10)));
+}
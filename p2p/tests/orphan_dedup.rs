@@ -0,0 +1,118 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{peer_info, server_with_adapter, test_dir, TestAdapter, TestChain};
+use crate::core::core::{Block, BlockHeader};
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{BlockAccept, ChainAdapter, PeerInfo};
+
+/// Adapter finding every block an orphan, counting the blocks it receives.
+struct OrphanAdapter {
+	received: AtomicUsize,
+}
+
+impl TestChain for OrphanAdapter {
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<BlockAccept, chain::Error> {
+		self.received.fetch_add(1, Ordering::Relaxed);
+		Ok(BlockAccept::Orphan)
+	}
+}
+
+fn orphan_server(
+	db_root: &str,
+	config: p2p::P2PConfig,
+) -> (p2p::Server, Arc<TestAdapter<OrphanAdapter>>) {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	let adapter = Arc::new(TestAdapter(OrphanAdapter {
+		received: AtomicUsize::new(0),
+	}));
+	let server = server_with_adapter(db_root, p2p::Capabilities::UNKNOWN, config, adapter.clone());
+	(server, adapter)
+}
+
+fn block(height: u64) -> Block {
+	Block {
+		header: BlockHeader {
+			height,
+			..BlockHeader::default()
+		},
+		..Block::default()
+	}
+}
+
+// Hand the block to the server as delivered by the peer at this address.
+fn deliver(server: &p2p::Server, block: Block, addr: &str) {
+	let info = peer_info(PeerAddr::Ip(addr.parse().unwrap()));
+	let accept = server
+		.peers
+		.block_received(block, &info, chain::Options::NONE)
+		.unwrap();
+	assert_eq!(accept, BlockAccept::Orphan);
+}
+
+// The same orphan delivered by three peers only reaches the chain once, other
+// orphans still do.
+#[test]
+fn orphan_delivered_once() {
+	let (server, adapter) = orphan_server(test_dir("orphan_dedup"), p2p::P2PConfig::default());
+	for addr in &["10.0.0.1:3414", "10.0.0.2:3414", "10.0.0.3:3414"] {
+		deliver(&server, block(10), addr);
+	}
+	assert_eq!(adapter.received.load(Ordering::Relaxed), 1);
+
+	deliver(&server, block(11), "10.0.0.1:3414");
+	assert_eq!(adapter.received.load(Ordering::Relaxed), 2);
+}
+
+// Orphans are forgotten once the window is over, or when tracking more than
+// the cap.
+#[test]
+fn orphan_tracking_bounded() {
+	let config = p2p::P2PConfig {
+		orphan_tracking_window: Some(0),
+		..p2p::P2PConfig::default()
+	};
+	let (server, adapter) = orphan_server(test_dir("orphan_window"), config);
+	deliver(&server, block(10), "10.0.0.1:3414");
+	deliver(&server, block(10), "10.0.0.2:3414");
+	assert_eq!(adapter.received.load(Ordering::Relaxed), 2);
+
+	let config = p2p::P2PConfig {
+		max_tracked_orphans: Some(1),
+		..p2p::P2PConfig::default()
+	};
+	let (server, adapter) = orphan_server(test_dir("orphan_cap"), config);
+	deliver(&server, block(10), "10.0.0.1:3414");
+	deliver(&server, block(11), "10.0.0.1:3414");
+	deliver(&server, block(11), "10.0.0.2:3414");
+	assert_eq!(adapter.received.load(Ordering::Relaxed), 2);
+	// block 10 was forgotten to make room for block 11
+	deliver(&server, block(10), "10.0.0.2:3414");
+	assert_eq!(adapter.received.load(Ordering::Relaxed), 3);
+}
@@ -0,0 +1,68 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::thread;
+
+mod common;
+
+use self::common::{new_server, test_dir};
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+
+fn bound_server(db_root: &str, outbound_bind: Option<IpAddr>) -> p2p::Server {
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		outbound_bind,
+		..p2p::P2PConfig::default()
+	};
+	new_server(db_root, config)
+}
+
+// Have the server connect to a local listener, returning the address the
+// connection came from. The handshake fails, we only look at the socket.
+fn connection_origin(server: &p2p::Server) -> IpAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	let accepted = thread::spawn(move || {
+		let (_stream, origin) = listener.accept().unwrap();
+		origin
+	});
+	let _ = server.connect(PeerAddr::Ip(addr), 100_000);
+	let origin: SocketAddr = accepted.join().unwrap();
+	origin.ip()
+}
+
+// Outbound connections originate from the configured local address (any of
+// 127.0.0.0/8 is local on the loopback interface).
+#[test]
+fn outbound_bind_local_address() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let local: IpAddr = "127.0.0.2".parse().unwrap();
+	let server = bound_server(test_dir("outbound_bind"), Some(local));
+	assert_eq!(connection_origin(&server), local);
+
+	let server = bound_server(test_dir("outbound_bind_default"), None);
+	assert_eq!(
+		connection_origin(&server),
+		"127.0.0.1".parse::<IpAddr>().unwrap()
+	);
+}
@@ -0,0 +1,135 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use chrono::prelude::Utc;
+use chrono::Duration;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{PeerData, ReasonForBan, State};
+
+fn config(port: u16) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		..p2p::P2PConfig::default()
+	}
+}
+
+fn start_server(db_root: &str, config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	server
+}
+
+fn defunct_peer(addr: PeerAddr) -> PeerData {
+	PeerData {
+		addr,
+		capabilities: p2p::Capabilities::UNKNOWN,
+		user_agent: "test".to_string(),
+		flags: State::Defunct,
+		last_banned: 0,
+		ban_reason: ReasonForBan::None,
+		last_connected: Utc::now().timestamp(),
+		score: 0,
+	}
+}
+
+// Short of outbound peers for long enough, we also try the peers we failed to
+// reach before and fill our slots with them.
+#[test]
+fn persistent_deficit_relaxes_selection() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = start_server(
+		test_dir("outbound_deficit"),
+		p2p::P2PConfig {
+			peer_min_preferred_outbound_count: Some(2),
+			outbound_deficit_relax_after: Some(60),
+			..config(open_port())
+		},
+	);
+
+	// two reachable peers, which we previously failed to connect to
+	let remotes: Vec<PeerAddr> = (0..2)
+		.map(|i| {
+			let port = open_port();
+			start_server(test_dir(&format!("outbound_deficit_{}", i)), config(port));
+			let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+			server.peers.save_peer(&defunct_peer(addr.clone())).unwrap();
+			addr
+		})
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+
+	assert_eq!(server.peers.outbound_deficit(), 2);
+	let start = Utc::now();
+	assert!(!server.peers.check_outbound_deficit(start));
+	assert!(server.peers.outbound_candidates(128, false).is_empty());
+
+	// not long enough yet
+	assert!(!server
+		.peers
+		.check_outbound_deficit(start + Duration::seconds(30)));
+
+	assert!(server
+		.peers
+		.check_outbound_deficit(start + Duration::seconds(61)));
+	let candidates = server.peers.outbound_candidates(128, true);
+	assert_eq!(candidates.len(), 2);
+	for candidate in candidates {
+		assert!(remotes.contains(&candidate.addr));
+		server.connect(candidate.addr, 100_000).unwrap();
+	}
+	thread::sleep(time::Duration::from_millis(500));
+
+	assert_eq!(server.peers.outbound_deficit(), 0);
+	assert!(!server
+		.peers
+		.check_outbound_deficit(start + Duration::seconds(62)));
+
+	// a new deficit starts counting from scratch
+	server.peers.stop();
+	assert_eq!(server.peers.outbound_deficit(), 2);
+	assert!(!server
+		.peers
+		.check_outbound_deficit(start + Duration::seconds(63)));
+}
@@ -0,0 +1,98 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+
+fn config(max_inbound: Option<u32>) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peer_max_inbound_count: max_inbound,
+		peer_listener_buffer_count: Some(8),
+		..p2p::P2PConfig::default()
+	}
+}
+
+fn start_server(db_root: &str, config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let inner = server.clone();
+	let _ = thread::spawn(move || inner.listen(100_000));
+	server
+}
+
+// The listener buffer only adds slack above a positive inbound limit.
+#[test]
+fn inbound_accept_limit() {
+	assert_eq!(config(Some(0)).inbound_accept_limit(), 0);
+	assert_eq!(config(Some(1)).inbound_accept_limit(), 9);
+	assert_eq!(config(None).inbound_accept_limit(), 128 + 8);
+}
+
+// With no inbound connections allowed nobody gets in, even within the
+// buffer, while we still connect out.
+#[test]
+fn outbound_only() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let outbound_only_config = config(Some(0));
+	let outbound_only_addr = SocketAddr::new(outbound_only_config.host, outbound_only_config.port);
+	let outbound_only = start_server(test_dir("outbound_only"), outbound_only_config);
+
+	let other_config = config(None);
+	let other_addr = SocketAddr::new(other_config.host, other_config.port);
+	let other = start_server(test_dir("outbound_only_other"), other_config);
+	thread::sleep(time::Duration::from_secs(1));
+
+	// nobody gets in
+	let _ = other.connect(PeerAddr::Ip(outbound_only_addr), 100_000);
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(outbound_only.peers.peer_inbound_count(), 0);
+	assert_eq!(other.peers.peer_outbound_count(), 0);
+
+	// but we get out
+	outbound_only
+		.connect(PeerAddr::Ip(other_addr), 100_000)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(outbound_only.peers.peer_outbound_count(), 1);
+	assert_eq!(other.peers.peer_inbound_count(), 1);
+}
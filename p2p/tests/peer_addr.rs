@@ -12,12 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use grin_p2p as p2p;
 
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{PeerAddr, ONION_P2P_PORT};
+use crate::p2p::{AddressFamilyPreference, NetworkClass};
 
 // Test the behavior of a hashmap of peers keyed by peer_addr.
 #[test]
@@ -87,3 +88,263 @@ fn test_peer_addr_hashing() {
 
 	assert_eq!(peers.len(), 3); // now it should be 3.
 }
+
+// Loopback peers are told apart by port, regular ones by ip only, and the two
+// kinds never mix, with Hash agreeing with Eq in every case.
+#[test]
+fn test_peer_addr_loopback_equality() {
+	let addr = |s: &str| PeerAddr::Ip(s.parse().unwrap());
+	let set = |addrs: &[PeerAddr]| addrs.iter().cloned().collect::<HashSet<PeerAddr>>();
+
+	// loopback vs non-loopback, on the same port
+	let loopback = addr("127.0.0.1:3414");
+	let regular = addr("10.0.0.1:3414");
+	assert_ne!(loopback, regular);
+	assert_ne!(regular, loopback);
+	assert_eq!(set(&[loopback.clone(), regular.clone()]).len(), 2);
+
+	// two loopbacks differing only by port
+	let other_loopback = addr("127.0.0.1:3415");
+	assert_ne!(loopback, other_loopback);
+	assert_eq!(set(&[loopback.clone(), other_loopback.clone()]).len(), 2);
+	assert_eq!(loopback, addr("127.0.0.1:3414"));
+	assert_eq!(addr("[::1]:3414"), addr("[::1]:3414"));
+	assert_ne!(addr("[::1]:3414"), addr("[::1]:3415"));
+
+	// two non-loopbacks differing only by port
+	let other_regular = addr("10.0.0.1:3415");
+	assert_eq!(regular, other_regular);
+	assert_eq!(other_regular, regular);
+	assert_eq!(set(&[regular.clone(), other_regular.clone()]).len(), 1);
+
+	// an IPv4-mapped loopback is a loopback too
+	let mapped = addr("[::ffff:127.0.0.1]:3414");
+	assert_ne!(mapped, addr("[::ffff:127.0.0.1]:3415"));
+	assert_ne!(mapped, loopback);
+	assert_ne!(mapped, regular);
+	let all = set(&[
+		loopback.clone(),
+		other_loopback,
+		regular.clone(),
+		other_regular,
+		mapped.clone(),
+		addr("[::ffff:127.0.0.1]:3415"),
+	]);
+	assert_eq!(all.len(), 5);
+	assert!(all.contains(&loopback));
+	assert!(all.contains(&addr("10.0.0.1:1")));
+	assert!(!all.contains(&addr("127.0.0.1:1")));
+}
+
+// Ip peers are reached directly, proxy or not.
+#[test]
+fn test_transport_addr_ip() {
+	let proxy = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9050);
+	let socket_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)), 3414);
+	let peer_addr = PeerAddr::Ip(socket_addr);
+
+	assert_eq!(peer_addr.transport_addr(None), Some(socket_addr));
+	assert_eq!(peer_addr.transport_addr(Some(proxy)), Some(socket_addr));
+}
+
+// Onion peers are reached through the tor proxy, and not at all without
+// one.
+#[test]
+fn test_transport_addr_tor() {
+	let proxy = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9050);
+	let domain = "maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd.onion".to_string();
+	let peer_addr = PeerAddr::Onion(domain);
+
+	assert_eq!(peer_addr.transport_addr(Some(proxy)), Some(proxy));
+	assert_eq!(peer_addr.transport_addr(None), None);
+}
+
+// Wire encodings match fixed vectors and read back to the same address
+// (compared on bytes as well, equality ignores the port).
+#[test]
+fn test_wire_bytes() {
+	let v4 = PeerAddr::Ip("1.2.3.4:3414".parse().unwrap());
+	let v4_bytes = vec![0, 1, 2, 3, 4, 0x0d, 0x56];
+
+	let v6 = PeerAddr::Ip("[2001:db8::1]:3414".parse().unwrap());
+	let v6_bytes = vec![
+		1, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x0d, 0x56,
+	];
+
+	let onion = PeerAddr::Onion("abc.onion".to_string());
+	let mut onion_bytes = vec![2, 0, 0, 0, 0, 0, 0, 0, 9];
+	onion_bytes.extend_from_slice(b"abc.onion");
+
+	for (addr, bytes) in vec![(v4, v4_bytes), (v6, v6_bytes), (onion, onion_bytes)] {
+		assert_eq!(addr.to_wire_bytes().unwrap(), bytes);
+		let read = PeerAddr::from_wire_bytes(&bytes).unwrap();
+		assert_eq!(read, addr);
+		assert_eq!(read.to_wire_bytes().unwrap(), bytes);
+	}
+
+	// Truncated input is an error, not a partial address.
+	assert!(PeerAddr::from_wire_bytes(&[0, 1, 2]).is_err());
+}
+
+// An onion address with the default port is kept without it, one with
+// another port keeps it to be dialed there. The tor address (the base32
+// pubkey) never includes the port either way.
+#[test]
+fn test_onion_with_port() {
+	let pubkey = "maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd";
+	let onion = format!("{}.onion", pubkey);
+
+	let plain = PeerAddr::from_str(&onion);
+	assert_eq!(plain, PeerAddr::Onion(onion.clone()));
+	assert_eq!(plain.tor_address().unwrap(), pubkey);
+	assert_eq!(plain.onion_target(), Some((onion.clone(), ONION_P2P_PORT)));
+
+	let with_port = PeerAddr::from_str(&format!("{}:3414", onion));
+	assert_eq!(with_port, PeerAddr::Onion(onion.clone()));
+	assert_eq!(with_port.tor_address().unwrap(), pubkey);
+	assert_eq!(
+		with_port.onion_target(),
+		Some((onion.clone(), ONION_P2P_PORT))
+	);
+
+	let other_port = PeerAddr::from_str(&format!("{}:9999", onion));
+	assert_eq!(other_port, PeerAddr::Onion(format!("{}:9999", onion)));
+	assert_ne!(other_port, plain);
+	assert_eq!(other_port.tor_address().unwrap(), pubkey);
+	assert_eq!(other_port.onion_target(), Some((onion.clone(), 9999)));
+	assert_eq!(other_port.network_class(), NetworkClass::Onion);
+	assert_eq!(
+		PeerAddr::parse_checked(&format!("tor://{}:9999", onion)),
+		Some(other_port)
+	);
+	assert_eq!(
+		PeerAddr::Ip("1.2.3.4:3414".parse().unwrap()).onion_target(),
+		None
+	);
+
+	assert_eq!(
+		PeerAddr::split_onion_port(&format!("{}:13414", onion)),
+		Some((onion.clone(), 13414))
+	);
+	assert_eq!(
+		PeerAddr::split_onion_port(&onion),
+		Some((onion.clone(), 3414))
+	);
+	assert_eq!(PeerAddr::split_onion_port("1.2.3.4:3414"), None);
+	assert_eq!(PeerAddr::split_onion_port("1.2.3.4"), None);
+}
+
+// Onion addresses come back from their Display form (with the tor:// scheme)
+// as they were, with or without the scheme and port in the config entry.
+#[test]
+fn test_onion_display_round_trip() {
+	let onion = "maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd.onion".to_string();
+	let onion_peer = PeerAddr::Onion(onion.clone());
+
+	assert_eq!(format!("{}", onion_peer), format!("tor://{}", onion));
+	assert_eq!(PeerAddr::from_str(&format!("{}", onion_peer)), onion_peer);
+
+	for entry in vec![
+		onion.clone(),
+		format!("tor://{}", onion),
+		format!("tor://{}:3414", onion),
+	] {
+		let peer = PeerAddr::from_str(&entry);
+		assert_eq!(peer, onion_peer);
+		assert_eq!(peer.as_key(), onion);
+	}
+}
+
+#[test]
+fn test_network_class() {
+	let class = |addr: &str| PeerAddr::Ip(addr.parse().unwrap()).network_class();
+
+	assert_eq!(class("8.8.8.8:3414"), NetworkClass::Ipv4Public);
+	assert_eq!(class("10.0.0.1:3414"), NetworkClass::Ipv4Private);
+	assert_eq!(class("172.16.0.1:3414"), NetworkClass::Ipv4Private);
+	assert_eq!(class("192.168.1.1:3414"), NetworkClass::Ipv4Private);
+	assert_eq!(class("169.254.0.1:3414"), NetworkClass::Ipv4Private);
+	assert_eq!(class("127.0.0.1:3414"), NetworkClass::Loopback);
+	assert_eq!(class("0.0.0.0:3414"), NetworkClass::Reserved);
+	assert_eq!(class("255.255.255.255:3414"), NetworkClass::Reserved);
+	assert_eq!(class("192.0.2.1:3414"), NetworkClass::Reserved);
+	assert_eq!(class("224.0.0.1:3414"), NetworkClass::Reserved);
+
+	assert_eq!(
+		class("[2001:4860:4860::8888]:3414"),
+		NetworkClass::Ipv6Public
+	);
+	assert_eq!(class("[fd00::1]:3414"), NetworkClass::Ipv6Private);
+	assert_eq!(class("[fe80::1]:3414"), NetworkClass::Ipv6LinkLocal);
+	assert_eq!(class("[::1]:3414"), NetworkClass::Loopback);
+	assert_eq!(class("[::]:3414"), NetworkClass::Reserved);
+	assert_eq!(class("[ff02::1]:3414"), NetworkClass::Reserved);
+
+	assert_eq!(
+		PeerAddr::Onion(
+			"maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd.onion".to_string()
+		)
+		.network_class(),
+		NetworkClass::Onion
+	);
+	assert_eq!(
+		PeerAddr::Onion("not-an-onion".to_string()).network_class(),
+		NetworkClass::Reserved
+	);
+}
+
+// Host names resolving to no address at all don't panic, they're kept as they
+// are like names failing to resolve.
+#[test]
+fn test_from_str_resolves_to_nothing() {
+	let name = "nothing.invalid:3414";
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![], AddressFamilyPreference::Any),
+		PeerAddr::Onion(name.to_string())
+	);
+	assert_eq!(PeerAddr::from_str(name), PeerAddr::Onion(name.to_string()));
+
+	let resolved: SocketAddr = "1.2.3.4:3414".parse().unwrap();
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![resolved], AddressFamilyPreference::Any),
+		PeerAddr::Ip(resolved)
+	);
+	assert_eq!(PeerAddr::parse_checked(name), None);
+}
+
+// A name resolving to both families gives an address of the preferred one,
+// the resolver order being kept by default.
+#[test]
+fn test_from_resolved_address_family() {
+	let name = "seed.example.com:3414";
+	let v4: SocketAddr = "1.2.3.4:3414".parse().unwrap();
+	let v6: SocketAddr = "[2001:db8::1]:3414".parse().unwrap();
+
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![v4, v6], AddressFamilyPreference::Any),
+		PeerAddr::Ip(v4)
+	);
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![v6, v4], AddressFamilyPreference::Any),
+		PeerAddr::Ip(v6)
+	);
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![v4, v6], AddressFamilyPreference::Ipv6),
+		PeerAddr::Ip(v6)
+	);
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![v6, v4], AddressFamilyPreference::Ipv4),
+		PeerAddr::Ip(v4)
+	);
+	// Only the other family available, it's still used.
+	assert_eq!(
+		PeerAddr::from_resolved(name, vec![v4], AddressFamilyPreference::Ipv6),
+		PeerAddr::Ip(v4)
+	);
+
+	let v6_second: SocketAddr = "[2001:db8::2]:3414".parse().unwrap();
+	assert_eq!(
+		AddressFamilyPreference::Ipv6.order(vec![v4, v6, v6_second]),
+		vec![v6, v6_second, v4]
+	);
+}
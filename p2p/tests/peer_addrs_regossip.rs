@@ -0,0 +1,133 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+mod common;
+
+use self::common::test_dir;
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::{select_regossip, NetAdapter, PeerAddr};
+
+fn addr(addr: &str) -> PeerAddr {
+	PeerAddr::Ip(addr.parse().unwrap())
+}
+
+fn onion() -> PeerAddr {
+	PeerAddr::Onion("maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd.onion".to_string())
+}
+
+#[test]
+fn routable_addrs() {
+	assert!(addr("8.8.8.8:3414").is_routable());
+	assert!(addr("[2001:4860:4860::8888]:3414").is_routable());
+	assert!(onion().is_routable());
+
+	assert!(!addr("8.8.8.8:0").is_routable());
+	assert!(!addr("127.0.0.1:3414").is_routable());
+	assert!(!addr("0.0.0.0:3414").is_routable());
+	assert!(!addr("10.0.0.1:3414").is_routable());
+	assert!(!addr("192.168.1.1:3414").is_routable());
+	assert!(!addr("169.254.0.1:3414").is_routable());
+	assert!(!addr("192.0.2.1:3414").is_routable());
+	assert!(!addr("224.0.0.1:3414").is_routable());
+	assert!(!addr("[::1]:3414").is_routable());
+	assert!(!addr("[fd00::1]:3414").is_routable());
+	assert!(!addr("[fe80::1]:3414").is_routable());
+	assert!(!PeerAddr::Onion("not-an-onion".to_string()).is_routable());
+}
+
+// Only addresses we connected to get passed on, the others wait until we do
+// and unroutable ones are dropped.
+#[test]
+fn regossip_connected_only() {
+	let connected_addr = addr("8.8.8.8:3414");
+	let never_connected = addr("9.9.9.9:3414");
+	let unroutable = addr("10.0.0.1:3414");
+	let mut pending: VecDeque<PeerAddr> = vec![
+		never_connected.clone(),
+		connected_addr.clone(),
+		unroutable.clone(),
+		onion(),
+	]
+	.into_iter()
+	.collect();
+	let mut connected = HashSet::new();
+	connected.insert(connected_addr.clone());
+	connected.insert(onion());
+	// connected but unroutable, still never passed on
+	connected.insert(unroutable);
+
+	let selected = select_regossip(&mut pending, &connected, 10);
+	assert_eq!(selected, vec![connected_addr, onion()]);
+	assert_eq!(pending, vec![never_connected.clone()]);
+
+	// nothing more until we connect to the remaining one
+	assert!(select_regossip(&mut pending, &connected, 10).is_empty());
+	assert_eq!(pending.len(), 1);
+
+	connected.insert(never_connected.clone());
+	assert_eq!(
+		select_regossip(&mut pending, &connected, 10),
+		vec![never_connected]
+	);
+	assert!(pending.is_empty());
+}
+
+// At most cap addresses per batch, the oldest first, the rest go in the next.
+#[test]
+fn regossip_cap() {
+	let addrs: Vec<PeerAddr> = (1..=5)
+		.map(|i| addr(&format!("8.8.8.{}:3414", i)))
+		.collect();
+	let mut pending: VecDeque<PeerAddr> = addrs.iter().cloned().collect();
+	let connected: HashSet<PeerAddr> = addrs.iter().cloned().collect();
+
+	assert_eq!(select_regossip(&mut pending, &connected, 2), addrs[0..2]);
+	assert_eq!(select_regossip(&mut pending, &connected, 2), addrs[2..4]);
+	assert_eq!(select_regossip(&mut pending, &connected, 2), addrs[4..5]);
+	assert!(select_regossip(&mut pending, &connected, 2).is_empty());
+}
+
+// Addresses we're sent but never connected to aren't passed on by the server.
+#[test]
+fn received_addrs_not_regossiped() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let server = p2p::Server::new(
+		test_dir("regossip"),
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	server
+		.peers
+		.peer_addrs_received(vec![addr("8.8.8.8:3414"), addr("9.9.9.9:3414"), onion()]);
+	assert!(server.peers.regossip_peer_addrs().is_empty());
+}
@@ -0,0 +1,115 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{healthy_peer, new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Peer, PeerData};
+
+// A healthy peer we can hand out when asked for peers.
+fn peer_list_peer(addr: PeerAddr) -> PeerData {
+	PeerData {
+		capabilities: p2p::Capabilities::PEER_LIST,
+		..healthy_peer(addr)
+	}
+}
+
+// Connects the client to the server, always advertising the same address.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// A second peer addresses request within the interval is answered with the
+// previous response, peers saved since then aren't sent. Reconnecting doesn't
+// get the peer a fresh answer either.
+#[test]
+fn peer_addrs_request_throttled() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(
+		test_dir("peer_addrs_throttle"),
+		p2p_config.clone(),
+	));
+	let first = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let second = PeerAddr::Ip("10.0.0.2:3414".parse().unwrap());
+	server
+		.peers
+		.save_peer(&peer_list_peer(first.clone()))
+		.unwrap();
+
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	// the client saves the addresses it receives in its own store
+	let client = new_server(
+		test_dir("peer_addrs_throttle_client"),
+		p2p::P2PConfig::default(),
+	);
+	let peer = connect(&p2p_config, &client);
+
+	peer.send_peer_request(p2p::Capabilities::PEER_LIST)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert!(client.peers.exists_peer(first).unwrap());
+
+	server
+		.peers
+		.save_peer(&peer_list_peer(second.clone()))
+		.unwrap();
+	peer.send_peer_request(p2p::Capabilities::PEER_LIST)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert!(!client.peers.exists_peer(second.clone()).unwrap());
+
+	peer.stop();
+	thread::sleep(time::Duration::from_secs(1));
+	let peer = connect(&p2p_config, &client);
+	peer.send_peer_request(p2p::Capabilities::PEER_LIST)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert!(!client.peers.exists_peer(second).unwrap());
+}
@@ -0,0 +1,150 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util::StopState;
+
+use std::sync::Arc;
+
+mod common;
+
+use self::common::test_dir;
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::ser::{self, ProtocolVersion, Writeable, Writer};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, PeerData, ReasonForBan, State, PEER_DATA_VERSION};
+
+// PeerData as stored by nodes from before the layout got versioned.
+struct LegacyPeerData {
+	addr: PeerAddr,
+	last_connected: Option<i64>,
+}
+
+impl Writeable for LegacyPeerData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.addr.write(writer)?;
+		writer.write_u32(Capabilities::FULL_NODE.bits())?;
+		writer.write_bytes(b"MW/MWC 4.0.0")?;
+		writer.write_u8(State::Banned as u8)?;
+		writer.write_i64(1_600_000_000)?;
+		writer.write_i32(ReasonForBan::BadBlock as i32)?;
+		if let Some(last_connected) = self.last_connected {
+			writer.write_i64(last_connected)?;
+		}
+		Ok(())
+	}
+}
+
+fn read_peer_data(bytes: Vec<u8>) -> PeerData {
+	ser::deserialize(&mut &bytes[..], ProtocolVersion::local()).unwrap()
+}
+
+// Records written before versioning load, with the fields they lack defaulted.
+#[test]
+fn legacy_peer_data_loads_with_defaults() {
+	let addr = PeerAddr::Ip("1.2.3.4:3414".parse().unwrap());
+	let legacy = LegacyPeerData {
+		addr: addr.clone(),
+		last_connected: Some(1_600_000_100),
+	};
+	let peer = read_peer_data(ser::ser_vec(&legacy, ProtocolVersion::local()).unwrap());
+	assert_eq!(peer.addr, addr);
+	assert_eq!(peer.capabilities, Capabilities::FULL_NODE);
+	assert_eq!(peer.user_agent, "MW/MWC 4.0.0");
+	assert_eq!(peer.flags, State::Banned);
+	assert_eq!(peer.last_banned, 1_600_000_000);
+	assert_eq!(peer.ban_reason, ReasonForBan::BadBlock);
+	assert_eq!(peer.last_connected, 1_600_000_100);
+	assert_eq!(peer.score, 0);
+
+	// even older records don't have last_connected either
+	let legacy = LegacyPeerData {
+		addr,
+		last_connected: None,
+	};
+	let peer = read_peer_data(ser::ser_vec(&legacy, ProtocolVersion::local()).unwrap());
+	assert!(peer.last_connected > 0);
+	assert_eq!(peer.score, 0);
+}
+
+// The current layout carries its version and round trips every field.
+#[test]
+fn versioned_peer_data_round_trips() {
+	let peer = PeerData {
+		addr: PeerAddr::Ip("5.6.7.8:3414".parse().unwrap()),
+		capabilities: Capabilities::FULL_NODE,
+		user_agent: "MW/MWC 5.0.0".to_string(),
+		flags: State::Healthy,
+		last_banned: 0,
+		ban_reason: ReasonForBan::None,
+		last_connected: 1_600_000_200,
+		score: -7,
+	};
+	let bytes = ser::ser_vec(&peer, ProtocolVersion::local()).unwrap();
+	assert_eq!(bytes[bytes.len() - 5], PEER_DATA_VERSION);
+
+	let read = read_peer_data(bytes.clone());
+	assert_eq!(read.score, -7);
+	assert_eq!(read.last_connected, 1_600_000_200);
+
+	// fields appended by a later version are skipped rather than failing
+	let mut newer = bytes;
+	let last = newer.len() - 5;
+	newer[last] = PEER_DATA_VERSION + 1;
+	newer.extend_from_slice(&[1, 2, 3, 4]);
+	assert_eq!(read_peer_data(newer).score, -7);
+}
+
+// Saving a peer anew, as when banning it, keeps the score we had for it.
+#[test]
+fn score_kept_on_save() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	let server = p2p::Server::new(
+		test_dir("peer_data_score"),
+		Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = PeerAddr::Ip("5.6.7.8:3414".parse().unwrap());
+	server
+		.peers
+		.save_peer(&PeerData {
+			addr: addr.clone(),
+			capabilities: Capabilities::FULL_NODE,
+			user_agent: "MW/MWC 5.0.0".to_string(),
+			flags: State::Healthy,
+			last_banned: 0,
+			ban_reason: ReasonForBan::None,
+			last_connected: 1_600_000_200,
+			score: -7,
+		})
+		.unwrap();
+	server
+		.peers
+		.add_banned(addr.clone(), ReasonForBan::BadBlock)
+		.unwrap();
+
+	let peer = server.peers.get_peer(addr).unwrap();
+	assert_eq!(peer.flags, State::Banned);
+	assert_eq!(peer.score, -7);
+}
@@ -18,24 +18,19 @@ use grin_p2p as p2p;
 use grin_util as util;
 use grin_util::StopState;
 
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::net::{SocketAddr, TcpStream};
 use std::sync::Arc;
 use std::{thread, time};
 
+mod common;
+
+use self::common::open_port;
 use crate::core::core::hash::Hash;
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::p2p::types::PeerAddr;
 use crate::p2p::Peer;
 
-fn open_port() -> u16 {
-	// use port 0 to allow the OS to assign an open port
-	// TcpListener's Drop impl will unbind the port as soon as
-	// listener goes out of scope
-	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-	listener.local_addr().unwrap().port()
-}
-
 // Setup test with AutomatedTesting chain_type;
 fn test_setup() {
 	// Set "global" chain type here as we spawn peer threads for read/write.
@@ -0,0 +1,158 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use chrono::Utc;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::{
+	Capabilities, Direction, Peer, PeerAddr, PeerMetricsExporter, PeerSetSnapshot, PeerSnapshot,
+};
+
+fn peer(direction: Direction, version: u32) -> PeerSnapshot {
+	PeerSnapshot {
+		capabilities: Capabilities::UNKNOWN,
+		user_agent: "test".to_string(),
+		version: ProtocolVersion(version),
+		direction,
+	}
+}
+
+fn snapshot(peers: Vec<PeerSnapshot>) -> PeerSetSnapshot {
+	let peers: HashMap<PeerAddr, PeerSnapshot> = peers
+		.into_iter()
+		.enumerate()
+		.map(|(i, peer)| {
+			let addr = format!("10.0.0.{}:3414", i + 1);
+			(PeerAddr::Ip(addr.parse().unwrap()), peer)
+		})
+		.collect();
+	PeerSetSnapshot {
+		taken_at: Utc::now(),
+		peers,
+	}
+}
+
+// Every metric comes with its help and type, and the expected label sets.
+#[test]
+fn exposition_format() {
+	let peers = snapshot(vec![
+		peer(Direction::Outbound, 3),
+		peer(Direction::Outbound, 4),
+		peer(Direction::Inbound, 4),
+		peer(Direction::OutboundTor, 4),
+	]);
+	let out = PeerMetricsExporter::new(peers, 1_000, 2_000, 3).render();
+
+	for (name, kind) in &[
+		("mwc_p2p_peers", "gauge"),
+		("mwc_p2p_peers_by_version", "gauge"),
+		("mwc_p2p_sent_bytes_total", "counter"),
+		("mwc_p2p_received_bytes_total", "counter"),
+		("mwc_p2p_bans_total", "counter"),
+	] {
+		assert!(
+			out.contains(&format!("# TYPE {} {}\n", name, kind)),
+			"{}",
+			name
+		);
+		assert!(out.contains(&format!("# HELP {} ", name)), "{}", name);
+	}
+
+	assert!(out.contains("mwc_p2p_peers{direction=\"inbound\"} 1\n"));
+	assert!(out.contains("mwc_p2p_peers{direction=\"outbound\"} 2\n"));
+	assert!(out.contains("mwc_p2p_peers{direction=\"inbound_tor\"} 0\n"));
+	assert!(out.contains("mwc_p2p_peers{direction=\"outbound_tor\"} 1\n"));
+
+	assert!(out.contains("mwc_p2p_peers_by_version{version=\"3\"} 1\n"));
+	assert!(out.contains("mwc_p2p_peers_by_version{version=\"4\"} 3\n"));
+
+	assert!(out.contains("mwc_p2p_sent_bytes_total 1000\n"));
+	assert!(out.contains("mwc_p2p_received_bytes_total 2000\n"));
+	assert!(out.contains("mwc_p2p_bans_total 3\n"));
+}
+
+// Without peers all directions still show up, at 0, and no version does.
+#[test]
+fn exposition_no_peers() {
+	let out = PeerMetricsExporter::new(snapshot(vec![]), 0, 0, 0).render();
+	assert!(out.contains("mwc_p2p_peers{direction=\"outbound\"} 0\n"));
+	assert!(!out.contains("mwc_p2p_peers_by_version{"));
+	assert!(out.contains("mwc_p2p_bans_total 0\n"));
+}
+
+// The byte totals count the traffic of every connection and keep counting
+// past disconnects.
+#[test]
+fn traffic_totals_survive_disconnects() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("traffic_totals"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(test_dir("traffic_totals_client"), p2p::P2PConfig::default());
+	let totals = client.peers.traffic_totals();
+	let mut last = (0, 0);
+	for _ in 0..2 {
+		let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+		let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+		let peer = Peer::connect(
+			socket,
+			Capabilities::UNKNOWN,
+			Difficulty::min(),
+			PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+			&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+			client.peers.clone(),
+			100_000,
+			None,
+			client.clone(),
+		)
+		.unwrap();
+		peer.send_ping(Difficulty::min(), 0).unwrap();
+		thread::sleep(time::Duration::from_secs(1));
+		peer.stop();
+		thread::sleep(time::Duration::from_millis(500));
+
+		// the ping went out and its pong came back, on top of what we counted
+		assert!(totals.sent() > last.0);
+		assert!(totals.received() > last.1);
+		last = (totals.sent(), totals.received());
+	}
+
+	let out = PeerMetricsExporter::from_peers(&client.peers).render();
+	assert!(out.contains(&format!("mwc_p2p_sent_bytes_total {}\n", last.0)));
+	assert!(out.contains(&format!("mwc_p2p_received_bytes_total {}\n", last.1)));
+}
@@ -0,0 +1,142 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use chrono::prelude::Utc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Direction, PeerSetSnapshot, PeerSnapshot};
+
+fn start_server(db_root: &str, port: u16) -> Arc<p2p::Server> {
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	server
+}
+
+fn local_addr(port: u16) -> PeerAddr {
+	PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
+}
+
+// Peers we connect to show up as added, the ones we disconnect from as removed.
+#[test]
+fn connect_and_disconnect_diffed() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server = start_server(test_dir("peer_snapshot"), open_port());
+	let remotes: Vec<PeerAddr> = (0..2)
+		.map(|i| {
+			let port = open_port();
+			start_server(test_dir(&format!("peer_snapshot_{}", i)), port);
+			local_addr(port)
+		})
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let before = server.peers.snapshot();
+	assert!(before.peers.is_empty());
+
+	for remote in &remotes {
+		server.connect(remote.clone(), 100_000).unwrap();
+	}
+	thread::sleep(time::Duration::from_millis(500));
+	let connected = server.peers.snapshot();
+	let diff = before.diff(&connected);
+	let mut added = remotes.clone();
+	added.sort_by_key(|addr| addr.to_string());
+	assert_eq!(diff.added, added);
+	assert!(diff.removed.is_empty());
+	assert!(diff.changed.is_empty());
+	assert_eq!(connected.peers[&remotes[0]].direction, Direction::Outbound);
+
+	server
+		.peers
+		.get_connected_peer(remotes[0].clone())
+		.unwrap()
+		.stop();
+	thread::sleep(time::Duration::from_millis(500));
+	let disconnected = server.peers.snapshot();
+	let diff = connected.diff(&disconnected);
+	assert!(diff.added.is_empty());
+	assert_eq!(diff.removed, vec![remotes[0].clone()]);
+	assert!(diff.changed.is_empty());
+
+	assert!(disconnected.diff(&server.peers.snapshot()).is_empty());
+}
+
+// A peer still connected with different attributes is reported as changed.
+#[test]
+fn changed_attributes_diffed() {
+	let addr = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let peer = PeerSnapshot {
+		capabilities: Capabilities::HEADER_HIST,
+		user_agent: "test".to_string(),
+		version: ProtocolVersion::local(),
+		direction: Direction::Inbound,
+	};
+	let mut peers = HashMap::new();
+	peers.insert(addr.clone(), peer.clone());
+	let before = PeerSetSnapshot {
+		taken_at: Utc::now(),
+		peers,
+	};
+
+	let mut after = before.clone();
+	assert!(before.diff(&after).is_empty());
+
+	after.peers.insert(
+		addr.clone(),
+		PeerSnapshot {
+			capabilities: Capabilities::HEADER_HIST | Capabilities::TXHASHSET_HIST,
+			..peer
+		},
+	);
+	let diff = before.diff(&after);
+	assert!(diff.added.is_empty());
+	assert!(diff.removed.is_empty());
+	assert_eq!(diff.changed, vec![addr]);
+}
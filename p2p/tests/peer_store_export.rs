@@ -0,0 +1,167 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+
+use self::common::{new_server, test_dir};
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, ExportedPeer, PeerData, ReasonForBan, State};
+
+fn fresh_server(db_root: &str) -> p2p::Server {
+	let _ = fs::remove_dir_all(db_root);
+	new_server(db_root, p2p::P2PConfig::default())
+}
+
+fn peer(addr: PeerAddr, flags: State, ban_reason: ReasonForBan) -> PeerData {
+	PeerData {
+		addr,
+		capabilities: Capabilities::HEADER_HIST | Capabilities::PEER_LIST,
+		user_agent: "MW/MWC 4.3.0".to_string(),
+		flags,
+		last_banned: if flags == State::Banned {
+			1_600_000_000
+		} else {
+			0
+		},
+		ban_reason,
+		last_connected: 1_600_000_100,
+		score: 0,
+	}
+}
+
+fn known_peers() -> Vec<PeerData> {
+	vec![
+		peer(
+			PeerAddr::Ip("8.8.8.8:3414".parse().unwrap()),
+			State::Healthy,
+			ReasonForBan::None,
+		),
+		peer(
+			PeerAddr::Ip("[2001:4860:4860::8888]:3414".parse().unwrap()),
+			State::Defunct,
+			ReasonForBan::None,
+		),
+		peer(
+			PeerAddr::Ip("9.9.9.9:3414".parse().unwrap()),
+			State::Banned,
+			ReasonForBan::BadBlock,
+		),
+		peer(
+			PeerAddr::Onion(
+				"maxs4wuipojxv5gagcrvgsd3zjn7qkmi3rukiozqoq4uwtgelxbz6nqd.onion".to_string(),
+			),
+			State::Healthy,
+			ReasonForBan::None,
+		),
+	]
+}
+
+fn assert_same(imported: &PeerData, expected: &PeerData) {
+	assert_eq!(imported.addr, expected.addr);
+	assert_eq!(imported.capabilities, expected.capabilities);
+	assert_eq!(imported.user_agent, expected.user_agent);
+	assert_eq!(imported.flags, expected.flags);
+	assert_eq!(imported.last_banned, expected.last_banned);
+	assert_eq!(imported.ban_reason, expected.ban_reason);
+	assert_eq!(imported.last_connected, expected.last_connected);
+}
+
+// Exported peers come back identical in a fresh store, invalid entries added
+// to the file are skipped.
+#[test]
+fn export_import_round_trip() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let source = fresh_server(test_dir("peer_export_source"));
+	for peer in known_peers() {
+		source.peers.save_peer(&peer).unwrap();
+	}
+	let path = PathBuf::from(test_dir("peer_export.json"));
+	assert_eq!(source.peers.export_json(&path).unwrap(), 4);
+
+	// tamper with the file, adding entries no checked parser accepts
+	let json = fs::read_to_string(&path).unwrap();
+	let mut exported: Vec<ExportedPeer> = serde_json::from_str(&json).unwrap();
+	for addr in &["not an address", "some.host.name:3414", ".onion"] {
+		let mut invalid = exported[0].clone();
+		invalid.addr = addr.to_string();
+		exported.push(invalid);
+	}
+	fs::write(&path, serde_json::to_string(&exported).unwrap()).unwrap();
+
+	let target = fresh_server(test_dir("peer_export_target"));
+	assert_eq!(target.peers.import_json(&path, false).unwrap(), 4);
+	assert_eq!(target.peers.all_peers().len(), 4);
+	for expected in known_peers() {
+		let imported = target.peers.get_peer(expected.addr.clone()).unwrap();
+		assert_same(&imported, &expected);
+	}
+
+	let _ = fs::remove_file(path);
+}
+
+// Merging keeps what we know and only adds new peers, replacing drops what
+// isn't in the file.
+#[test]
+fn import_merge_or_replace() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let source = fresh_server(test_dir("peer_merge_source"));
+	for peer in known_peers() {
+		source.peers.save_peer(&peer).unwrap();
+	}
+	let path = PathBuf::from(test_dir("peer_merge.json"));
+	source.peers.export_json(&path).unwrap();
+
+	let target = fresh_server(test_dir("peer_merge_target"));
+	let ours = peer(
+		PeerAddr::Ip("1.1.1.1:3414".parse().unwrap()),
+		State::Healthy,
+		ReasonForBan::None,
+	);
+	// known to us as healthy, banned in the file
+	let known = peer(
+		PeerAddr::Ip("9.9.9.9:3414".parse().unwrap()),
+		State::Healthy,
+		ReasonForBan::None,
+	);
+	target.peers.save_peer(&ours).unwrap();
+	target.peers.save_peer(&known).unwrap();
+
+	assert_eq!(target.peers.import_json(&path, true).unwrap(), 3);
+	assert_eq!(target.peers.all_peers().len(), 5);
+	assert_same(&target.peers.get_peer(ours.addr.clone()).unwrap(), &ours);
+	assert_same(&target.peers.get_peer(known.addr.clone()).unwrap(), &known);
+
+	assert_eq!(target.peers.import_json(&path, false).unwrap(), 4);
+	assert_eq!(target.peers.all_peers().len(), 4);
+	assert!(!target.peers.exists_peer(ours.addr).unwrap());
+	assert_eq!(
+		target.peers.get_peer(known.addr).unwrap().flags,
+		State::Banned
+	);
+
+	let _ = fs::remove_file(path);
+}
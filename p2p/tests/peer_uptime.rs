@@ -0,0 +1,102 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+mod common;
+
+use crate::core::core::hash::Hash;
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::msg::{Shake, PEER_UPTIME_VERSION};
+use crate::p2p::types::{select_stable, PeerAddr, MAX_PEER_UPTIME};
+use crate::p2p::PeerInfo;
+
+fn peer_info(addr: &str, peer_uptime: Option<Duration>) -> PeerInfo {
+	PeerInfo {
+		peer_uptime,
+		..common::peer_info(PeerAddr::Ip(addr.parse().unwrap()))
+	}
+}
+
+// Of two otherwise equal candidates the one advertising the longer uptime is
+// preferred, whatever the order. Not advertising an uptime comes last.
+#[test]
+fn select_longest_uptime() {
+	let short = peer_info("10.0.0.1:3414", Some(Duration::from_secs(60)));
+	let long = peer_info("10.0.0.2:3414", Some(Duration::from_secs(86_400)));
+	let unknown = peer_info("10.0.0.3:3414", None);
+
+	let candidates = vec![short.clone(), long.clone()];
+	assert_eq!(select_stable(&candidates, |i| i).unwrap().addr, long.addr);
+	let candidates = vec![long.clone(), short.clone()];
+	assert_eq!(select_stable(&candidates, |i| i).unwrap().addr, long.addr);
+
+	let candidates = vec![unknown.clone(), short.clone()];
+	assert_eq!(select_stable(&candidates, |i| i).unwrap().addr, short.addr);
+
+	// No uptimes at all, the first candidate is kept.
+	let other = peer_info("10.0.0.4:3414", None);
+	let candidates = vec![unknown.clone(), other];
+	assert_eq!(
+		select_stable(&candidates, |i| i).unwrap().addr,
+		unknown.addr
+	);
+
+	let empty: Vec<PeerInfo> = vec![];
+	assert!(select_stable(&empty, |i| i).is_none());
+}
+
+// Absurd claimed uptimes are capped rather than overflowing.
+#[test]
+fn claimed_uptime_capped() {
+	let absurd = peer_info("10.0.0.5:3414", Some(Duration::from_secs(u64::MAX)));
+	let uptime = absurd.uptime().unwrap();
+	assert!(uptime >= MAX_PEER_UPTIME);
+	assert!(uptime < MAX_PEER_UPTIME + Duration::from_secs(60));
+
+	let long = peer_info("10.0.0.2:3414", Some(Duration::from_secs(86_400)));
+	let candidates = vec![long, absurd.clone()];
+	assert_eq!(select_stable(&candidates, |i| i).unwrap().addr, absurd.addr);
+}
+
+// The uptime and clock are only sent to (and read from) peers on a recent
+// enough protocol version.
+#[test]
+fn shake_uptime_gated_on_version() {
+	let shake = |version| Shake {
+		version,
+		capabilities: p2p::Capabilities::UNKNOWN,
+		genesis: Hash::from_vec(&vec![]),
+		total_difficulty: Difficulty::min(),
+		user_agent: "test".to_string(),
+		uptime: Some(3600),
+		timestamp: Some(1_600_000_000),
+	};
+
+	let bytes = ser::ser_vec(&shake(PEER_UPTIME_VERSION), PEER_UPTIME_VERSION).unwrap();
+	let read: Shake = ser::deserialize(&mut &bytes[..], PEER_UPTIME_VERSION).unwrap();
+	assert_eq!(read.uptime, Some(3600));
+	assert_eq!(read.timestamp, Some(1_600_000_000));
+
+	let old = ProtocolVersion(PEER_UPTIME_VERSION.value() - 1);
+	let old_bytes = ser::ser_vec(&shake(old), old).unwrap();
+	assert_eq!(old_bytes.len() + 16, bytes.len());
+	let read: Shake = ser::deserialize(&mut &old_bytes[..], old).unwrap();
+	assert_eq!(read.uptime, None);
+	assert_eq!(read.timestamp, None);
+}
@@ -0,0 +1,156 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use chrono::Utc;
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::msg::{self, Hand, MsgHeader, Shake, Type};
+use crate::p2p::types::{max_plausible_difficulty, PeerAddr};
+use crate::p2p::ReasonForBan;
+
+const HEAD_HEIGHT: u64 = 10;
+const BLOCK_DIFFICULTY: u64 = 1_000;
+
+/// Chain whose head was just mined at HEAD_HEIGHT, every block at the same
+/// difficulty.
+struct FreshChain;
+
+impl FreshChain {
+	fn header(height: u64) -> BlockHeader {
+		let mut header = BlockHeader::default();
+		header.height = height;
+		header.timestamp = Utc::now();
+		header.pow.total_difficulty = Difficulty::from_num(BLOCK_DIFFICULTY * (height + 1));
+		header
+	}
+}
+
+impl TestChain for FreshChain {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Self::header(HEAD_HEIGHT).total_difficulty())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(HEAD_HEIGHT)
+	}
+	fn headers_by_height(&self, start: u64, count: u32) -> Result<Vec<BlockHeader>, chain::Error> {
+		Ok((start..(start + count as u64).min(HEAD_HEIGHT + 1))
+			.map(Self::header)
+			.collect())
+	}
+	fn header_head(&self) -> Option<chain::Tip> {
+		Some(chain::Tip::from_header(&Self::header(HEAD_HEIGHT)))
+	}
+}
+
+// Sends a hand claiming the provided difficulty, returns the address the
+// server sees us from and whether it shook our hand.
+fn send_hand(addr: SocketAddr, total_difficulty: Difficulty) -> (PeerAddr, bool) {
+	let version = ProtocolVersion::local();
+	let mut stream = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let hand = Hand {
+		version,
+		capabilities: p2p::Capabilities::UNKNOWN,
+		nonce: 42,
+		genesis: Hash::from_vec(&vec![]),
+		total_difficulty,
+		sender_addr: PeerAddr::Ip(stream.local_addr().unwrap()),
+		receiver_addr: PeerAddr::Ip(addr),
+		user_agent: "test".to_string(),
+		uptime: None,
+		timestamp: None,
+		listening: None,
+	};
+	let body = ser::ser_vec(&hand, version).unwrap();
+	let header = ser::ser_vec(&MsgHeader::new(Type::Hand, body.len() as u64), version).unwrap();
+	stream.write_all(&header).unwrap();
+	stream.write_all(&body).unwrap();
+	let shaken = msg::read_message::<Shake, _>(&mut stream, version, Type::Shake).is_ok();
+	thread::sleep(time::Duration::from_secs(1));
+	(PeerAddr::Ip(stream.local_addr().unwrap()), shaken)
+}
+
+// The bound grows with the age of our head and gives up on heads too old to
+// tell anything from.
+#[test]
+fn plausible_difficulty_bound() {
+	let total = Difficulty::from_num(1_000_000);
+	let block = Difficulty::from_num(BLOCK_DIFFICULTY);
+	let fresh = max_plausible_difficulty(total, block, 0).unwrap();
+	assert!(fresh > total);
+	assert!(max_plausible_difficulty(total, block, 3_600).unwrap() > fresh);
+	assert!(max_plausible_difficulty(total, block, 365 * 86_400).is_none());
+}
+
+// A peer claiming far more work than anyone could have accumulated since our
+// head gets refused in the handshake and banned, an honest peer is accepted.
+#[test]
+fn impossible_difficulty_banned() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let addr = SocketAddr::new(config.host, config.port);
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("plausible_difficulty"),
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(TestAdapter(FreshChain)),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let inner = server.clone();
+	let _ = thread::spawn(move || inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let honest_diff = Difficulty::from_num(BLOCK_DIFFICULTY * (HEAD_HEIGHT + 5));
+	let (honest, shaken) = send_hand(addr, honest_diff);
+	assert!(shaken);
+	assert!(!server.peers.is_banned(honest));
+
+	let absurd_diff = Difficulty::from_num(u64::max_value() / 2);
+	let (liar, shaken) = send_hand(addr, absurd_diff);
+	assert!(!shaken);
+	assert!(server.peers.is_banned(liar.clone()));
+	assert_eq!(
+		server.peers.get_peer(liar).unwrap().ban_reason,
+		ReasonForBan::ImpossibleDifficulty
+	);
+}
@@ -0,0 +1,132 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Peer, ProtocolNegotiation};
+
+// A peer overriding its protocol version to 2 advertises it during the
+// handshake and both sides settle on version 2.
+#[test]
+fn protocol_version_override() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	// Overrides are bounded to the versions we support.
+	let bounded = |v| {
+		p2p::P2PConfig {
+			protocol_version: Some(v),
+			..p2p::P2PConfig::default()
+		}
+		.protocol_version()
+	};
+	assert_eq!(bounded(0), ProtocolVersion(1));
+	assert_eq!(bounded(2), ProtocolVersion(2));
+	assert_eq!(bounded(7), ProtocolVersion::local());
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		..p2p::P2PConfig::default()
+	};
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let server_inner = p2p::Server::new(
+		test_dir("protocol_version"),
+		p2p::Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	let server = Arc::new(server_inner.clone());
+
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let v2_config = p2p::P2PConfig {
+		protocol_version: Some(2),
+		..p2p_config.clone()
+	};
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let my_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
+	let peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		my_addr.clone(),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), v2_config, None),
+		net_adapter,
+		100_000,
+		None,
+		server_inner,
+	)
+	.unwrap();
+	assert_eq!(peer.info.version, ProtocolVersion(2));
+
+	thread::sleep(time::Duration::from_secs(1));
+	let server_peer = server.peers.get_connected_peer(my_addr).unwrap();
+	assert_eq!(server_peer.info.version, ProtocolVersion(2));
+
+	// Both sides keep what each advertised and the version agreed on.
+	assert_eq!(
+		peer.info.negotiation,
+		Some(ProtocolNegotiation {
+			our_capabilities: p2p::Capabilities::UNKNOWN,
+			our_version_max: ProtocolVersion(2),
+			advertised_capabilities: server.effective_capabilities(),
+			advertised_version_max: ProtocolVersion::local(),
+			negotiated_version: ProtocolVersion(2),
+		})
+	);
+	assert_eq!(
+		server_peer.info.negotiation,
+		Some(ProtocolNegotiation {
+			our_capabilities: server.effective_capabilities(),
+			our_version_max: ProtocolVersion::local(),
+			advertised_capabilities: p2p::Capabilities::UNKNOWN,
+			advertised_version_max: ProtocolVersion(2),
+			negotiated_version: ProtocolVersion(2),
+		})
+	);
+	let display = p2p::types::PeerInfoDisplay::from(server_peer.info.clone());
+	assert_eq!(display.negotiation, server_peer.info.negotiation);
+
+	// The connection still works with the negotiated version.
+	peer.send_ping(Difficulty::min(), 0).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server_peer.info.total_difficulty(), Difficulty::min());
+}
@@ -0,0 +1,92 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+mod common;
+
+use self::common::test_dir;
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{PeerData, ReasonForBan, State};
+
+fn banned_peer(addr: PeerAddr, last_banned: i64) -> PeerData {
+	PeerData {
+		addr,
+		capabilities: p2p::Capabilities::UNKNOWN,
+		user_agent: "test".to_string(),
+		flags: State::Banned,
+		last_banned,
+		ban_reason: ReasonForBan::BadBlock,
+		last_connected: last_banned,
+		score: 0,
+	}
+}
+
+// Only the bans older than the ban window are pruned from storage.
+#[test]
+fn prune_expired_bans() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let config = p2p::P2PConfig::default();
+	let ban_window = config.ban_window();
+	let server = p2p::Server::new(
+		test_dir("prune_bans"),
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let now = Utc::now();
+	let expired = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let active = PeerAddr::Ip("10.0.0.2:3414".parse().unwrap());
+	let expired_at = (now - Duration::seconds(ban_window + 60)).timestamp();
+	let active_at = (now - Duration::seconds(ban_window - 60)).timestamp();
+	server
+		.peers
+		.save_peer(&banned_peer(expired.clone(), expired_at))
+		.unwrap();
+	server
+		.peers
+		.save_peer(&banned_peer(active.clone(), active_at))
+		.unwrap();
+
+	assert_eq!(server.peers.prune_expired_bans(now), 1);
+	assert!(server.peers.get_peer(expired).is_err());
+	assert!(server.peers.is_banned(active.clone()));
+
+	// Nothing left to prune until the remaining ban expires.
+	assert_eq!(server.peers.prune_expired_bans(now), 0);
+	assert_eq!(
+		server
+			.peers
+			.prune_expired_bans(now + Duration::seconds(120)),
+		1
+	);
+	assert!(server.peers.get_peer(active).is_err());
+}
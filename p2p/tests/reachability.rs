@@ -0,0 +1,164 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+
+// Each node probing its reachability on its own loopback ip, peers only
+// check an ip once a minute.
+fn local_config(ip: &str, reachability_probe_interval: Option<u64>) -> p2p::P2PConfig {
+	let ip: IpAddr = ip.parse().unwrap();
+	p2p::P2PConfig {
+		host: ip,
+		port: open_port(),
+		outbound_bind: Some(ip),
+		reachability_probe_interval,
+		..p2p::P2PConfig::default()
+	}
+}
+
+fn listen(server: &Arc<p2p::Server>) {
+	let server = server.clone();
+	let _ = thread::spawn(move || server.listen(100_000));
+}
+
+fn addr_of(server: &p2p::Server) -> PeerAddr {
+	PeerAddr::Ip(SocketAddr::new(server.config.host, server.config.port))
+}
+
+fn wait_for_answer(server: &p2p::Server) -> Option<bool> {
+	for _ in 0..100 {
+		if server.self_reachable().is_some() {
+			break;
+		}
+		thread::sleep(time::Duration::from_millis(100));
+	}
+	server.self_reachable()
+}
+
+// A node probing its reachability only advertises its listener once peers
+// could connect back to it, a node whose listener can't be reached keeps
+// telling peers it doesn't accept connections. Several peers must agree.
+#[test]
+fn reachability_probe() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let checker = Arc::new(new_server(
+		test_dir("reachability_checker"),
+		local_config("127.0.0.1", None),
+	));
+	let other_checker = Arc::new(new_server(
+		test_dir("reachability_checker2"),
+		local_config("127.0.0.1", None),
+	));
+	let late_peer = Arc::new(new_server(
+		test_dir("reachability_late_peer"),
+		local_config("127.0.0.1", None),
+	));
+	listen(&checker);
+	listen(&other_checker);
+	listen(&late_peer);
+
+	// Reachable node, listening on its port.
+	let reachable = Arc::new(new_server(
+		test_dir("reachability_reachable"),
+		local_config("127.0.0.2", Some(600)),
+	));
+	listen(&reachable);
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(reachable.self_reachable(), None);
+	assert!(!reachable.advertises_listener());
+
+	reachable.connect(addr_of(&checker), 100_000).unwrap();
+	reachable.connect(addr_of(&other_checker), 100_000).unwrap();
+	// Our real address, flagged as not accepting connections.
+	let seen = checker
+		.peers
+		.get_connected_peer(addr_of(&reachable))
+		.unwrap();
+	assert!(!seen.info.inbound_reachable);
+
+	assert!(reachable.probe_reachability(Instant::now()));
+	// Only once per interval.
+	assert!(!reachable.probe_reachability(Instant::now()));
+	assert_eq!(wait_for_answer(&reachable), Some(true));
+	assert!(reachable.advertises_listener());
+
+	// Answers to checks we didn't ask for are ignored.
+	reachable.reachability_checked(&addr_of(&other_checker), 42, false);
+	reachable.reachability_checked(&addr_of(&checker), 42, false);
+	assert_eq!(reachable.self_reachable(), Some(true));
+
+	// Now confirmed, new connections advertise the listener.
+	reachable.connect(addr_of(&late_peer), 100_000).unwrap();
+	let seen = late_peer
+		.peers
+		.get_connected_peer(addr_of(&reachable))
+		.unwrap();
+	assert!(seen.info.inbound_reachable);
+
+	// Unreachable node, nothing listens on its port. A single answer isn't
+	// enough to decide.
+	let unreachable = Arc::new(new_server(
+		test_dir("reachability_unreachable"),
+		local_config("127.0.0.3", Some(600)),
+	));
+	unreachable.connect(addr_of(&checker), 100_000).unwrap();
+	let now = Instant::now();
+	assert!(unreachable.probe_reachability(now));
+	thread::sleep(time::Duration::from_secs(2));
+	assert_eq!(unreachable.self_reachable(), None);
+
+	// The first peer won't check us again this soon, the others agree.
+	unreachable
+		.connect(addr_of(&other_checker), 100_000)
+		.unwrap();
+	unreachable.connect(addr_of(&late_peer), 100_000).unwrap();
+	assert!(unreachable.probe_reachability(now + time::Duration::from_secs(601)));
+	assert_eq!(wait_for_answer(&unreachable), Some(false));
+	assert!(!unreachable.advertises_listener());
+
+	// Without probes we always advertise our listener.
+	let unprobed = Arc::new(new_server(
+		test_dir("reachability_unprobed"),
+		local_config("127.0.0.1", None),
+	));
+	assert!(!unprobed.probe_reachability(Instant::now()));
+	assert!(unprobed.advertises_listener());
+
+	for server in &[
+		checker,
+		other_checker,
+		late_peer,
+		reachable,
+		unreachable,
+		unprobed,
+	] {
+		server.stop();
+	}
+}
@@ -0,0 +1,127 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{NetAdapter, PeerAddr};
+use crate::p2p::{Capabilities, Peer};
+
+fn config(port: u16) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		..p2p::P2PConfig::default()
+	}
+}
+
+fn start_server(db_root: &str, config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	let server = Arc::new(new_server(db_root, config));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	server
+}
+
+fn local_addr(port: u16) -> PeerAddr {
+	PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
+}
+
+// Connect to the server from a client only dialing out, advertising port 0.
+fn connect_unreachable(server_config: &p2p::P2PConfig, client_root: &str) -> Peer {
+	let client = new_server(client_root, config(open_port()));
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		local_addr(0),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap()
+}
+
+fn reachable_and_unreachable(
+	db_root: &str,
+	gossip_reachable_only: Option<bool>,
+) -> (Arc<p2p::Server>, PeerAddr, Peer) {
+	let server_config = p2p::P2PConfig {
+		gossip_reachable_only,
+		..config(open_port())
+	};
+	let server = start_server(db_root, server_config.clone());
+	let remote_port = open_port();
+	start_server(&format!("{}_remote", db_root), config(remote_port));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let reachable = local_addr(remote_port);
+	server.connect(reachable.clone(), 100_000).unwrap();
+	let unreachable = connect_unreachable(&server_config, &format!("{}_client", db_root));
+	thread::sleep(time::Duration::from_millis(500));
+	(server, reachable, unreachable)
+}
+
+// Peers that connected to us without a listener aren't passed on, the ones
+// we could connect to are.
+#[test]
+fn unreachable_peer_not_gossiped() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, reachable, _unreachable) =
+		reachable_and_unreachable(test_dir("reachable_peers"), None);
+
+	let reachable_info = server.peers.get_connected_peer(reachable.clone()).unwrap();
+	assert!(reachable_info.info.inbound_reachable);
+	let unreachable_info = server.peers.get_connected_peer(local_addr(0)).unwrap();
+	assert!(!unreachable_info.info.inbound_reachable);
+
+	let addrs = server.peers.find_peer_addrs(Capabilities::UNKNOWN);
+	assert_eq!(addrs, vec![reachable]);
+
+	server.stop();
+}
+
+// With the preference off every healthy peer is passed on.
+#[test]
+fn unreachable_peer_gossiped_when_allowed() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let (server, reachable, _unreachable) =
+		reachable_and_unreachable(test_dir("reachable_peers_all"), Some(false));
+
+	let addrs = server.peers.find_peer_addrs(Capabilities::UNKNOWN);
+	assert_eq!(addrs.len(), 2);
+	assert!(addrs.contains(&reachable));
+	assert!(addrs.contains(&local_addr(0)));
+
+	server.stop();
+}
@@ -0,0 +1,147 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::p2p::{ReadBudget, LIGHT_READ_BYTES};
+
+// Once the budget is used up, large messages wait for room while small ones
+// keep getting read.
+#[test]
+fn heavy_reads_pause_light_reads_continue() {
+	let budget = ReadBudget::new(1_000_000);
+
+	let heavy = budget.try_reserve(600_000).unwrap();
+	assert_eq!(budget.used(), 600_000);
+	assert!(budget.try_reserve(600_000).is_none());
+
+	let light = budget.try_reserve(LIGHT_READ_BYTES).unwrap();
+	assert_eq!(budget.used(), 600_000 + LIGHT_READ_BYTES);
+	drop(light);
+	assert_eq!(budget.used(), 600_000);
+
+	drop(heavy);
+	assert_eq!(budget.used(), 0);
+	assert!(budget.try_reserve(600_000).is_some());
+}
+
+// A paused reader goes on as soon as the room it needs is given back, or
+// gives up when its connection is stopped.
+#[test]
+fn paused_reader_resumes_or_stops() {
+	let budget = Arc::new(ReadBudget::new(1_000_000));
+	let stopped = Arc::new(AtomicBool::new(false));
+	let heavy = budget.try_reserve(600_000).unwrap();
+
+	let reader = {
+		let budget = budget.clone();
+		let stopped = stopped.clone();
+		thread::spawn(move || budget.reserve(600_000, &stopped).map(drop))
+	};
+	while budget.paused() == 0 {
+		thread::sleep(Duration::from_millis(5));
+	}
+	drop(heavy);
+	assert!(reader.join().unwrap().is_some());
+	assert_eq!(budget.paused(), 0);
+	assert_eq!(budget.used(), 0);
+
+	let _heavy = budget.try_reserve(600_000).unwrap();
+	let reader = {
+		let budget = budget.clone();
+		let stopped = stopped.clone();
+		thread::spawn(move || budget.reserve(600_000, &stopped).map(drop))
+	};
+	while budget.paused() == 0 {
+		thread::sleep(Duration::from_millis(5));
+	}
+	stopped.store(true, Ordering::Relaxed);
+	assert!(reader.join().unwrap().is_none());
+	assert_eq!(budget.paused(), 0);
+}
+
+// No limit configured, everything gets read.
+#[test]
+fn zero_limit_never_pauses() {
+	let budget = ReadBudget::new(0);
+	let _a = budget.try_reserve(10_000_000).unwrap();
+	assert!(budget.try_reserve(10_000_000).is_some());
+}
+
+// A body larger than the whole budget is still read once nothing else is,
+// instead of stalling forever.
+#[test]
+fn oversized_read_proceeds_alone() {
+	let budget = ReadBudget::new(1_000_000);
+	let stopped = AtomicBool::new(false);
+
+	let oversized = budget.reserve(2_000_000, &stopped).unwrap();
+	assert_eq!(budget.used(), 2_000_000);
+	assert!(budget.try_reserve(600_000).is_none());
+	drop(oversized);
+	assert!(budget.try_reserve(600_000).is_some());
+}
+
+// Room given back goes to the smallest paused read, the largest one keeps
+// waiting until there's enough for it.
+#[test]
+fn largest_paused_reads_wait_longest() {
+	let budget = Arc::new(ReadBudget::new(1_000_000));
+	let stopped = Arc::new(AtomicBool::new(false));
+	let heavy = budget.try_reserve(600_000).unwrap();
+
+	let (got_tx, got_rx) = mpsc::channel();
+	let (release_tx, release_rx) = mpsc::channel::<()>();
+	let spawn_reader = |bytes: u64, release: Option<mpsc::Receiver<()>>| {
+		let budget = budget.clone();
+		let stopped = stopped.clone();
+		let got_tx = got_tx.clone();
+		thread::spawn(move || {
+			let reservation = budget.reserve(bytes, &stopped).unwrap();
+			got_tx.send(bytes).unwrap();
+			if let Some(release) = release {
+				release.recv().unwrap();
+			}
+			drop(reservation);
+		})
+	};
+	let wait_paused = |count: usize| {
+		while budget.paused() < count {
+			thread::sleep(Duration::from_millis(5));
+		}
+	};
+
+	let largest = spawn_reader(900_000, None);
+	wait_paused(1);
+	let smaller = spawn_reader(500_000, Some(release_rx));
+	wait_paused(2);
+
+	drop(heavy);
+	assert_eq!(got_rx.recv().unwrap(), 500_000);
+	thread::sleep(Duration::from_millis(100));
+	assert!(got_rx.try_recv().is_err());
+	assert_eq!(budget.paused(), 1);
+
+	release_tx.send(()).unwrap();
+	assert_eq!(got_rx.recv().unwrap(), 900_000);
+	smaller.join().unwrap();
+	largest.join().unwrap();
+	assert_eq!(budget.used(), 0);
+}
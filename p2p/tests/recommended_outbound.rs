@@ -0,0 +1,56 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use crate::p2p::types::{
+	recommended_outbound_count, MAX_RECOMMENDED_OUTBOUND_COUNT, MIN_RECOMMENDED_OUTBOUND_COUNT,
+};
+
+// Small networks get the lower bound, a medium one the default of 8 and huge
+// ones are capped.
+#[test]
+fn recommended_outbound_bounds() {
+	assert_eq!(
+		recommended_outbound_count(0),
+		MIN_RECOMMENDED_OUTBOUND_COUNT
+	);
+	assert_eq!(
+		recommended_outbound_count(5),
+		MIN_RECOMMENDED_OUTBOUND_COUNT
+	);
+	assert_eq!(recommended_outbound_count(100), 8);
+	assert_eq!(recommended_outbound_count(10_000), 15);
+	assert_eq!(
+		recommended_outbound_count(usize::max_value()),
+		MAX_RECOMMENDED_OUTBOUND_COUNT
+	);
+}
+
+// The recommendation never decreases as the network grows.
+#[test]
+fn recommended_outbound_monotonic() {
+	let mut prev = recommended_outbound_count(0);
+	let mut size = 1;
+	while size < usize::max_value() / 2 {
+		for n in [size, size + size / 2].iter() {
+			let count = recommended_outbound_count(*n);
+			assert!(count >= prev);
+			assert!(count >= MIN_RECOMMENDED_OUTBOUND_COUNT);
+			assert!(count <= MAX_RECOMMENDED_OUTBOUND_COUNT);
+			prev = count;
+		}
+		size *= 2;
+	}
+}
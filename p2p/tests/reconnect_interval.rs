@@ -0,0 +1,123 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use chrono::prelude::Utc;
+use chrono::Duration;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::msg::PeerAddrs;
+use crate::p2p::types::PeerAddr;
+
+fn config(port: u16) -> p2p::P2PConfig {
+	p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port,
+		..p2p::P2PConfig::default()
+	}
+}
+
+fn start_server(db_root: &str, config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	server
+}
+
+fn local_addr(port: u16) -> PeerAddr {
+	PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port))
+}
+
+// Connect to the remote and disconnect from it right away.
+fn connect_and_drop(server: &p2p::Server, remote: &PeerAddr) {
+	server.connect(remote.clone(), 100_000).unwrap();
+	thread::sleep(time::Duration::from_millis(500));
+	server
+		.peers
+		.get_connected_peer(remote.clone())
+		.unwrap()
+		.stop();
+	thread::sleep(time::Duration::from_millis(500));
+	server.peers.clean_peers(128, 128, &[]);
+	assert!(server.peers.get_connected_peer(remote.clone()).is_none());
+}
+
+// A peer we just got disconnected from is only redialed once the interval
+// elapsed, a shorter one for preferred peers. Peers we never got disconnected
+// from, or long ago, can be dialed right away.
+#[test]
+fn redial_deferred() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let remotes: Vec<PeerAddr> = (0..3)
+		.map(|i| {
+			let port = open_port();
+			start_server(test_dir(&format!("reconnect_interval_{}", i)), config(port));
+			local_addr(port)
+		})
+		.collect();
+	let server = start_server(
+		test_dir("reconnect_interval"),
+		p2p::P2PConfig {
+			peers_preferred: Some(PeerAddrs {
+				peers: vec![remotes[1].clone()],
+			}),
+			peer_min_reconnect_interval: Some(60),
+			peer_min_preferred_reconnect_interval: Some(10),
+			..config(open_port())
+		},
+	);
+	thread::sleep(time::Duration::from_secs(1));
+
+	connect_and_drop(&server, &remotes[0]);
+	connect_and_drop(&server, &remotes[1]);
+	let now = Utc::now();
+
+	assert!(!server.peers.reconnect_allowed(&remotes[0], now));
+	assert!(!server.peers.reconnect_allowed(&remotes[1], now));
+	assert!(server.peers.reconnect_allowed(&remotes[2], now));
+
+	let later = now + Duration::seconds(11);
+	assert!(!server.peers.reconnect_allowed(&remotes[0], later));
+	assert!(server.peers.reconnect_allowed(&remotes[1], later));
+
+	let much_later = now + Duration::seconds(61);
+	assert!(server.peers.reconnect_allowed(&remotes[0], much_later));
+	assert!(server.peers.reconnect_allowed(&remotes[1], much_later));
+}
@@ -0,0 +1,173 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use chrono::Utc;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, server_with_adapter, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer, PeerData, ReasonForBan, State};
+
+// Connect a client to the server, announcing the provided port as its own and
+// handshaking with the provided config.
+fn connect(
+	server_config: &p2p::P2PConfig,
+	client_config: &p2p::P2PConfig,
+	client: &p2p::Server,
+	port: u16,
+) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), client_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// Capabilities changed at runtime are advertised to new peers and pushed to
+// the connected ones recent enough to understand the update.
+#[test]
+fn set_capabilities_at_runtime() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(server_with_adapter(
+		test_dir("runtime_capabilities"),
+		Capabilities::FULL_NODE,
+		p2p_config.clone(),
+		Arc::new(p2p::DummyAdapter {}),
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.capabilities(), Capabilities::FULL_NODE);
+
+	let client = new_server(
+		test_dir("runtime_capabilities_client"),
+		p2p::P2PConfig::default(),
+	);
+	let old_config = p2p::P2PConfig {
+		protocol_version: Some(3),
+		..p2p_config.clone()
+	};
+	let peer = connect(&p2p_config, &p2p_config, &client, 5000);
+	let old_peer = connect(&p2p_config, &old_config, &client, 5001);
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(peer.info.current_capabilities(), Capabilities::FULL_NODE);
+	assert_eq!(old_peer.info.version, ProtocolVersion(3));
+
+	let reduced = Capabilities::FULL_NODE - Capabilities::TXHASHSET_HIST;
+	server.set_capabilities(reduced);
+	assert_eq!(server.capabilities(), reduced);
+	assert_eq!(server.effective_capabilities(), reduced);
+	thread::sleep(time::Duration::from_millis(500));
+
+	// The handshake capabilities are kept, the update applies on top.
+	assert_eq!(peer.info.capabilities, Capabilities::FULL_NODE);
+	assert_eq!(peer.info.current_capabilities(), reduced);
+	assert_eq!(
+		old_peer.info.current_capabilities(),
+		Capabilities::FULL_NODE
+	);
+
+	let new_peer = connect(&p2p_config, &p2p_config, &client, 5002);
+	assert_eq!(new_peer.info.capabilities, reduced);
+}
+
+// Capabilities a peer keeps changing always apply to its connection, but are
+// written to our peer store only once in a while.
+#[test]
+fn capabilities_updates_saved_sparingly() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(server_with_adapter(
+		test_dir("capabilities_saved"),
+		Capabilities::FULL_NODE,
+		p2p_config.clone(),
+		Arc::new(p2p::DummyAdapter {}),
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(
+		test_dir("capabilities_saved_client"),
+		p2p::P2PConfig::default(),
+	);
+	let peer = connect(&p2p_config, &p2p_config, &client, 5000);
+	client
+		.peers
+		.save_peer(&PeerData {
+			addr: peer.info.addr.clone(),
+			capabilities: Capabilities::FULL_NODE,
+			user_agent: "test".to_string(),
+			flags: State::Healthy,
+			last_banned: 0,
+			ban_reason: ReasonForBan::None,
+			last_connected: Utc::now().timestamp(),
+			score: 0,
+		})
+		.unwrap();
+	let stored = || {
+		client
+			.peers
+			.get_peer(peer.info.addr.clone())
+			.unwrap()
+			.capabilities
+	};
+
+	let reduced = Capabilities::FULL_NODE - Capabilities::TXHASHSET_HIST;
+	server.set_capabilities(reduced);
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(peer.info.current_capabilities(), reduced);
+	assert_eq!(stored(), reduced);
+
+	let no_peer_list = Capabilities::FULL_NODE - Capabilities::PEER_LIST;
+	server.set_capabilities(no_peer_list);
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(peer.info.current_capabilities(), no_peer_list);
+	assert_eq!(stored(), reduced);
+}
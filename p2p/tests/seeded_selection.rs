@@ -0,0 +1,84 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{healthy_peer, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::State;
+
+fn seeded_server(db_root: &str, seed: u64) -> p2p::Server {
+	let config = p2p::P2PConfig {
+		rng_seed: Some(seed),
+		..p2p::P2PConfig::default()
+	};
+	let server = p2p::Server::new(
+		db_root,
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	for i in 1..=20 {
+		let addr = PeerAddr::Ip(format!("10.0.0.{}:3414", i).parse().unwrap());
+		server.peers.save_peer(&healthy_peer(addr)).unwrap();
+	}
+	server
+}
+
+fn select(server: &p2p::Server) -> Vec<String> {
+	server
+		.peers
+		.find_peers(State::Healthy, p2p::Capabilities::UNKNOWN, 5)
+		.iter()
+		.map(|p| p.addr.to_string())
+		.collect()
+}
+
+// Two servers with the same seed and the same candidates pick the same peers,
+// call after call.
+#[test]
+fn seeded_peer_selection() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let server1 = seeded_server(test_dir("seeded_selection_1"), 42);
+	let server2 = seeded_server(test_dir("seeded_selection_2"), 42);
+
+	for _ in 0..3 {
+		let selected = select(&server1);
+		assert_eq!(selected.len(), 5);
+		assert_eq!(selected, select(&server2));
+	}
+
+	let mut items1: Vec<u32> = (0..50).collect();
+	let mut items2 = items1.clone();
+	server1.peers.shuffle(&mut items1);
+	server2.peers.shuffle(&mut items2);
+	assert_eq!(items1, items2);
+}
@@ -0,0 +1,122 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::msg::PeerAddrs;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+const SELF_ONION: &str = "ourselvesqbvwd7sm6vhbc7uwmg5kk4dv4nqmkthp6v7wfgt5q3n7ag2ad.onion";
+
+// Our own onion address is recognized as ourselves even when it's denied,
+// connecting to it is refused as a connection with self and a peer claiming
+// it gets disconnected without anybody being banned.
+#[test]
+fn self_onion_is_not_a_peer() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let self_onion = PeerAddr::Onion(SELF_ONION.to_string());
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_deny: Some(PeerAddrs {
+			peers: vec![self_onion.clone()],
+		}),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("self_onion"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			Some(SELF_ONION.to_string()),
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	assert!(server.is_self(&self_onion));
+	assert!(!server.is_self(&PeerAddr::Onion("someoneelse.onion".to_string())));
+	match server.connect(self_onion.clone(), 100_000) {
+		Err(p2p::Error::PeerWithSelf) => {}
+		res => panic!("unexpected result {:?}", res.map(|p| p.info.addr.clone())),
+	}
+
+	// A connection announcing our own onion address as its own.
+	let client_config = p2p::P2PConfig {
+		port: open_port(),
+		..p2p_config.clone()
+	};
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let client = p2p::Server::new(
+		test_dir("self_onion_client"),
+		Capabilities::FULL_NODE,
+		client_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		Capabilities::FULL_NODE,
+		Difficulty::min(),
+		PeerAddr::Ip(addr),
+		&p2p::handshake::Handshake::new(
+			Hash::from_vec(&vec![]),
+			client_config.clone(),
+			Some(SELF_ONION.to_string()),
+		),
+		net_adapter,
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	assert!(!server.peers.is_banned(self_onion.clone()));
+	assert!(server.peers.get_peer(self_onion).is_err());
+	assert!(!server.peers.is_banned(PeerAddr::Ip(SocketAddr::new(
+		client_config.host,
+		client_config.port
+	))));
+	assert!(!peer.is_banned());
+}
@@ -12,10 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use grin_core as core;
 use grin_p2p as p2p;
 
 use num::FromPrimitive;
 
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::msg::{read_header, read_locator, Locator, MsgHeader, Type};
+
 // Test that Healthy == 0.
 #[test]
 fn test_store_state_enum() {
@@ -78,3 +84,77 @@ fn test_capabilities() {
 			.contains(p2p::types::Capabilities::TX_KERNEL_HASH)
 	);
 }
+
+#[test]
+fn test_locator_len() {
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let locator = Locator {
+		hashes: vec![Hash::default(); p2p::MAX_LOCATORS as usize],
+		count: Some(p2p::MAX_BLOCK_HEADERS),
+	};
+	let body = ser::ser_vec(&locator, ProtocolVersion::local()).unwrap();
+	let header = MsgHeader::new(Type::GetHeaders, body.len() as u64);
+	let read = read_locator(&header, &mut &body[..], ProtocolVersion::local()).unwrap();
+	assert_eq!(read.hashes.len(), p2p::MAX_LOCATORS as usize);
+
+	// One hash too many, the locator writer refuses this so build it by hand.
+	let len = p2p::MAX_LOCATORS as usize + 1;
+	let mut body = vec![len as u8];
+	body.extend(vec![0u8; 32 * len]);
+	let header = MsgHeader::new(Type::GetHeaders, body.len() as u64);
+	match read_locator(&header, &mut &body[..], ProtocolVersion::local()) {
+		Err(p2p::Error::MsgLen) => {}
+		res => panic!("expected MsgLen, got {:?}", res),
+	}
+}
+
+// The number of headers asked for only goes to peers knowing about it, older
+// ones read the locator as they always did.
+#[test]
+fn test_locator_count() {
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let locator = Locator {
+		hashes: vec![Hash::default(); 3],
+		count: Some(32),
+	};
+	let body = ser::ser_vec(&locator, ProtocolVersion(4)).unwrap();
+	let header = MsgHeader::new(Type::GetHeaders, body.len() as u64);
+	let read = read_locator(&header, &mut &body[..], ProtocolVersion(4)).unwrap();
+	assert_eq!(read.hashes.len(), 3);
+	assert_eq!(read.count, Some(32));
+
+	let body = ser::ser_vec(&locator, ProtocolVersion(3)).unwrap();
+	assert_eq!(body.len(), 1 + 32 * 3);
+	let header = MsgHeader::new(Type::GetHeaders, body.len() as u64);
+	let read = read_locator(&header, &mut &body[..], ProtocolVersion(3)).unwrap();
+	assert_eq!(read.hashes.len(), 3);
+	assert_eq!(read.count, None);
+}
+
+// A message declaring a body larger than the configured cap is refused when
+// reading its header, the body is never read (or allocated).
+#[test]
+fn test_max_message_size() {
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+
+	let config = p2p::P2PConfig {
+		max_message_size: Some(1_000),
+		..p2p::P2PConfig::default()
+	};
+	let max_msg_size = config.max_message_size();
+
+	let header = MsgHeader::new(Type::Block, max_msg_size);
+	let bytes = ser::ser_vec(&header, ProtocolVersion::local()).unwrap();
+	assert!(read_header(&mut &bytes[..], ProtocolVersion::local(), max_msg_size).is_ok());
+
+	// No body follows, reading it would fail with an io error instead.
+	let header = MsgHeader::new(Type::Block, max_msg_size + 1);
+	let bytes = ser::ser_vec(&header, ProtocolVersion::local()).unwrap();
+	match read_header(&mut &bytes[..], ProtocolVersion::local(), max_msg_size) {
+		Err(p2p::Error::MsgLen) => {}
+		Err(e) => panic!("expected MsgLen, got {:?}", e),
+		Ok(_) => panic!("expected MsgLen"),
+	}
+}
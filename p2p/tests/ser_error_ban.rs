@@ -0,0 +1,73 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::sync::Arc;
+
+mod common;
+
+use self::common::{healthy_peer, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::p2p::types::{NetAdapter, PeerAddr};
+
+// A single malformed message is only logged, a peer repeatedly sending them
+// gets banned once it reaches the threshold.
+#[test]
+fn repeated_ser_errors_banned() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let config = p2p::P2PConfig {
+		ser_error_ban_threshold: Some(3),
+		..p2p::P2PConfig::default()
+	};
+	let server = p2p::Server::new(
+		test_dir("ser_error_ban"),
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let glitchy = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let broken = PeerAddr::Ip("10.0.0.2:3414".parse().unwrap());
+	server
+		.peers
+		.save_peer(&healthy_peer(glitchy.clone()))
+		.unwrap();
+	server
+		.peers
+		.save_peer(&healthy_peer(broken.clone()))
+		.unwrap();
+
+	server.peers.peer_ser_error(glitchy.clone());
+	assert!(!server.peers.is_banned(glitchy.clone()));
+
+	server.peers.peer_ser_error(broken.clone());
+	server.peers.peer_ser_error(broken.clone());
+	assert!(!server.peers.is_banned(broken.clone()));
+	server.peers.peer_ser_error(broken.clone());
+	assert!(server.peers.is_banned(broken));
+	assert!(!server.peers.is_banned(glitchy));
+}
@@ -0,0 +1,134 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, DeclineRecorder, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::msg::{DeclineReason, Type};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+/// Adapter counting the txhashset archive requests it gets to serve.
+struct ArchiveAdapter {
+	archive_calls: AtomicUsize,
+}
+
+impl TestChain for ArchiveAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn txhashset_archive_header(&self) -> Result<core::core::BlockHeader, chain::Error> {
+		self.archive_calls.fetch_add(1, Ordering::SeqCst);
+		Err(chain::ErrorKind::Other("no archive".to_string()).into())
+	}
+}
+
+// A server not serving txhashset archives doesn't advertise TXHASHSET_HIST
+// and declines archive requests without touching the chain, telling the
+// peer so.
+#[test]
+fn serve_txhashset_disabled() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		serve_txhashset: Some(false),
+		..p2p::P2PConfig::default()
+	};
+	let adapter = Arc::new(TestAdapter(ArchiveAdapter {
+		archive_calls: AtomicUsize::new(0),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("serve_txhashset"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	assert_eq!(
+		server.effective_capabilities(),
+		Capabilities::FULL_NODE - Capabilities::TXHASHSET_HIST
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let net_adapter = Arc::new(TestAdapter(DeclineRecorder::default()));
+	let client = p2p::Server::new(
+		test_dir("serve_txhashset_client"),
+		Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		net_adapter.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+	assert!(!peer
+		.info
+		.capabilities
+		.contains(Capabilities::TXHASHSET_HIST));
+	assert!(peer.info.capabilities.contains(Capabilities::HEADER_HIST));
+
+	peer.send_txhashset_request(0, Hash::from_vec(&vec![]))
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(adapter.archive_calls.load(Ordering::SeqCst), 0);
+	assert_eq!(
+		*net_adapter.declined.lock().unwrap(),
+		vec![(Type::TxHashSetRequest, DeclineReason::NotServed)]
+	);
+}
@@ -0,0 +1,109 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use chrono::{Duration, Utc};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Peer, SilentPeerPolicy};
+
+// Connect a client to the server, announcing the provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// A peer that never reports its difficulty nor height past the grace period
+// is flagged as silent and evicted, one that reported them is kept.
+#[test]
+fn silent_peer_evicted() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let default_config = p2p::P2PConfig::default();
+	assert_eq!(default_config.silent_peer_grace(), Duration::seconds(120));
+	assert_eq!(
+		default_config.silent_peer_policy(),
+		SilentPeerPolicy::Deprioritize
+	);
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		silent_peer_grace_secs: Some(1),
+		silent_peer_policy: Some(SilentPeerPolicy::Evict),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(test_dir("silent_peers"), p2p_config.clone()));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = new_server(test_dir("silent_peers_client"), p2p::P2PConfig::default());
+	let _silent = connect(&p2p_config, &client, 5000);
+	let _reporting = connect(&p2p_config, &client, 5001);
+	thread::sleep(time::Duration::from_millis(500));
+	assert_eq!(server.peers.peer_inbound_count(), 2);
+
+	let silent_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
+	let reporting_addr = PeerAddr::Ip("127.0.0.1:5001".parse().unwrap());
+	let reporting = server
+		.peers
+		.get_connected_peer(reporting_addr.clone())
+		.unwrap();
+	reporting.info.update(10, Difficulty::from_num(1_000), 0);
+
+	// Nobody is silent within the grace period.
+	assert!(server.peers.silent_peers(Utc::now()).is_empty());
+	server.peers.clean_peers(128, 8, &[]);
+	assert_eq!(server.peers.peer_inbound_count(), 2);
+
+	thread::sleep(time::Duration::from_millis(1500));
+	assert_eq!(
+		server.peers.silent_peers(Utc::now()),
+		vec![silent_addr.clone()]
+	);
+	let sync_peers = server.peers.sync_peers();
+	assert!(sync_peers.iter().all(|p| p.info.addr != silent_addr));
+
+	server.peers.clean_peers(128, 8, &[]);
+	assert_eq!(server.peers.peer_inbound_count(), 1);
+	assert!(server.peers.get_connected_peer(silent_addr).is_none());
+	assert!(server.peers.get_connected_peer(reporting_addr).is_some());
+}
@@ -0,0 +1,137 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::msg::{self, Hand, MsgHeader, Shake, Type};
+use crate::p2p::types::PeerAddr;
+
+fn start_server(db_root: &str, strict_protocol: bool) -> (Arc<p2p::Server>, SocketAddr) {
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		strict_protocol: Some(strict_protocol),
+		..p2p::P2PConfig::default()
+	};
+	let addr = SocketAddr::new(config.host, config.port);
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let inner = server.clone();
+	let _ = thread::spawn(move || inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	(server, addr)
+}
+
+fn write_msg(stream: &mut TcpStream, header: Vec<u8>, body: Vec<u8>) {
+	stream.write_all(&header).unwrap();
+	stream.write_all(&body).unwrap();
+}
+
+// Connects to the server and runs through the hand/shake by hand, so we can
+// write whatever we want on the connection afterwards.
+fn handshake(addr: SocketAddr) -> TcpStream {
+	let version = ProtocolVersion::local();
+	let mut stream = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let hand = Hand {
+		version,
+		capabilities: p2p::Capabilities::UNKNOWN,
+		nonce: 42,
+		genesis: Hash::from_vec(&vec![]),
+		total_difficulty: Difficulty::min(),
+		sender_addr: PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		receiver_addr: PeerAddr::Ip(addr),
+		user_agent: "test".to_string(),
+		uptime: None,
+		timestamp: None,
+		listening: None,
+	};
+	let body = ser::ser_vec(&hand, version).unwrap();
+	let header = ser::ser_vec(&MsgHeader::new(Type::Hand, body.len() as u64), version).unwrap();
+	write_msg(&mut stream, header, body);
+	let _: Shake = msg::read_message(&mut stream, version, Type::Shake).unwrap();
+	stream
+}
+
+// A well-framed message of a type no version of the protocol knows yet.
+fn send_unknown(stream: &mut TcpStream) {
+	let version = ProtocolVersion::local();
+	let body = vec![7u8; 16];
+	let mut header = ser::ser_vec(&MsgHeader::new(Type::Ping, body.len() as u64), version).unwrap();
+	// the type byte follows the 2 magic bytes
+	header[2] = 200;
+	write_msg(stream, header, body);
+	thread::sleep(time::Duration::from_secs(1));
+}
+
+fn setup() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+}
+
+// By default unknown message types are skipped, as coming from a newer peer.
+#[test]
+fn unknown_message_skipped() {
+	setup();
+	let (server, addr) = start_server(test_dir("strict_protocol_off"), false);
+
+	let mut stream = handshake(addr);
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_count(), 1);
+
+	send_unknown(&mut stream);
+	assert_eq!(server.peers.peer_count(), 1);
+	let peer_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
+	assert!(!server.peers.is_banned(peer_addr));
+}
+
+// In strict mode the connection is closed instead.
+#[test]
+fn unknown_message_strict() {
+	setup();
+	let (server, addr) = start_server(test_dir("strict_protocol_on"), true);
+
+	let mut stream = handshake(addr);
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_count(), 1);
+
+	send_unknown(&mut stream);
+	assert_eq!(server.peers.peer_count(), 0);
+}
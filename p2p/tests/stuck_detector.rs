@@ -0,0 +1,105 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::{RwLock, StopState};
+
+use chrono::prelude::Utc;
+use chrono::Duration;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+mod common;
+
+use self::common::test_dir;
+use crate::core::core::hash::Hash;
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::{PeerAddr, PeerLiveInfo};
+use crate::p2p::{ChainAdapter, PeerInfo};
+
+// A peer whose difficulty last changed 3 hours ago, long enough to be kicked.
+fn stale_peer_info(addr: PeerAddr) -> PeerInfo {
+	let live_info = PeerLiveInfo::new(Difficulty::min());
+	let info = PeerInfo {
+		capabilities: p2p::Capabilities::UNKNOWN,
+		user_agent: "test".to_string(),
+		peer_uptime: None,
+		version: ProtocolVersion::local(),
+		addr,
+		direction: p2p::Direction::Outbound,
+		inbound_reachable: true,
+		node_nonce: None,
+		negotiation: None,
+		live_info: Arc::new(RwLock::new(live_info)),
+		header_sync_requested: Arc::new(AtomicUsize::new(0)),
+		last_header: Arc::new(Mutex::new(Instant::now())),
+		last_header_reset: Arc::new(Mutex::new(Instant::now())),
+	};
+	info.live_info.write().stuck_detector = Utc::now() - Duration::hours(3);
+	info
+}
+
+fn is_stale(info: &PeerInfo) -> bool {
+	info.live_info.read().stuck_detector < Utc::now() - Duration::hours(2)
+}
+
+// Pings with an unchanged difficulty don't refresh the detector, progress does.
+#[test]
+fn note_progress_refreshes() {
+	let info = stale_peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+	info.update(10, Difficulty::min(), std::u64::MAX);
+	assert!(is_stale(&info));
+
+	info.note_progress();
+	assert!(!is_stale(&info));
+}
+
+// A peer serving valid headers isn't stuck, even before its advertised
+// difficulty catches up.
+#[test]
+fn valid_headers_refresh() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let config = p2p::P2PConfig {
+		minority_fork_threshold: Some(0),
+		..p2p::P2PConfig::default()
+	};
+	let server = p2p::Server::new(
+		test_dir("stuck_detector"),
+		p2p::Capabilities::UNKNOWN,
+		config,
+		Arc::new(p2p::DummyAdapter {}),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let info = stale_peer_info(PeerAddr::Ip("10.0.0.2:3414".parse().unwrap()));
+	assert!(server
+		.peers
+		.headers_received(&[BlockHeader::default()], &info, 0)
+		.unwrap());
+	assert!(!is_stale(&info));
+	assert_eq!(info.total_difficulty(), Difficulty::min());
+}
@@ -0,0 +1,144 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::{RwLock, StopState};
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, Peer};
+
+/// Adapter reporting whatever sync status the test sets.
+struct SyncingAdapter {
+	status: RwLock<chain::SyncStatus>,
+}
+
+impl TestChain for SyncingAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn sync_status(&self) -> chain::SyncStatus {
+		*self.status.read()
+	}
+}
+
+// Connect to the server and return the capabilities it advertised to us.
+fn advertised_capabilities(p2p_config: &p2p::P2PConfig, db_root: &str) -> Capabilities {
+	let net_adapter = Arc::new(p2p::DummyAdapter {});
+	let client = p2p::Server::new(
+		db_root,
+		Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		net_adapter.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		net_adapter,
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+	let capabilities = peer.info.capabilities;
+	peer.stop();
+	capabilities
+}
+
+// A syncing server only advertises its peer list, once synced (or close enough
+// to the most advanced peer) new peers see its full capabilities.
+#[test]
+fn syncing_advertises_reduced_capabilities() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		full_capabilities_max_lag: Some(10),
+		..p2p::P2PConfig::default()
+	};
+	let adapter = Arc::new(TestAdapter(SyncingAdapter {
+		status: RwLock::new(chain::SyncStatus::HeaderSync {
+			current_height: 0,
+			highest_height: 1000,
+		}),
+	}));
+	let server = Arc::new(
+		p2p::Server::new(
+			test_dir("sync_capabilities"),
+			Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			adapter.clone(),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let reduced = Capabilities::PEER_LIST | Capabilities::TOR_ADDRESS;
+	assert_eq!(
+		advertised_capabilities(&p2p_config, test_dir("sync_capabilities_client1")),
+		reduced
+	);
+
+	*adapter.status.write() = chain::SyncStatus::TxHashsetSetup;
+	assert_eq!(server.effective_capabilities(), reduced);
+
+	*adapter.status.write() = chain::SyncStatus::BodySync {
+		current_height: 995,
+		highest_height: 1000,
+	};
+	assert_eq!(server.effective_capabilities(), Capabilities::FULL_NODE);
+
+	*adapter.status.write() = chain::SyncStatus::NoSync;
+	assert_eq!(
+		advertised_capabilities(&p2p_config, test_dir("sync_capabilities_client2")),
+		Capabilities::FULL_NODE
+	);
+}
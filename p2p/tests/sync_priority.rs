@@ -0,0 +1,187 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util as util;
+
+mod common;
+
+use self::common::{open_port, server_with_adapter, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{rank_by_sync_priority, PeerAddr};
+use crate::p2p::{Peer, PeerInfo, SyncPriorityCtx, SyncPriorityWeights};
+
+/// Chain at the default test adapter difficulty of 1000.
+struct Chain;
+
+impl TestChain for Chain {}
+
+fn peer_info(addr: &str, total_difficulty: u64, rtt_ms: u64) -> PeerInfo {
+	let info = common::peer_info(PeerAddr::Ip(addr.parse().unwrap()));
+	{
+		let mut live_info = info.live_info.write();
+		live_info.total_difficulty = Difficulty::from_num(total_difficulty);
+		live_info.rtt = Some(Duration::from_millis(rtt_ms));
+	}
+	info
+}
+
+fn ranked(peers: &[PeerInfo], weights: SyncPriorityWeights) -> Vec<PeerAddr> {
+	let ctx = SyncPriorityCtx::new(weights, peers);
+	let mut ranked = peers.iter().collect::<Vec<_>>();
+	rank_by_sync_priority(&mut ranked, &ctx, |p| *p);
+	ranked.into_iter().map(|p| p.addr.clone()).collect()
+}
+
+// Difficulty dominates with the default weights, weighting the round trip
+// time heavily surfaces the fastest peer even with a slightly lower
+// difficulty.
+#[test]
+fn weights_change_ordering() {
+	let peers = vec![
+		peer_info("10.0.0.1:3414", 1_000, 500),
+		peer_info("10.0.0.2:3414", 900, 20),
+		peer_info("10.0.0.3:3414", 500, 10),
+	];
+	let addrs = peers.iter().map(|p| p.addr.clone()).collect::<Vec<_>>();
+
+	assert_eq!(
+		ranked(&peers, SyncPriorityWeights::default()),
+		vec![addrs[0].clone(), addrs[1].clone(), addrs[2].clone()]
+	);
+
+	let rtt_heavy = SyncPriorityWeights {
+		rtt: 10.0,
+		..SyncPriorityWeights::default()
+	};
+	assert_eq!(
+		ranked(&peers, rtt_heavy),
+		vec![addrs[1].clone(), addrs[2].clone(), addrs[0].clone()]
+	);
+
+	// Refusing most requests sinks a peer once reputation matters.
+	let reputation_heavy = SyncPriorityWeights {
+		reputation: 10.0,
+		..SyncPriorityWeights::default()
+	};
+	let mut ctx = SyncPriorityCtx::new(reputation_heavy, &peers);
+	ctx.refusal_rates.insert(addrs[0].clone(), 0.9);
+	assert!(peers[1].sync_priority(&ctx) > peers[0].sync_priority(&ctx));
+}
+
+// Unknown signals score nothing and the round trip time comes from our pings.
+#[test]
+fn rtt_from_ping() {
+	let info = peer_info("10.0.0.1:3414", 1_000, 0);
+	info.live_info.write().rtt = None;
+	let weights = SyncPriorityWeights {
+		difficulty: 0.0,
+		rtt: 1.0,
+		reputation: 0.0,
+		recency: 0.0,
+		uptime: 0.0,
+	};
+	let ctx = SyncPriorityCtx::new(weights, vec![&info]);
+	assert_eq!(info.sync_priority(&ctx), 0.0);
+
+	// a pong without a ping doesn't tell anything
+	info.pong_received();
+	assert_eq!(info.rtt(), None);
+
+	info.ping_sent();
+	info.pong_received();
+	assert!(info.rtt().is_some());
+	assert!(info.sync_priority(&ctx) > 0.9);
+}
+
+// Connect a client to the server, announcing the provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// A fast peer with less work than us would rank first on round trip time
+// alone but can't advance our sync, only peers with more work are ranked.
+#[test]
+fn ranked_sync_peers_have_more_work() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		sync_priority_weights: Some(SyncPriorityWeights {
+			rtt: 10.0,
+			..SyncPriorityWeights::default()
+		}),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(server_with_adapter(
+		test_dir("sync_priority"),
+		p2p::Capabilities::UNKNOWN,
+		p2p_config.clone(),
+		Arc::new(TestAdapter(Chain)),
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(Duration::from_secs(1));
+
+	let client = server_with_adapter(
+		test_dir("sync_priority_client"),
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		Arc::new(TestAdapter(Chain)),
+	);
+	let _behind = connect(&p2p_config, &client, 5000);
+	let _ahead = connect(&p2p_config, &client, 5001);
+	thread::sleep(Duration::from_millis(500));
+
+	let behind_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
+	let ahead_addr = PeerAddr::Ip("127.0.0.1:5001".parse().unwrap());
+	let behind = server.peers.get_connected_peer(behind_addr).unwrap();
+	behind.info.update(5, Difficulty::from_num(500), 0);
+	behind.info.live_info.write().rtt = Some(Duration::from_millis(10));
+	let ahead = server.peers.get_connected_peer(ahead_addr.clone()).unwrap();
+	ahead.info.update(20, Difficulty::from_num(2_000), 0);
+	ahead.info.live_info.write().rtt = Some(Duration::from_millis(500));
+
+	let sync_peers = server.peers.sync_peers();
+	assert_eq!(
+		sync_peers
+			.iter()
+			.map(|p| p.info.addr.clone())
+			.collect::<Vec<_>>(),
+		vec![ahead_addr]
+	);
+}
@@ -0,0 +1,134 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, test_dir, ArchiveSource, ArchiveTaker, TestAdapter};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{txhashset_fits, PeerAddr, TXHASHSET_UNZIP_OVERHEAD};
+use crate::p2p::Peer;
+
+const ARCHIVE_BYTES: usize = 100_000;
+
+// Asks a peer serving an archive for it, returns how many archives we wrote
+// and whether the peer is still connected after.
+fn download(name: &str, free_space: u64) -> (usize, bool) {
+	let db_root = test_dir(name);
+	std::fs::create_dir_all(db_root).unwrap();
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			Arc::new(TestAdapter(ArchiveSource {
+				height: 10,
+				bytes: ARCHIVE_BYTES,
+				dir: PathBuf::from(db_root),
+			})),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let taker = Arc::new(TestAdapter(ArchiveTaker {
+		acceptable: AtomicBool::new(true),
+		free_space: Some(free_space),
+		writes: AtomicUsize::new(0),
+	}));
+	let client = p2p::Server::new(
+		test_dir(&format!("{}_client", name)),
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		taker.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		taker.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+
+	peer.send_txhashset_request(10, Hash::default()).unwrap();
+	thread::sleep(time::Duration::from_secs(2));
+	(taker.writes.load(Ordering::SeqCst), peer.is_connected())
+}
+
+// An archive needs room for itself and its unzipped content.
+#[test]
+fn txhashset_needs_room_to_unzip() {
+	let archive: u64 = 500_000_000;
+	let needed = archive * (TXHASHSET_UNZIP_OVERHEAD + 1);
+
+	// low free space, refused
+	assert!(!txhashset_fits(archive, archive));
+	assert!(!txhashset_fits(archive, needed - 1));
+
+	// adequate free space, downloaded
+	assert!(txhashset_fits(archive, needed));
+	assert!(txhashset_fits(archive, 10 * needed));
+
+	// absurd sizes don't overflow
+	assert!(!txhashset_fits(u64::max_value(), u64::max_value() - 1));
+	assert!(txhashset_fits(0, 0));
+}
+
+// Without room for the archive it's declined and the peer kept, with enough
+// room it's downloaded.
+#[test]
+fn txhashset_refused_without_room() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let needed = ARCHIVE_BYTES as u64 * (TXHASHSET_UNZIP_OVERHEAD + 1);
+	assert_eq!(download("txhashset_no_room", needed - 1), (0, true));
+	assert_eq!(download("txhashset_room", needed), (1, true));
+}
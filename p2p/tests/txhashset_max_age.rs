@@ -0,0 +1,208 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir, ArchiveSource, ArchiveTaker, TestAdapter};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{NetAdapter, PeerAddr};
+use crate::p2p::Peer;
+
+// Connect a client to the server, announcing the provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+fn start_server(db_root: &str, config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	let server = Arc::new(new_server(db_root, config));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	server
+}
+
+// Set the height the server knows a connected peer at, as a ping would.
+fn set_height(server: &p2p::Server, port: u16, height: u64) {
+	let addr = PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port));
+	let peer = server.peers.get_connected_peer(addr).unwrap();
+	peer.info.update(height, Difficulty::min(), std::u64::MAX);
+}
+
+// Archives anchored further below the highest peer than the configured max
+// age are rejected, the ones within it are downloaded.
+#[test]
+fn txhashset_archive_max_age() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		txhashset_max_age: Some(100),
+		..p2p::P2PConfig::default()
+	};
+	let server = start_server(test_dir("txhashset_max_age"), p2p_config.clone());
+
+	// Nothing to compare against without peers.
+	assert!(server.peers.txhashset_archive_acceptable(0));
+
+	let client = new_server(
+		test_dir("txhashset_max_age_client"),
+		p2p::P2PConfig::default(),
+	);
+	let _peers: Vec<Peer> = (5000..5002)
+		.map(|port| connect(&p2p_config, &client, port))
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+	set_height(&server, 5000, 500);
+	set_height(&server, 5001, 1000);
+
+	// The network tip is the highest peer.
+	assert!(!server.peers.txhashset_archive_acceptable(0));
+	assert!(!server.peers.txhashset_archive_acceptable(899));
+	assert!(server.peers.txhashset_archive_acceptable(900));
+	assert!(server.peers.txhashset_archive_acceptable(1000));
+}
+
+// Without a configured max age any archive is accepted, as before.
+#[test]
+fn txhashset_archive_no_max_age() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = start_server(test_dir("txhashset_no_max_age"), p2p_config.clone());
+
+	let client = new_server(
+		test_dir("txhashset_no_max_age_client"),
+		p2p::P2PConfig::default(),
+	);
+	let _peer = connect(&p2p_config, &client, 5000);
+	thread::sleep(time::Duration::from_secs(1));
+	set_height(&server, 5000, 1_000_000);
+
+	assert!(server.peers.txhashset_archive_acceptable(0));
+}
+
+// An archive we find too old is declined without dropping the peer, the
+// connection stays usable for the next archive it sends.
+#[test]
+fn old_txhashset_declined() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let db_root = test_dir("old_txhashset_declined");
+	std::fs::create_dir_all(db_root).unwrap();
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::FULL_NODE,
+			p2p_config.clone(),
+			Arc::new(TestAdapter(ArchiveSource {
+				height: 10,
+				bytes: 100_000,
+				dir: PathBuf::from(db_root),
+			})),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let taker = Arc::new(TestAdapter(ArchiveTaker {
+		acceptable: AtomicBool::new(false),
+		free_space: None,
+		writes: AtomicUsize::new(0),
+	}));
+	let client = p2p::Server::new(
+		test_dir("old_txhashset_declined_client"),
+		p2p::Capabilities::UNKNOWN,
+		p2p::P2PConfig::default(),
+		taker.clone(),
+		Hash::from_vec(&vec![]),
+		Arc::new(StopState::new()),
+		0,
+		None,
+	)
+	.unwrap();
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip("127.0.0.1:5000".parse().unwrap()),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone(), None),
+		taker.clone(),
+		100_000,
+		None,
+		client,
+	)
+	.unwrap();
+
+	peer.send_txhashset_request(10, Hash::default()).unwrap();
+	thread::sleep(time::Duration::from_secs(2));
+	assert_eq!(taker.writes.load(Ordering::SeqCst), 0);
+	assert!(peer.is_connected());
+	assert!(!server
+		.peers
+		.is_banned(PeerAddr::Ip("127.0.0.1:5000".parse().unwrap())));
+
+	taker.acceptable.store(true, Ordering::SeqCst);
+	peer.send_txhashset_request(10, Hash::default()).unwrap();
+	thread::sleep(time::Duration::from_secs(2));
+	assert_eq!(taker.writes.load(Ordering::SeqCst), 1);
+	assert!(peer.is_connected());
+}
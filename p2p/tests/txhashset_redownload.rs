@@ -0,0 +1,190 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::fs::File;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{open_port, server_with_adapter, test_dir, TestAdapter, TestChain};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{Capabilities, ChainAdapter, Peer, PeerInfo};
+
+/// Adapter whose first txhashset writes fail as corrupt, later ones succeed.
+struct CorruptAdapter {
+	writes: AtomicUsize,
+	corrupt_writes: usize,
+}
+
+impl TestChain for CorruptAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn txhashset_write(
+		&self,
+		_h: Hash,
+		_txhashset_data: File,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if self.writes.fetch_add(1, Ordering::SeqCst) < self.corrupt_writes {
+			Err(chain::ErrorKind::CorruptTxHashSet("bad kernel sums".to_string()).into())
+		} else {
+			Ok(false)
+		}
+	}
+}
+
+fn corrupting_server(db_root: &str, config: p2p::P2PConfig, corrupt_writes: usize) -> p2p::Server {
+	let adapter = CorruptAdapter {
+		writes: AtomicUsize::new(0),
+		corrupt_writes,
+	};
+	server_with_adapter(
+		db_root,
+		Capabilities::FULL_NODE,
+		config,
+		Arc::new(TestAdapter(adapter)),
+	)
+}
+
+// Connect a client with more work than the server, announcing the provided
+// port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16) -> Peer {
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		Capabilities::FULL_NODE,
+		Difficulty::from_num(1000),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), server_config.clone(), None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+fn archive(db_root: &str) -> File {
+	let path = format!("{}/txhashset.zip", db_root);
+	File::create(&path).unwrap();
+	File::open(&path).unwrap()
+}
+
+// A txhashset proving corrupt isn't held against its sender with a ban, but
+// the next download goes to another peer, a bounded number of times.
+#[test]
+fn corrupt_txhashset_redownloaded_elsewhere() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let db_root = test_dir("txhashset_redownload");
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		max_txhashset_redownloads: Some(1),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(corrupting_server(db_root, p2p_config.clone(), 1));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = corrupting_server(
+		test_dir("txhashset_redownload_client"),
+		p2p::P2PConfig::default(),
+		0,
+	);
+	let _peers: Vec<Peer> = (5000..5002)
+		.map(|port| connect(&p2p_config, &client, port))
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_inbound_count(), 2);
+
+	let first = server.peers.txhashset_peer().unwrap();
+	let written = server
+		.peers
+		.txhashset_write(Hash::default(), archive(db_root), &first.info)
+		.unwrap();
+	assert!(!written);
+	assert!(!server.peers.is_banned(first.info.addr.clone()));
+
+	// The second download goes to the other peer and succeeds.
+	let second = server.peers.txhashset_peer().unwrap();
+	assert_ne!(second.info.addr, first.info.addr);
+	let written = server
+		.peers
+		.txhashset_write(Hash::default(), archive(db_root), &second.info)
+		.unwrap();
+	assert!(!written);
+
+	// Success forgets about corrupt archives, the first peer is fine again.
+	assert!(server.peers.txhashset_peer().is_some());
+}
+
+// Once every download proved corrupt we give up instead of looping forever.
+#[test]
+fn corrupt_txhashset_gives_up() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let db_root = test_dir("txhashset_give_up");
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		max_txhashset_redownloads: Some(1),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(corrupting_server(db_root, p2p_config.clone(), 10));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let client = corrupting_server(
+		test_dir("txhashset_give_up_client"),
+		p2p::P2PConfig::default(),
+		0,
+	);
+	let _peers: Vec<Peer> = (5000..5003)
+		.map(|port| connect(&p2p_config, &client, port))
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.peer_inbound_count(), 3);
+
+	for _ in 0..2 {
+		let peer = server.peers.txhashset_peer().unwrap();
+		server
+			.peers
+			.txhashset_write(Hash::default(), archive(db_root), &peer.info)
+			.unwrap();
+	}
+	// A third peer is still around but we're out of attempts.
+	assert!(server.peers.txhashset_peer().is_none());
+}
@@ -0,0 +1,152 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+use self::common::{open_port, peer_info, server_with_adapter, test_dir, TestAdapter, TestChain};
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::pow::{Difficulty, ProofOfWork};
+use crate::p2p::types::PeerAddr;
+use crate::p2p::{ChainAdapter, PeerInfo};
+
+/// Adapter at height 10 with a total difficulty of 1000, counting the headers
+/// passed on to it.
+#[derive(Default)]
+struct CountingAdapter {
+	received: AtomicUsize,
+}
+
+impl TestChain for CountingAdapter {
+	fn headers_received(
+		&self,
+		headers: &[core::core::BlockHeader],
+		_: &PeerInfo,
+		_: u64,
+	) -> Result<bool, chain::Error> {
+		self.received.fetch_add(headers.len(), Ordering::Relaxed);
+		Ok(true)
+	}
+}
+
+fn counting_server(db_root: &str, adapter: Arc<TestAdapter<CountingAdapter>>) -> p2p::Server {
+	let config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		max_uncorroborated_headers: Some(100),
+		..p2p::P2PConfig::default()
+	};
+	server_with_adapter(db_root, p2p::Capabilities::UNKNOWN, config, adapter)
+}
+
+// Headers at the provided heights, carrying 5000 of total difficulty.
+fn headers(heights: RangeInclusive<u64>) -> Vec<BlockHeader> {
+	heights
+		.map(|height| BlockHeader {
+			height,
+			pow: ProofOfWork {
+				total_difficulty: Difficulty::from_num(5000),
+				..ProofOfWork::default()
+			},
+			..BlockHeader::default()
+		})
+		.collect()
+}
+
+// A single peer feeding us headers past the cap is throttled, without being
+// banned.
+#[test]
+fn uncorroborated_headers_throttled() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let adapter = Arc::new(TestAdapter(CountingAdapter::default()));
+	let server = counting_server(test_dir("uncorroborated_headers"), adapter.clone());
+	let addr = PeerAddr::Ip("10.0.0.1:3414".parse().unwrap());
+	let info = peer_info(addr.clone());
+
+	// within the cap, all taken
+	assert!(server
+		.peers
+		.headers_received(&headers(50..=60), &info, 0)
+		.unwrap());
+	assert_eq!(adapter.received.swap(0, Ordering::Relaxed), 11);
+
+	// crossing it, only the ones up to tip + 100
+	assert!(server
+		.peers
+		.headers_received(&headers(105..=115), &info, 0)
+		.unwrap());
+	assert_eq!(adapter.received.swap(0, Ordering::Relaxed), 6);
+
+	// past it, none
+	assert!(server
+		.peers
+		.headers_received(&headers(111..=120), &info, 0)
+		.unwrap());
+	assert_eq!(adapter.received.swap(0, Ordering::Relaxed), 0);
+	assert!(!server.peers.is_banned(addr));
+}
+
+// Headers past the cap go through once another peer claims at least as much
+// total difficulty as they carry.
+#[test]
+fn corroborated_headers_proceed() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let adapter = Arc::new(TestAdapter(CountingAdapter::default()));
+	let server = counting_server(test_dir("corroborated_headers"), adapter.clone());
+	let info = peer_info(PeerAddr::Ip("10.0.0.1:3414".parse().unwrap()));
+
+	let remote_adapter = Arc::new(TestAdapter(CountingAdapter::default()));
+	let remote = Arc::new(counting_server(
+		test_dir("corroborated_headers_remote"),
+		remote_adapter,
+	));
+	let remote_inner = remote.clone();
+	let _ = thread::spawn(move || remote_inner.listen(100_000));
+	thread::sleep(Duration::from_millis(500));
+	let remote_addr = PeerAddr::Ip(SocketAddr::new(remote.config.host, remote.config.port));
+	server.connect(remote_addr.clone(), 100_000).unwrap();
+	let other = server.peers.get_connected_peer(remote_addr).unwrap();
+
+	// claiming less work than the headers carry doesn't corroborate them
+	other.info.live_info.write().total_difficulty = Difficulty::from_num(4000);
+	assert!(server
+		.peers
+		.headers_received(&headers(111..=120), &info, 0)
+		.unwrap());
+	assert_eq!(adapter.received.swap(0, Ordering::Relaxed), 0);
+
+	other.info.live_info.write().total_difficulty = Difficulty::from_num(6000);
+	assert!(server
+		.peers
+		.headers_received(&headers(111..=120), &info, 0)
+		.unwrap());
+	assert_eq!(adapter.received.swap(0, Ordering::Relaxed), 10);
+}
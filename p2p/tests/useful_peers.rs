@@ -0,0 +1,85 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use grin_core as core;
+use grin_p2p as p2p;
+
+mod common;
+
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{select_useful, PeerAddr};
+use crate::p2p::PeerInfo;
+
+fn peer_info(addr: &str, peer_uptime: Option<Duration>) -> PeerInfo {
+	let info = PeerInfo {
+		peer_uptime,
+		..common::peer_info(PeerAddr::Ip(addr.parse().unwrap()))
+	};
+	info.live_info.write().total_difficulty = Difficulty::from_num(1000);
+	info
+}
+
+// Between two peers of equal difficulty the one that served us valid data
+// more recently is ranked higher, whatever the order.
+#[test]
+fn select_most_recently_useful() {
+	let stale = peer_info("10.0.0.1:3414", None);
+	let recent = peer_info("10.0.0.2:3414", None);
+	stale.live_info.write().last_useful = Some(Utc::now() - chrono::Duration::minutes(10));
+	recent.note_progress();
+	assert!(recent.last_useful() > stale.last_useful());
+
+	let candidates = vec![stale.clone(), recent.clone()];
+	assert_eq!(select_useful(&candidates, |i| i).unwrap().addr, recent.addr);
+	let candidates = vec![recent.clone(), stale.clone()];
+	assert_eq!(select_useful(&candidates, |i| i).unwrap().addr, recent.addr);
+}
+
+// Proven useful peers come before untested ones, even ones advertising a
+// longer uptime. Among untested peers the uptime still decides.
+#[test]
+fn select_useful_before_untested() {
+	let untested = peer_info("10.0.0.1:3414", Some(Duration::from_secs(86_400)));
+	let useful = peer_info("10.0.0.2:3414", Some(Duration::from_secs(60)));
+	useful.note_progress();
+
+	let candidates = vec![untested.clone(), useful.clone()];
+	assert_eq!(select_useful(&candidates, |i| i).unwrap().addr, useful.addr);
+
+	let other = peer_info("10.0.0.3:3414", None);
+	let candidates = vec![other, untested.clone()];
+	assert_eq!(
+		select_useful(&candidates, |i| i).unwrap().addr,
+		untested.addr
+	);
+
+	let empty: Vec<PeerInfo> = vec![];
+	assert!(select_useful(&empty, |i| i).is_none());
+}
+
+// Peers that served us valid data about as recently are equally useful, the
+// most stable looking one of them is picked.
+#[test]
+fn select_among_recently_useful() {
+	let older = peer_info("10.0.0.1:3414", Some(Duration::from_secs(86_400)));
+	let newer = peer_info("10.0.0.2:3414", Some(Duration::from_secs(60)));
+	older.live_info.write().last_useful = Some(Utc::now() - chrono::Duration::seconds(10));
+	newer.note_progress();
+
+	let candidates = vec![newer.clone(), older.clone()];
+	assert_eq!(select_useful(&candidates, |i| i).unwrap().addr, older.addr);
+}
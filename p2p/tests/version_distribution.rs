@@ -0,0 +1,141 @@
+// Copyright 2020 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+mod common;
+
+use self::common::{new_server, open_port, test_dir};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::PeerAddr;
+use crate::p2p::Peer;
+
+// Connect a client speaking the provided protocol version, announcing the
+// provided port as its own.
+fn connect(server_config: &p2p::P2PConfig, client: &p2p::Server, port: u16, version: u32) -> Peer {
+	let config = p2p::P2PConfig {
+		protocol_version: Some(version),
+		..server_config.clone()
+	};
+	let addr = SocketAddr::new(server_config.host, server_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr::Ip(SocketAddr::new("127.0.0.1".parse().unwrap(), port)),
+		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), config, None),
+		client.peers.clone(),
+		100_000,
+		None,
+		client.clone(),
+	)
+	.unwrap()
+}
+
+// Connected peers are counted per negotiated protocol version.
+#[test]
+fn version_distribution_counts() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(
+		test_dir("version_distribution"),
+		p2p_config.clone(),
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	assert!(server.peers.version_distribution().is_empty());
+
+	let client = new_server(
+		test_dir("version_distribution_client"),
+		p2p::P2PConfig::default(),
+	);
+	let _peers: Vec<Peer> = vec![
+		(5000, 1),
+		(5001, 2),
+		(5002, 2),
+		(5003, 3),
+		(5004, 3),
+		(5005, 3),
+	]
+	.into_iter()
+	.map(|(port, version)| connect(&p2p_config, &client, port, version))
+	.collect();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let mut expected = HashMap::new();
+	expected.insert(ProtocolVersion(1), 1);
+	expected.insert(ProtocolVersion(2), 2);
+	expected.insert(ProtocolVersion(3), 3);
+	assert_eq!(server.peers.version_distribution(), expected);
+}
+
+// The oldest and newest versions spoken by connected peers, none without peers.
+#[test]
+fn min_max_connected_version() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(new_server(
+		test_dir("connected_version"),
+		p2p_config.clone(),
+	));
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen(100_000));
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.min_connected_version(), None);
+	assert_eq!(server.peers.max_connected_version(), None);
+
+	let client = new_server(
+		test_dir("connected_version_client"),
+		p2p::P2PConfig::default(),
+	);
+	let _peers: Vec<Peer> = vec![(5000, 3), (5001, 2), (5002, 4)]
+		.into_iter()
+		.map(|(port, version)| connect(&p2p_config, &client, port, version))
+		.collect();
+	thread::sleep(time::Duration::from_secs(1));
+
+	assert_eq!(
+		server.peers.min_connected_version(),
+		Some(ProtocolVersion(2))
+	);
+	assert_eq!(
+		server.peers.max_connected_version(),
+		Some(ProtocolVersion(4))
+	);
+}
@@ -38,7 +38,7 @@ use crate::core::pow::Difficulty;
 use crate::core::ser::ProtocolVersion;
 use crate::core::{core, global};
 use crate::p2p;
-use crate::p2p::types::PeerInfo;
+use crate::p2p::types::{BlockAccept, PeerInfo};
 use crate::pool::{self, BlockChain, PoolAdapter};
 use crate::util::OneTime;
 use chrono::prelude::*;
@@ -47,6 +47,14 @@ use rand::prelude::*;
 use std::sync::atomic::AtomicI64;
 use std::sync::atomic::Ordering;
 
+/// How full a collection of `len` items out of `max` is, from 0.0 to 1.0.
+fn fill_ratio(len: usize, max: usize) -> f64 {
+	if max == 0 {
+		return 1.0;
+	}
+	(len as f64 / max as f64).min(1.0)
+}
+
 // NetToChainAdapter need a memory cache to prevent data overloading for network core nodes (non leaf nodes)
 // This cache will drop sequense of the events during the second
 struct EventCache {
@@ -131,6 +139,17 @@ where
 		self.tx_pool.read().retrieve_tx_by_kernel_hash(kernel_hash)
 	}
 
+	/// No transaction weighs less than 1, the pool refuses anything paying
+	/// less than its base fee.
+	fn min_relay_fee(&self) -> u64 {
+		self.tx_pool.read().config.accept_fee_base
+	}
+
+	/// Blocks below the tail got compacted away.
+	fn prune_height(&self) -> u64 {
+		self.chain().tail().map(|tail| tail.height).unwrap_or(0)
+	}
+
 	fn tx_kernel_received(
 		&self,
 		kernel_hash: Hash,
@@ -195,17 +214,17 @@ where
 		b: core::Block,
 		peer_info: &PeerInfo,
 		opts: chain::Options,
-	) -> Result<bool, chain::Error> {
+	) -> Result<BlockAccept, chain::Error> {
 		let b_hash = b.hash();
 		if self.processed_blocks.contains(&b_hash, true) {
 			debug!("block_received, cache for {} Rejected", b_hash);
-			return Ok(true);
+			return Ok(BlockAccept::Duplicate);
 		} else {
 			debug!("block_received, cache for {} OK", b_hash);
 		}
 
 		if self.chain().block_exists(b.hash())? {
-			return Ok(true);
+			return Ok(BlockAccept::Duplicate);
 		}
 
 		info!(
@@ -258,6 +277,7 @@ where
 						}
 					}
 					self.process_block(block, peer_info, chain::Options::NONE)
+						.map(bool::from)
 				}
 				Err(e) => {
 					debug!("Invalid hydrated block {}: {:?}", cb_hash, e);
@@ -319,6 +339,7 @@ where
 						block.inputs().version_str(),
 					);
 					self.process_block(block, peer_info, chain::Options::NONE)
+						.map(bool::from)
 				} else if self.sync_state.status() == SyncStatus::NoSync {
 					debug!("adapter: block invalid after hydration, requesting full block");
 					self.request_block(&cb.header, peer_info, chain::Options::NONE);
@@ -387,6 +408,12 @@ where
 			}
 		}
 
+		// headers-only relays never get the block, so never see it accepted,
+		// they pass the header on as soon as they validated it
+		if self.config.p2p_config.headers_only() {
+			self.peers().broadcast_header(&bh);
+		}
+
 		// we have successfully processed a block header
 		// so we can go request the block itself
 		self.request_compact_block(&bh, peer_info);
@@ -526,9 +553,20 @@ where
 		bhs: &[core::BlockHeader],
 		header_cache_size: u64,
 	) -> Result<bool, chain::Error> {
+		// check the PoW of the whole batch on the worker pool first, so the
+		// chain locks are only held while applying the headers in order
+		if let Err(e) = self.validate_headers_batch(bhs) {
+			if e.is_bad_data() {
+				return Ok(false);
+			} else {
+				return Err(e);
+			}
+		}
+
 		let mut hashmap = self.header_cache.lock().unwrap();
 		// try to add headers to our header chain
-		match self.chain().sync_block_headers(bhs, chain::Options::SYNC) {
+		let opts = chain::Options::SYNC | chain::Options::POW_VERIFIED;
+		match self.chain().sync_block_headers(bhs, opts) {
 			Ok(_) => {
 				for bh in bhs {
 					let mut tip_processed = self.tip_processed.lock().unwrap();
@@ -590,6 +628,15 @@ where
 		Ok(headers)
 	}
 
+	fn headers_by_height(
+		&self,
+		start: u64,
+		count: u32,
+	) -> Result<Vec<core::BlockHeader>, chain::Error> {
+		debug!("headers by height: {} (count {})", start, count);
+		self.chain().headers_by_height(start, count as u64)
+	}
+
 	/// Gets a full block by its hash.
 	/// Will convert to v2 compatibility based on peer protocol version.
 	fn get_block(&self, h: Hash, peer_info: &PeerInfo) -> Option<core::Block> {
@@ -630,6 +677,32 @@ where
 		}
 	}
 
+	fn in_maintenance(&self) -> bool {
+		self.chain().in_maintenance()
+	}
+
+	fn sync_status(&self) -> SyncStatus {
+		self.sync_state.status()
+	}
+
+	fn knows_header(&self, hash: Hash) -> bool {
+		self.chain().get_block_header(&hash).is_ok()
+	}
+
+	/// How full the fullest of our transaction pool, stem pool and orphan
+	/// blocks waiting for their parent is.
+	fn load_factor(&self) -> f64 {
+		let (txpool, stempool) = {
+			let tx_pool = self.tx_pool.read();
+			(
+				fill_ratio(tx_pool.total_size(), tx_pool.config.max_pool_size),
+				fill_ratio(tx_pool.stempool.size(), tx_pool.config.max_stempool_size),
+			)
+		};
+		let orphans = fill_ratio(self.chain().orphans_len(), chain::MAX_ORPHAN_SIZE);
+		txpool.max(stempool).max(orphans)
+	}
+
 	fn txhashset_download_update(
 		&self,
 		start_time: DateTime<Utc>,
@@ -688,8 +761,18 @@ where
 			Err(e) => {
 				self.chain().clean_txhashset_sandbox();
 				error!("Failed to save txhashset archive: {}", e);
+				// the archive didn't validate, let the p2p layer know so it
+				// downloads it again from somebody else
+				let corrupt = if e.is_bad_data() {
+					Some(chain::ErrorKind::CorruptTxHashSet(e.to_string()))
+				} else {
+					None
+				};
 				self.sync_state.set_sync_error(e);
-				Ok(false)
+				match corrupt {
+					Some(kind) => Err(kind.into()),
+					None => Ok(false),
+				}
 			}
 		}
 	}
@@ -701,6 +784,39 @@ where
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.chain().get_tmpfile_pathname(tmpfile_name)
 	}
+
+	fn tmp_dir_free_space(&self) -> Option<u64> {
+		let mut dir = self.chain().get_tmp_dir();
+		// the tmp dir only gets created with the first tmp file
+		if !dir.exists() {
+			dir.pop();
+		}
+		match fs2::available_space(&dir) {
+			Ok(n) => Some(n),
+			Err(e) => {
+				warn!("tmp_dir_free_space: failed to query {:?}: {}", dir, e);
+				None
+			}
+		}
+	}
+
+	fn common_ancestor_height(&self, locator: &[Hash]) -> Option<u64> {
+		self.chain().common_ancestor_height(locator)
+	}
+
+	fn header_head(&self) -> Option<chain::Tip> {
+		self.chain().header_head().ok()
+	}
+
+	fn missing_block_ranges(&self, from: u64, to: u64) -> Vec<(u64, u64)> {
+		match self.chain().missing_block_ranges(from, to) {
+			Ok(ranges) => ranges,
+			Err(e) => {
+				error!("missing_block_ranges: {} to {}: {:?}", from, to, e);
+				vec![]
+			}
+		}
+	}
 }
 
 impl<B, P, V> NetToChainAdapter<B, P, V>
@@ -773,6 +889,24 @@ where
 		None
 	}
 
+	/// Validate the PoW of a batch of headers on the chain worker pool. Returns
+	/// the error of the first invalid header in the batch, if any.
+	pub fn validate_headers_batch(&self, bhs: &[BlockHeader]) -> Result<(), chain::Error> {
+		let results = self.chain().validate_headers_pow(bhs);
+		for (bh, res) in bhs.iter().zip(results) {
+			if let Err(e) = res {
+				debug!(
+					"Block header {} at height {} failed validation: {:?}",
+					bh.hash(),
+					bh.height,
+					e
+				);
+				return Err(e);
+			}
+		}
+		Ok(())
+	}
+
 	// pushing the new block through the chain pipeline
 	// remembering to reset the head if we have a bad block
 	fn process_block(
@@ -780,7 +914,7 @@ where
 		b: core::Block,
 		peer_info: &PeerInfo,
 		opts: chain::Options,
-	) -> Result<bool, chain::Error> {
+	) -> Result<BlockAccept, chain::Error> {
 		// We cannot process blocks earlier than the horizon so check for this here.
 		{
 			let head = self.chain().head()?;
@@ -788,7 +922,7 @@ where
 				.height
 				.saturating_sub(global::cut_through_horizon() as u64);
 			if b.header.height < horizon {
-				return Ok(true);
+				return Ok(BlockAccept::Accepted);
 			}
 		}
 
@@ -799,11 +933,22 @@ where
 			Ok(_) => {
 				self.validate_chain(bhash);
 				self.check_compact();
-				Ok(true)
+				self.check_txhashset_archive();
+				Ok(BlockAccept::Accepted)
 			}
 			Err(ref e) if e.is_bad_data() => {
 				self.validate_chain(bhash);
-				Ok(false)
+				Ok(BlockAccept::Invalid(e.kind().to_string()))
+			}
+			// a busy store may let the block through next time, the p2p layer
+			// processes it again
+			Err(e) if e.is_transient() => {
+				debug!(
+					"process_block: block {} failed transiently: {}",
+					bhash,
+					e.kind()
+				);
+				Err(e)
 			}
 			Err(e) => {
 				match e.kind() {
@@ -817,7 +962,11 @@ where
 								self.request_block(&previous, peer_info, chain::Options::NONE)
 							}
 						}
-						Ok(true)
+						Ok(BlockAccept::Orphan)
+					}
+					chain::ErrorKind::Unfit(_) => {
+						debug!("process_block: block {} already known: {}", bhash, e.kind());
+						Ok(BlockAccept::Duplicate)
 					}
 					_ => {
 						debug!(
@@ -825,7 +974,7 @@ where
 							bhash,
 							e.kind()
 						);
-						Ok(true)
+						Ok(BlockAccept::Accepted)
 					}
 				}
 			}
@@ -881,6 +1030,27 @@ where
 		}
 	}
 
+	fn check_txhashset_archive(&self) {
+		// Nothing to prepare if we don't serve archives, or while syncing.
+		if !self.config.p2p_config.serve_txhashset() || self.sync_state.is_syncing() {
+			return;
+		}
+
+		// Build the archive peers will ask for next as soon as the one we
+		// offer went stale, in a different thread as it takes a while.
+		let chain = self.chain();
+		if !chain.txhashset_archive_stale() {
+			return;
+		}
+		let _ = thread::Builder::new()
+			.name("txhashset_archive".to_string())
+			.spawn(move || {
+				if let Err(e) = chain.refresh_txhashset_archive() {
+					error!("Could not build txhashset archive: {:?}", e);
+				}
+			});
+	}
+
 	fn request_transaction(&self, h: Hash, peer_info: &PeerInfo) {
 		self.send_tx_request_to_peer(h, peer_info, |peer, h| peer.send_tx_request(h))
 	}
@@ -908,6 +1078,9 @@ where
 	where
 		F: Fn(&p2p::Peer, Hash) -> Result<(), p2p::Error>,
 	{
+		if self.config.p2p_config.headers_only() {
+			return;
+		}
 		match self.peers().get_connected_peer(peer_info.addr.clone()) {
 			None => debug!(
 				"send_tx_request_to_peer: can't send request to peer {:?}, not connected",
@@ -925,6 +1098,9 @@ where
 	where
 		F: Fn(&p2p::Peer, Hash) -> Result<(), p2p::Error>,
 	{
+		if self.config.p2p_config.headers_only() {
+			return;
+		}
 		match self.chain().block_exists(h) {
 			Ok(false) => match self.peers().get_connected_peer(peer_info.addr.clone()) {
 				None => debug!(
@@ -16,6 +16,7 @@
 //! to collect information about server status
 
 use crate::util::RwLock;
+use std::collections::HashMap;
 use std::sync::atomic::*;
 use std::sync::Arc;
 use std::time::SystemTime;
@@ -27,6 +28,7 @@ use crate::core::ser::ProtocolVersion;
 use chrono::prelude::*;
 
 use crate::chain::SyncStatus;
+use crate::grin::seed::SeedBreakerState;
 use crate::p2p;
 use grin_core::pow::Difficulty;
 
@@ -61,6 +63,10 @@ pub struct ServerStats {
 	pub stratum_stats: Arc<StratumStats>,
 	/// Peer stats
 	pub peer_stats: Vec<PeerStats>,
+	/// Connected peers per negotiated protocol version
+	pub peer_versions: HashMap<ProtocolVersion, u32>,
+	/// State of the breaker backing off DNS seed resolution
+	pub seed_breaker: SeedBreakerState,
 	/// Difficulty calculation statistics
 	pub diff_stats: DiffStats,
 	/// Transaction pool statistics
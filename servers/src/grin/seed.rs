@@ -20,8 +20,6 @@
 use chrono::prelude::{DateTime, Utc};
 use chrono::{Duration, MIN_DATE};
 use grin_p2p::PeerAddr::Onion;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
 use std::collections::HashMap;
 use std::net::ToSocketAddrs;
 use std::sync::{mpsc, Arc};
@@ -30,9 +28,9 @@ use std::{cmp, str, thread, time};
 use crate::core::global;
 use crate::p2p;
 use crate::p2p::libp2p_connection;
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{PeerAddr, SeedSource};
 use crate::p2p::ChainAdapter;
-use crate::util::StopState;
+use crate::util::{RwLock, StopState};
 
 // MWC - all DNS hosts are updated with seed1.mwc.mw/seed2.mwc.mw and others
 const MAINNET_DNS_SEEDS: &'static [&'static str] = &[
@@ -52,10 +50,108 @@ const FLOONET_DNS_SEEDS: &'static [&'static str] = &[
 	"kin4i3wohlsqlzrdwdlowh2kaa7wtkxsvp6asn7vttspnrwowgquglyd.onion",
 ];
 
+/// How often we retry resolving our DNS seeds while we have no connected peers.
+const SEED_RETRY_INTERVAL_SECS: i64 = 60;
+
+/// Consecutive failed resolutions before the seed breaker starts backing off.
+const SEED_FAILURE_THRESHOLD: u32 = 3;
+
+/// Upper bound on the seed retry interval while the breaker is open.
+const SEED_MAX_RETRY_INTERVAL_SECS: i64 = 3600;
+
+/// State of the seed resolution circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeedBreakerState {
+	/// Seeds resolve (or failed less than the threshold), retrying at the base interval.
+	Closed,
+	/// Seeds repeatedly failed to resolve, retrying at an exponentially growing interval.
+	Open,
+}
+
+/// Circuit breaker backing off DNS seed resolution after repeated failures
+/// (no network, DNS down) so we don't keep hammering resolvers and logs.
+pub struct SeedBreaker {
+	failures: u32,
+	last_attempt: DateTime<Utc>,
+}
+
+impl SeedBreaker {
+	pub fn new() -> SeedBreaker {
+		SeedBreaker {
+			failures: 0,
+			last_attempt: MIN_DATE.and_hms(0, 0, 0),
+		}
+	}
+
+	/// Current state of the breaker, for diagnostics.
+	pub fn state(&self) -> SeedBreakerState {
+		if self.failures >= SEED_FAILURE_THRESHOLD {
+			SeedBreakerState::Open
+		} else {
+			SeedBreakerState::Closed
+		}
+	}
+
+	/// Number of consecutive failed resolutions.
+	pub fn failures(&self) -> u32 {
+		self.failures
+	}
+
+	/// Interval to wait between two resolutions, doubling with every failure
+	/// past the threshold, up to SEED_MAX_RETRY_INTERVAL_SECS.
+	pub fn interval(&self) -> Duration {
+		let secs = match self.state() {
+			SeedBreakerState::Closed => SEED_RETRY_INTERVAL_SECS,
+			SeedBreakerState::Open => {
+				let exp = cmp::min(self.failures - SEED_FAILURE_THRESHOLD + 1, 16);
+				cmp::min(
+					SEED_RETRY_INTERVAL_SECS << exp,
+					SEED_MAX_RETRY_INTERVAL_SECS,
+				)
+			}
+		};
+		Duration::seconds(secs)
+	}
+
+	/// Whether enough time passed since the last resolution to try again.
+	pub fn should_attempt(&self, now: DateTime<Utc>) -> bool {
+		now - self.last_attempt >= self.interval()
+	}
+
+	/// Record the outcome of a resolution. Only state changes are logged.
+	pub fn record(&mut self, success: bool, now: DateTime<Utc>) {
+		let prev_state = self.state();
+		self.last_attempt = now;
+		if success {
+			self.failures = 0;
+		} else {
+			self.failures = self.failures.saturating_add(1);
+		}
+		match (prev_state, self.state()) {
+			(SeedBreakerState::Closed, SeedBreakerState::Open) => warn!(
+				"seed: no seed addresses resolved {} times in a row, backing off to {}s",
+				self.failures,
+				self.interval().num_seconds()
+			),
+			(SeedBreakerState::Open, SeedBreakerState::Closed) => {
+				info!("seed: resolved seeds again, back to normal retry interval")
+			}
+			_ => {}
+		}
+	}
+}
+
+impl Default for SeedBreaker {
+	fn default() -> SeedBreaker {
+		SeedBreaker::new()
+	}
+}
+
 pub fn connect_and_monitor(
 	p2p_server: Arc<p2p::Server>,
 	capabilities: p2p::Capabilities,
-	seed_list: Box<dyn Fn() -> Vec<PeerAddr> + Send>,
+	seed_list_fn: Box<dyn Fn() -> Vec<(PeerAddr, SeedSource)> + Send>,
+	seed_breaker: Arc<RwLock<SeedBreaker>>,
 	preferred_peers: &[PeerAddr],
 	stop_state: Arc<StopState>,
 	header_cache_size: u64,
@@ -71,7 +167,10 @@ pub fn connect_and_monitor(
 			// open a channel with a listener that connects every peer address sent below
 			// max peer count
 			let (tx, rx) = mpsc::channel();
-			let seed_list = seed_list();
+			let mut seed_list = seed_list_fn();
+			seed_breaker
+				.write()
+				.record(!seed_list.is_empty(), Utc::now());
 
 			// check seeds first
 			connect_to_seeds_and_preferred_peers(
@@ -81,11 +180,15 @@ pub fn connect_and_monitor(
 				&preferred_peers,
 			);
 
-			libp2p_connection::set_seed_list(&seed_list, true);
+			libp2p_connection::set_seed_list(
+				&seed_list.iter().map(|(addr, _)| addr.clone()).collect(),
+				true,
+			);
 
 			let mut prev = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_expire_check = MIN_DATE.and_hms(0, 0, 0);
 			let mut prev_ping = Utc::now();
+			let mut prev_regossip = time::Instant::now();
 			let mut start_attempt = 0;
 			let mut connecting_history: HashMap<PeerAddr, DateTime<Utc>> = HashMap::new();
 			loop {
@@ -106,7 +209,17 @@ pub fn connect_and_monitor(
 				};
 
 				if connected_peers == 0 {
-					info!("No peers connected, trying to reconnect to seeds!");
+					// the breaker logs when resolving starts or stops failing
+					if seed_breaker.read().should_attempt(Utc::now()) {
+						debug!("No peers connected, resolving seeds again");
+						let resolved = seed_list_fn();
+						seed_breaker
+							.write()
+							.record(!resolved.is_empty(), Utc::now());
+						if !resolved.is_empty() {
+							seed_list = resolved;
+						}
+					}
 					connect_to_seeds_and_preferred_peers(
 						peers.clone(),
 						tx.clone(),
@@ -122,6 +235,7 @@ pub fn connect_and_monitor(
 				// Check for and remove expired peers from the storage
 				if peer_count > 0 && Utc::now() - prev_expire_check > Duration::hours(1) {
 					peers.remove_expired();
+					peers.prune_expired_bans(Utc::now());
 
 					prev_expire_check = Utc::now();
 				}
@@ -181,6 +295,27 @@ pub fn connect_and_monitor(
 					}
 				}
 
+				// Pass on the peer addresses we received, in capped batches
+				if prev_regossip.elapsed() > p2p_server.config.peer_addrs_regossip_interval() {
+					peers.regossip_peer_addrs();
+					prev_regossip = time::Instant::now();
+				}
+
+				// Keep idle NAT mappings and tor circuits warm
+				peers.send_keepalives(time::Instant::now());
+
+				// Notice capability changes of long-lived peers
+				peers.probe_capabilities(time::Instant::now());
+
+				// Process again the blocks the chain failed on transiently
+				peers.retry_deferred_blocks(time::Instant::now());
+
+				// Let our peers know of changes of our own capabilities
+				p2p_server.refresh_capabilities();
+
+				// Check our listener is reachable from outside, if configured to
+				p2p_server.probe_reachability(time::Instant::now());
+
 				thread::sleep(time::Duration::from_secs(1));
 			}
 		})
@@ -222,17 +357,24 @@ fn monitor_peers(
 	}
 
 	debug!(
-		"monitor_peers: on {}:{}, {} connected ({} most_work). \
+		"monitor_peers: on {}:{}, {} connected ({} distinct nodes, {} most_work). \
 		 all {} = {} healthy + {} banned + {} defunct",
 		config.host,
 		config.port,
 		peers.peer_count(),
+		peers.distinct_node_count(),
 		peers.most_work_peers().len(),
 		total_count,
 		healthy_count,
 		banned_count,
 		defuncts.len(),
 	);
+	debug!(
+		"monitor_peers: {} outbound peers recommended for {} known peers (max_outbound {})",
+		p2p::types::recommended_outbound_count(total_count),
+		total_count,
+		config.peer_max_outbound_count(),
+	);
 
 	// maintenance step first, clean up p2p server peers
 	peers.clean_peers(
@@ -241,23 +383,24 @@ fn monitor_peers(
 		preferred_peers,
 	);
 
+	let relaxed = peers.check_outbound_deficit(Utc::now());
 	if peers.enough_outbound_peers() {
 		return;
 	}
 
-	// loop over connected peers
-	// ask them for their list of peers
-	let mut connected_peers: Vec<PeerAddr> = vec![];
-	for p in peers.connected_peers() {
-		trace!(
-			"monitor_peers: {}:{} ask {} for more peers",
-			config.host,
-			config.port,
-			p.info.addr,
-		);
-		let _ = p.send_peer_request(p2p::Capabilities::PEER_LIST);
-		connected_peers.push(p.info.addr.clone())
-	}
+	// ask connected peers for their list of peers
+	let asked = peers.request_peer_addrs();
+	trace!(
+		"monitor_peers: {}:{} asked {} peers for more peers",
+		config.host,
+		config.port,
+		asked,
+	);
+	let connected_peers: Vec<PeerAddr> = peers
+		.connected_peers()
+		.iter()
+		.map(|p| p.info.addr.clone())
+		.collect();
 
 	// Attempt to connect to any preferred peers.
 	for p in preferred_peers {
@@ -273,7 +416,7 @@ fn monitor_peers(
 	// take a random defunct peer and mark it healthy: over a long period any
 	// peer will see another as defunct eventually, gives us a chance to retry
 	if !defuncts.is_empty() {
-		defuncts.shuffle(&mut thread_rng());
+		peers.shuffle(&mut defuncts);
 		let _ = peers.update_state(defuncts[0].addr.clone(), p2p::State::Healthy);
 	}
 
@@ -282,11 +425,8 @@ fn monitor_peers(
 	// intentionally make too many attempts (2x) as some (most?) will fail
 	// as many nodes in our db are not publicly accessible
 	let max_peer_attempts = 128;
-	let new_peers = peers.find_peers(
-		p2p::State::Healthy,
-		p2p::Capabilities::UNKNOWN,
-		max_peer_attempts as usize,
-	);
+	// (defunct peers too once we've been short of outbound peers for a while)
+	let new_peers = peers.outbound_candidates(max_peer_attempts as usize, relaxed);
 
 	// Only queue up connection attempts for candidate peers where we
 	// are confident we do not yet know about this peer.
@@ -299,26 +439,47 @@ fn monitor_peers(
 	}
 }
 
-// Check if we have any pre-existing peer in db. If so, start with those,
-// otherwise use the seeds provided.
+// Anchor peers from our previous run are dialed first. Then check if we have
+// any pre-existing peer in db. If so, continue with those, otherwise use the
+// seeds provided. Where each address came from is noted for the peers we end
+// up connected to.
 fn connect_to_seeds_and_preferred_peers(
 	peers: Arc<p2p::Peers>,
 	tx: mpsc::Sender<PeerAddr>,
-	seed_list: Vec<PeerAddr>,
+	seed_list: Vec<(PeerAddr, SeedSource)>,
 	peers_preferred: &[PeerAddr],
 ) {
+	let mut peer_addrs = peers.anchors();
+	for addr in &peer_addrs {
+		peers.note_seed_source(addr.clone(), SeedSource::Anchor);
+	}
+
 	// check if we have some peers in db
 	// look for peers that are able to give us other peers (via PEER_LIST capability)
-	let peers = peers.find_peers(p2p::State::Healthy, p2p::Capabilities::PEER_LIST, 100);
+	let known = peers.find_peers(p2p::State::Healthy, p2p::Capabilities::PEER_LIST, 100);
 
-	// if so, get their addresses, otherwise use our seeds
-	let mut peer_addrs = if peers.len() > 3 {
-		peers.iter().map(|p| p.addr.clone()).collect::<Vec<_>>()
+	// if so, get their addresses (learned through gossip), otherwise use our seeds
+	let seed_addrs = if known.len() > 3 {
+		known.iter().map(|p| p.addr.clone()).collect::<Vec<_>>()
 	} else {
 		seed_list
+			.into_iter()
+			.map(|(addr, source)| {
+				peers.note_seed_source(addr.clone(), source);
+				addr
+			})
+			.collect()
 	};
+	for addr in seed_addrs {
+		if !peer_addrs.contains(&addr) {
+			peer_addrs.push(addr);
+		}
+	}
 
 	// If we have preferred peers add them to the initial list
+	for addr in peers_preferred {
+		peers.note_seed_source(addr.clone(), SeedSource::List);
+	}
 	peer_addrs.extend_from_slice(peers_preferred);
 
 	if peer_addrs.is_empty() {
@@ -349,6 +510,11 @@ fn listen_for_addrs(
 	// here to prevent it backing up.
 	let mut addrs: Vec<PeerAddr> = rx.try_iter().collect();
 
+	// Dialing is paused, keep the peer set as it is.
+	if !p2p.is_dialing_enabled() {
+		return;
+	}
+
 	if attempt_all {
 		for x in peers.all_peers() {
 			match x.flags {
@@ -368,9 +534,19 @@ fn listen_for_addrs(
 	// Even if there are many addresses to try we will only try a bounded number of them for safety.
 	let connect_min_interval = 30;
 	let max_outbound_attempts = 128;
+	let addrs = peers.dial_candidates(addrs);
 	for addr in addrs.into_iter().take(max_outbound_attempts) {
-		// ignore the duplicate connecting to same peer within 30 seconds
 		let now = Utc::now();
+		// give peers we just got disconnected from some time first
+		if !peers.reconnect_allowed(&addr, now) {
+			debug!(
+				"peer_connect: deferring redial of recently disconnected {}",
+				addr
+			);
+			continue;
+		}
+
+		// ignore the duplicate connecting to same peer within 30 seconds
 		if let Some(last_connect_time) = connecting_history.get(&addr) {
 			if *last_connect_time + Duration::seconds(connect_min_interval) > now {
 				debug!(
@@ -379,11 +555,15 @@ fn listen_for_addrs(
 					last_connect_time.format("%H:%M:%S%.3f").to_string(),
 				);
 				continue;
-			} else if let Some(history) = connecting_history.get_mut(&addr) {
-				*history = now;
 			}
 		}
 
+		// don't set up tor circuits too often, the dial is made later
+		if !peers.dial_allowed(&addr, now) {
+			debug!("peer_connect: deferring onion dial to {}", addr);
+			continue;
+		}
+
 		connecting_history.insert(addr.clone(), now);
 
 		let peers_c = peers.clone();
@@ -443,8 +623,10 @@ fn listen_for_addrs(
 	}
 }
 
-pub fn default_dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
-	Box::new(|| {
+pub fn default_dns_seeds(
+	preference: p2p::AddressFamilyPreference,
+) -> Box<dyn Fn() -> Vec<(PeerAddr, SeedSource)> + Send> {
+	Box::new(move || {
 		let net_seeds = if global::is_floonet() {
 			FLOONET_DNS_SEEDS
 		} else {
@@ -466,24 +648,31 @@ pub fn default_dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 					}
 				})
 				.collect(),
+			preference,
 		)
 	})
 }
 
-fn resolve_dns_to_addrs(dns_records: &Vec<String>) -> Vec<PeerAddr> {
-	let mut addresses: Vec<PeerAddr> = vec![];
+fn resolve_dns_to_addrs(
+	dns_records: &Vec<String>,
+	preference: p2p::AddressFamilyPreference,
+) -> Vec<(PeerAddr, SeedSource)> {
+	let mut addresses: Vec<(PeerAddr, SeedSource)> = vec![];
 	for dns in dns_records {
+		let source = SeedSource::Dns(dns.split(':').next().unwrap_or(dns).to_string());
 		if dns.ends_with(".onion") {
-			addresses.push(PeerAddr::from_str(&dns))
+			addresses.push((PeerAddr::from_str(&dns), source))
 		} else {
 			debug!("Retrieving addresses from dns {}", dns);
 			match dns.to_socket_addrs() {
-				Ok(addrs) => addresses.append(
-					&mut addrs
-						.map(PeerAddr::Ip)
-						.filter(|addr| !addresses.contains(addr))
-						.collect(),
-				),
+				Ok(addrs) => {
+					for addr in preference.order(addrs.collect()) {
+						let addr = PeerAddr::Ip(addr);
+						if !addresses.iter().any(|(a, _)| *a == addr) {
+							addresses.push((addr, source.clone()));
+						}
+					}
+				}
 				Err(e) => debug!("Failed to resolve dns {:?} got error {:?}", dns, e),
 			};
 		}
@@ -492,8 +681,241 @@ fn resolve_dns_to_addrs(dns_records: &Vec<String>) -> Vec<PeerAddr> {
 	addresses
 }
 
-/// Convenience function when the seed list is immediately known. Mostly used
-/// for tests.
-pub fn predefined_seeds(addrs: Vec<PeerAddr>) -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
-	Box::new(move || addrs.clone())
+/// Convenience function when the seed list is immediately known (configured
+/// seeds, tests).
+pub fn predefined_seeds(
+	addrs: Vec<PeerAddr>,
+) -> Box<dyn Fn() -> Vec<(PeerAddr, SeedSource)> + Send> {
+	Box::new(move || {
+		addrs
+			.iter()
+			.map(|addr| (addr.clone(), SeedSource::List))
+			.collect()
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::hash::Hash;
+	use crate::util;
+	use std::fs;
+	use std::net::{SocketAddr, TcpListener};
+
+	fn open_port() -> u16 {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		listener.local_addr().unwrap().port()
+	}
+
+	fn new_p2p_server(db_root: &str) -> Arc<p2p::Server> {
+		let config = p2p::P2PConfig {
+			host: "127.0.0.1".parse().unwrap(),
+			port: open_port(),
+			seeding_type: p2p::Seeding::Programmatic,
+			..p2p::P2PConfig::default()
+		};
+		let server = p2p::Server::new(
+			db_root,
+			p2p::Capabilities::UNKNOWN,
+			config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&vec![]),
+			Arc::new(StopState::new()),
+			0,
+			None,
+		)
+		.unwrap();
+		Arc::new(server)
+	}
+
+	#[test]
+	fn test_anchors_dialed_before_seeds() {
+		global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+		util::init_test_logger();
+		let test_dir = "target/test_output/seed_anchors";
+		let _ = fs::remove_dir_all(test_dir);
+
+		let anchor = new_p2p_server(&format!("{}/anchor", test_dir));
+		let anchor_inner = anchor.clone();
+		let _ = thread::spawn(move || anchor_inner.listen(100_000));
+		thread::sleep(time::Duration::from_secs(1));
+
+		let node = new_p2p_server(&format!("{}/node", test_dir));
+		let anchor_addr = PeerAddr::Ip(SocketAddr::new(anchor.config.host, anchor.config.port));
+		node.connect(anchor_addr.clone(), 100_000).unwrap();
+		assert_eq!(node.peers.peer_outbound_count(), 1);
+
+		// Simulate a restart, outbound peers are saved as anchors when peers are stopped.
+		node.pause();
+		assert_eq!(node.peers.peer_count(), 0);
+		assert_eq!(node.peers.anchors(), vec![anchor_addr.clone()]);
+
+		let seed_addr = PeerAddr::Ip("127.0.0.1:1".parse().unwrap());
+		let (tx, rx) = mpsc::channel();
+		connect_to_seeds_and_preferred_peers(
+			node.peers.clone(),
+			tx,
+			vec![(seed_addr.clone(), SeedSource::List)],
+			&[],
+		);
+		let dialed: Vec<PeerAddr> = rx.try_iter().collect();
+		assert_eq!(dialed, vec![anchor_addr, seed_addr]);
+
+		anchor.stop();
+		node.stop();
+		let _ = fs::remove_dir_all(test_dir);
+	}
+
+	#[test]
+	fn test_seed_source_tagging() {
+		global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+		util::init_test_logger();
+		let test_dir = "target/test_output/seed_sources";
+		let _ = fs::remove_dir_all(test_dir);
+
+		let dns_seed = new_p2p_server(&format!("{}/dns_seed", test_dir));
+		let list_seed = new_p2p_server(&format!("{}/list_seed", test_dir));
+		for seed in &[dns_seed.clone(), list_seed.clone()] {
+			let seed = seed.clone();
+			let _ = thread::spawn(move || seed.listen(100_000));
+		}
+		thread::sleep(time::Duration::from_secs(1));
+
+		let node = new_p2p_server(&format!("{}/node", test_dir));
+		let dns_addr = PeerAddr::Ip(SocketAddr::new(dns_seed.config.host, dns_seed.config.port));
+		let list_addr = PeerAddr::Ip(SocketAddr::new(
+			list_seed.config.host,
+			list_seed.config.port,
+		));
+		let dns_source = SeedSource::Dns("seed.example.org".to_string());
+
+		let (tx, rx) = mpsc::channel();
+		connect_to_seeds_and_preferred_peers(
+			node.peers.clone(),
+			tx,
+			vec![
+				(dns_addr.clone(), dns_source.clone()),
+				(list_addr.clone(), SeedSource::List),
+			],
+			&[],
+		);
+		for addr in rx.try_iter() {
+			node.connect(addr, 100_000).unwrap();
+		}
+
+		let dns_peer = node.peers.get_connected_peer(dns_addr).unwrap();
+		assert_eq!(dns_peer.info.seed_source(), Some(dns_source.clone()));
+		let list_peer = node.peers.get_connected_peer(list_addr).unwrap();
+		assert_eq!(list_peer.info.seed_source(), Some(SeedSource::List));
+
+		let breakdown = node.peers.seed_source_breakdown();
+		assert_eq!(breakdown.len(), 2);
+		assert_eq!(breakdown.get(&dns_source), Some(&1));
+		assert_eq!(breakdown.get(&SeedSource::List), Some(&1));
+
+		dns_seed.stop();
+		list_seed.stop();
+		node.stop();
+		let _ = fs::remove_dir_all(test_dir);
+	}
+
+	#[test]
+	fn test_dialing_disabled() {
+		global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+		util::init_test_logger();
+		let test_dir = "target/test_output/seed_dialing";
+		let _ = fs::remove_dir_all(test_dir);
+
+		let seed = new_p2p_server(&format!("{}/seed", test_dir));
+		let seed_inner = seed.clone();
+		let _ = thread::spawn(move || seed_inner.listen(100_000));
+		thread::sleep(time::Duration::from_secs(1));
+		let seed_addr = PeerAddr::Ip(SocketAddr::new(seed.config.host, seed.config.port));
+
+		let node = new_p2p_server(&format!("{}/node", test_dir));
+		assert!(node.is_dialing_enabled());
+		node.set_dialing_enabled(false);
+		assert!(!node.is_dialing_enabled());
+
+		// Below the outbound target, but nothing gets dialed.
+		let mut history = HashMap::new();
+		let (tx, rx) = mpsc::channel();
+		tx.send(seed_addr.clone()).unwrap();
+		listen_for_addrs(
+			node.peers.clone(),
+			node.clone(),
+			p2p::Capabilities::UNKNOWN,
+			&rx,
+			&mut history,
+			100_000,
+			false,
+		);
+		thread::sleep(time::Duration::from_secs(1));
+		assert!(history.is_empty());
+		assert_eq!(node.peers.peer_outbound_count(), 0);
+
+		// Dialing resumes once enabled again.
+		node.set_dialing_enabled(true);
+		tx.send(seed_addr.clone()).unwrap();
+		listen_for_addrs(
+			node.peers.clone(),
+			node.clone(),
+			p2p::Capabilities::UNKNOWN,
+			&rx,
+			&mut history,
+			100_000,
+			false,
+		);
+		for _ in 0..50 {
+			if node.peers.peer_outbound_count() > 0 {
+				break;
+			}
+			thread::sleep(time::Duration::from_millis(100));
+		}
+		assert!(history.contains_key(&seed_addr));
+		assert_eq!(node.peers.peer_outbound_count(), 1);
+
+		seed.stop();
+		node.stop();
+		let _ = fs::remove_dir_all(test_dir);
+	}
+
+	#[test]
+	fn test_seed_breaker() {
+		let mut breaker = SeedBreaker::default();
+		let now = Utc::now();
+		assert!(breaker.should_attempt(now));
+		let base = breaker.interval();
+
+		// Below the threshold we keep retrying at the base interval.
+		for _ in 0..SEED_FAILURE_THRESHOLD - 1 {
+			breaker.record(false, now);
+			assert_eq!(breaker.state(), SeedBreakerState::Closed);
+			assert_eq!(breaker.interval(), base);
+		}
+
+		// Past it every failure widens the interval, up to the cap.
+		let mut prev = base;
+		for _ in 0..3 {
+			breaker.record(false, now);
+			assert_eq!(breaker.state(), SeedBreakerState::Open);
+			assert!(breaker.interval() > prev);
+			prev = breaker.interval();
+		}
+		assert!(!breaker.should_attempt(now + base));
+		assert!(breaker.should_attempt(now + prev));
+		for _ in 0..20 {
+			breaker.record(false, now);
+		}
+		assert_eq!(
+			breaker.interval(),
+			Duration::seconds(SEED_MAX_RETRY_INTERVAL_SECS)
+		);
+
+		// A single success resets it.
+		breaker.record(true, now);
+		assert_eq!(breaker.state(), SeedBreakerState::Closed);
+		assert_eq!(breaker.failures(), 0);
+		assert_eq!(breaker.interval(), base);
+	}
 }
@@ -107,6 +107,8 @@ pub struct Server {
 	/// Maintain a lock_file so we do not run multiple Grin nodes from same dir.
 	lock_file: Arc<File>,
 	connect_thread: Option<JoinHandle<()>>,
+	/// Backs off resolving our DNS seeds while they keep failing
+	seed_breaker: Arc<RwLock<seed::SeedBreaker>>,
 	sync_thread: JoinHandle<()>,
 	dandelion_thread: JoinHandle<()>,
 }
@@ -504,6 +506,7 @@ impl Server {
 		net_adapter.init(p2p_server.peers.clone());
 
 		let mut connect_thread = None;
+		let seed_breaker = Arc::new(RwLock::new(seed::SeedBreaker::default()));
 
 		if config.p2p_config.seeding_type != p2p::Seeding::Programmatic {
 			let seeder = match config.p2p_config.seeding_type {
@@ -519,7 +522,9 @@ impl Server {
 						));
 					}
 				},
-				p2p::Seeding::DNSSeed => seed::default_dns_seeds(),
+				p2p::Seeding::DNSSeed => {
+					seed::default_dns_seeds(config.p2p_config.preferred_address_family())
+				}
 				_ => unreachable!(),
 			};
 
@@ -532,6 +537,7 @@ impl Server {
 				p2p_server.clone(),
 				config.p2p_config.capabilities,
 				seeder,
+				seed_breaker.clone(),
 				&preferred_peers,
 				stop_state.clone(),
 				header_cache_size,
@@ -613,6 +619,7 @@ impl Server {
 			stop_state,
 			lock_file,
 			connect_thread,
+			seed_breaker,
 			sync_thread,
 			dandelion_thread,
 		})
@@ -968,6 +975,8 @@ impl Server {
 			disk_usage_gb: disk_usage_gb,
 			stratum_stats: self.state_info.stratum_stats.clone(),
 			peer_stats: peer_stats,
+			peer_versions: self.p2p.peers.version_distribution(),
+			seed_breaker: self.seed_breaker.read().state(),
 			diff_stats: diff_stats,
 			tx_stats: tx_stats,
 		})
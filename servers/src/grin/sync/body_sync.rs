@@ -20,6 +20,7 @@ use std::sync::Arc;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::core::core::hash::Hash;
 use crate::p2p;
+use crate::p2p::types::route_block_requests;
 
 pub struct BodySync {
 	chain: Arc<chain::Chain>,
@@ -99,15 +100,22 @@ impl BodySync {
 			chain::MAX_ORPHAN_SIZE.saturating_sub(self.chain.orphans_len()) + 1,
 		);
 
-		let hashes_to_get = hashes
-			.iter()
-			.filter(|x| {
-				// only ask for blocks that we have not yet processed
-				// either successfully stored or in our orphan list
-				self.chain.get_block(x).is_err() && !self.chain.is_orphan(x)
-			})
+		// only ask for blocks that we have not yet processed, either
+		// successfully stored or in our orphan list, hashes go from right past
+		// the fork point with our header chain to the header head
+		let from = match hashes.first() {
+			Some(hash) => self.chain.get_block_header(hash)?.height,
+			None => return Ok(false),
+		};
+		let to = from + hashes.len() as u64 - 1;
+		let (heights, hashes_to_get): (Vec<u64>, Vec<&Hash>) = self
+			.chain
+			.missing_block_ranges(from, to)?
+			.into_iter()
+			.flat_map(|(start, end)| start..=end)
+			.filter_map(|height| hashes.get((height - from) as usize).map(|h| (height, h)))
 			.take(block_count)
-			.collect::<Vec<_>>();
+			.unzip();
 
 		if !hashes_to_get.is_empty() {
 			let body_head = self.chain.head()?;
@@ -125,15 +133,20 @@ impl BodySync {
 			self.blocks_requested = 0;
 			self.receive_timeout = Utc::now() + Duration::seconds(6);
 
-			let mut peers_iter = peers.iter().cycle();
-			for hash in hashes_to_get.clone() {
-				if let Some(peer) = peers_iter.next() {
-					if let Err(e) = peer.send_block_request(*hash, chain::Options::SYNC) {
-						debug!("Skipped request to {}: {:?}", peer.info.addr, e);
-						peer.stop();
-					} else {
-						self.blocks_requested += 1;
+			// only route each block to peers whose tip is at or above it and
+			// that didn't prune it yet
+			let routes = route_block_requests(&peers, &heights, |p| &p.info);
+			for (hash, peer) in hashes_to_get.iter().zip(routes) {
+				match peer {
+					Some(peer) => {
+						if let Err(e) = peer.send_block_request(**hash, chain::Options::SYNC) {
+							debug!("Skipped request to {}: {:?}", peer.info.addr, e);
+							peer.stop();
+						} else {
+							self.blocks_requested += 1;
+						}
 					}
+					None => debug!("body_sync: no peer can serve block {}", hash),
 				}
 			}
 		}
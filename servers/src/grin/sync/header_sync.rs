@@ -19,7 +19,9 @@ use std::sync::Arc;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::common::types::Error;
 use crate::core::core::hash::{Hash, Hashed};
-use crate::p2p::{self, types::ReasonForBan, Peer};
+use crate::p2p::msg::LOCATOR_COUNT_VERSION;
+use crate::p2p::types::ReasonForBan;
+use crate::p2p::{self, Peer};
 
 pub struct HeaderSync {
 	sync_state: Arc<SyncState>,
@@ -28,6 +30,8 @@ pub struct HeaderSync {
 	prev_header_sync: (DateTime<Utc>, u64, u64),
 	syncing_peer: Option<Arc<Peer>>,
 	stalling_ts: Option<DateTime<Utc>>,
+	// number of headers asked for in our last request
+	requested_batch: u64,
 }
 
 impl HeaderSync {
@@ -43,6 +47,7 @@ impl HeaderSync {
 			prev_header_sync: (Utc::now(), 0, 0),
 			syncing_peer: None,
 			stalling_ts: None,
+			requested_batch: p2p::MAX_BLOCK_HEADERS as u64,
 		}
 	}
 
@@ -101,7 +106,7 @@ impl HeaderSync {
 
 		// received all necessary headers, can ask for more
 		let all_headers_received =
-			header_head.height >= prev_height + (p2p::MAX_BLOCK_HEADERS as u64) - 4;
+			header_head.height >= prev_height + self.requested_batch.saturating_sub(4);
 		// no headers processed and we're past timeout, need to ask for more
 		let stalling = header_head.height <= latest_height && now > timeout;
 
@@ -127,6 +132,19 @@ impl HeaderSync {
 				self.stalling_ts = None;
 			}
 
+			// give up on the request to the syncing peer, freeing its header
+			// sync slot and asking it for fewer headers next time
+			if stalling {
+				if let Some(ref peer) = self.syncing_peer {
+					peer.info.reset_header_sync();
+					peer.info.header_batch_timed_out();
+				}
+			} else if all_headers_received {
+				if let Some(ref peer) = self.syncing_peer {
+					peer.info.header_batch_answered();
+				}
+			}
+
 			if all_headers_received {
 				// reset the stalling start time if syncing goes well
 				self.stalling_ts = None;
@@ -172,29 +190,65 @@ impl HeaderSync {
 		if let Ok(header_head) = self.chain.header_head() {
 			let difficulty = header_head.total_difficulty;
 
-			if let Some(peer) = self.peers.most_work_peer() {
-				if peer.info.total_difficulty() > difficulty {
-					return self.request_headers(peer);
-				}
+			// only peers with more work than our header chain can advance it,
+			// over the max_header_sync_peers limit they wait for a free slot
+			let mut peers = self.peers.sync_peers();
+			peers.retain(|p| p.info.total_difficulty() > difficulty);
+			let candidates = self.peers.header_sync_candidates(peers);
+			match self.peers.select_sync_peer(&candidates) {
+				Some(peer) => return self.request_headers(peer),
+				None => debug!("sync: no header sync candidate available"),
 			}
 		}
 		return None;
 	}
 
-	/// Request some block headers from a peer to advance us.
+	/// Request some block headers from a peer to advance us. Peers supporting
+	/// it are asked for as many as they showed they can promptly serve, older
+	/// ones always send a full batch.
 	fn request_headers(&mut self, peer: Arc<Peer>) -> Option<Arc<Peer>> {
 		if let Ok(locator) = self.get_locator() {
+			self.log_fork(&peer, &locator);
+			let count = if peer.info.version >= LOCATOR_COUNT_VERSION {
+				peer.info.header_batch_size()
+			} else {
+				p2p::MAX_BLOCK_HEADERS
+			};
 			debug!(
-				"sync: request_headers: asking {} for headers, {:?}",
-				peer.info.addr, locator,
+				"sync: request_headers: asking {} for {} headers, {:?}",
+				peer.info.addr, count, locator,
 			);
 
-			let _ = peer.send_header_request(locator);
+			let _ = peer.send_header_request(locator, count);
+			self.requested_batch = count as u64;
 			return Some(peer);
 		}
 		return None;
 	}
 
+	/// Logs how many blocks ago the chain we're syncing from the peer diverged
+	/// from our header chain, if it did. As far as the locator tells, its
+	/// hashes get sparser going back.
+	fn log_fork(&self, peer: &Peer, locator: &[Hash]) {
+		let header_head = match self.chain.header_head() {
+			Ok(header_head) => header_head,
+			Err(_) => return,
+		};
+		match self.chain.get_locator_hashes(&[header_head.height]) {
+			Ok(ref hashes) if hashes.first() == Some(&header_head.last_block_h) => return,
+			Err(_) => return,
+			_ => {}
+		}
+		if let Some(ancestor) = self.chain.common_ancestor_height(locator) {
+			info!(
+				"sync: {} diverged from our header chain {} blocks ago, at {}",
+				peer.info.addr,
+				header_head.height.saturating_sub(ancestor),
+				ancestor,
+			);
+		}
+	}
+
 	/// We build a locator based on sync_head.
 	/// Even if sync_head is significantly out of date we will "reset" it once we
 	/// start getting headers back from a peer.
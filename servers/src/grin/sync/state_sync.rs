@@ -19,7 +19,7 @@ use std::sync::Arc;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::core::core::hash::Hashed;
 use crate::core::global;
-use crate::p2p::{self, Peer};
+use crate::p2p::{self, Capabilities, Peer};
 
 /// Fast sync has 3 "states":
 /// * syncing headers
@@ -117,9 +117,18 @@ impl StateSync {
 		if sync_need_restart || header_head.height == highest_height {
 			let (go, download_timeout) = self.state_sync_due();
 
-			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
+			if let SyncStatus::TxHashsetDownload(status) = self.sync_state.status() {
 				if download_timeout {
 					error!("state_sync: TxHashsetDownload status timeout in 10 minutes!");
+					// not a single byte of the archive, the peer declined our request
+					if status.downloaded_size == 0 {
+						if let Some(ref peer) = self.state_sync_peer {
+							self.peers.capability_refused(
+								peer.info.addr.clone(),
+								Capabilities::TXHASHSET_HIST,
+							);
+						}
+					}
 					self.sync_state.set_sync_error(
 						chain::ErrorKind::SyncError(format!("{:?}", p2p::Error::Timeout)).into(),
 					);
@@ -163,7 +172,8 @@ impl StateSync {
 		let mut txhashset_height = header_head.height.saturating_sub(threshold);
 		txhashset_height = txhashset_height.saturating_sub(txhashset_height % archive_interval);
 
-		if let Some(peer) = self.peers.most_work_peer() {
+		// skips peers whose txhashset already proved corrupt
+		if let Some(peer) = self.peers.txhashset_peer() {
 			// ask for txhashset at state_sync_threshold
 			let mut txhashset_head = self
 				.chain
@@ -202,10 +212,12 @@ impl StateSync {
 				error!("state_sync: send_txhashset_request err! {:?}", e);
 				return Err(e);
 			}
+			self.peers
+				.capability_requested(peer.info.addr.clone(), Capabilities::TXHASHSET_HIST);
 			return Ok(peer);
 		}
 		Err(p2p::Error::PeerException(
-			"peer, most_work_peer is not found".to_string(),
+			"peer, txhashset_peer is not found".to_string(),
 		))
 	}
 
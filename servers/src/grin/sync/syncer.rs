@@ -17,6 +17,7 @@ use std::thread;
 use std::time;
 
 use crate::chain::{self, SyncState, SyncStatus};
+use crate::core::consensus::difficulty_ratio;
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::grin::sync::body_sync::BodySync;
@@ -25,6 +26,15 @@ use crate::grin::sync::state_sync::StateSync;
 use crate::p2p;
 use crate::util::StopState;
 
+/// Whether enough of our peers advertise at least the work of our header
+/// chain to commit to downloading its blocks or the txhashset. A single peer
+/// feeding us a long fake header chain doesn't get it past header sync.
+fn corroborates(corroborated: Option<Difficulty>, header_head: &chain::Tip) -> bool {
+	corroborated.map_or(false, |difficulty| {
+		difficulty >= header_head.total_difficulty
+	})
+}
+
 pub fn run_sync(
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
@@ -189,6 +199,11 @@ impl SyncRunner {
 			// except for state sync that only runs if body sync return true (means txhashset is needed)
 			unwrap_or_restart_loop!(header_sync.check_run(&header_head, highest_height));
 
+			// headers-only relays never sync block bodies nor the txhashset
+			if self.peers.headers_only() {
+				continue;
+			}
+
 			let mut check_state_sync = false;
 			match self.sync_state.status() {
 				SyncStatus::TxHashsetDownload { .. }
@@ -203,6 +218,12 @@ impl SyncRunner {
 						continue;
 					}
 
+					// nor do we start downloading blocks or the txhashset toward
+					// more work than enough peers claim
+					if !corroborates(self.peers.corroborated_difficulty(), &header_head) {
+						continue;
+					}
+
 					let check_run =
 						unwrap_or_restart_loop!(body_sync.check_run(&head, highest_height));
 					if check_run {
@@ -220,7 +241,12 @@ impl SyncRunner {
 	/// Whether we're currently syncing the chain or we're fully caught up and
 	/// just receiving blocks through gossip.
 	fn needs_syncing(&self) -> Result<(bool, u64), chain::Error> {
-		let local_diff = self.chain.head()?.total_difficulty;
+		// headers-only relays never move their body head, only headers count
+		let local_diff = if self.peers.headers_only() {
+			self.chain.header_head()?.total_difficulty
+		} else {
+			self.chain.head()?.total_difficulty
+		};
 		let mut is_syncing = self.sync_state.is_syncing();
 		let peer = self.peers.most_work_peer();
 
@@ -264,9 +290,10 @@ impl SyncRunner {
 			let peer_diff = peer_info.total_difficulty();
 			if peer_diff > local_diff + threshold {
 				info!(
-					"sync: total_difficulty {}, peer_difficulty {}, threshold {} (last 5 blocks), enabling sync",
+					"sync: total_difficulty {}, peer_difficulty {} ({:.4}x ours), threshold {} (last 5 blocks), enabling sync",
 					local_diff,
 					peer_diff,
+					difficulty_ratio(peer_diff, local_diff),
 					threshold,
 				);
 				is_syncing = true;
@@ -275,3 +302,29 @@ impl SyncRunner {
 		Ok((is_syncing, peer_info.height()))
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn tip(total_difficulty: u64) -> chain::Tip {
+		chain::Tip {
+			total_difficulty: Difficulty::from_num(total_difficulty),
+			..chain::Tip::default()
+		}
+	}
+
+	// Sync stays headers-only until enough peers back the work of our header
+	// chain, peers merely ahead of our body head don't vouch for a header
+	// chain much longer than theirs.
+	#[test]
+	fn bodies_need_corroborated_header_head() {
+		assert!(!corroborates(None, &tip(1_000)));
+		assert!(!corroborates(
+			Some(Difficulty::from_num(1_010)),
+			&tip(100_000)
+		));
+		assert!(corroborates(Some(Difficulty::from_num(1_000)), &tip(1_000)));
+		assert!(corroborates(Some(Difficulty::from_num(2_000)), &tip(1_000)));
+	}
+}